@@ -0,0 +1,72 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use remoulade_core::{
+    ext::Extensions,
+    freg::{FRegType, F128},
+    inst::Instruction,
+    reg::RegType,
+};
+
+#[derive(Clone, Copy)]
+struct All;
+
+impl Extensions for All {
+    const ZBA: bool = true;
+    const ZBB: bool = true;
+    const ZBC: bool = true;
+    const ZBS: bool = true;
+    const ZBKB: bool = true;
+    const ZBKC: bool = true;
+    const ZBKX: bool = true;
+    const ZKND: bool = true;
+    const ZKNE: bool = true;
+    const ZKNH: bool = true;
+    const ZKSED: bool = true;
+    const ZKSH: bool = true;
+    const ZKR: bool = true;
+    const ZICOND: bool = true;
+    const ZICBOM: bool = true;
+    const ZICBOZ: bool = true;
+    const ZIHINTPAUSE: bool = true;
+    const ZFH: bool = true;
+    const ZFHMIN: bool = true;
+    const ZFBFMIN: bool = true;
+    const H: bool = true;
+    const V: bool = true;
+}
+
+fn check<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions>(
+    raw32: u32,
+) {
+    let inst = Instruction::<I, E, M, A, F, X>::decode_raw32(raw32);
+    let text = inst.to_string();
+    assert_eq!(inst.encode_raw32(), Some(raw32), "{text}");
+}
+
+fuzz_target!(|data: &[u8]| {
+    // NOTE: Two bytes are fed in as a compressed parcel, four as a full word.
+    let raw32 = match *data {
+        [a, b] => u16::from_le_bytes([a, b]) as u32,
+        [a, b, c, d] => u32::from_le_bytes([a, b, c, d]),
+        _ => return,
+    };
+
+    check::<u32, false, false, false, (), ()>(raw32);
+    check::<u32, true, false, false, (), ()>(raw32);
+    check::<u32, false, true, true, f32, ()>(raw32);
+    check::<u32, false, true, true, f64, All>(raw32);
+    check::<u64, true, true, true, (), ()>(raw32);
+    check::<u64, false, true, true, f64, ()>(raw32);
+    check::<u64, false, true, true, f64, All>(raw32);
+    check::<u128, false, true, true, F128, All>(raw32);
+});
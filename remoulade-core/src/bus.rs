@@ -0,0 +1,101 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! The physical memory bus seen by a hart.
+
+use crate::snapshot::{Reader, Snapshot, SnapshotError, Writer};
+
+/// Raised by a [`Bus`] when nothing responds at an address.
+///
+/// The hart turns this into the access fault matching the kind of access.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AccessFault;
+
+/// A physical address space.
+///
+/// Accesses are little-endian and may be misaligned.
+pub trait Bus {
+    fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), AccessFault>;
+    fn write(&mut self, addr: u64, buf: &[u8]) -> Result<(), AccessFault>;
+}
+
+impl<B: Bus + ?Sized> Bus for &mut B {
+    fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), AccessFault> {
+        (**self).read(addr, buf)
+    }
+
+    fn write(&mut self, addr: u64, buf: &[u8]) -> Result<(), AccessFault> {
+        (**self).write(addr, buf)
+    }
+}
+
+/// Plain memory mapped at `base`.
+pub struct Ram {
+    base: u64,
+    mem: Vec<u8>,
+}
+
+impl Ram {
+    pub fn new(base: u64, size: usize) -> Self {
+        Self {
+            base,
+            mem: vec![0; size],
+        }
+    }
+
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.mem
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.mem
+    }
+
+    fn range(&self, addr: u64, len: usize) -> Option<std::ops::Range<usize>> {
+        let start = usize::try_from(addr.checked_sub(self.base)?).ok()?;
+        let end = start.checked_add(len)?;
+        (end <= self.mem.len()).then_some(start..end)
+    }
+}
+
+impl Bus for Ram {
+    fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), AccessFault> {
+        let range = self.range(addr, buf.len()).ok_or(AccessFault)?;
+        buf.copy_from_slice(&self.mem[range]);
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u64, buf: &[u8]) -> Result<(), AccessFault> {
+        let range = self.range(addr, buf.len()).ok_or(AccessFault)?;
+        self.mem[range].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+impl Snapshot for Ram {
+    fn save(&self, w: &mut Writer) {
+        w.u64(self.base);
+        w.u64(self.mem.len() as u64);
+        w.bytes(&self.mem);
+    }
+
+    /// Restores the contents of memory, which has to be mapped at the same base and have the
+    /// same size.
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        r.expect("base of RAM", self.base)?;
+        r.expect("size of RAM", self.mem.len() as u64)?;
+        let len = self.mem.len();
+        self.mem.copy_from_slice(r.bytes(len)?);
+        Ok(())
+    }
+}
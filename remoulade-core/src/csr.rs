@@ -7,7 +7,11 @@
 //
 // Copyright (C) 2024 mumblingdrunkard
 
-#[derive(Copy, Clone, Debug)]
+use std::sync::OnceLock;
+
+use crate::snapshot::{Reader, Snapshot, SnapshotError, Writer};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 /// CSR registers.
 ///
 /// Variants are documented as ``Number | Privilege | Description``
@@ -20,6 +24,26 @@ pub enum Csr {
     /// ``0x003 | URW | Floating-Point Control and Status Register (`Frm + Fflags`).``
     Fcsr,
 
+    // Unprivileged Vector CSRs
+    /// ``0x008 | URW | Vector start position.``
+    Vstart,
+    /// ``0x009 | URW | Fixed-point accrued saturation flag.``
+    Vxsat,
+    /// ``0x00A | URW | Fixed-point rounding mode.``
+    Vxrm,
+    /// ``0x00F | URW | Vector control and status register (`Vxrm + Vxsat`).``
+    Vcsr,
+    /// ``0xC20 | URO | Vector length.``
+    Vl,
+    /// ``0xC21 | URO | Vector data type register.``
+    Vtype,
+    /// ``0xC22 | URO | Vector register length in bytes.``
+    Vlenb,
+
+    // Unprivileged Entropy Source CSR
+    /// ``0x015 | URW | Seed for cryptographic random bit generators.``
+    Seed,
+
     // Unprivileged Counters/Timers
     /// ``0xC00 | URO | Cycle counter for RDCYCLE instruction.``
     Cycle,
@@ -181,6 +205,10 @@ pub enum Csr {
     /// ``0x180 | SRW | Supervisor address translation and protection.``
     Satp,
 
+    // Supervisor Count Overflow
+    /// ``0xDA0 | SRO | Supervisor count overflow.``
+    Scountovf,
+
     // Debug/Trace Registers
     /// ``0x5A8 | SRW | Supervisor-mode context register.``
     Scontext,
@@ -217,6 +245,10 @@ pub enum Csr {
     /// ``0x61A | HRW | Additional hypervisor env. conf. register, RV32 only.``
     Henvcfgh,
 
+    // Hypervisor Protection and Translation
+    /// ``0x680 | HRW | Hypervisor guest address translation and protection.``
+    Hgatp,
+
     // Debug/Trace Registers
     /// ``0x6A8 | HRW | Hypervisor-mode context register.``
     Hcontext,
@@ -664,6 +696,8 @@ pub enum Csr {
     Tdata2,
     /// ``0x7A3 | MRW | Third Debug/Trace trigger data register.``
     Tdata3,
+    /// ``0x7A4 | MRW | Debug/Trace trigger information.``
+    Tinfo,
     /// ``0x7A8 | MRW | Machine-mode context register.``
     Mcontext,
 
@@ -687,6 +721,14 @@ impl Csr {
             0x001 => Fflags,
             0x002 => Frm,
             0x003 => Fcsr,
+            0x008 => Vstart,
+            0x009 => Vxsat,
+            0x00A => Vxrm,
+            0x00F => Vcsr,
+            0xC20 => Vl,
+            0xC21 => Vtype,
+            0xC22 => Vlenb,
+            0x015 => Seed,
             0xC00 => Cycle,
             0xC01 => Time,
             0xC02 => Instret,
@@ -762,6 +804,7 @@ impl Csr {
             0x143 => Stval,
             0x144 => Sip,
             0x180 => Satp,
+            0xDA0 => Scountovf,
             0x5A8 => Scontext,
             0x600 => Hstatus,
             0x602 => Hedeleg,
@@ -776,6 +819,7 @@ impl Csr {
             0xE12 => Hgeip,
             0x60A => Henvcfg,
             0x61A => Henvcfgh,
+            0x680 => Hgatp,
             0x6A8 => Hcontext,
             0x605 => Htimedelta,
             0x615 => Htimedeltah,
@@ -988,6 +1032,7 @@ impl Csr {
             0x7A1 => Tdata1,
             0x7A2 => Tdata2,
             0x7A3 => Tdata3,
+            0x7A4 => Tinfo,
             0x7A8 => Mcontext,
             0x7B0 => Dcsr,
             0x7B1 => Dpc,
@@ -1002,10 +1047,41 @@ impl Csr {
     pub fn decode_raw32(raw32: u32) -> Option<Self> {
         Self::checked_from_u32(raw32 >> 20)
     }
+
+    /// The address of this CSR.
+    pub fn as_u32(self) -> u32 {
+        // NOTE: Inverted from `checked_from_u32`, so there is only one table to keep up to date.
+        static ADDRESSES: OnceLock<[u32; CSR_FILE_SIZE]> = OnceLock::new();
+        let addresses = ADDRESSES.get_or_init(|| {
+            let mut addresses = [0; CSR_FILE_SIZE];
+            for addr in 0..0x1000 {
+                if let Some(csr) = Self::checked_from_u32(addr) {
+                    addresses[csr as usize] = addr;
+                }
+            }
+            addresses
+        });
+        addresses[self as usize]
+    }
+
+    pub fn encode_raw32(self) -> u32 {
+        self.as_u32() << 20
+    }
+
+    /// The position of `self` in the numbered group `first..=last`, if it's part of it.
+    pub(crate) fn offset_in(self, first: Self, last: Self) -> Option<usize> {
+        let (csr, first, last) = (self as usize, first as usize, last as usize);
+        (first..=last).contains(&csr).then(|| csr - first)
+    }
 }
 
+/// Backing storage for every CSR.
+///
+/// Values are stored at 64 bits so the same file serves RV32 and RV64 harts.
+/// Side effects of accesses are the responsibility of the hart.
+#[derive(Clone)]
 pub struct CsrFile {
-    reg: [u32; CSR_FILE_SIZE],
+    reg: [u64; CSR_FILE_SIZE],
 }
 
 impl Default for CsrFile {
@@ -1020,4 +1096,48 @@ impl CsrFile {
             reg: [0; CSR_FILE_SIZE],
         }
     }
+
+    pub fn read(&self, csr: Csr) -> u64 {
+        self.reg[csr as usize]
+    }
+
+    pub fn write(&mut self, csr: Csr, value: u64) {
+        self.reg[csr as usize] = value;
+    }
+}
+
+impl Snapshot for CsrFile {
+    fn save(&self, w: &mut Writer) {
+        w.u64(CSR_FILE_SIZE as u64);
+        for &value in &self.reg {
+            w.u64(value);
+        }
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        r.expect("number of CSRs", CSR_FILE_SIZE as u64)?;
+        for value in &mut self.reg {
+            *value = r.u64()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Csr, CSR_FILE_SIZE};
+
+    #[test]
+    fn test_addresses() {
+        // Every CSR has exactly one address, which it maps back to
+        let mut count = 0;
+        for addr in 0..0x1000 {
+            if let Some(csr) = Csr::checked_from_u32(addr) {
+                assert_eq!(csr.as_u32(), addr, "{csr:?}");
+                count += 1;
+            }
+        }
+        assert_eq!(count, CSR_FILE_SIZE);
+        assert_eq!(Csr::checked_from_u32(0x1000), None);
+    }
 }
@@ -0,0 +1,28 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! External debug support following the RISC-V Debug Specification 1.0.
+//!
+//! A [`DebugModule`] controls the harts, a JTAG [`Dtm`] gives access to it, and
+//! [`RemoteBitbang`] lets OpenOCD drive the JTAG pins over TCP:
+//!
+//! ```text
+//! OpenOCD --TCP--> RemoteBitbang --pins--> Dtm --DMI--> DebugModule --> harts, bus
+//! ```
+//!
+//! None of these run on their own; the embedder polls the server between calls to
+//! [`Hart::run`](crate::hart::Hart::run).
+
+mod bitbang;
+mod dm;
+mod dtm;
+
+pub use bitbang::RemoteBitbang;
+pub use dm::{DebugHart, DebugModule};
+pub use dtm::Dtm;
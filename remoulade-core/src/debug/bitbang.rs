@@ -0,0 +1,184 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! A server for OpenOCD's `remote_bitbang` adapter driver.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    bus::Bus,
+    debug::{DebugHart, Dtm},
+};
+
+/// Drives the pins of a [`Dtm`] from one OpenOCD connection at a time.
+///
+/// OpenOCD connects with
+///
+/// ```text
+/// adapter driver remote_bitbang
+/// remote_bitbang host localhost
+/// remote_bitbang port <port>
+/// ```
+pub struct RemoteBitbang {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    dtm: Dtm,
+}
+
+impl RemoteBitbang {
+    /// Listens on `addr` without accepting anything yet.
+    pub fn bind(addr: impl ToSocketAddrs, dtm: Dtm) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+            dtm,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn dtm(&self) -> &Dtm {
+        &self.dtm
+    }
+
+    pub fn dtm_mut(&mut self) -> &mut Dtm {
+        &mut self.dtm
+    }
+
+    /// Handles what OpenOCD sent since the last call, without waiting for more.
+    ///
+    /// Call this regularly, for instance between calls to [`Hart::run`](crate::hart::Hart::run).
+    pub fn poll(&mut self, harts: &mut [&mut dyn DebugHart], bus: &mut dyn Bus) -> io::Result<()> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => match self.listener.accept() {
+                Ok((client, _)) => {
+                    client.set_nonblocking(true)?;
+                    client.set_nodelay(true)?;
+                    self.client.insert(client)
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            },
+        };
+
+        let mut buf = [0; 4096];
+        let mut reply = Vec::new();
+        let mut quit = false;
+        loop {
+            let len = match client.read(&mut buf) {
+                Ok(0) => {
+                    quit = true;
+                    break;
+                }
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.client = None;
+                    return Err(e);
+                }
+            };
+            for &command in &buf[..len] {
+                match command {
+                    b'0'..=b'7' => {
+                        let pins = command - b'0';
+                        let (tck, tms, tdi) = (pins & 4 != 0, pins & 2 != 0, pins & 1 != 0);
+                        self.dtm.set_pins(tck, tms, tdi, harts, bus);
+                    }
+                    b'R' => reply.push(if self.dtm.tdo() { b'1' } else { b'0' }),
+                    // NOTE: `r` to `u` encode TRST and SRST, with resetting the rest of the
+                    //       platform left to the embedder.
+                    b't' | b'u' => self.dtm.reset(),
+                    b'Q' => quit = true,
+                    // NOTE: `B` and `b` blink an LED.
+                    _ => {}
+                }
+            }
+            if quit {
+                break;
+            }
+        }
+
+        if !reply.is_empty() {
+            client.set_nonblocking(false)?;
+            client.write_all(&reply)?;
+            client.set_nonblocking(true)?;
+        }
+        if quit {
+            self.client = None;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+    };
+
+    use super::RemoteBitbang;
+    use crate::{
+        bus::Ram,
+        debug::{DebugHart, DebugModule, Dtm},
+        hart::Hart,
+    };
+
+    type TestHart = Hart<0, u64, false, false, false, (), false, false>;
+
+    #[test]
+    fn test_idcode() {
+        let mut hart = TestHart::new();
+        let mut ram = Ram::new(0, 0x1000);
+        let dtm = Dtm::new(DebugModule::new(), 0xdead_beef);
+        let mut server = RemoteBitbang::bind("127.0.0.1:0", dtm).unwrap();
+        let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+
+        // NOTE: Reset, go to Shift-DR and read the 32 bits of IDCODE.
+        let mut commands = b"t".to_vec();
+        let mut clock = |tms: u8| commands.extend([b'0' + 2 * tms, b'R', b'4' + 2 * tms]);
+        for tms in [1, 1, 1, 1, 1, 0, 1, 0, 0] {
+            clock(tms);
+        }
+        for i in 0..32 {
+            clock((i == 31) as u8);
+        }
+        commands.push(b'Q');
+        client.write_all(&commands).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let harts: &mut [&mut dyn DebugHart] = &mut [&mut hart];
+        let mut reply = Vec::new();
+        while reply.len() < 41 {
+            server.poll(harts, &mut ram).unwrap();
+            let mut buf = [0; 64];
+            client.set_nonblocking(true).unwrap();
+            match client.read(&mut buf) {
+                Ok(len) => reply.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => panic!("{e}"),
+            }
+        }
+
+        let idcode = reply[9..]
+            .iter()
+            .rev()
+            .fold(0u32, |idcode, &bit| idcode << 1 | (bit - b'0') as u32);
+        assert_eq!(idcode, 0xdead_beef);
+    }
+}
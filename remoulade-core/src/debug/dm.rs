@@ -0,0 +1,634 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! The Debug Module, as seen through the Debug Module Interface (DMI).
+
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::{FRd, FRegType, FRs1},
+    hart::{exec::Error, Hart},
+    reg::{IRd, IRs1, RegType},
+    snapshot::{Reader, Snapshot, SnapshotError, Writer},
+};
+
+/// A hart as controlled by the [`DebugModule`].
+///
+/// Registers are numbered like in the Access Register abstract command: CSRs from `0x0000`,
+/// integer registers from `0x1000` and floating-point registers from `0x1020`.
+pub trait DebugHart {
+    /// `XLEN`
+    fn xlen(&self) -> u32;
+    fn halted(&self) -> bool;
+    fn halt(&mut self);
+    fn resume(&mut self);
+    /// The width of register `regno`, or `None` if the hart has no such register.
+    fn register_bits(&self, regno: u32) -> Option<u32>;
+    fn read_register(&self, regno: u32) -> u128;
+    fn write_register(&mut self, regno: u32, value: u128);
+    fn execute_progbuf(&mut self, bus: &mut dyn Bus, progbuf: &[u32]) -> Result<(), Error>;
+    fn invalidate_code(&mut self, addr: u64, len: u64);
+}
+
+const GPR_BASE: u32 = 0x1000;
+const FPR_BASE: u32 = 0x1020;
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > DebugHart for Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    fn xlen(&self) -> u32 {
+        I::BITS
+    }
+
+    fn halted(&self) -> bool {
+        self.debug_mode()
+    }
+
+    fn halt(&mut self) {
+        Hart::halt(self)
+    }
+
+    fn resume(&mut self) {
+        Hart::resume(self)
+    }
+
+    fn register_bits(&self, regno: u32) -> Option<u32> {
+        let gprs = if E { 16 } else { 32 };
+        match regno {
+            // NOTE: `fflags`, `frm` and `fcsr` are 32 bits wide whatever XLEN is.
+            0x001..=0x003 => Csr::checked_from_u32(regno).map(|_| 32),
+            // NOTE: The CSR file is 64 bits wide, even on RV128.
+            0..GPR_BASE => Csr::checked_from_u32(regno).map(|_| I::BITS.min(64)),
+            _ if (GPR_BASE..GPR_BASE + gprs).contains(&regno) => Some(I::BITS),
+            _ if (FPR_BASE..FPR_BASE + 32).contains(&regno) && F::BITS != 0 => Some(F::BITS),
+            _ => None,
+        }
+    }
+
+    fn read_register(&self, regno: u32) -> u128 {
+        match regno {
+            0..GPR_BASE => Csr::checked_from_u32(regno).map_or(0, |csr| self.read_csr(csr).into()),
+            GPR_BASE..FPR_BASE => self.reg().get_rs1(IRs1::wrapping_from_u32(regno)).as_u128(),
+            _ => self
+                .freg()
+                .get_rs1(FRs1::wrapping_from_u32(regno))
+                .to_bits(),
+        }
+    }
+
+    fn write_register(&mut self, regno: u32, value: u128) {
+        match regno {
+            0..GPR_BASE => {
+                if let Some(csr) = Csr::checked_from_u32(regno) {
+                    self.write_csr(csr, value as u64);
+                }
+            }
+            GPR_BASE..FPR_BASE => self
+                .reg_mut()
+                .set_rd(IRd::wrapping_from_u32(regno), I::from_u128(value)),
+            _ => self
+                .freg_mut()
+                .set_rd(FRd::wrapping_from_u32(regno), F::from_bits(value)),
+        }
+    }
+
+    fn execute_progbuf(&mut self, mut bus: &mut dyn Bus, progbuf: &[u32]) -> Result<(), Error> {
+        Hart::execute_progbuf(self, &mut bus, progbuf)
+    }
+
+    fn invalidate_code(&mut self, addr: u64, len: u64) {
+        Hart::invalidate_code(self, addr, len)
+    }
+}
+
+const DATA0: u32 = 0x04;
+const DMCONTROL: u32 = 0x10;
+const DMSTATUS: u32 = 0x11;
+const HARTINFO: u32 = 0x12;
+const ABSTRACTCS: u32 = 0x16;
+const COMMAND: u32 = 0x17;
+const ABSTRACTAUTO: u32 = 0x18;
+const PROGBUF0: u32 = 0x20;
+const HALTSUM0: u32 = 0x40;
+
+const DATA_COUNT: usize = 4;
+const PROGBUF_SIZE: usize = 8;
+
+const HALTREQ: u32 = 1 << 31;
+const RESUMEREQ: u32 = 1 << 30;
+const ACKHAVERESET: u32 = 1 << 28;
+const NDMRESET: u32 = 1 << 1;
+const DMACTIVE: u32 = 1;
+
+const ALLHAVERESET: u32 = 0b11 << 18;
+const ALLRESUMEACK: u32 = 0b11 << 16;
+const ALLNONEXISTENT: u32 = 0b11 << 14;
+const ALLRUNNING: u32 = 0b11 << 10;
+const ALLHALTED: u32 = 0b11 << 8;
+const IMPEBREAK: u32 = 1 << 22;
+const AUTHENTICATED: u32 = 1 << 7;
+const VERSION: u32 = 3;
+
+const ACCESS_REGISTER: u32 = 0;
+const ACCESS_MEMORY: u32 = 2;
+const AAMVIRTUAL: u32 = 1 << 23;
+const POSTINCREMENT: u32 = 1 << 19;
+const POSTEXEC: u32 = 1 << 18;
+const TRANSFER: u32 = 1 << 17;
+const WRITE: u32 = 1 << 16;
+
+/// Abstract command errors, as reported in `abstractcs.cmderr`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CmdErr {
+    NotSupported = 2,
+    Exception = 3,
+    HaltResume = 4,
+    Bus = 5,
+}
+
+/// A Debug Module for up to 2^20 harts, with abstract register and memory access and a program
+/// buffer.
+// NOTE: Abstract commands complete before the DMI access that started them, so the module is
+//       never busy.
+#[derive(Clone, Debug, Default)]
+pub struct DebugModule {
+    active: bool,
+    ndmreset: bool,
+    hartsel: usize,
+    data: [u32; DATA_COUNT],
+    progbuf: [u32; PROGBUF_SIZE],
+    cmderr: u32,
+    command: u32,
+    abstractauto: u32,
+    /// Per hart, whether it resumed since the last resume request.
+    resumeack: Vec<bool>,
+    /// Per hart, whether the debugger acknowledged its reset.
+    reset_acked: Vec<bool>,
+}
+
+impl DebugModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the DMI register at `addr`.
+    pub fn read(&mut self, addr: u32, harts: &mut [&mut dyn DebugHart], bus: &mut dyn Bus) -> u32 {
+        match addr {
+            DMCONTROL => {
+                let hartsel = self.hartsel as u32;
+                let ndmreset = if self.ndmreset { NDMRESET } else { 0 };
+                (hartsel & 0x3ff) << 16 | (hartsel >> 10) << 6 | ndmreset | self.active as u32
+            }
+            DMSTATUS => {
+                let status = IMPEBREAK | AUTHENTICATED | VERSION;
+                let Some(hart) = harts.get(self.hartsel) else {
+                    return status | ALLNONEXISTENT;
+                };
+                let mut status = status | if hart.halted() { ALLHALTED } else { ALLRUNNING };
+                if self.resumeack.get(self.hartsel) == Some(&true) {
+                    status |= ALLRESUMEACK;
+                }
+                if self.reset_acked.get(self.hartsel) != Some(&true) {
+                    status |= ALLHAVERESET;
+                }
+                status
+            }
+            // NOTE: `dscratch0` and `dscratch1`, with the data registers only reachable through
+            //       abstract commands.
+            HARTINFO => 2 << 20,
+            ABSTRACTCS => (PROGBUF_SIZE as u32) << 24 | self.cmderr << 8 | DATA_COUNT as u32,
+            COMMAND => 0,
+            ABSTRACTAUTO => self.abstractauto,
+            HALTSUM0 => {
+                let first = self.hartsel & !0x1f;
+                let window = harts.iter().skip(first).take(32);
+                window
+                    .enumerate()
+                    .filter(|(_, hart)| hart.halted())
+                    .fold(0, |sum, (i, _)| sum | 1 << i)
+            }
+            _ if (DATA0..DATA0 + DATA_COUNT as u32).contains(&addr) => {
+                let index = (addr - DATA0) as usize;
+                let value = self.data[index];
+                self.autoexec(index, harts, bus);
+                value
+            }
+            _ if (PROGBUF0..PROGBUF0 + PROGBUF_SIZE as u32).contains(&addr) => {
+                let index = (addr - PROGBUF0) as usize;
+                let value = self.progbuf[index];
+                self.autoexec(16 + index, harts, bus);
+                value
+            }
+            _ => 0,
+        }
+    }
+
+    /// Writes `value` to the DMI register at `addr`.
+    pub fn write(
+        &mut self,
+        addr: u32,
+        value: u32,
+        harts: &mut [&mut dyn DebugHart],
+        bus: &mut dyn Bus,
+    ) {
+        if addr == DMCONTROL {
+            self.write_dmcontrol(value, harts);
+            return;
+        }
+        if !self.active {
+            return;
+        }
+
+        match addr {
+            ABSTRACTCS => self.cmderr &= !(value >> 8 & 0b111),
+            COMMAND if self.cmderr == 0 => {
+                self.command = value;
+                self.execute(harts, bus);
+            }
+            ABSTRACTAUTO => {
+                let data = (1 << DATA_COUNT) - 1;
+                let progbuf = ((1 << PROGBUF_SIZE) - 1) << 16;
+                self.abstractauto = value & (data | progbuf);
+            }
+            _ if (DATA0..DATA0 + DATA_COUNT as u32).contains(&addr) => {
+                let index = (addr - DATA0) as usize;
+                self.data[index] = value;
+                self.autoexec(index, harts, bus);
+            }
+            _ if (PROGBUF0..PROGBUF0 + PROGBUF_SIZE as u32).contains(&addr) => {
+                let index = (addr - PROGBUF0) as usize;
+                self.progbuf[index] = value;
+                self.autoexec(16 + index, harts, bus);
+            }
+            _ => {}
+        }
+    }
+
+    fn write_dmcontrol(&mut self, value: u32, harts: &mut [&mut dyn DebugHart]) {
+        if value & DMACTIVE == 0 {
+            *self = Self {
+                resumeack: std::mem::take(&mut self.resumeack),
+                reset_acked: std::mem::take(&mut self.reset_acked),
+                ..Self::default()
+            };
+            return;
+        }
+
+        self.active = true;
+        // NOTE: Resetting the rest of the platform is up to the embedder, which can watch
+        //       `dmcontrol.ndmreset`.
+        self.ndmreset = value & NDMRESET != 0;
+        self.hartsel = (value >> 16 & 0x3ff | (value >> 6 & 0x3ff) << 10) as usize;
+        self.resumeack
+            .resize(self.resumeack.len().max(harts.len()), false);
+        self.reset_acked
+            .resize(self.reset_acked.len().max(harts.len()), false);
+
+        let sel = self.hartsel;
+        let Some(hart) = harts.get_mut(sel) else {
+            return;
+        };
+        if value & ACKHAVERESET != 0 {
+            self.reset_acked[sel] = true;
+        }
+        if value & HALTREQ != 0 {
+            hart.halt();
+            self.resumeack[sel] = false;
+        } else if value & RESUMEREQ != 0 {
+            hart.resume();
+            self.resumeack[sel] = true;
+        }
+    }
+
+    /// Repeats the last command if `abstractauto` asks for it on accesses to `bit`'s register.
+    fn autoexec(&mut self, bit: usize, harts: &mut [&mut dyn DebugHart], bus: &mut dyn Bus) {
+        if self.abstractauto >> bit & 1 != 0 && self.cmderr == 0 {
+            self.execute(harts, bus);
+        }
+    }
+
+    fn execute(&mut self, harts: &mut [&mut dyn DebugHart], bus: &mut dyn Bus) {
+        let Some(hart) = harts.get_mut(self.hartsel) else {
+            self.cmderr = CmdErr::HaltResume as u32;
+            return;
+        };
+        let result = match self.command >> 24 {
+            ACCESS_REGISTER => self.access_register(&mut **hart, bus),
+            ACCESS_MEMORY => self.access_memory(harts, bus),
+            _ => Err(CmdErr::NotSupported),
+        };
+        if let Err(e) = result {
+            self.cmderr = e as u32;
+        }
+    }
+
+    fn access_register(
+        &mut self,
+        hart: &mut dyn DebugHart,
+        bus: &mut dyn Bus,
+    ) -> Result<(), CmdErr> {
+        let command = self.command;
+        if !hart.halted() {
+            return Err(CmdErr::HaltResume);
+        }
+
+        let regno = command & 0xffff;
+        if command & TRANSFER != 0 {
+            let bits = 8 << (command >> 20 & 0b111);
+            let width = hart.register_bits(regno).ok_or(CmdErr::Exception)?;
+            if bits < 32 || bits > width {
+                return Err(CmdErr::NotSupported);
+            }
+            match command & WRITE {
+                0 => self.set_arg(0, bits, hart.read_register(regno)),
+                _ => hart.write_register(regno, self.arg(0, bits)),
+            }
+        }
+        if command & POSTINCREMENT != 0 {
+            self.command = command & !0xffff | (regno + 1) & 0xffff;
+        }
+        if command & POSTEXEC != 0 {
+            hart.execute_progbuf(bus, &self.progbuf)
+                .map_err(|_| CmdErr::Exception)?;
+        }
+        Ok(())
+    }
+
+    /// Accesses physical memory directly through the bus.
+    ///
+    /// Not supported on RV128, where the 128-bit address wouldn't fit in `data` next to the data.
+    // NOTE: The address argument is `XLEN` bits wide, as OpenOCD expects.
+    fn access_memory(
+        &mut self,
+        harts: &mut [&mut dyn DebugHart],
+        bus: &mut dyn Bus,
+    ) -> Result<(), CmdErr> {
+        let command = self.command;
+        let xlen = harts[self.hartsel].xlen();
+        let size = 1usize << (command >> 20 & 0b111);
+        if command & AAMVIRTUAL != 0 || xlen > 64 || size * 8 > xlen as usize {
+            return Err(CmdErr::NotSupported);
+        }
+
+        let addr = self.arg(1, xlen) as u64;
+        let bits = (size as u32 * 8).max(32);
+        let mut buf = [0; 8];
+        match command & WRITE {
+            0 => {
+                bus.read(addr, &mut buf[..size]).map_err(|_| CmdErr::Bus)?;
+                self.set_arg(0, bits, u64::from_le_bytes(buf).into());
+            }
+            _ => {
+                buf = (self.arg(0, bits) as u64).to_le_bytes();
+                bus.write(addr, &buf[..size]).map_err(|_| CmdErr::Bus)?;
+                for hart in harts.iter_mut() {
+                    hart.invalidate_code(addr, size as u64);
+                }
+            }
+        }
+        if command & POSTINCREMENT != 0 {
+            self.set_arg(1, xlen, addr.wrapping_add(size as u64).into());
+        }
+        Ok(())
+    }
+
+    /// Argument `index` of an abstract command with `bits`-wide arguments.
+    fn arg(&self, index: usize, bits: u32) -> u128 {
+        let words = (bits / 32) as usize;
+        let data = &self.data[index * words..][..words];
+        data.iter()
+            .rev()
+            .fold(0, |arg, &word| arg << 32 | word as u128)
+    }
+
+    fn set_arg(&mut self, index: usize, bits: u32, value: u128) {
+        let words = (bits / 32) as usize;
+        let data = &mut self.data[index * words..][..words];
+        for (i, word) in data.iter_mut().enumerate() {
+            *word = (value >> (32 * i)) as u32;
+        }
+    }
+}
+
+impl Snapshot for DebugModule {
+    fn save(&self, w: &mut Writer) {
+        w.bool(self.active);
+        w.bool(self.ndmreset);
+        w.u64(self.hartsel as u64);
+        for &word in self.data.iter().chain(&self.progbuf) {
+            w.u32(word);
+        }
+        w.u32(self.cmderr);
+        w.u32(self.command);
+        w.u32(self.abstractauto);
+        w.u64(self.resumeack.len() as u64);
+        for (&resumeack, &reset_acked) in self.resumeack.iter().zip(&self.reset_acked) {
+            w.bool(resumeack);
+            w.bool(reset_acked);
+        }
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        self.active = r.bool()?;
+        self.ndmreset = r.bool()?;
+        self.hartsel = r.u64()? as usize;
+        for word in self.data.iter_mut().chain(&mut self.progbuf) {
+            *word = r.u32()?;
+        }
+        self.cmderr = r.u32()?;
+        self.command = r.u32()?;
+        self.abstractauto = r.u32()?;
+        let harts = r.u64()? as usize;
+        self.resumeack.clear();
+        self.reset_acked.clear();
+        for _ in 0..harts {
+            self.resumeack.push(r.bool()?);
+            self.reset_acked.push(r.bool()?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebugHart, DebugModule};
+    use crate::{
+        bus::{Bus, Ram},
+        hart::{testing::boot, Hart},
+        reg::IRs1,
+    };
+
+    type TestHart = Hart<0, u64, false, false, false, f64, false, false>;
+
+    const DATA0: u32 = 0x04;
+    const DATA1: u32 = 0x05;
+    const DATA2: u32 = 0x06;
+    const DMCONTROL: u32 = 0x10;
+    const DMSTATUS: u32 = 0x11;
+    const ABSTRACTCS: u32 = 0x16;
+    const COMMAND: u32 = 0x17;
+    const PROGBUF0: u32 = 0x20;
+
+    #[test]
+    fn test_run_control() {
+        let mut hart: TestHart = boot();
+        let mut ram = Ram::new(0, 0x1_0000);
+        let mut dm = DebugModule::new();
+        let harts: &mut [&mut dyn DebugHart] = &mut [&mut hart];
+
+        assert_eq!(dm.read(DMCONTROL, harts, &mut ram), 0);
+        dm.write(DMCONTROL, 1, harts, &mut ram);
+        assert_eq!(dm.read(DMSTATUS, harts, &mut ram) & 0xf_ff0f, 0xc_0c03);
+        dm.write(DMCONTROL, 1 << 28 | 1, harts, &mut ram);
+        assert_eq!(dm.read(DMSTATUS, harts, &mut ram) & 0xf_ff0f, 0x0_0c03);
+
+        dm.write(DMCONTROL, 1 << 31 | 1, harts, &mut ram);
+        assert_eq!(dm.read(DMSTATUS, harts, &mut ram) & 0xf_ff0f, 0x0_0303);
+        assert_eq!(dm.read(0x40, harts, &mut ram), 1);
+
+        dm.write(DMCONTROL, 1 << 30 | 1, harts, &mut ram);
+        assert_eq!(dm.read(DMSTATUS, harts, &mut ram) & 0xf_ff0f, 0x3_0c03);
+
+        // NOTE: Hart 1 doesn't exist.
+        dm.write(DMCONTROL, 1 << 16 | 1, harts, &mut ram);
+        assert_eq!(dm.read(DMSTATUS, harts, &mut ram) & 0xf_ff0f, 0x0_c003);
+        assert_eq!(dm.read(DMCONTROL, harts, &mut ram), 1 << 16 | 1);
+
+        dm.write(DMCONTROL, 0, harts, &mut ram);
+        assert_eq!(dm.read(DMCONTROL, harts, &mut ram), 0);
+        assert!(!hart.debug_mode());
+    }
+
+    #[test]
+    fn test_abstract_commands() {
+        let mut hart: TestHart = boot();
+        let mut ram = Ram::new(0, 0x1_0000);
+        let mut dm = DebugModule::new();
+        let harts: &mut [&mut dyn DebugHart] = &mut [&mut hart];
+        dm.write(DMCONTROL, 1, harts, &mut ram);
+
+        // NOTE: Registers can only be accessed while halted.
+        dm.write(COMMAND, 3 << 20 | 1 << 17 | 0x1001, harts, &mut ram);
+        assert_eq!(dm.read(ABSTRACTCS, harts, &mut ram) >> 8 & 0b111, 4);
+        dm.write(COMMAND, 3 << 20 | 1 << 17 | 0x1001, harts, &mut ram);
+        assert_eq!(dm.read(ABSTRACTCS, harts, &mut ram) >> 8 & 0b111, 4);
+        dm.write(ABSTRACTCS, 0b111 << 8, harts, &mut ram);
+        assert_eq!(dm.read(ABSTRACTCS, harts, &mut ram), 8 << 24 | 4);
+
+        dm.write(DMCONTROL, 1 << 31 | 1, harts, &mut ram);
+
+        // write x10, then read it back with postincrement, into x11
+        dm.write(DATA0, 0x89ab_cdef, harts, &mut ram);
+        dm.write(DATA1, 0x0123_4567, harts, &mut ram);
+        dm.write(
+            COMMAND,
+            3 << 20 | 1 << 17 | 1 << 16 | 0x100a,
+            harts,
+            &mut ram,
+        );
+        dm.write(DATA0, 0, harts, &mut ram);
+        dm.write(DATA1, 0, harts, &mut ram);
+        dm.write(
+            COMMAND,
+            3 << 20 | 1 << 19 | 1 << 17 | 0x100a,
+            harts,
+            &mut ram,
+        );
+        assert_eq!(dm.read(DATA0, harts, &mut ram), 0x89ab_cdef);
+        assert_eq!(dm.read(DATA1, harts, &mut ram), 0x0123_4567);
+
+        // NOTE: `abstractauto` repeats the command, now for x11.
+        dm.write(0x18, 1, harts, &mut ram);
+        assert_eq!(dm.read(DATA0, harts, &mut ram), 0x89ab_cdef);
+        dm.write(0x18, 0, harts, &mut ram);
+        assert_eq!(dm.read(DATA0, harts, &mut ram), 0);
+
+        // NOTE: 128-bit accesses and missing registers are rejected.
+        dm.write(COMMAND, 4 << 20 | 1 << 17 | 0x100a, harts, &mut ram);
+        assert_eq!(dm.read(ABSTRACTCS, harts, &mut ram) >> 8 & 0b111, 2);
+        dm.write(ABSTRACTCS, 0b111 << 8, harts, &mut ram);
+        dm.write(COMMAND, 3 << 20 | 1 << 17 | 0xc000, harts, &mut ram);
+        assert_eq!(dm.read(ABSTRACTCS, harts, &mut ram) >> 8 & 0b111, 3);
+        dm.write(ABSTRACTCS, 0b111 << 8, harts, &mut ram);
+
+        // dpc, then f1
+        dm.write(COMMAND, 3 << 20 | 1 << 17 | 0x7b1, harts, &mut ram);
+        assert_eq!(dm.read(DATA0, harts, &mut ram), 0x1000);
+        dm.write(DATA0, 0, harts, &mut ram);
+        dm.write(DATA1, 0x3ff0_0000, harts, &mut ram);
+        dm.write(
+            COMMAND,
+            3 << 20 | 1 << 17 | 1 << 16 | 0x1021,
+            harts,
+            &mut ram,
+        );
+        assert_eq!(dm.read(ABSTRACTCS, harts, &mut ram) >> 8 & 0b111, 0);
+
+        // program buffer: sd x10, 0(x11) with x11 = 0x2000
+        dm.write(DATA0, 0x2000, harts, &mut ram);
+        dm.write(DATA1, 0, harts, &mut ram);
+        dm.write(
+            COMMAND,
+            3 << 20 | 1 << 17 | 1 << 16 | 0x100b,
+            harts,
+            &mut ram,
+        );
+        dm.write(PROGBUF0, 0x00a5b023, harts, &mut ram);
+        dm.write(PROGBUF0 + 1, 0x00100073, harts, &mut ram);
+        dm.write(COMMAND, 1 << 18, harts, &mut ram);
+        assert_eq!(dm.read(ABSTRACTCS, harts, &mut ram) >> 8 & 0b111, 0);
+        assert_eq!(
+            ram.as_slice()[0x2000..0x2008],
+            0x0123_4567_89ab_cdefu64.to_le_bytes()
+        );
+
+        // memory: read 32 bits at 0x2004 with postincrement, then write a byte at 0x2008
+        dm.write(DATA2, 0x2004, harts, &mut ram);
+        dm.write(DATA2 + 1, 0, harts, &mut ram);
+        dm.write(COMMAND, 2 << 24 | 2 << 20 | 1 << 19, harts, &mut ram);
+        assert_eq!(dm.read(DATA0, harts, &mut ram), 0x0123_4567);
+        assert_eq!(dm.read(DATA2, harts, &mut ram), 0x2008);
+        dm.write(DATA0, 0x5a, harts, &mut ram);
+        dm.write(COMMAND, 2 << 24 | 1 << 16, harts, &mut ram);
+        assert_eq!(ram.as_slice()[0x2008], 0x5a);
+
+        // NOTE: Bus errors and virtual accesses.
+        dm.write(DATA2, 0x2_0000, harts, &mut ram);
+        dm.write(COMMAND, 2 << 24 | 2 << 20, harts, &mut ram);
+        assert_eq!(dm.read(ABSTRACTCS, harts, &mut ram) >> 8 & 0b111, 5);
+        dm.write(ABSTRACTCS, 0b111 << 8, harts, &mut ram);
+        dm.write(COMMAND, 2 << 24 | 1 << 23, harts, &mut ram);
+        assert_eq!(dm.read(ABSTRACTCS, harts, &mut ram) >> 8 & 0b111, 2);
+
+        // NOTE: RV128 memory accesses are rejected rather than reading past `data`.
+        let mut rv128 = Hart::<0, u128, false, false, false, (), false, false>::new();
+        let rv128: &mut [&mut dyn DebugHart] = &mut [&mut rv128];
+        dm.write(DMCONTROL, 1 << 31 | 1, rv128, &mut ram);
+        dm.write(ABSTRACTCS, 0b111 << 8, rv128, &mut ram);
+        dm.write(COMMAND, 2 << 24 | 2 << 20, rv128, &mut ram);
+        assert_eq!(dm.read(ABSTRACTCS, rv128, &mut ram) >> 8 & 0b111, 2);
+
+        assert_eq!(hart.reg().get_rs1(IRs1::X10), 0x0123_4567_89ab_cdef);
+        assert_eq!(hart.reg().get_rs1(IRs1::X11), 0x2000);
+        assert_eq!(hart.freg().get_rs1(crate::freg::FRs1::F1), 1.0);
+        let mut word = [0; 4];
+        ram.read(0x2004, &mut word).unwrap();
+        assert_eq!(u32::from_le_bytes(word), 0x0123_4567);
+    }
+}
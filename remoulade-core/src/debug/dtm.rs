@@ -0,0 +1,296 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! A JTAG Debug Transport Module.
+
+use crate::{
+    bus::Bus,
+    debug::{DebugHart, DebugModule},
+};
+
+/// TAP controller states.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    TestLogicReset,
+    RunTestIdle,
+    SelectDrScan,
+    CaptureDr,
+    ShiftDr,
+    Exit1Dr,
+    PauseDr,
+    Exit2Dr,
+    UpdateDr,
+    SelectIrScan,
+    CaptureIr,
+    ShiftIr,
+    Exit1Ir,
+    PauseIr,
+    Exit2Ir,
+    UpdateIr,
+}
+
+impl State {
+    fn next(self, tms: bool) -> Self {
+        use State::*;
+        match (self, tms) {
+            (TestLogicReset, false) => RunTestIdle,
+            (TestLogicReset, true) => TestLogicReset,
+            (RunTestIdle, false) => RunTestIdle,
+            (RunTestIdle, true) => SelectDrScan,
+            (SelectDrScan, false) => CaptureDr,
+            (SelectDrScan, true) => SelectIrScan,
+            (CaptureDr, false) => ShiftDr,
+            (CaptureDr, true) => Exit1Dr,
+            (ShiftDr, false) => ShiftDr,
+            (ShiftDr, true) => Exit1Dr,
+            (Exit1Dr, false) => PauseDr,
+            (Exit1Dr, true) => UpdateDr,
+            (PauseDr, false) => PauseDr,
+            (PauseDr, true) => Exit2Dr,
+            (Exit2Dr, false) => ShiftDr,
+            (Exit2Dr, true) => UpdateDr,
+            (UpdateDr, false) => RunTestIdle,
+            (UpdateDr, true) => SelectDrScan,
+            (SelectIrScan, false) => CaptureIr,
+            (SelectIrScan, true) => TestLogicReset,
+            (CaptureIr, false) => ShiftIr,
+            (CaptureIr, true) => Exit1Ir,
+            (ShiftIr, false) => ShiftIr,
+            (ShiftIr, true) => Exit1Ir,
+            (Exit1Ir, false) => PauseIr,
+            (Exit1Ir, true) => UpdateIr,
+            (PauseIr, false) => PauseIr,
+            (PauseIr, true) => Exit2Ir,
+            (Exit2Ir, false) => ShiftIr,
+            (Exit2Ir, true) => UpdateIr,
+            (UpdateIr, false) => RunTestIdle,
+            (UpdateIr, true) => SelectDrScan,
+        }
+    }
+}
+
+const IR_BITS: u32 = 5;
+const IDCODE: u8 = 0x01;
+const DTMCS: u8 = 0x10;
+const DMI: u8 = 0x11;
+
+const ABITS: u32 = 7;
+
+/// A JTAG TAP with the `dtmcs` and `dmi` registers of the debug specification, in front of a
+/// [`DebugModule`].
+///
+/// Inputs are sampled on the rising edge of TCK and TDO changes on the falling edge.
+#[derive(Clone, Debug)]
+pub struct Dtm {
+    dm: DebugModule,
+    idcode: u32,
+    state: State,
+    tck: bool,
+    tdo: bool,
+    ir: u8,
+    instruction: u8,
+    dr: u128,
+    dr_bits: u32,
+    /// The `dmi` value captured after the last operation: address, data and a status of 0.
+    dmi: u128,
+}
+
+impl Dtm {
+    pub fn new(dm: DebugModule, idcode: u32) -> Self {
+        Self {
+            dm,
+            idcode,
+            state: State::TestLogicReset,
+            tck: false,
+            tdo: false,
+            ir: IDCODE,
+            instruction: IDCODE,
+            dr: 0,
+            dr_bits: 1,
+            dmi: 0,
+        }
+    }
+
+    pub fn dm(&self) -> &DebugModule {
+        &self.dm
+    }
+
+    pub fn dm_mut(&mut self) -> &mut DebugModule {
+        &mut self.dm
+    }
+
+    pub fn tdo(&self) -> bool {
+        self.tdo
+    }
+
+    /// Asynchronously resets the TAP, as TRST does.
+    pub fn reset(&mut self) {
+        self.state = State::TestLogicReset;
+        self.instruction = IDCODE;
+    }
+
+    /// Drives the JTAG pins, performing DMI operations on `harts` and `bus` as they complete.
+    pub fn set_pins(
+        &mut self,
+        tck: bool,
+        tms: bool,
+        tdi: bool,
+        harts: &mut [&mut dyn DebugHart],
+        bus: &mut dyn Bus,
+    ) {
+        let rising = !self.tck && tck;
+        let falling = self.tck && !tck;
+        self.tck = tck;
+
+        if rising {
+            match self.state {
+                State::CaptureDr => self.capture_dr(),
+                State::ShiftDr => {
+                    self.dr = self.dr >> 1 | (tdi as u128) << (self.dr_bits - 1);
+                }
+                // NOTE: The two least significant bits must capture as 0b01.
+                State::CaptureIr => self.ir = 0b00001,
+                State::ShiftIr => self.ir = self.ir >> 1 | (tdi as u8) << (IR_BITS - 1),
+                _ => {}
+            }
+            self.state = self.state.next(tms);
+        } else if falling {
+            match self.state {
+                State::TestLogicReset => self.instruction = IDCODE,
+                State::ShiftDr => self.tdo = self.dr & 1 != 0,
+                State::ShiftIr => self.tdo = self.ir & 1 != 0,
+                State::UpdateDr => self.update_dr(harts, bus),
+                State::UpdateIr => self.instruction = self.ir,
+                _ => {}
+            }
+        }
+    }
+
+    fn capture_dr(&mut self) {
+        (self.dr, self.dr_bits) = match self.instruction {
+            IDCODE => (self.idcode.into(), 32),
+            // NOTE: Version 1 (0.13 and 1.0), with one idle cycle suggested between accesses.
+            DTMCS => (1 << 12 | (ABITS as u128) << 4 | 1, 32),
+            DMI => (self.dmi, ABITS + 34),
+            _ => (0, 1),
+        };
+    }
+
+    fn update_dr(&mut self, harts: &mut [&mut dyn DebugHart], bus: &mut dyn Bus) {
+        // NOTE: DMI operations never fail or overlap, which leaves `dtmcs.dmireset` and
+        //       `dtmcs.dmihardreset` nothing to clear.
+        if self.instruction != DMI {
+            return;
+        }
+
+        let addr = (self.dr >> 34) as u32 & ((1 << ABITS) - 1);
+        let data = (self.dr >> 2) as u32;
+        let data = match self.dr & 0b11 {
+            1 => self.dm.read(addr, harts, bus),
+            2 => {
+                self.dm.write(addr, data, harts, bus);
+                data
+            }
+            _ => return,
+        };
+        self.dmi = (addr as u128) << 34 | (data as u128) << 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dtm;
+    use crate::{
+        bus::Ram,
+        debug::{DebugHart, DebugModule},
+        hart::Hart,
+    };
+
+    type TestHart = Hart<0, u64, false, false, false, (), false, false>;
+
+    /// Clocks the TAP once per element of `tms`, shifting `tdi` in and returning what was
+    /// shifted out.
+    fn clock(
+        dtm: &mut Dtm,
+        tms: &[bool],
+        tdi: u128,
+        harts: &mut [&mut dyn DebugHart],
+        ram: &mut Ram,
+    ) -> u128 {
+        let mut tdo = 0;
+        for (i, &tms) in tms.iter().enumerate() {
+            let tdi = tdi >> i & 1 != 0;
+            dtm.set_pins(false, tms, tdi, harts, ram);
+            tdo |= (dtm.tdo() as u128) << i;
+            dtm.set_pins(true, tms, tdi, harts, ram);
+        }
+        tdo
+    }
+
+    /// Goes from Run-Test/Idle through a scan of `bits` bits and back.
+    fn scan(
+        dtm: &mut Dtm,
+        ir: bool,
+        bits: usize,
+        tdi: u128,
+        harts: &mut [&mut dyn DebugHart],
+        ram: &mut Ram,
+    ) -> u128 {
+        let select: &[bool] = if ir {
+            &[true, true, false, false]
+        } else {
+            &[true, false, false]
+        };
+        clock(dtm, select, 0, harts, ram);
+        let mut tms = vec![false; bits];
+        tms[bits - 1] = true;
+        let tdo = clock(dtm, &tms, tdi, harts, ram);
+        clock(dtm, &[true, false], 0, harts, ram);
+        tdo
+    }
+
+    #[test]
+    fn test_jtag() {
+        let mut hart = TestHart::new();
+        let mut ram = Ram::new(0, 0x1000);
+        let mut dtm = Dtm::new(DebugModule::new(), 0x1000_0001);
+        let harts: &mut [&mut dyn DebugHart] = &mut [&mut hart];
+
+        clock(&mut dtm, &[true; 5], 0, harts, &mut ram);
+        clock(&mut dtm, &[false], 0, harts, &mut ram);
+        assert_eq!(scan(&mut dtm, false, 32, 0, harts, &mut ram), 0x1000_0001);
+
+        assert_eq!(scan(&mut dtm, true, 5, 0x10, harts, &mut ram), 0b00001);
+        assert_eq!(scan(&mut dtm, false, 32, 0, harts, &mut ram), 0x1071);
+
+        // NOTE: Reads return their data on the next scan.
+        assert_eq!(scan(&mut dtm, true, 5, 0x11, harts, &mut ram), 0b00001);
+        let write = |addr: u128, data: u128| addr << 34 | data << 2 | 2;
+        let read = |addr: u128| addr << 34 | 1;
+        scan(
+            &mut dtm,
+            false,
+            41,
+            write(0x10, 1 << 31 | 1),
+            harts,
+            &mut ram,
+        );
+        scan(&mut dtm, false, 41, read(0x11), harts, &mut ram);
+        let dmstatus = scan(&mut dtm, false, 41, 0, harts, &mut ram);
+        assert_eq!(dmstatus >> 34, 0x11);
+        assert_eq!(dmstatus >> 2 & 0x3ff, 0x383);
+        assert_eq!(dmstatus & 0b11, 0);
+        assert!(hart.debug_mode());
+
+        let harts: &mut [&mut dyn DebugHart] = &mut [&mut hart];
+        dtm.reset();
+        clock(&mut dtm, &[false], 0, harts, &mut ram);
+        assert_eq!(scan(&mut dtm, false, 32, 0, harts, &mut ram), 0x1000_0001);
+    }
+}
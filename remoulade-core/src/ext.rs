@@ -0,0 +1,106 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Selection of optional extensions that don't warrant a parameter of their own on
+//! [`Hart`](crate::hart::Hart).
+
+/// Enables optional extensions.
+///
+/// Every extension is disabled unless overridden, so `()` selects none of them.
+///
+/// ```
+/// use remoulade_core::ext::Extensions;
+///
+/// #[derive(Clone, Copy)]
+/// struct Rva22;
+///
+/// impl Extensions for Rva22 {
+///     const ZBA: bool = true;
+///     const ZBB: bool = true;
+///     const ZBS: bool = true;
+/// }
+/// ```
+pub trait Extensions: 'static + Copy {
+    /// Address generation instructions.
+    const ZBA: bool = false;
+    /// Basic bit-manipulation.
+    const ZBB: bool = false;
+    /// Carry-less multiplication.
+    const ZBC: bool = false;
+    /// Single-bit instructions.
+    const ZBS: bool = false;
+
+    /// Bit-manipulation for cryptography.
+    const ZBKB: bool = false;
+    /// Carry-less multiplication for cryptography.
+    const ZBKC: bool = false;
+    /// Crossbar permutations.
+    const ZBKX: bool = false;
+    /// NIST suite: AES decryption.
+    const ZKND: bool = false;
+    /// NIST suite: AES encryption.
+    const ZKNE: bool = false;
+    /// NIST suite: SHA-256 and SHA-512 hash functions.
+    const ZKNH: bool = false;
+    /// ShangMi suite: SM4 block cipher.
+    const ZKSED: bool = false;
+    /// ShangMi suite: SM3 hash function.
+    const ZKSH: bool = false;
+    /// Entropy source through the `seed` CSR.
+    const ZKR: bool = false;
+
+    /// Integer conditional operations.
+    const ZICOND: bool = false;
+    /// Cache-block management: `cbo.clean`, `cbo.flush` and `cbo.inval`.
+    const ZICBOM: bool = false;
+    /// Cache-block zero: `cbo.zero`.
+    const ZICBOZ: bool = false;
+    /// Size in bytes of the blocks `cbo.zero` writes.
+    ///
+    /// Must be a power of two.
+    const CBOZ_BLOCK_SIZE: u64 = 64;
+    /// The `pause` hint, which is passed on to the host as a spin-loop hint.
+    const ZIHINTPAUSE: bool = false;
+
+    /// Half-precision floating-point.
+    ///
+    /// Like the other floating-point extensions, this requires `F` to be at least `f32`.
+    const ZFH: bool = false;
+    /// Half-precision loads, stores, moves and conversions.
+    const ZFHMIN: bool = false;
+    /// BF16 loads, stores, moves and conversions to and from single precision.
+    const ZFBFMIN: bool = false;
+
+    /// Hypervisor extension.
+    const H: bool = false;
+
+    /// Number of physical memory protection entries.
+    ///
+    /// Must be 0, 16 or 64.
+    const PMP_ENTRIES: usize = 0;
+    /// PMP enhancements for memory access and execution prevention in machine mode, configured
+    /// through `mseccfg`.
+    const SMEPMP: bool = false;
+
+    /// Number of Sdtrig triggers, selected through `tselect`.
+    const TRIGGERS: usize = 0;
+
+    /// Vector extension.
+    const V: bool = false;
+    /// Width of a vector register in bits when [`V`](Self::V) is enabled.
+    ///
+    /// Must be a power of two no less than `ELEN` and no more than 65536.
+    const VLEN: u32 = 128;
+    /// Widest vector element in bits.
+    ///
+    /// Must be 32 or 64.
+    const ELEN: u32 = 64;
+}
+
+impl Extensions for () {}
@@ -10,22 +10,104 @@
 //! Module containing register file and register types, as well as functions and utilities to use
 //! them effectively.
 
-pub trait FRegType {}
-impl FRegType for () {}
-impl FRegType for f32 {}
-impl FRegType for f64 {}
+use crate::{
+    snapshot::{Reader, Snapshot, SnapshotError, Writer},
+    softfloat::{self, Format, Rounding},
+};
+
+pub trait FRegType: 'static + Copy + Default {
+    /// `FLEN`, or 0 when there are no floating-point registers.
+    const BITS: u32;
+
+    fn to_bits(self) -> u128;
+    fn from_bits(bits: u128) -> Self;
+}
+
+impl FRegType for () {
+    const BITS: u32 = 0;
+
+    fn to_bits(self) -> u128 {
+        0
+    }
+    fn from_bits(_: u128) -> Self {}
+}
+
+impl FRegType for f32 {
+    const BITS: u32 = 32;
+
+    fn to_bits(self) -> u128 {
+        f32::to_bits(self) as u128
+    }
+    fn from_bits(bits: u128) -> Self {
+        f32::from_bits(bits as u32)
+    }
+}
+
+impl FRegType for f64 {
+    const BITS: u32 = 64;
+
+    fn to_bits(self) -> u128 {
+        f64::to_bits(self) as u128
+    }
+    fn from_bits(bits: u128) -> Self {
+        f64::from_bits(bits as u64)
+    }
+}
+
+/// An IEEE 754 binary128 value.
+///
+/// Rust has no stable `f128`, so this only holds the bit pattern and all arithmetic happens in
+/// software.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct F128(u128);
+
+impl F128 {
+    pub const fn from_bits(bits: u128) -> Self {
+        Self(bits)
+    }
+
+    pub const fn to_bits(self) -> u128 {
+        self.0
+    }
+}
+
+impl From<f64> for F128 {
+    fn from(value: f64) -> Self {
+        let (double, quad) = (Format::DOUBLE, Format::QUAD);
+        let bits = value.to_bits() as u128;
+        // NOTE: Widening is exact, so the rounding mode and flags don't matter.
+        Self(softfloat::convert(
+            double,
+            quad,
+            bits,
+            Rounding::NearestEven,
+            &mut 0,
+        ))
+    }
+}
+
+impl FRegType for F128 {
+    const BITS: u32 = 128;
+
+    fn to_bits(self) -> u128 {
+        self.0
+    }
+    fn from_bits(bits: u128) -> Self {
+        Self(bits)
+    }
+}
 
 pub struct FRegFile<T: FRegType> {
     reg: [T; 33],
 }
 
-impl<T: Default + FRegType + Copy> Default for FRegFile<T> {
+impl<T: FRegType> Default for FRegFile<T> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<T: Default + FRegType + Copy> FRegFile<T> {
+impl<T: FRegType> FRegFile<T> {
     pub fn new() -> Self {
         Self {
             reg: [T::default(); 33],
@@ -105,6 +187,42 @@ pub enum FRs2 {
     F31,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FRs3 {
+    F0 = 0,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    F25,
+    F26,
+    F27,
+    F28,
+    F29,
+    F30,
+    F31,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum FRd {
     F0 = 0,
@@ -184,13 +302,13 @@ impl FRs1 {
             28 => FRs1::F28,
             29 => FRs1::F29,
             30 => FRs1::F30,
-            31 => FRs1::F31,
-            _ => unsafe { std::hint::unreachable_unchecked() },
+            // NOTE: Only 31 is left after masking to 5 bits.
+            _ => FRs1::F31,
         }
     }
 
     pub fn decode_raw32(raw32: u32) -> Self {
-        Self::checked_from_u32(raw32 >> 15 & 0x1f).expect("Extracted value was larger than 5 bits")
+        Self::wrapping_from_u32(raw32 >> 15)
     }
 }
 
@@ -236,13 +354,65 @@ impl FRs2 {
             28 => FRs2::F28,
             29 => FRs2::F29,
             30 => FRs2::F30,
-            31 => FRs2::F31,
-            _ => unsafe { std::hint::unreachable_unchecked() },
+            // NOTE: Only 31 is left after masking to 5 bits.
+            _ => FRs2::F31,
+        }
+    }
+
+    pub fn decode_raw32(raw32: u32) -> Self {
+        Self::wrapping_from_u32(raw32 >> 20)
+    }
+}
+
+impl FRs3 {
+    pub fn checked_from_u32(b: u32) -> Option<Self> {
+        let res = match b {
+            0..=31 => Self::wrapping_from_u32(b),
+            _ => None?,
+        };
+        Some(res)
+    }
+
+    pub fn wrapping_from_u32(b: u32) -> Self {
+        match b & 0x1f {
+            0 => FRs3::F0,
+            1 => FRs3::F1,
+            2 => FRs3::F2,
+            3 => FRs3::F3,
+            4 => FRs3::F4,
+            5 => FRs3::F5,
+            6 => FRs3::F6,
+            7 => FRs3::F7,
+            8 => FRs3::F8,
+            9 => FRs3::F9,
+            10 => FRs3::F10,
+            11 => FRs3::F11,
+            12 => FRs3::F12,
+            13 => FRs3::F13,
+            14 => FRs3::F14,
+            15 => FRs3::F15,
+            16 => FRs3::F16,
+            17 => FRs3::F17,
+            18 => FRs3::F18,
+            19 => FRs3::F19,
+            20 => FRs3::F20,
+            21 => FRs3::F21,
+            22 => FRs3::F22,
+            23 => FRs3::F23,
+            24 => FRs3::F24,
+            25 => FRs3::F25,
+            26 => FRs3::F26,
+            27 => FRs3::F27,
+            28 => FRs3::F28,
+            29 => FRs3::F29,
+            30 => FRs3::F30,
+            // NOTE: Only 31 is left after masking to 5 bits.
+            _ => FRs3::F31,
         }
     }
 
     pub fn decode_raw32(raw32: u32) -> Self {
-        Self::checked_from_u32(raw32 >> 20 & 0x1f).expect("Extracted value was larger than 5 bits")
+        Self::wrapping_from_u32(raw32 >> 27)
     }
 }
 
@@ -288,17 +458,17 @@ impl FRd {
             28 => FRd::F28,
             29 => FRd::F29,
             30 => FRd::F30,
-            31 => FRd::F31,
-            _ => unsafe { std::hint::unreachable_unchecked() },
+            // NOTE: Only 31 is left after masking to 5 bits.
+            _ => FRd::F31,
         }
     }
 
     pub fn decode_raw32(raw32: u32) -> Self {
-        Self::checked_from_u32(raw32 >> 7 & 0x1f).expect("Extracted value was larger than 5 bits")
+        Self::wrapping_from_u32(raw32 >> 7)
     }
 }
 
-impl<T: FRegType + Copy> FRegFile<T> {
+impl<T: FRegType> FRegFile<T> {
     pub fn get_rs1(&self, rs1: FRs1) -> T {
         unsafe { *self.reg.get_unchecked(rs1 as usize) }
     }
@@ -307,9 +477,28 @@ impl<T: FRegType + Copy> FRegFile<T> {
         unsafe { *self.reg.get_unchecked(rs2 as usize) }
     }
 
+    pub fn get_rs3(&self, rs3: FRs3) -> T {
+        unsafe { *self.reg.get_unchecked(rs3 as usize) }
+    }
+
     pub fn set_rd(&mut self, rd: FRd, value: T) {
         unsafe {
             *self.reg.get_unchecked_mut(rd as usize) = value;
         }
     }
 }
+
+impl<T: FRegType> Snapshot for FRegFile<T> {
+    fn save(&self, w: &mut Writer) {
+        for value in &self.reg[..32] {
+            w.u128(value.to_bits());
+        }
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        for value in &mut self.reg[..32] {
+            *value = T::from_bits(r.u128()?);
+        }
+        Ok(())
+    }
+}
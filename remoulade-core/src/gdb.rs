@@ -0,0 +1,1291 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! A server for GDB's remote serial protocol.
+//!
+//! Each hart is presented to GDB as a thread, numbered from 1.
+//! Software breakpoints are `ebreak`s written into memory, while hardware breakpoints and
+//! watchpoints use triggers (Sdtrig) that halt the hart in debug mode.
+//!
+//! With [`GdbServer::set_history`], GDB can also step and continue backwards
+//! (`reverse-stepi`, `reverse-continue`).
+//!
+//! ```text
+//! (gdb) target remote localhost:<port>
+//! ```
+
+mod history;
+mod packet;
+mod target;
+
+use std::{
+    collections::BTreeMap,
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    ops::Range,
+};
+
+use crate::{
+    bus::Bus,
+    debug::DebugHart,
+    ext::Extensions,
+    freg::FRegType,
+    hart::{exec::Error, Hart},
+    reg::RegType,
+    replay::Journal,
+    snapshot::Snapshot,
+};
+use history::{Access, History};
+use packet::{Decoder, Event};
+use target::Register;
+
+/// A hart as controlled by the [`GdbServer`].
+pub trait GdbHart: DebugHart + Snapshot {
+    fn pc(&self) -> u64;
+    fn set_pc(&mut self, pc: u64);
+    fn step(&mut self, bus: &mut dyn Bus) -> Result<(), Error>;
+    fn run(&mut self, bus: &mut dyn Bus, budget: u64) -> Result<(), Error>;
+    /// The index the hart's events are logged under in a [`Journal`].
+    fn id(&self) -> usize;
+    fn set_journal(&mut self, journal: Option<Journal>);
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > GdbHart for Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    fn pc(&self) -> u64 {
+        Hart::pc(self).as_u128() as u64
+    }
+
+    fn set_pc(&mut self, pc: u64) {
+        Hart::set_pc(self, I::from_u128(pc.into()))
+    }
+
+    fn step(&mut self, mut bus: &mut dyn Bus) -> Result<(), Error> {
+        Hart::step(self, &mut bus)
+    }
+
+    fn run(&mut self, mut bus: &mut dyn Bus, budget: u64) -> Result<(), Error> {
+        Hart::run(self, &mut bus, budget)
+    }
+
+    fn id(&self) -> usize {
+        ID
+    }
+
+    fn set_journal(&mut self, journal: Option<Journal>) {
+        Hart::set_journal(self, journal)
+    }
+}
+
+const TSELECT: u32 = 0x7a0;
+const TDATA1: u32 = 0x7a1;
+const TDATA2: u32 = 0x7a2;
+
+// Fields of `mcontrol6`
+const MCONTROL6: u64 = 6;
+const VS: u64 = 1 << 24;
+const VU: u64 = 1 << 23;
+const HIT0: u64 = 1 << 22;
+const ENTER_DEBUG: u64 = 1 << 12;
+const NAPOT: u64 = 1 << 7;
+const MMODE: u64 = 1 << 6;
+const SMODE: u64 = 1 << 4;
+const UMODE: u64 = 1 << 3;
+const EXECUTE: u64 = 1 << 2;
+const STORE: u64 = 1 << 1;
+const LOAD: u64 = 1 << 0;
+
+const EBREAK: u32 = 0x00100073;
+const C_EBREAK: u16 = 0x9002;
+
+/// The largest memory access GDB is asked to make at once, which keeps packets below the
+/// advertised `PacketSize`.
+const MAX_ACCESS: usize = 0x1000;
+
+/// A hardware breakpoint (`Z1`) or watchpoint (`Z2` to `Z4`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Watchpoint {
+    kind: u8,
+    addr: u64,
+    len: u64,
+}
+
+impl Watchpoint {
+    /// `tdata1` and `tdata2` of a trigger that halts in debug mode on this watchpoint.
+    fn trigger(self, xlen: u32) -> Option<(u64, u64)> {
+        let access = match self.kind {
+            1 => EXECUTE,
+            2 => STORE,
+            3 => LOAD,
+            _ => LOAD | STORE,
+        };
+        // NOTE: Hardware breakpoints give the length of the instruction, which an address
+        //       match on its first byte covers.
+        let (napot, tdata2) = match self.len {
+            _ if self.kind == 1 || self.len <= 1 => (0, self.addr),
+            len if len.is_power_of_two() && self.addr.is_multiple_of(len) => {
+                (NAPOT, self.addr | (len / 2 - 1))
+            }
+            _ => None?,
+        };
+
+        let xlen = xlen.min(64);
+        let kind = MCONTROL6 << (xlen - 4);
+        let dmode = 1 << (xlen - 5);
+        let modes = VS | VU | MMODE | SMODE | UMODE;
+        Some((kind | dmode | ENTER_DEBUG | napot | modes | access, tdata2))
+    }
+
+    fn stop_reason(self) -> String {
+        match self.kind {
+            1 => "hwbreak:;".into(),
+            2 => format!("watch:{:x};", self.addr),
+            3 => format!("rwatch:{:x};", self.addr),
+            _ => format!("awatch:{:x};", self.addr),
+        }
+    }
+}
+
+/// Programs trigger `index` of `hart`, returning whether it took the configuration.
+fn program_trigger(hart: &mut dyn GdbHart, index: usize, tdata1: u64, tdata2: u64) -> bool {
+    // NOTE: Triggers that enter debug mode can only be changed from debug mode.
+    let running = !hart.halted();
+    if running {
+        hart.halt();
+    }
+
+    hart.write_register(TSELECT, index as u128);
+    let selected = hart.read_register(TSELECT) == index as u128;
+    if selected {
+        hart.write_register(TDATA1, 0);
+        hart.write_register(TDATA2, tdata2.into());
+        hart.write_register(TDATA1, tdata1.into());
+    }
+    let xlen = hart.xlen().min(64);
+    let fields = 0xf << (xlen - 4) | 0xf << 12 | EXECUTE | STORE | LOAD;
+    let programmed = selected && hart.read_register(TDATA1) as u64 & fields == tdata1 & fields;
+
+    if running {
+        hart.resume();
+    }
+    programmed
+}
+
+fn invalidate_code(harts: &mut [&mut dyn GdbHart], addr: u64, len: u64) {
+    for hart in harts.iter_mut() {
+        hart.invalidate_code(addr, len);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    let digits = hex.as_bytes();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn parse(number: &str) -> Option<u64> {
+    u64::from_str_radix(number, 16).ok()
+}
+
+/// Parses `addr,len`.
+fn parse_range(range: &str) -> Option<(u64, u64)> {
+    let (addr, len) = range.split_once(',')?;
+    Some((parse(addr)?, parse(len)?))
+}
+
+/// Parses a thread ID into a hart index, with `None` for "all" (`-1`) or "any" (`0`).
+fn parse_thread(thread: &str) -> Option<usize> {
+    match thread {
+        "-1" | "0" => None,
+        _ => parse(thread)?.checked_sub(1).map(|index| index as usize),
+    }
+}
+
+/// Serves one GDB connection at a time.
+///
+/// Harts don't run until GDB continues them, and run freely once it detaches.
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    decoder: Decoder,
+    /// The last packet sent, in case GDB asks for it again
+    last: Vec<u8>,
+    running: bool,
+    /// The stop reply for a step that raised an exception, sent once the embedder took the trap
+    pending_stop: Option<String>,
+    /// The hart selected with `Hg`
+    thread: usize,
+    /// The original contents of memory under each software breakpoint
+    breakpoints: BTreeMap<u64, Vec<u8>>,
+    /// Hardware breakpoints and watchpoints, by trigger index
+    watchpoints: Vec<Option<Watchpoint>>,
+    /// What the harts executed, if reverse execution is enabled
+    history: Option<History>,
+}
+
+impl GdbServer {
+    /// Listens on `addr` without accepting anything yet.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+            decoder: Decoder::default(),
+            last: Vec::new(),
+            running: false,
+            pending_stop: None,
+            thread: 0,
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            history: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Keeps a history of execution so GDB can step and continue backwards, or stops keeping
+    /// it.
+    ///
+    /// Harts run one instruction at a time while history is kept.
+    /// Stores to `ram` are undone by writing back what was there before, which relies on the
+    /// harts being the only thing changing it.
+    /// Everything else is taken to be devices, which are never read to undo a store.
+    /// What the harts read from devices, `time` and `seed` is logged in a [`Journal`] per hart,
+    /// replacing any they had, and answered from it when executing forward again, while their
+    /// writes to devices are dropped.
+    pub fn set_history(&mut self, ram: Option<Vec<Range<u64>>>) {
+        self.history = ram.map(History::new);
+    }
+
+    /// Whether GDB lets the harts run.
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    /// Handles what GDB sent since the last call, then runs each hart for up to `budget`
+    /// instructions if GDB let them continue.
+    ///
+    /// Returns the exceptions that aren't GDB's breakpoints, along with the index of the hart
+    /// that raised them, for the embedder to take as traps before polling again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `harts` is empty.
+    pub fn poll(
+        &mut self,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+        budget: u64,
+    ) -> io::Result<Option<(usize, Error)>> {
+        assert!(!harts.is_empty(), "GDB needs at least one hart");
+        if self.client.is_none() {
+            self.accept()?;
+        }
+
+        let mut exception = None;
+        let mut buf = [0; 4096];
+        while let Some(client) = &mut self.client {
+            let len = match client.read(&mut buf) {
+                Ok(0) => {
+                    self.detach(harts, bus);
+                    break;
+                }
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.detach(harts, bus);
+                    return Err(e);
+                }
+            };
+            for &byte in &buf[..len] {
+                let Some(event) = self.decoder.push(byte) else {
+                    continue;
+                };
+                if let Some(raised) = self.handle(event, harts, bus)? {
+                    exception = Some(raised);
+                }
+            }
+        }
+        if exception.is_some() {
+            return Ok(exception);
+        }
+
+        if let Some(stop) = self.pending_stop.take() {
+            self.send(stop.as_bytes())?;
+        }
+        if !self.running {
+            return Ok(None);
+        }
+        if let Some(history) = &mut self.history {
+            history.sync(harts);
+        }
+        let mut stop = None;
+        for index in 0..harts.len() {
+            let result = match &mut self.history {
+                Some(history) => history.run(index, harts, bus, budget),
+                None => harts[index].run(bus, budget),
+            };
+            match self.stop_reason(index, harts, result) {
+                Ok(None) => {}
+                Ok(Some(reason)) => {
+                    stop = Some(Ok((index, reason)));
+                    break;
+                }
+                Err(e) => {
+                    stop = Some(Err((index, e)));
+                    break;
+                }
+            }
+        }
+        if let Some(history) = &mut self.history {
+            history.settle(harts);
+        }
+
+        match stop {
+            Some(Ok((index, reason))) => {
+                self.running = false;
+                self.thread = index;
+                self.send(format!("T05thread:{:x};{reason}", index + 1).as_bytes())?;
+                Ok(None)
+            }
+            Some(Err(raised)) => Ok(Some(raised)),
+            None => Ok(None),
+        }
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        match self.listener.accept() {
+            Ok((client, _)) => {
+                client.set_nonblocking(true)?;
+                client.set_nodelay(true)?;
+                self.client = Some(client);
+                self.decoder = Decoder::default();
+                self.running = false;
+                self.thread = 0;
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes all breakpoints and watchpoints and lets the harts run.
+    fn detach(&mut self, harts: &mut [&mut dyn GdbHart], bus: &mut dyn Bus) {
+        for (addr, original) in std::mem::take(&mut self.breakpoints) {
+            let _ = bus.write(addr, &original);
+            invalidate_code(harts, addr, original.len() as u64);
+        }
+        for index in 0..self.watchpoints.len() {
+            self.remove_watchpoint(index, harts);
+        }
+        self.watchpoints.clear();
+        self.client = None;
+        self.running = true;
+        self.pending_stop = None;
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let Some(client) = &mut self.client else {
+            return Ok(());
+        };
+        client.set_nonblocking(false)?;
+        client.write_all(bytes)?;
+        client.set_nonblocking(true)
+    }
+
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let packet = packet::encode(data);
+        self.write_raw(&packet)?;
+        self.last = packet;
+        Ok(())
+    }
+
+    fn handle(
+        &mut self,
+        event: Event,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+    ) -> io::Result<Option<(usize, Error)>> {
+        match event {
+            Event::Packet(packet) => {
+                self.write_raw(b"+")?;
+                return self.command(&packet, harts, bus);
+            }
+            Event::Corrupt => self.write_raw(b"-")?,
+            Event::Nak => self.write_raw(&self.last.clone())?,
+            Event::Interrupt if self.running => {
+                self.running = false;
+                self.send(format!("T02thread:{:x};", self.thread + 1).as_bytes())?;
+            }
+            Event::Interrupt => {}
+        }
+        Ok(None)
+    }
+
+    /// Why hart `index` stopped after returning `result`, or `None` if it didn't.
+    ///
+    /// Exceptions other than GDB's breakpoints are passed on.
+    fn stop_reason(
+        &mut self,
+        index: usize,
+        harts: &mut [&mut dyn GdbHart],
+        result: Result<(), Error>,
+    ) -> Result<Option<String>, Error> {
+        let hart = &mut *harts[index];
+        if hart.halted() {
+            let mut reason = String::new();
+            for (trigger, watchpoint) in self.watchpoints.iter().enumerate() {
+                let Some(watchpoint) = watchpoint else {
+                    continue;
+                };
+                hart.write_register(TSELECT, trigger as u128);
+                let tdata1 = hart.read_register(TDATA1) as u64;
+                if tdata1 & HIT0 != 0 {
+                    hart.write_register(TDATA1, (tdata1 & !HIT0).into());
+                    reason = watchpoint.stop_reason();
+                }
+            }
+            // NOTE: The trigger fired before the access, so the instruction hasn't executed.
+            hart.resume();
+            return Ok(Some(reason));
+        }
+
+        match result {
+            Ok(()) => Ok(None),
+            Err(Error::Breakpoint) if self.breakpoints.contains_key(&hart.pc()) => {
+                Ok(Some("swbreak:;".into()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn step(
+        &mut self,
+        index: usize,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+    ) -> io::Result<Option<(usize, Error)>> {
+        self.running = false;
+        self.thread = index;
+        let result = match &mut self.history {
+            Some(history) => {
+                history.sync(harts);
+                let (result, _) = history.step(index, harts, bus);
+                history.settle(harts);
+                result
+            }
+            None => harts[index].step(bus),
+        };
+        let stop = format!("T05thread:{:x};", index + 1);
+        match self.stop_reason(index, harts, result) {
+            Ok(reason) => {
+                let reason = reason.unwrap_or_default();
+                self.send(format!("{stop}{reason}").as_bytes())?;
+                Ok(None)
+            }
+            Err(e) => {
+                self.pending_stop = Some(stop);
+                Ok(Some((index, e)))
+            }
+        }
+    }
+
+    fn command(
+        &mut self,
+        packet: &[u8],
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+    ) -> io::Result<Option<(usize, Error)>> {
+        let Ok(packet) = std::str::from_utf8(packet) else {
+            self.send(b"")?;
+            return Ok(None);
+        };
+        let (command, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => {
+                self.running = false;
+                format!("T05thread:{:x};", self.thread + 1)
+            }
+            "q" => self.query(args, harts),
+            "H" => match parse_thread(args.get(1..).unwrap_or_default()) {
+                Some(index) if index >= harts.len() => "E01".into(),
+                Some(index) => {
+                    self.thread = index;
+                    "OK".into()
+                }
+                None => "OK".into(),
+            },
+            "T" => match parse_thread(args) {
+                Some(index) if index < harts.len() => "OK".into(),
+                _ => "E01".into(),
+            },
+            "g" => {
+                let hart = &*harts[self.thread];
+                (0..=Register::PC)
+                    .filter_map(|regnum| read_register(hart, regnum))
+                    .collect()
+            }
+            "G" => {
+                let hart = &mut *harts[self.thread];
+                let mut values = args;
+                for regnum in 0..=Register::PC {
+                    let Some(reg) = Register::from_regnum(regnum) else {
+                        continue;
+                    };
+                    let Some(bits) = reg.bits(hart) else {
+                        continue;
+                    };
+                    let digits = (bits / 4) as usize;
+                    let Some(value) = values.get(..digits) else {
+                        break;
+                    };
+                    write_register(hart, regnum, value);
+                    values = &values[digits..];
+                }
+                self.forget_history();
+                "OK".into()
+            }
+            "p" => parse(args)
+                .and_then(|regnum| read_register(&*harts[self.thread], regnum as usize))
+                .unwrap_or_else(|| "E01".into()),
+            "P" => {
+                let written = args.split_once('=').and_then(|(regnum, value)| {
+                    write_register(&mut *harts[self.thread], parse(regnum)? as usize, value)
+                });
+                self.forget_history();
+                match written {
+                    Some(()) => "OK".into(),
+                    None => "E01".into(),
+                }
+            }
+            "m" => {
+                let mut buf = Vec::new();
+                let read = parse_range(args).and_then(|(addr, len)| {
+                    buf.resize((len as usize).min(MAX_ACCESS), 0);
+                    bus.read(addr, &mut buf).ok()
+                });
+                match read {
+                    Some(()) => hex(&buf),
+                    None => "E01".into(),
+                }
+            }
+            "M" => {
+                let written = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    let data = unhex(data).filter(|data| data.len() as u64 == len)?;
+                    bus.write(addr, &data).ok()?;
+                    invalidate_code(harts, addr, len);
+                    Some(())
+                });
+                self.forget_history();
+                match written {
+                    Some(()) => "OK".into(),
+                    None => "E01".into(),
+                }
+            }
+            "c" | "C" => {
+                // NOTE: Signals can't be delivered, as the harts don't take traps themselves.
+                if let ("c", Some(addr)) = (command, parse(args)) {
+                    harts[self.thread].set_pc(addr);
+                    self.forget_history();
+                }
+                self.running = true;
+                return Ok(None);
+            }
+            "s" | "S" => {
+                if let ("s", Some(addr)) = (command, parse(args)) {
+                    harts[self.thread].set_pc(addr);
+                    self.forget_history();
+                }
+                return self.step(self.thread, harts, bus);
+            }
+            "b" if self.history.is_some() && matches!(args, "s" | "c") => {
+                return self.reverse(args == "s", harts, bus);
+            }
+            "v" => return self.v_command(args, harts, bus),
+            "Z" | "z" => {
+                let done = self.breakpoint(command == "Z", args, harts, bus);
+                match done {
+                    Some(true) => "OK".into(),
+                    Some(false) => "E01".into(),
+                    None => String::new(),
+                }
+            }
+            "D" => {
+                self.send(b"OK")?;
+                self.detach(harts, bus);
+                return Ok(None);
+            }
+            "k" => {
+                self.detach(harts, bus);
+                return Ok(None);
+            }
+            _ => String::new(),
+        };
+        self.send(reply.as_bytes())?;
+        Ok(None)
+    }
+
+    fn query(&mut self, query: &str, harts: &mut [&mut dyn GdbHart]) -> String {
+        match query {
+            _ if query.starts_with("Supported") => {
+                let reverse = match self.history {
+                    Some(_) => ";ReverseStep+;ReverseContinue+",
+                    None => "",
+                };
+                format!(
+                    "PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+;vContSupported+{reverse}",
+                    4 * MAX_ACCESS
+                )
+            }
+            "fThreadInfo" => {
+                let threads: Vec<_> = (1..=harts.len()).map(|id| format!("{id:x}")).collect();
+                format!("m{}", threads.join(","))
+            }
+            "sThreadInfo" => "l".into(),
+            "C" => format!("QC{:x}", self.thread + 1),
+            "Attached" => "1".into(),
+            _ => {
+                let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") else {
+                    return String::new();
+                };
+                let Some((offset, len)) = parse_range(range) else {
+                    return "E01".into();
+                };
+                let xml = target::target_xml(&*harts[0]);
+                let start = (offset as usize).min(xml.len());
+                let end = start.saturating_add(len as usize).min(xml.len());
+                let more = if end < xml.len() { 'm' } else { 'l' };
+                format!("{more}{}", &xml[start..end])
+            }
+        }
+    }
+
+    fn v_command(
+        &mut self,
+        command: &str,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+    ) -> io::Result<Option<(usize, Error)>> {
+        let reply = match command {
+            "Cont?" => "vCont;c;C;s;S",
+            "Kill" => {
+                self.send(b"OK")?;
+                self.detach(harts, bus);
+                return Ok(None);
+            }
+            _ => {
+                let Some(actions) = command.strip_prefix("Cont;") else {
+                    self.send(b"")?;
+                    return Ok(None);
+                };
+                // NOTE: Stepping one hart leaves the others stopped, as with
+                //       `set scheduler-locking step`.
+                for action in actions.split(';') {
+                    let (action, thread) = action.split_once(':').unwrap_or((action, "-1"));
+                    if action.starts_with(['s', 'S']) {
+                        let index = parse_thread(thread).unwrap_or(self.thread);
+                        if index >= harts.len() {
+                            break;
+                        }
+                        return self.step(index, harts, bus);
+                    }
+                }
+                self.running = true;
+                return Ok(None);
+            }
+        };
+        self.send(reply.as_bytes())?;
+        Ok(None)
+    }
+
+    /// Inserts or removes the breakpoint or watchpoint described by `args`, or returns `None`
+    /// for kinds that aren't supported.
+    fn breakpoint(
+        &mut self,
+        insert: bool,
+        args: &str,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+    ) -> Option<bool> {
+        let mut args = args.split([',', ';']);
+        let kind = args.next()?.parse::<u8>().ok()?;
+        let addr = parse(args.next()?)?;
+        let len = parse(args.next()?)?;
+
+        if kind == 0 {
+            return Some(match insert {
+                true => self.insert_breakpoint(addr, len, harts, bus),
+                false => self.remove_breakpoint(addr, harts, bus),
+            });
+        }
+        if kind > 4 {
+            return None;
+        }
+
+        let watchpoint = Watchpoint { kind, addr, len };
+        if !insert {
+            let index = self.watchpoints.iter().position(|&w| w == Some(watchpoint));
+            if let Some(index) = index {
+                self.remove_watchpoint(index, harts);
+            }
+            return Some(true);
+        }
+
+        let Some((tdata1, tdata2)) = watchpoint.trigger(harts[0].xlen()) else {
+            return Some(false);
+        };
+        let index = match self.watchpoints.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.watchpoints.push(None);
+                self.watchpoints.len() - 1
+            }
+        };
+        let programmed = harts
+            .iter_mut()
+            .all(|hart| program_trigger(&mut **hart, index, tdata1, tdata2));
+        if programmed {
+            self.watchpoints[index] = Some(watchpoint);
+        } else {
+            self.remove_watchpoint(index, harts);
+        }
+        Some(programmed)
+    }
+
+    fn insert_breakpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+    ) -> bool {
+        if self.breakpoints.contains_key(&addr) {
+            return true;
+        }
+        let ebreak = match len {
+            2 => C_EBREAK.to_le_bytes().to_vec(),
+            4 => EBREAK.to_le_bytes().to_vec(),
+            _ => return false,
+        };
+        let mut original = vec![0; ebreak.len()];
+        if bus.read(addr, &mut original).is_err() || bus.write(addr, &ebreak).is_err() {
+            return false;
+        }
+        invalidate_code(harts, addr, len);
+        self.breakpoints.insert(addr, original);
+        true
+    }
+
+    fn remove_breakpoint(
+        &mut self,
+        addr: u64,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+    ) -> bool {
+        let Some(original) = self.breakpoints.remove(&addr) else {
+            return true;
+        };
+        invalidate_code(harts, addr, original.len() as u64);
+        bus.write(addr, &original).is_ok()
+    }
+
+    /// Drops the history after GDB changed the harts or memory, which can't be undone.
+    fn forget_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    /// Goes back to before the last instruction the selected hart retired for `bs`, or to the
+    /// last breakpoint or watchpoint hit for `bc`.
+    fn reverse(
+        &mut self,
+        step: bool,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+    ) -> io::Result<Option<(usize, Error)>> {
+        self.running = false;
+        let Some(mut history) = self.history.take() else {
+            return Ok(None);
+        };
+        history.sync(harts);
+
+        // NOTE: Executing forward again mustn't stop at breakpoints, so they're lifted until
+        //       the harts are where they should be.
+        for (&addr, original) in &self.breakpoints {
+            let _ = bus.write(addr, original);
+            invalidate_code(harts, addr, original.len() as u64);
+        }
+        let triggers = self.watchpoints.len();
+
+        let mut stop = None;
+        if step {
+            if let Some((k, position)) = history.last_step(self.thread) {
+                let steps = history.rewind(k, harts, bus, triggers);
+                history.replay(&steps[..position], harts, bus, |_, _, _, _| {});
+                stop = Some((self.thread, String::new()));
+            }
+        } else {
+            for k in (0..history.len()).rev() {
+                let steps = history.rewind(k, harts, bus, triggers);
+                let mut hit = None;
+                history.replay(&steps, harts, bus, |position, index, pc, accesses| {
+                    if let Some(reason) = self.hit(pc, accesses) {
+                        hit = Some((position, index, reason));
+                    }
+                });
+                if let Some((position, index, reason)) = hit {
+                    let steps = history.rewind(k, harts, bus, triggers);
+                    history.replay(&steps[..position], harts, bus, |_, _, _, _| {});
+                    stop = Some((index, reason));
+                    break;
+                }
+            }
+        }
+        let (index, reason) = match stop {
+            Some(stop) => stop,
+            None => {
+                if history.len() > 0 {
+                    history.rewind(0, harts, bus, triggers);
+                    history.replay(&[], harts, bus, |_, _, _, _| {});
+                }
+                (self.thread, "replaylog:begin;".into())
+            }
+        };
+
+        for &addr in self.breakpoints.keys() {
+            let ebreak = match self.breakpoints[&addr].len() {
+                2 => C_EBREAK.to_le_bytes().to_vec(),
+                _ => EBREAK.to_le_bytes().to_vec(),
+            };
+            let _ = bus.write(addr, &ebreak);
+            invalidate_code(harts, addr, ebreak.len() as u64);
+        }
+        for (trigger, watchpoint) in self.watchpoints.iter().enumerate() {
+            let Some((tdata1, tdata2)) = watchpoint.and_then(|w| w.trigger(harts[0].xlen())) else {
+                continue;
+            };
+            for hart in harts.iter_mut() {
+                program_trigger(&mut **hart, trigger, tdata1, tdata2);
+            }
+        }
+        history.settle(harts);
+        self.history = Some(history);
+
+        self.thread = index;
+        self.send(format!("T05thread:{:x};{reason}", index + 1).as_bytes())?;
+        Ok(None)
+    }
+
+    /// The stop reason of a breakpoint at `pc` or a watchpoint matching `accesses`, if any.
+    fn hit(&self, pc: u64, accesses: &[Access]) -> Option<String> {
+        if self.breakpoints.contains_key(&pc) {
+            return Some("swbreak:;".into());
+        }
+        self.watchpoints.iter().flatten().find_map(|&watchpoint| {
+            let hit = match watchpoint.kind {
+                1 => watchpoint.addr == pc,
+                kind => accesses.iter().any(|access| {
+                    // NOTE: Fetching the instruction doesn't count as reading it.
+                    let fetch = !access.write && access.addr >= pc && access.addr < pc + 4;
+                    let kind_matches = match kind {
+                        2 => access.write,
+                        3 => !access.write && !fetch,
+                        _ => !fetch,
+                    };
+                    let end = watchpoint.addr + watchpoint.len.max(1);
+                    kind_matches && access.addr < end && watchpoint.addr < access.addr + access.len
+                }),
+            };
+            hit.then(|| watchpoint.stop_reason())
+        })
+    }
+
+    fn remove_watchpoint(&mut self, index: usize, harts: &mut [&mut dyn GdbHart]) {
+        self.watchpoints[index] = None;
+        for hart in harts.iter_mut() {
+            program_trigger(&mut **hart, index, 0, 0);
+        }
+    }
+}
+
+/// Reads register `regnum` of `hart` as hex, or `None` if it doesn't have it.
+fn read_register(hart: &dyn GdbHart, regnum: usize) -> Option<String> {
+    let reg = Register::from_regnum(regnum)?;
+    let bits = reg.bits(hart)?;
+    let value = match reg {
+        Register::Pc => hart.pc().into(),
+        Register::Other(regno) => hart.read_register(regno),
+    };
+    Some(hex(&value.to_le_bytes()[..(bits / 8) as usize]))
+}
+
+/// Writes the hex `value` to register `regnum` of `hart`.
+fn write_register(hart: &mut dyn GdbHart, regnum: usize, value: &str) -> Option<()> {
+    let reg = Register::from_regnum(regnum)?;
+    let bits = reg.bits(hart)?;
+    let bytes = unhex(value).filter(|bytes| bytes.len() == (bits / 8) as usize)?;
+    let value = bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | byte as u128);
+    match reg {
+        Register::Pc => hart.set_pc(value as u64),
+        Register::Other(regno) => hart.write_register(regno, value),
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{ErrorKind, Read, Write},
+        net::TcpStream,
+        ops::Range,
+    };
+
+    use super::{packet, GdbHart, GdbServer};
+    use crate::{
+        bus::{AccessFault, Bus, Ram},
+        ext::Extensions,
+        hart::{
+            testing::{boot, write_program},
+            Hart,
+        },
+    };
+
+    #[derive(Clone, Copy)]
+    struct Triggers;
+
+    impl Extensions for Triggers {
+        const TRIGGERS: usize = 2;
+    }
+
+    /// Where the tests' RAM is.
+    const RAM: Range<u64> = 0..0x1_0000;
+
+    type TestHart<const ID: usize> =
+        Hart<ID, u64, false, false, false, f64, false, false, Triggers>;
+
+    struct Gdb<'a, 'b, B = Ram> {
+        server: GdbServer,
+        client: TcpStream,
+        harts: &'a mut [&'b mut dyn GdbHart],
+        ram: B,
+        received: Vec<u8>,
+    }
+
+    impl<B: Bus> Gdb<'_, '_, B> {
+        fn send(&mut self, bytes: &[u8]) {
+            self.client.write_all(bytes).unwrap();
+        }
+
+        /// Polls the server until it replies with a packet.
+        fn reply(&mut self) -> String {
+            loop {
+                let exception = self.server.poll(self.harts, &mut self.ram, 100).unwrap();
+                assert_eq!(exception, None);
+                let mut buf = [0; 4096];
+                match self.client.read(&mut buf) {
+                    Ok(len) => self.received.extend_from_slice(&buf[..len]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => panic!("{e}"),
+                }
+
+                // NOTE: Drops the acknowledgements in front of the packet.
+                let start = self.received.iter().position(|&byte| byte == b'$');
+                self.received.drain(..start.unwrap_or(self.received.len()));
+                let Some(end) = self.received.iter().position(|&byte| byte == b'#') else {
+                    continue;
+                };
+                if self.received.len() < end + 3 {
+                    continue;
+                }
+                let packet: Vec<_> = self.received.drain(..end + 3).collect();
+                assert_eq!(packet::encode(&packet[1..end]), packet);
+                return String::from_utf8(packet[1..end].to_vec()).unwrap();
+            }
+        }
+
+        fn request(&mut self, packet: &str) -> String {
+            self.send(&packet::encode(packet.as_bytes()));
+            self.reply()
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let mut ram = Ram::new(0, 0x1_0000);
+        let program: [u32; 4] = [
+            0x00108093, // addi x1, x1, 1
+            0x00108093, // addi x1, x1, 1
+            0x00113023, // sd x1, 0(x2)
+            0x0000006f, // j .
+        ];
+        write_program(&mut ram, 0x1000, &program);
+        ram.write(0x2000, &0x0000006fu32.to_le_bytes()).unwrap();
+
+        let mut hart0: TestHart<0> = boot();
+        let mut hart1 = TestHart::<1>::new();
+        hart1.set_pc(0x2000);
+        let server = GdbServer::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let mut gdb = Gdb {
+            server,
+            client,
+            harts: &mut [&mut hart0, &mut hart1],
+            ram,
+            received: Vec::new(),
+        };
+
+        assert!(gdb
+            .request("qSupported:swbreak+")
+            .starts_with("PacketSize="));
+        assert_eq!(gdb.request("?"), "T05thread:1;");
+        assert_eq!(gdb.request("qfThreadInfo"), "m1,2");
+        assert_eq!(gdb.request("qsThreadInfo"), "l");
+        assert_eq!(gdb.request("p20"), "0010000000000000");
+        assert_eq!(gdb.request("P2=0030000000000000"), "OK");
+        assert_eq!(gdb.request("g").len(), 33 * 16);
+        assert_eq!(gdb.request("p21"), "0000000000000000");
+        assert_eq!(gdb.request("p1041"), "E01");
+        // NOTE: `fcsr` is 32 bits wide, as in the target description.
+        assert_eq!(gdb.request("P44=e0000000"), "OK");
+        assert_eq!(gdb.request("p44"), "e0000000");
+        assert_eq!(gdb.request("P44=e000000000000000"), "E01");
+        assert_eq!(gdb.request("Hg2"), "OK");
+        assert_eq!(gdb.request("p20"), "0020000000000000");
+        assert_eq!(gdb.request("Hg3"), "E01");
+        assert_eq!(gdb.request("Hg1"), "OK");
+
+        let mut xml = String::new();
+        loop {
+            let request = format!("qXfer:features:read:target.xml:{:x},800", xml.len());
+            let chunk = gdb.request(&request);
+            xml.push_str(&chunk[1..]);
+            if chunk.starts_with('l') {
+                break;
+            }
+        }
+        assert!(xml.starts_with("<?xml"));
+        assert!(xml.contains("<architecture>riscv:rv64</architecture>"));
+        assert!(xml.contains(r#"<reg name="f31" bitsize="64" type="ieee_double" regnum="64"/>"#));
+        assert!(xml.contains(r#"<reg name="fcsr" bitsize="32" type="int" regnum="68"/>"#));
+        assert!(xml.contains(r#"<reg name="tdata1" bitsize="64" type="int" regnum="2018"/>"#));
+
+        // NOTE: Software breakpoints stop before the instruction.
+        assert_eq!(gdb.request("Z0,1004,4"), "OK");
+        assert_eq!(gdb.request("c"), "T05thread:1;swbreak:;");
+        assert_eq!(gdb.request("p20"), "0410000000000000");
+        assert_eq!(gdb.request("z0,1004,4"), "OK");
+        assert_eq!(gdb.request("s"), "T05thread:1;");
+        assert_eq!(gdb.request("p1"), "0200000000000000");
+
+        // NOTE: So do watchpoints, leaving GDB to step over the access.
+        assert_eq!(gdb.request("Z2,3000,8"), "OK");
+        assert_eq!(gdb.request("Z2,3001,3"), "E01");
+        assert_eq!(gdb.request("vCont;c"), "T05thread:1;watch:3000;");
+        assert_eq!(gdb.request("p20"), "0810000000000000");
+        assert_eq!(gdb.request("z2,3000,8"), "OK");
+        assert_eq!(gdb.request("vCont;s:1"), "T05thread:1;");
+        assert_eq!(gdb.request("m3000,8"), "0200000000000000");
+        assert_eq!(gdb.request("M3008,2:abcd"), "OK");
+        assert_eq!(gdb.request("m3008,2"), "abcd");
+        assert_eq!(gdb.request("m10000,1"), "E01");
+
+        // NOTE: Both harts now spin until interrupted.
+        gdb.send(&packet::encode(b"c"));
+        gdb.send(b"\x03");
+        assert_eq!(gdb.reply(), "T02thread:1;");
+        assert_eq!(gdb.request("D"), "OK");
+        assert!(gdb.server.running());
+    }
+
+    #[test]
+    fn test_reverse() {
+        let mut ram = Ram::new(0, 0x1_0000);
+        let program: [u32; 5] = [
+            0x00108093, // addi x1, x1, 1
+            0x00108093, // addi x1, x1, 1
+            0x00113023, // sd x1, 0(x2)
+            0x00108093, // addi x1, x1, 1
+            0x0000006f, // j .
+        ];
+        write_program(&mut ram, 0x1000, &program);
+        ram.write(0x2000, &0x0000006fu32.to_le_bytes()).unwrap();
+
+        let mut hart0: TestHart<0> = boot();
+        let mut hart1 = TestHart::<1>::new();
+        hart1.set_pc(0x2000);
+        let mut server = GdbServer::bind("127.0.0.1:0").unwrap();
+        server.set_history(Some(vec![RAM]));
+        let client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let mut gdb = Gdb {
+            server,
+            client,
+            harts: &mut [&mut hart0, &mut hart1],
+            ram,
+            received: Vec::new(),
+        };
+
+        assert!(gdb
+            .request("qSupported:swbreak+")
+            .ends_with(";ReverseStep+;ReverseContinue+"));
+        assert_eq!(gdb.request("P2=0030000000000000"), "OK");
+        for _ in 0..4 {
+            assert_eq!(gdb.request("s"), "T05thread:1;");
+        }
+        assert_eq!(gdb.request("p20"), "1010000000000000");
+        assert_eq!(gdb.request("m3000,8"), "0200000000000000");
+
+        // NOTE: Stepping back undoes registers and memory alike.
+        assert_eq!(gdb.request("bs"), "T05thread:1;");
+        assert_eq!(gdb.request("p20"), "0c10000000000000");
+        assert_eq!(gdb.request("p1"), "0200000000000000");
+        assert_eq!(gdb.request("bs"), "T05thread:1;");
+        assert_eq!(gdb.request("p20"), "0810000000000000");
+        assert_eq!(gdb.request("m3000,8"), "0000000000000000");
+
+        // NOTE: Both harts run on, and continuing back stops at the last hit.
+        assert_eq!(gdb.request("Z0,1010,4"), "OK");
+        assert_eq!(gdb.request("c"), "T05thread:1;swbreak:;");
+        assert_eq!(gdb.request("z0,1010,4"), "OK");
+        assert_eq!(gdb.request("Z2,3000,8"), "OK");
+        assert_eq!(gdb.request("bc"), "T05thread:1;watch:3000;");
+        assert_eq!(gdb.request("p20"), "0810000000000000");
+        assert_eq!(gdb.request("z2,3000,8"), "OK");
+        assert_eq!(gdb.request("Z0,1004,4"), "OK");
+        assert_eq!(gdb.request("bc"), "T05thread:1;swbreak:;");
+        assert_eq!(gdb.request("p20"), "0410000000000000");
+        assert_eq!(gdb.request("p1"), "0100000000000000");
+        assert_eq!(gdb.request("m1004,4"), "73001000");
+        assert_eq!(gdb.request("bc"), "T05thread:1;replaylog:begin;");
+        assert_eq!(gdb.request("p20"), "0010000000000000");
+        assert_eq!(gdb.request("p1"), "0000000000000000");
+
+        // NOTE: The breakpoint is still in place going forward again.
+        assert_eq!(gdb.request("c"), "T05thread:1;swbreak:;");
+        assert_eq!(gdb.request("p20"), "0410000000000000");
+    }
+
+    /// RAM, a counter at 0x10000 that counts reads, and a write-only register at 0x10008.
+    struct Devices {
+        ram: Ram,
+        reads: u64,
+        written: Vec<u64>,
+    }
+
+    impl Bus for Devices {
+        fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), AccessFault> {
+            match addr {
+                0x1_0000 => {
+                    self.reads += 1;
+                    buf.copy_from_slice(&(self.reads * 100).to_le_bytes()[..buf.len()]);
+                    Ok(())
+                }
+                0x1_0008 => Err(AccessFault),
+                _ => self.ram.read(addr, buf),
+            }
+        }
+
+        fn write(&mut self, addr: u64, buf: &[u8]) -> Result<(), AccessFault> {
+            match addr {
+                0x1_0000 => Err(AccessFault),
+                0x1_0008 => {
+                    let mut value = [0; 8];
+                    value[..buf.len()].copy_from_slice(buf);
+                    self.written.push(u64::from_le_bytes(value));
+                    Ok(())
+                }
+                _ => self.ram.write(addr, buf),
+            }
+        }
+    }
+
+    #[test]
+    fn test_reverse_devices() {
+        let mut ram = Ram::new(0, 0x1_0000);
+        let program: [u32; 4] = [
+            0x0001b083, // ld x1, 0(x3)
+            0x0011b423, // sd x1, 8(x3)
+            0x00113023, // sd x1, 0(x2)
+            0x0000006f, // j .
+        ];
+        write_program(&mut ram, 0x1000, &program);
+
+        let mut hart: TestHart<0> = boot();
+        let mut server = GdbServer::bind("127.0.0.1:0").unwrap();
+        server.set_history(Some(vec![RAM]));
+        let client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let mut gdb = Gdb {
+            server,
+            client,
+            harts: &mut [&mut hart],
+            ram: Devices {
+                ram,
+                reads: 0,
+                written: Vec::new(),
+            },
+            received: Vec::new(),
+        };
+
+        assert_eq!(gdb.request("P2=0030000000000000"), "OK");
+        assert_eq!(gdb.request("P3=0000010000000000"), "OK");
+        for _ in 0..3 {
+            assert_eq!(gdb.request("s"), "T05thread:1;");
+        }
+        assert_eq!(gdb.request("m3000,8"), "6400000000000000");
+
+        // NOTE: Executing forward again reads the counter from the journal, and doesn't repeat
+        //       the write to the device.
+        assert_eq!(gdb.request("bs"), "T05thread:1;");
+        assert_eq!(gdb.request("p20"), "0810000000000000");
+        assert_eq!(gdb.request("p1"), "6400000000000000");
+        assert_eq!(gdb.request("m3000,8"), "0000000000000000");
+        assert_eq!(gdb.request("bs"), "T05thread:1;");
+        assert_eq!(gdb.request("p1"), "6400000000000000");
+        assert_eq!((gdb.ram.reads, &gdb.ram.written[..]), (1, &[100][..]));
+
+        // NOTE: Going forward from there reads the device live again.
+        assert_eq!(gdb.request("bs"), "T05thread:1;");
+        assert_eq!(gdb.request("s"), "T05thread:1;");
+        assert_eq!(gdb.request("p1"), "c800000000000000");
+    }
+}
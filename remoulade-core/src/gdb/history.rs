@@ -0,0 +1,345 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Execution history for reverse execution.
+//!
+//! The harts are saved to a checkpoint every so often, every store to RAM is logged along with
+//! what it overwrote, and every other input to the harts is logged in a [`Journal`].
+//! Going back undoes the stores, restores the checkpoint and executes forward again with the
+//! journal replaying, which arrives at the same state as long as nothing but the harts changes
+//! RAM.
+
+use std::ops::Range;
+
+use crate::{
+    bus::{AccessFault, Bus},
+    hart::exec::Error,
+    replay::{Journal, Logged},
+    snapshot::{self, Snapshot},
+};
+
+use super::{program_trigger, GdbHart};
+
+/// Instructions executed between checkpoints, bounding how many are executed again to go back.
+const CHECKPOINT_INTERVAL: usize = 10_000;
+
+/// Checkpoints kept before the oldest is forgotten.
+const MAX_CHECKPOINTS: usize = 100;
+
+/// A memory access made by an instruction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) struct Access {
+    pub(super) addr: u64,
+    pub(super) len: u64,
+    pub(super) write: bool,
+}
+
+struct Checkpoint {
+    /// Snapshot of every hart
+    harts: Vec<u8>,
+    /// Length of the undo log when taken
+    undo: usize,
+    /// Index of the hart executing each instruction since
+    steps: Vec<usize>,
+    /// Inputs to each hart since
+    journals: Vec<Journal>,
+}
+
+/// A bus logging the accesses of an instruction, and what its stores to RAM overwrote.
+struct Recording<'a> {
+    bus: Logged<&'a mut dyn Bus>,
+    ram: &'a [Range<u64>],
+    undo: &'a mut Vec<(u64, Vec<u8>)>,
+    /// Whether devices already saw the stores to them, which are dropped
+    replaying: bool,
+    accesses: Vec<Access>,
+}
+
+impl Bus for Recording<'_> {
+    fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), AccessFault> {
+        self.bus.read(addr, buf)?;
+        self.accesses.push(Access {
+            addr,
+            len: buf.len() as u64,
+            write: false,
+        });
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u64, buf: &[u8]) -> Result<(), AccessFault> {
+        let end = addr.checked_add(buf.len() as u64);
+        let ram = self
+            .ram
+            .iter()
+            .any(|ram| ram.start <= addr && end.is_some_and(|end| end <= ram.end));
+        // NOTE: Reading a device can have side effects, so only RAM is read to undo a store.
+        if ram {
+            let mut old = vec![0; buf.len()];
+            self.bus.read(addr, &mut old)?;
+            self.bus.write(addr, buf)?;
+            self.undo.push((addr, old));
+        } else if !self.replaying {
+            self.bus.write(addr, buf)?;
+        }
+        self.accesses.push(Access {
+            addr,
+            len: buf.len() as u64,
+            write: true,
+        });
+        Ok(())
+    }
+}
+
+pub(super) struct History {
+    ram: Vec<Range<u64>>,
+    /// Everything but `ram`, whose reads are journaled
+    devices: Vec<Range<u64>>,
+    checkpoints: Vec<Checkpoint>,
+    /// Whether instructions are being executed again, whose stores devices already saw
+    replaying: bool,
+    /// Stores to undo, by address and what was there before
+    undo: Vec<(u64, Vec<u8>)>,
+    /// Snapshot of the harts when they last stopped running, to tell whether anything else
+    /// changed them since
+    last: Vec<u8>,
+}
+
+fn save(harts: &[&mut dyn GdbHart]) -> Vec<u8> {
+    let names: Vec<_> = (0..harts.len())
+        .map(|index| format!("hart{index}"))
+        .collect();
+    let parts: Vec<(&str, &dyn Snapshot)> = names
+        .iter()
+        .zip(harts)
+        .map(|(name, hart)| (name.as_str(), &**hart as &dyn Snapshot))
+        .collect();
+    let mut buf = Vec::new();
+    snapshot::save(&mut buf, &parts).expect("Writing to a `Vec` can't fail");
+    buf
+}
+
+fn restore(harts: &mut [&mut dyn GdbHart], buf: &[u8]) {
+    let names: Vec<_> = (0..harts.len())
+        .map(|index| format!("hart{index}"))
+        .collect();
+    let mut parts: Vec<(&str, &mut dyn Snapshot)> = names
+        .iter()
+        .zip(harts)
+        .map(|(name, hart)| (name.as_str(), &mut **hart as &mut dyn Snapshot))
+        .collect();
+    snapshot::restore(&mut &buf[..], &mut parts).expect("Harts restore from their own snapshots");
+}
+
+impl History {
+    pub(super) fn new(mut ram: Vec<Range<u64>>) -> Self {
+        ram.sort_by_key(|range| range.start);
+        let mut devices = Vec::new();
+        let mut start = 0;
+        for range in &ram {
+            if start < range.start {
+                devices.push(start..range.start);
+            }
+            start = start.max(range.end);
+        }
+        devices.push(start..u64::MAX);
+        Self {
+            ram,
+            devices,
+            checkpoints: Vec::new(),
+            replaying: false,
+            undo: Vec::new(),
+            last: Vec::new(),
+        }
+    }
+
+    /// Forgets everything executed so far.
+    pub(super) fn clear(&mut self) {
+        self.checkpoints.clear();
+        self.undo.clear();
+        self.last.clear();
+    }
+
+    /// Forgets the oldest checkpoints, and the stores made since them, to start a new one.
+    ///
+    /// This can't happen while going back, as it moves every checkpoint down.
+    fn bound(&mut self) {
+        while self.checkpoints.len() >= MAX_CHECKPOINTS {
+            self.checkpoints.remove(0);
+            let forgotten = self.checkpoints[0].undo;
+            self.undo.drain(..forgotten);
+            for checkpoint in &mut self.checkpoints {
+                checkpoint.undo -= forgotten;
+            }
+        }
+    }
+
+    /// Starts a new checkpoint, in place of the last one if nothing was executed since it.
+    fn checkpoint(&mut self, harts: &[&mut dyn GdbHart]) {
+        if self
+            .checkpoints
+            .last()
+            .is_some_and(|checkpoint| checkpoint.steps.is_empty())
+        {
+            self.checkpoints.pop();
+        }
+        self.checkpoints.push(Checkpoint {
+            harts: save(harts),
+            undo: self.undo.len(),
+            steps: Vec::new(),
+            journals: harts.iter().map(|_| Journal::record()).collect(),
+        });
+    }
+
+    /// Starts a new checkpoint if the harts changed since they last ran, e.g. by taking a trap.
+    pub(super) fn sync(&mut self, harts: &[&mut dyn GdbHart]) {
+        if self.checkpoints.is_empty() || save(harts) != self.last {
+            self.bound();
+            self.checkpoint(harts);
+        }
+    }
+
+    /// Notes the state the harts stopped running in.
+    pub(super) fn settle(&mut self, harts: &[&mut dyn GdbHart]) {
+        self.last = save(harts);
+    }
+
+    /// Executes an instruction on hart `index`, logging it if it retired.
+    pub(super) fn step(
+        &mut self,
+        index: usize,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+    ) -> (Result<(), Error>, Vec<Access>) {
+        let checkpoint = self
+            .checkpoints
+            .last()
+            .expect("Harts only step after a checkpoint");
+        let journal = &checkpoint.journals[index];
+        let hart = &mut *harts[index];
+        let halted = hart.halted();
+        let mut recording = Recording {
+            bus: journal.bus(hart.id(), bus, self.devices.clone()),
+            ram: &self.ram,
+            undo: &mut self.undo,
+            replaying: self.replaying,
+            accesses: Vec::new(),
+        };
+        hart.set_journal(Some(journal.clone()));
+        let result = hart.step(&mut recording);
+        hart.set_journal(None);
+        let accesses = recording.accesses;
+
+        // NOTE: Nothing retires on exceptions, while halted, or when a trigger halts the hart.
+        let retired = result.is_ok() && !halted && !hart.halted();
+        if let (true, Some(checkpoint)) = (retired, self.checkpoints.last_mut()) {
+            checkpoint.steps.push(index);
+        }
+        (result, accesses)
+    }
+
+    /// Executes up to `budget` instructions on hart `index`, like [`GdbHart::run`].
+    pub(super) fn run(
+        &mut self,
+        index: usize,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+        budget: u64,
+    ) -> Result<(), Error> {
+        for _ in 0..budget {
+            if harts[index].halted() {
+                break;
+            }
+            if self
+                .checkpoints
+                .last()
+                .is_some_and(|checkpoint| checkpoint.steps.len() >= CHECKPOINT_INTERVAL)
+            {
+                self.bound();
+                self.checkpoint(harts);
+            }
+            self.step(index, harts, bus).0?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// The checkpoint and position in it of the last instruction hart `index` retired.
+    pub(super) fn last_step(&self, index: usize) -> Option<(usize, usize)> {
+        self.checkpoints
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(k, checkpoint)| {
+                let position = checkpoint.steps.iter().rposition(|&hart| hart == index)?;
+                Some((k, position))
+            })
+    }
+
+    /// Goes back to checkpoint `k`, forgetting everything after it, and returns the harts that
+    /// executed the instructions since.
+    ///
+    /// The harts must then be [replayed](Self::replay) before anything else.
+    ///
+    /// The first `triggers` triggers are GDB's, and are turned off.
+    pub(super) fn rewind(
+        &mut self,
+        k: usize,
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+        triggers: usize,
+    ) -> Vec<usize> {
+        let checkpoint = &mut self.checkpoints[k];
+        for (addr, old) in self.undo.drain(checkpoint.undo..).rev() {
+            let _ = bus.write(addr, &old);
+            for hart in harts.iter_mut() {
+                hart.invalidate_code(addr, old.len() as u64);
+            }
+        }
+        restore(harts, &checkpoint.harts);
+        let steps = std::mem::take(&mut checkpoint.steps);
+        for journal in &mut checkpoint.journals {
+            *journal = Journal::replay(journal.events());
+        }
+        self.checkpoints.truncate(k + 1);
+
+        for hart in harts.iter_mut() {
+            for index in 0..triggers {
+                program_trigger(&mut **hart, index, 0, 0);
+            }
+        }
+        steps
+    }
+
+    /// Executes `steps` again after a [`rewind`](Self::rewind), calling `inspect` with the
+    /// position, hart, `pc` and accesses of each.
+    ///
+    /// A new checkpoint follows, as the journals of the last one may have more to replay.
+    pub(super) fn replay(
+        &mut self,
+        steps: &[usize],
+        harts: &mut [&mut dyn GdbHart],
+        bus: &mut dyn Bus,
+        mut inspect: impl FnMut(usize, usize, u64, &[Access]),
+    ) {
+        self.replaying = true;
+        for (position, &index) in steps.iter().enumerate() {
+            let pc = harts[index].pc();
+            let (result, accesses) = self.step(index, harts, bus);
+            if result.is_err() {
+                break;
+            }
+            inspect(position, index, pc, &accesses);
+        }
+        self.replaying = false;
+        self.checkpoint(harts);
+    }
+}
@@ -0,0 +1,145 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Framing of remote serial protocol packets.
+
+/// What GDB sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Event {
+    /// A packet with a valid checksum, to be acknowledged with `+`.
+    Packet(Vec<u8>),
+    /// A packet with a bad checksum, to be answered with `-`.
+    Corrupt,
+    /// A `-` asking for the last reply again.
+    Nak,
+    /// Ctrl-C.
+    Interrupt,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum State {
+    #[default]
+    Idle,
+    Data,
+    Checksum(u8),
+}
+
+/// Splits the bytes received from GDB into [`Event`]s.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Decoder {
+    state: State,
+    data: Vec<u8>,
+    checksum: u8,
+    received: u8,
+}
+
+impl Decoder {
+    pub(super) fn push(&mut self, byte: u8) -> Option<Event> {
+        match self.state {
+            State::Idle => match byte {
+                b'$' => {
+                    self.state = State::Data;
+                    self.data.clear();
+                    self.checksum = 0;
+                    self.received = 0;
+                    None
+                }
+                b'-' => Some(Event::Nak),
+                0x03 => Some(Event::Interrupt),
+                // NOTE: Acknowledgements are ignored, as replies are sent over TCP.
+                _ => None,
+            },
+            State::Data => {
+                match byte {
+                    b'#' => self.state = State::Checksum(0),
+                    _ => {
+                        self.data.push(byte);
+                        self.checksum = self.checksum.wrapping_add(byte);
+                    }
+                }
+                None
+            }
+            State::Checksum(digits) => {
+                let digit = (byte as char).to_digit(16).unwrap_or(0) as u8;
+                self.received = self.received << 4 | digit;
+                if digits == 0 {
+                    self.state = State::Checksum(1);
+                    return None;
+                }
+                self.state = State::Idle;
+                match self.received == self.checksum {
+                    true => Some(Event::Packet(unescape(&self.data))),
+                    false => Some(Event::Corrupt),
+                }
+            }
+        }
+    }
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => out.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+/// Frames `data` as a packet, escaping the bytes that would end it early.
+pub(super) fn encode(data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![b'$'];
+    for &byte in data {
+        match byte {
+            b'$' | b'#' | b'}' | b'*' => packet.extend([b'}', byte ^ 0x20]),
+            _ => packet.push(byte),
+        }
+    }
+    let checksum = packet[1..]
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    packet.extend(format!("#{checksum:02x}").bytes());
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, Decoder, Event};
+
+    #[test]
+    fn test_framing() {
+        assert_eq!(encode(b"OK"), b"$OK#9a");
+        assert_eq!(encode(b"a#b"), b"$a}\x03b#43");
+
+        let mut decoder = Decoder::default();
+        let events: Vec<_> = b"+$g#67-\x03$m0,4#00$X}]#00"
+            .iter()
+            .filter_map(|&byte| decoder.push(byte))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                Event::Packet(b"g".to_vec()),
+                Event::Nak,
+                Event::Interrupt,
+                Event::Corrupt,
+                Event::Corrupt,
+            ]
+        );
+
+        let packet = encode(b"X0,1:}");
+        let events: Vec<_> = packet
+            .iter()
+            .filter_map(|&byte| decoder.push(byte))
+            .collect();
+        assert_eq!(events, [Event::Packet(b"X0,1:}".to_vec())]);
+    }
+}
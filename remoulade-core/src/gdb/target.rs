@@ -0,0 +1,128 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Target descriptions and GDB's register numbering.
+
+use std::fmt::Write;
+
+use crate::{csr::Csr, debug::DebugHart};
+
+const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// A register as numbered by GDB.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum Register {
+    Pc,
+    /// A register numbered like in [`DebugHart`].
+    Other(u32),
+}
+
+impl Register {
+    pub(super) const PC: usize = 32;
+    const FIRST_FPR: usize = 33;
+    const FIRST_CSR: usize = 65;
+
+    pub(super) fn from_regnum(regnum: usize) -> Option<Self> {
+        let regno = match regnum {
+            0..Self::PC => 0x1000 + regnum,
+            Self::PC => return Some(Self::Pc),
+            Self::FIRST_FPR..Self::FIRST_CSR => 0x1020 + regnum - Self::FIRST_FPR,
+            _ if regnum - Self::FIRST_CSR < 0x1000 => regnum - Self::FIRST_CSR,
+            _ => None?,
+        };
+        Some(Self::Other(regno as u32))
+    }
+
+    /// The width of this register in `hart`, or `None` if it doesn't have it.
+    pub(super) fn bits(self, hart: &dyn DebugHart) -> Option<u32> {
+        match self {
+            Self::Pc => Some(hart.xlen()),
+            Self::Other(regno) => hart.register_bits(regno),
+        }
+    }
+}
+
+/// The target description of `hart`, listing the registers it has.
+pub(super) fn target_xml(hart: &dyn DebugHart) -> String {
+    let xlen = hart.xlen();
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n");
+    let _ = writeln!(
+        xml,
+        "<target version=\"1.0\">\n<architecture>riscv:rv{xlen}</architecture>"
+    );
+
+    xml.push_str("<feature name=\"org.gnu.gdb.riscv.cpu\">\n");
+    for (regnum, name) in ABI_NAMES.iter().enumerate() {
+        if hart.register_bits(0x1000 + regnum as u32).is_some() {
+            let kind = match regnum {
+                1 => "code_ptr",
+                2..=4 => "data_ptr",
+                _ => "int",
+            };
+            push_reg(&mut xml, name, xlen, kind, regnum);
+        }
+    }
+    push_reg(&mut xml, "pc", xlen, "code_ptr", Register::PC);
+    xml.push_str("</feature>\n");
+
+    let fpu = hart.register_bits(0x1020);
+    if let Some(flen) = fpu {
+        xml.push_str("<feature name=\"org.gnu.gdb.riscv.fpu\">\n");
+        let kind = match flen {
+            32 => "ieee_single",
+            64 => "ieee_double",
+            _ => "int",
+        };
+        for i in 0..32 {
+            push_reg(
+                &mut xml,
+                &format!("f{i}"),
+                flen,
+                kind,
+                Register::FIRST_FPR + i,
+            );
+        }
+        // `fflags`, `frm` and `fcsr`
+        for regno in 0x001..=0x003 {
+            if let Some(bits) = hart.register_bits(regno) {
+                let regnum = Register::FIRST_CSR + regno as usize;
+                push_reg(&mut xml, &csr_name(regno), bits, "int", regnum);
+            }
+        }
+        xml.push_str("</feature>\n");
+    }
+
+    xml.push_str("<feature name=\"org.gnu.gdb.riscv.csr\">\n");
+    for regno in 0..0x1000 {
+        let float = (0x001..=0x003).contains(&regno);
+        if let (Some(bits), false) = (hart.register_bits(regno), float) {
+            let regnum = Register::FIRST_CSR + regno as usize;
+            push_reg(&mut xml, &csr_name(regno), bits, "int", regnum);
+        }
+    }
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+fn push_reg(xml: &mut String, name: &str, bits: u32, kind: &str, regnum: usize) {
+    let _ = writeln!(
+        xml,
+        r#"<reg name="{name}" bitsize="{bits}" type="{kind}" regnum="{regnum}"/>"#
+    );
+}
+
+fn csr_name(regno: u32) -> String {
+    let csr = Csr::checked_from_u32(regno).expect("Registers below 0x1000 are CSRs");
+    format!("{csr:?}").to_lowercase()
+}
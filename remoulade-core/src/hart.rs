@@ -7,23 +7,601 @@
 //
 // Copyright (C) 2024 mumblingdrunkard
 
+mod block;
+pub mod counters;
+mod crypto;
+mod debug;
 pub mod exec;
+mod float;
+mod hypervisor;
+#[cfg(feature = "jit")]
+mod jit;
+mod replay;
+mod snapshot;
+#[cfg(test)]
+pub(crate) mod testing;
+mod timing;
+mod trace;
+pub mod translate;
+mod trap;
+mod trigger;
+mod vector;
+
+use std::any::TypeId;
+
+pub use crypto::Entropy;
+pub use trace::{Commit, Effect, ParseCommitError, Trace};
 
 use crate::{
-    freg::{FRegFile, FRegType},
+    bus::Bus,
+    csr::{Csr, CsrFile},
+    ext::Extensions,
+    freg::{FRegFile, FRegType, F128},
+    hart::{
+        block::{BlockCache, Space},
+        counters::Event,
+        debug::Cause,
+        exec::Error,
+        translate::Fault,
+        trigger::Triggers,
+    },
+    inst::Instruction,
+    mmu::{
+        cache::Caches,
+        paging::Mode,
+        pmp::{Access, Pmp},
+    },
     reg::{RegFile, RegType},
+    replay::Journal,
+    vreg::VRegFile,
 };
 
+/// Lets user mode read `seed`.
+const MSECCFG_USEED: u64 = 1 << 8;
+/// Lets supervisor mode read `seed`.
+const MSECCFG_SSEED: u64 = 1 << 9;
+
+/// Privilege mode the hart executes in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
 pub struct Hart<
     const ID: usize,
     I: RegType,
+    const E: bool,
     const M: bool,
     const A: bool,
     F: FRegType,
     const ZIFENCEI: bool,
     const C: bool,
+    X: Extensions = (),
 > {
     pc: I,
+    privilege: Privilege,
     reg: RegFile<I>,
     freg: FRegFile<F>,
+    vreg: VRegFile,
+    csr: CsrFile,
+    /// Address reserved by the last `LR`
+    reservation: Option<u64>,
+    /// Source of the values read from `seed`
+    entropy: Entropy,
+    blocks: BlockCache<I, E, M, A, F, X>,
+    /// `cycle`, `time`, `instret` and the `hpmcounter`s, by their index in `mcounteren`
+    counters: [u64; 32],
+    /// Which `hpmcounter`s have an event selected
+    hpm_active: u32,
+    /// Which counters the instruction being executed wrote, so it doesn't count itself in them
+    counters_written: u32,
+    pmp: Pmp,
+    /// Whether the hart runs a guest, i.e. in VS- or VU-mode
+    virt: bool,
+    fault: Option<Fault>,
+    triggers: Triggers,
+    /// Whether the hart is halted in debug mode
+    debug: bool,
+    /// Set when the breakpoint being raised enters debug mode instead
+    halt: Option<Cause>,
+    /// Whether to halt after the next instruction, as `dcsr.step` asks
+    stepping: bool,
+    /// Whether a single step raised an exception and halts before the next instruction
+    step_halt: bool,
+    trace: Option<Trace>,
+    journal: Option<Journal>,
+    caches: Option<Caches>,
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Default for Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    pub fn new() -> Self {
+        let mut csr = CsrFile::new();
+        csr.write(Csr::Misa, Self::misa());
+        csr.write(Csr::Mhartid, ID as u64);
+        csr.write(Csr::Dcsr, debug::DCSR_RESET);
+        if X::V {
+            csr.write(Csr::Vlenb, X::VLEN as u64 / 8);
+            csr.write(Csr::Vtype, Self::xlen_msb());
+        }
+
+        Self {
+            pc: I::default(),
+            privilege: Privilege::Machine,
+            reg: RegFile::new(),
+            freg: FRegFile::new(),
+            vreg: VRegFile::new(if X::V { X::VLEN } else { 0 }),
+            csr,
+            reservation: None,
+            entropy: Entropy::default(),
+            blocks: BlockCache::new(),
+            counters: [0; 32],
+            hpm_active: 0,
+            counters_written: 0,
+            pmp: Pmp::new(X::PMP_ENTRIES),
+            virt: false,
+            fault: None,
+            triggers: Triggers::new(X::TRIGGERS, I::BITS.min(64)),
+            debug: false,
+            halt: None,
+            stepping: false,
+            step_halt: false,
+            trace: None,
+            journal: None,
+            caches: None,
+        }
+    }
+
+    /// The `misa` value describing this hart's configuration.
+    fn misa() -> u64 {
+        let extension = |letter: u8| 1 << (letter - b'A');
+
+        // NOTE: E and I are mutually exclusive.
+        let mut misa = match E {
+            true => extension(b'E'),
+            false => extension(b'I'),
+        };
+        if M {
+            misa |= extension(b'M');
+        }
+        if A {
+            misa |= extension(b'A');
+        }
+        if TypeId::of::<F>() == TypeId::of::<f32>() {
+            misa |= extension(b'F');
+        }
+        if TypeId::of::<F>() == TypeId::of::<f64>() {
+            misa |= extension(b'F') | extension(b'D');
+        }
+        if TypeId::of::<F>() == TypeId::of::<F128>() {
+            misa |= extension(b'F') | extension(b'D') | extension(b'Q');
+        }
+        if C {
+            misa |= extension(b'C');
+        }
+        if X::V {
+            misa |= extension(b'V');
+        }
+        if X::H {
+            misa |= extension(b'H');
+        }
+        // NOTE: B stands for the combination of Zba, Zbb and Zbs.
+        if X::ZBA && X::ZBB && X::ZBS {
+            misa |= extension(b'B');
+        }
+
+        // NOTE: MXL lives in the two most significant bits, which for RV128 are out of reach of
+        //       the 64-bit CSR file, so it takes the top two bits of that instead.
+        let mxl = match I::BITS {
+            32 => 1 << 30,
+            64 => 2 << 62,
+            _ => 3 << 62,
+        };
+        misa | mxl
+    }
+
+    pub fn pc(&self) -> I {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: I) {
+        self.pc = pc;
+    }
+
+    pub fn privilege(&self) -> Privilege {
+        self.privilege
+    }
+
+    /// Switches privilege mode, e.g. to run user code without an `mret`.
+    pub fn set_privilege(&mut self, privilege: Privilege) {
+        self.privilege = privilege;
+    }
+
+    pub fn reg(&self) -> &RegFile<I> {
+        &self.reg
+    }
+
+    pub fn reg_mut(&mut self) -> &mut RegFile<I> {
+        &mut self.reg
+    }
+
+    pub fn freg(&self) -> &FRegFile<F> {
+        &self.freg
+    }
+
+    pub fn freg_mut(&mut self) -> &mut FRegFile<F> {
+        &mut self.freg
+    }
+
+    pub fn vreg(&self) -> &VRegFile {
+        &self.vreg
+    }
+
+    pub fn vreg_mut(&mut self) -> &mut VRegFile {
+        &mut self.vreg
+    }
+
+    pub fn csr(&self) -> &CsrFile {
+        &self.csr
+    }
+
+    /// Reads a CSR the way an instruction reading it would.
+    ///
+    /// Unlike [`csr`](Self::csr), this includes the live counters and the selected trigger.
+    pub fn read_csr(&self, csr: Csr) -> u64 {
+        match csr {
+            Csr::Sstatus | Csr::Ssie | Csr::Sip => self.read_supervisor(csr),
+            _ => self
+                .read_counter(csr)
+                .or_else(|| self.read_trigger(csr))
+                .unwrap_or_else(|| self.csr.read(csr)),
+        }
+    }
+
+    /// Writes a CSR, applying the side effects an instruction writing it would have.
+    pub fn write_csr(&mut self, csr: Csr, value: u64) {
+        match csr {
+            // NOTE: Writes selecting an unsupported mode have no effect.
+            Csr::Satp | Csr::Vsatp | Csr::Hgatp => {
+                if Mode::decode(I::BITS, value).is_some() {
+                    let value = match csr {
+                        Csr::Hgatp => value & !0b11,
+                        _ => value,
+                    };
+                    self.csr.write(csr, value);
+                    self.blocks.flush();
+                }
+            }
+            Csr::Mstatus | Csr::Vsstatus => self.csr.write(csr, Self::summarize(value)),
+            Csr::Sstatus | Csr::Ssie | Csr::Sip => self.write_supervisor(csr, value),
+            Csr::Hstatus => self.write_hstatus(value),
+            // NOTE: Extensions can't be turned off, which WARL allows by ignoring writes.
+            Csr::Misa => {}
+            Csr::Dcsr => self.write_dcsr(value),
+            Csr::Dpc => self.csr.write(csr, value & if C { !0b1 } else { !0b11 }),
+            // NOTE: `fcsr` mirrors `frm` and `fflags`.
+            Csr::Fcsr => {
+                self.csr.write(Csr::Fcsr, value & 0xff);
+                self.csr.write(Csr::Frm, value >> 5 & 0b111);
+                self.csr.write(Csr::Fflags, value & 0x1f);
+            }
+            Csr::Frm => {
+                let fcsr = self.csr.read(Csr::Fcsr);
+                self.csr.write(Csr::Frm, value & 0b111);
+                self.csr
+                    .write(Csr::Fcsr, fcsr & 0x1f | (value & 0b111) << 5);
+            }
+            Csr::Fflags => {
+                let fcsr = self.csr.read(Csr::Fcsr);
+                self.csr.write(Csr::Fflags, value & 0x1f);
+                self.csr.write(Csr::Fcsr, fcsr & !0x1f | value & 0x1f);
+            }
+            // NOTE: `vcsr` mirrors `vxrm` and `vxsat`.
+            Csr::Vcsr => {
+                self.csr.write(Csr::Vcsr, value & 0b111);
+                self.csr.write(Csr::Vxrm, value >> 1 & 0b11);
+                self.csr.write(Csr::Vxsat, value & 1);
+            }
+            Csr::Vxrm => {
+                let vcsr = self.csr.read(Csr::Vcsr);
+                self.csr.write(Csr::Vxrm, value & 0b11);
+                self.csr.write(Csr::Vcsr, vcsr & 1 | (value & 0b11) << 1);
+            }
+            Csr::Vxsat => {
+                let vcsr = self.csr.read(Csr::Vcsr);
+                self.csr.write(Csr::Vxsat, value & 1);
+                self.csr.write(Csr::Vcsr, vcsr & !1 | value & 1);
+            }
+            Csr::Vstart => self.csr.write(csr, value & (X::VLEN as u64 - 1)),
+            // NOTE: Writes to `seed` are ignored.
+            Csr::Seed => {}
+            // NOTE: There is no `time` to inhibit.
+            Csr::Mcountinhibit => self.csr.write(csr, value & !0b10),
+            Csr::Mseccfg if X::SMEPMP || X::ZKR => {
+                let mut mseccfg = match X::ZKR {
+                    true => value & (MSECCFG_USEED | MSECCFG_SSEED),
+                    false => 0,
+                };
+                if X::SMEPMP {
+                    self.pmp.write_mseccfg(value);
+                    mseccfg |= self.pmp.mseccfg();
+                    self.blocks.flush();
+                }
+                self.csr.write(csr, mseccfg);
+            }
+            // NOTE: Without Smepmp or Zkr, `mseccfg` has no writable bits.
+            Csr::Mseccfg | Csr::Mseccfgh => {}
+            _ if self.write_counter(csr, value) => {}
+            _ if self.write_pmp(csr, value) => {}
+            _ if self.write_trigger(csr, value) => {}
+            _ => self.csr.write(csr, value),
+        }
+    }
+
+    /// Writes a `pmpcfg` or `pmpaddr` CSR, returning `false` if `csr` is neither.
+    fn write_pmp(&mut self, csr: Csr, value: u64) -> bool {
+        if let Some(n) = csr.offset_in(Csr::Pmpcfg0, Csr::Pmpcfg15) {
+            // NOTE: Outside RV32 only the even `pmpcfg`s exist, each holding eight entries.
+            let count = (I::BITS as usize / 8).min(8);
+            if count == 8 && n % 2 == 1 {
+                return true;
+            }
+            for i in 0..count {
+                self.pmp.write_cfg(n * 4 + i, (value >> (8 * i)) as u8);
+            }
+            let value = (0..count).fold(0, |value, i| {
+                value | (self.pmp.cfg(n * 4 + i) as u64) << (8 * i)
+            });
+            self.csr.write(csr, value);
+        } else if let Some(n) = csr.offset_in(Csr::Pmpaddr0, Csr::Pmpaddr63) {
+            let bits = if I::BITS == 32 { 32 } else { 54 };
+            self.pmp.write_addr(n, value, bits);
+            self.csr.write(csr, self.pmp.addr(n));
+        } else {
+            return false;
+        }
+        // Translated code was only checked against the previous entries
+        self.blocks.flush();
+        true
+    }
+
+    /// Checks an access to `seed`, which less privileged modes need `mseccfg` to allow.
+    fn check_seed_csr(&self, csr: Csr) -> Result<(), Error> {
+        if csr != Csr::Seed {
+            return Ok(());
+        }
+        let mseccfg = self.csr.read(Csr::Mseccfg);
+        let allowed = match self.privilege {
+            Privilege::Machine => true,
+            Privilege::Supervisor => mseccfg & MSECCFG_SSEED != 0,
+            Privilege::User => mseccfg & MSECCFG_USEED != 0,
+        };
+        match (allowed, self.virt) {
+            (false, _) => Err(Error::IllegalInstruction),
+            (true, true) => Err(Error::VirtualInstruction),
+            (true, false) => Ok(()),
+        }
+    }
+
+    /// Checks an access to a PMP CSR, if `csr` is one.
+    fn check_pmp_csr(&self, csr: Csr) -> Result<(), Error> {
+        let cfg = csr.offset_in(Csr::Pmpcfg0, Csr::Pmpcfg15);
+        let pmp = cfg.is_some()
+            || csr.offset_in(Csr::Pmpaddr0, Csr::Pmpaddr63).is_some()
+            || matches!(csr, Csr::Mseccfg | Csr::Mseccfgh);
+        let illegal = (pmp && self.privilege != Privilege::Machine)
+            || (I::BITS != 32 && (csr == Csr::Mseccfgh || cfg.is_some_and(|n| n % 2 == 1)));
+        match illegal {
+            true => Err(Error::IllegalInstruction),
+            false => Ok(()),
+        }
+    }
+
+    /// Replaces the source of the entropy read from `seed`.
+    pub fn set_entropy(&mut self, entropy: Entropy) {
+        self.entropy = entropy;
+    }
+
+    /// Drops translated code overlapping `addr..addr + len`.
+    ///
+    /// Stores made by this hart are tracked automatically.
+    /// Anything else that writes to memory containing code (other harts, DMA, a debugger) must
+    /// call this.
+    pub fn invalidate_code(&mut self, addr: u64, len: u64) {
+        self.blocks.invalidate(addr, len);
+    }
+
+    /// Fetches the instruction at `pc` without executing it.
+    pub fn fetch(
+        &mut self,
+        bus: &mut impl Bus,
+        pc: u64,
+    ) -> Result<(Instruction<I, E, M, A, F, X>, u8), Error> {
+        let mut parcel = [0; 4];
+        self.fault = None;
+        let addr = self.translate_addr(bus, pc, 2, Access::Execute)?;
+        bus.read(addr, &mut parcel[..2])
+            .map_err(|_| Error::InstructionAccessFault)?;
+
+        if C && parcel[0] & 0b11 != 0b11 {
+            let raw16 = u16::from_le_bytes([parcel[0], parcel[1]]);
+            return Ok((Instruction::Illegal16 { raw16 }, 2));
+        }
+
+        let upper = pc.checked_add(2).ok_or(Error::InstructionAccessFault)?;
+        let addr = self.translate_addr(bus, upper, 2, Access::Execute)?;
+        bus.read(addr, &mut parcel[2..])
+            .map_err(|_| Error::InstructionAccessFault)?;
+        let raw32 = u32::from_le_bytes(parcel);
+        Ok((Instruction::decode_raw32(raw32), 4))
+    }
+
+    /// Accounts for exception `e` being raised to the embedder, which takes the trap.
+    fn exception(&mut self, e: Error) {
+        self.count_event(Event::Trap);
+        self.match_exception(e);
+    }
+
+    /// Executes a single instruction without going through the block cache.
+    ///
+    /// On error, `pc` is left pointing at the instruction that caused it.
+    /// Does nothing while halted in debug mode.
+    pub fn step(&mut self, bus: &mut impl Bus) -> Result<(), Error> {
+        if self.debug {
+            return Ok(());
+        }
+        let pc = self.pc.as_addr().ok_or(Error::InstructionAccessFault)?;
+        let (inst, len) = self
+            .fetch_timed(bus, pc)
+            .inspect_err(|&e| self.exception(e))?;
+        self.execute_counted(bus, inst, len)
+    }
+
+    /// Executes up to `budget` instructions using translated blocks.
+    ///
+    /// Blocks are translated on first execution and linked to the blocks that follow them so
+    /// steady-state execution skips both decoding and the block lookup.
+    ///
+    /// On error, `pc` is left pointing at the instruction that caused it.
+    /// Returns early when the hart halts in debug mode.
+    pub fn run(&mut self, bus: &mut impl Bus, budget: u64) -> Result<(), Error> {
+        let mut remaining = budget;
+        let mut prev = None;
+
+        'dispatch: while remaining > 0 && !self.debug {
+            let pc = self.pc.as_addr().ok_or(Error::InstructionAccessFault)?;
+            let space = self.fetch_space();
+
+            let index = match prev.and_then(|prev| self.blocks.follow(prev, space, pc)) {
+                Some(index) => index,
+                None => {
+                    let index = match self.blocks.lookup(space, pc) {
+                        Some(index) => index,
+                        None => self
+                            .translate(bus, space, pc)
+                            .inspect_err(|&e| self.exception(e))?,
+                    };
+                    // NOTE: Translating may have flushed the cache, taking `prev` with it.
+                    if self.blocks.take_invalidated() {
+                        prev = None;
+                    }
+                    if let Some(prev) = prev {
+                        self.blocks.link(prev, pc, index);
+                    }
+                    index
+                }
+            };
+
+            self.blocks.take_invalidated();
+            // NOTE: Native code neither reports events, checks triggers, logs what it retires,
+            //       looks up the caches nor halts after single steps, so it only runs while none
+            //       of that is needed.
+            let interpret = self.counting_events()
+                || self.triggers_armed()
+                || self.tracing()
+                || self.caches.is_some()
+                || self.stepping
+                || self.step_halt;
+            let native = match interpret {
+                true => 0,
+                false => self.run_native(index, remaining),
+            };
+            self.retire_native(native as u64);
+            remaining -= native as u64;
+
+            for offset in native..self.blocks.len(index) {
+                if remaining == 0 {
+                    break 'dispatch;
+                }
+                let (inst, len) = self.blocks.get(index, offset);
+                if self.caches.is_some() {
+                    let pc = self.pc.as_u128() as u64;
+                    let fetches = self.blocks.fetches(index, pc, len);
+                    self.account(true, fetches);
+                }
+                self.execute_counted(bus, inst, len)?;
+                remaining -= 1;
+                if self.debug {
+                    break 'dispatch;
+                }
+
+                // The block we're executing may be gone, in which case links from it are too.
+                if self.blocks.take_invalidated() {
+                    prev = None;
+                    continue 'dispatch;
+                }
+            }
+
+            prev = Some(index);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "jit"))]
+    fn run_native(&mut self, _index: usize, _budget: u64) -> usize {
+        0
+    }
+
+    fn translate(&mut self, bus: &mut impl Bus, space: Space, pc: u64) -> Result<usize, Error> {
+        let mut insts = Vec::new();
+        let mut next = pc;
+
+        loop {
+            let (inst, len) = match self.fetch(bus, next) {
+                Ok(fetched) => fetched,
+                // Faults are raised when execution actually reaches the instruction
+                Err(_) if !insts.is_empty() => break,
+                Err(e) => Err(e)?,
+            };
+            insts.push((inst, len));
+            next += len as u64;
+
+            if block::ends_block(&inst)
+                || insts.len() == block::MAX_BLOCK_LEN
+                || block::page(next) != block::page(pc)
+            {
+                break;
+            }
+        }
+
+        // NOTE: An instruction straddling a page boundary makes the block live in both pages.
+        //       The fetches already walked the page tables, so looking them up again isn't
+        //       another miss.
+        let mut pages = vec![self.fetch_address(bus, pc)?];
+        if block::page(next - 1) != block::page(pc) {
+            pages.push(self.fetch_address(bus, next - 1)?);
+        }
+        let pages: Vec<_> = pages.into_iter().map(block::page).collect();
+        Ok(self.blocks.insert(space, pc, &pages, insts))
+    }
 }
@@ -0,0 +1,458 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Cache of pre-decoded straight-line blocks.
+//!
+//! A block starts wherever execution enters it and runs until the first instruction that may
+//! change control flow or the translation environment, the end of the page, or
+//! [`MAX_BLOCK_LEN`] instructions, whichever comes first.
+//! Blocks never share instructions with blocks from other pages, so invalidating a page is enough
+//! to drop every block containing a modified instruction.
+//!
+//! Blocks are found by the virtual address they start at in the [`Space`] they were fetched in,
+//! and invalidated by the physical pages they were fetched from.
+
+use std::collections::HashMap;
+
+#[cfg(feature = "jit")]
+use crate::hart::jit;
+use crate::{
+    ext::Extensions,
+    freg::FRegType,
+    hart::Privilege,
+    inst::{IKind, Instruction},
+    reg::RegType,
+};
+
+pub(super) const MAX_BLOCK_LEN: usize = 64;
+
+/// Upper bound on translated blocks before the whole cache is dropped.
+const MAX_BLOCKS: usize = 1 << 16;
+
+const PAGE_SHIFT: u32 = 12;
+const PAGE_SIZE: u64 = 1 << PAGE_SHIFT;
+
+pub(super) fn page(addr: u64) -> u64 {
+    addr >> PAGE_SHIFT
+}
+
+/// The translation environment of instruction fetches.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(super) struct Space {
+    pub(super) privilege: Privilege,
+    pub(super) virt: bool,
+    /// `satp`, or `vsatp` while virtualized, with the mode and ASID, or 0 without translation
+    pub(super) atp: u64,
+    /// `hgatp` with the mode and VMID while virtualized, or 0 without translation
+    pub(super) gatp: u64,
+}
+
+/// Whether translation must stop after `inst`.
+pub(super) fn ends_block<
+    I: RegType,
+    const E: bool,
+    const M: bool,
+    const A: bool,
+    F: FRegType,
+    X: Extensions,
+>(
+    inst: &Instruction<I, E, M, A, F, X>,
+) -> bool {
+    use Instruction::*;
+    match inst {
+        BType { .. } | Jal { .. } | Ecall | Ebreak | Mret | Sret | Dret => true,
+        SfenceVma { .. } | CsrType { .. } => true,
+        IType { kind, .. } => matches!(kind, IKind::Jalr | IKind::Fencei),
+        Illegal32 { .. } | Illegal16 { .. } | Unused { .. } => true,
+        _ => false,
+    }
+}
+
+/// A decoded instruction together with its length in bytes.
+type Decoded<I, const E: bool, const M: bool, const A: bool, F, X> =
+    (Instruction<I, E, M, A, F, X>, u8);
+
+struct Block<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions> {
+    space: Space,
+    start: u64,
+    /// The physical pages the block was fetched from, the first at the page offset of `start`
+    pages: Box<[u64]>,
+    insts: Box<[Decoded<I, E, M, A, F, X>]>,
+    /// Blocks execution continued in after this one, as `(pc, index)`.
+    ///
+    /// Two entries cover both sides of a conditional branch.
+    links: [Option<(u64, usize)>; 2],
+    valid: bool,
+    #[cfg(feature = "jit")]
+    hits: u32,
+    #[cfg(feature = "jit")]
+    native: Option<jit::Native>,
+}
+
+pub(super) struct BlockCache<
+    I: RegType,
+    const E: bool,
+    const M: bool,
+    const A: bool,
+    F: FRegType,
+    X: Extensions,
+> {
+    // NOTE: Invalidated blocks keep their slot until the next flush so that indices held in links
+    //       stay meaningful.
+    blocks: Vec<Block<I, E, M, A, F, X>>,
+    by_pc: HashMap<(Space, u64), usize>,
+    by_page: HashMap<u64, Vec<usize>>,
+    invalidated: bool,
+}
+
+impl<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions>
+    BlockCache<I, E, M, A, F, X>
+{
+    pub(super) fn new() -> Self {
+        Self {
+            blocks: Vec::new(),
+            by_pc: HashMap::new(),
+            by_page: HashMap::new(),
+            invalidated: false,
+        }
+    }
+
+    pub(super) fn lookup(&self, space: Space, pc: u64) -> Option<usize> {
+        self.by_pc.get(&(space, pc)).copied()
+    }
+
+    /// Follows a link out of block `from`, if one leads to `pc` in `space`.
+    pub(super) fn follow(&self, from: usize, space: Space, pc: u64) -> Option<usize> {
+        self.blocks[from]
+            .links
+            .iter()
+            .flatten()
+            .find(|&&(target, index)| {
+                let block = &self.blocks[index];
+                target == pc && block.valid && block.space == space
+            })
+            .map(|&(_, index)| index)
+    }
+
+    pub(super) fn link(&mut self, from: usize, pc: u64, to: usize) {
+        let links = &mut self.blocks[from].links;
+        match links.iter_mut().find(|link| link.is_none()) {
+            Some(link) => *link = Some((pc, to)),
+            None => links[1] = Some((pc, to)),
+        }
+    }
+
+    pub(super) fn len(&self, index: usize) -> usize {
+        self.blocks[index].insts.len()
+    }
+
+    pub(super) fn get(&self, index: usize, offset: usize) -> Decoded<I, E, M, A, F, X> {
+        self.blocks[index].insts[offset]
+    }
+
+    /// The physical addresses and lengths fetching the `len` bytes at `pc` in block `index`
+    /// reads, as [`Hart::fetch`](crate::hart::Hart::fetch) would without walking page tables.
+    pub(super) fn fetches(&self, index: usize, pc: u64, len: u8) -> Vec<(u64, u64, bool)> {
+        let block = &self.blocks[index];
+        let addr = (block.pages[0] << PAGE_SHIFT) + (pc - (block.start & !(PAGE_SIZE - 1)));
+        let end = pc + len as u64;
+        match block.pages.get(1) {
+            // NOTE: The instruction straddles the two pages.
+            Some(&next) if page(end - 1) != page(pc) => vec![
+                (addr, 2, false),
+                (next << PAGE_SHIFT, len as u64 - 2, false),
+            ],
+            _ => vec![(addr, len as u64, false)],
+        }
+    }
+
+    /// Returns the native translation of block `index`, translating it once it is hot.
+    #[cfg(feature = "jit")]
+    pub(super) fn native(&mut self, index: usize, alignment: u64) -> Option<&jit::Native> {
+        let block = &mut self.blocks[index];
+        if block.hits < jit::HOT_THRESHOLD {
+            block.hits += 1;
+            if block.hits == jit::HOT_THRESHOLD {
+                block.native = jit::compile(block.start, &block.insts, alignment);
+            }
+        }
+        block.native.as_ref()
+    }
+
+    /// Adds the block starting at `start` in `space`, fetched from the physical `pages`,
+    /// returning its index.
+    pub(super) fn insert(
+        &mut self,
+        space: Space,
+        start: u64,
+        pages: &[u64],
+        insts: Vec<Decoded<I, E, M, A, F, X>>,
+    ) -> usize {
+        if self.blocks.len() == MAX_BLOCKS {
+            self.flush();
+        }
+
+        let index = self.blocks.len();
+        self.blocks.push(Block {
+            space,
+            start,
+            pages: pages.into(),
+            insts: insts.into_boxed_slice(),
+            links: [None; 2],
+            valid: true,
+            #[cfg(feature = "jit")]
+            hits: 0,
+            #[cfg(feature = "jit")]
+            native: None,
+        });
+        self.by_pc.insert((space, start), index);
+        for &page in pages {
+            self.by_page.entry(page).or_default().push(index);
+        }
+
+        index
+    }
+
+    /// Drops every block with instructions in the pages overlapping `addr..addr + len`.
+    pub(super) fn invalidate(&mut self, addr: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let last = addr.saturating_add(len - 1);
+        for page in page(addr)..=page(last) {
+            let Some(indices) = self.by_page.remove(&page) else {
+                continue;
+            };
+            for index in indices {
+                let block = &mut self.blocks[index];
+                block.valid = false;
+                // NOTE: The slot stays until the next flush, but its native code is unmapped now.
+                #[cfg(feature = "jit")]
+                {
+                    block.native = None;
+                }
+            }
+            self.by_pc.retain(|_, index| self.blocks[*index].valid);
+            self.invalidated = true;
+        }
+    }
+
+    pub(super) fn flush(&mut self) {
+        self.blocks.clear();
+        self.by_pc.clear();
+        self.by_page.clear();
+        self.invalidated = true;
+    }
+
+    /// Returns whether any block was dropped since the last call.
+    pub(super) fn take_invalidated(&mut self) -> bool {
+        std::mem::take(&mut self.invalidated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_BLOCKS;
+    use crate::{
+        bus::{Bus, Ram},
+        csr::Csr,
+        hart::{
+            exec::Error,
+            testing::{boot, load, write_program, BASE},
+            Hart, Privilege,
+        },
+        reg::{IRd, IRs1},
+    };
+
+    type Rv64 = Hart<0, u64, false, true, true, (), true, false>;
+
+    #[test]
+    fn test_loop_matches_step() {
+        let program = [
+            0x00a00093, // addi x1, x0, 10
+            0x00000113, // addi x2, x0, 0
+            0x00110133, // add x2, x2, x1
+            0xfff08093, // addi x1, x1, -1
+            0xfe009ce3, // bne x1, x0, -8
+            0x00000073, // ecall
+        ];
+
+        let mut stepped: Rv64 = boot();
+        let mut ram = load(&program);
+        while stepped.step(&mut ram).is_ok() {}
+
+        let mut run: Rv64 = boot();
+        let mut ram = load(&program);
+        assert_eq!(run.run(&mut ram, u64::MAX), Err(Error::EcallFromMMode));
+
+        assert_eq!(run.pc(), BASE + 20);
+        assert_eq!(run.pc(), stepped.pc());
+        assert_eq!(run.reg().get_rs1(IRs1::X2), 55);
+        assert_eq!(run.reg().get_rs1(IRs1::X2), stepped.reg().get_rs1(IRs1::X2));
+    }
+
+    #[test]
+    fn test_budget() {
+        let program = [
+            0x00108093, // addi x1, x1, 1
+            0xffdff06f, // jal x0, -4
+        ];
+
+        let mut hart: Rv64 = boot();
+        let mut ram = load(&program);
+        assert_eq!(hart.run(&mut ram, 7), Ok(()));
+        assert_eq!(hart.reg().get_rs1(IRs1::X1), 4);
+        assert_eq!(hart.pc(), BASE + 4);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let program = [
+            0x00000097, // auipc x1, 0
+            0x0140a103, // lw x2, 20(x1)
+            0x0020a623, // sw x2, 12(x1)
+            0x00100193, // addi x3, x0, 1
+            0x00000073, // ecall
+            0x00200193, // addi x3, x0, 2
+        ];
+
+        let mut hart: Rv64 = boot();
+        let mut ram = load(&program);
+        assert_eq!(hart.run(&mut ram, u64::MAX), Err(Error::EcallFromMMode));
+        assert_eq!(hart.reg().get_rs1(IRs1::X3), 2);
+    }
+
+    #[test]
+    fn test_external_write_invalidates() {
+        let program = [
+            0x00100193, // addi x3, x0, 1
+            0x00000073, // ecall
+        ];
+
+        let mut hart: Rv64 = boot();
+        let mut ram = load(&program);
+        assert_eq!(hart.run(&mut ram, u64::MAX), Err(Error::EcallFromMMode));
+        assert_eq!(hart.reg().get_rs1(IRs1::X3), 1);
+
+        let patch = 0x00200193u32; // addi x3, x0, 2
+        ram.write(BASE, &patch.to_le_bytes()).unwrap();
+        hart.invalidate_code(BASE, 4);
+
+        hart.set_pc(BASE);
+        assert_eq!(hart.run(&mut ram, u64::MAX), Err(Error::EcallFromMMode));
+        assert_eq!(hart.reg().get_rs1(IRs1::X3), 2);
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn test_invalidate_unmaps() {
+        let program = [
+            0x00108093, // addi x1, x1, 1
+            0xffdff06f, // jal x0, -4
+        ];
+
+        let mut hart: Rv64 = boot();
+        let mut ram = load(&program);
+        let budget = 2 * 2 * super::jit::HOT_THRESHOLD as u64;
+        assert_eq!(hart.run(&mut ram, budget), Ok(()));
+        let native = |hart: &Rv64| {
+            let blocks = hart.blocks.blocks.iter();
+            blocks.filter(|block| block.native.is_some()).count()
+        };
+        assert_eq!(native(&hart), 1);
+
+        // Invalidated blocks release their native code without waiting for a flush
+        hart.invalidate_code(BASE, 4);
+        assert_eq!(native(&hart), 0);
+    }
+
+    #[test]
+    fn test_flush() {
+        // NOTE: Every jump ends a block, so this translates more blocks than the cache holds,
+        //       flushing it while execution is linking one block to the next.
+        let jumps = MAX_BLOCKS + 16;
+        let mut ram = Ram::new(BASE, 4 * (jumps + 1));
+        for i in 0..jumps {
+            let jal = 0x0040006fu32; // jal x0, 4
+            ram.write(BASE + 4 * i as u64, &jal.to_le_bytes()).unwrap();
+        }
+        let ecall = 0x00000073u32;
+        ram.write(BASE + 4 * jumps as u64, &ecall.to_le_bytes())
+            .unwrap();
+
+        let mut hart: Rv64 = boot();
+        assert_eq!(hart.run(&mut ram, u64::MAX), Err(Error::EcallFromMMode));
+        assert_eq!(hart.pc(), BASE + 4 * jumps as u64);
+
+        // Blocks translated after the flush are linked and followed like any other
+        hart.set_pc(BASE);
+        assert_eq!(hart.run(&mut ram, u64::MAX), Err(Error::EcallFromMMode));
+        assert_eq!(hart.pc(), BASE + 4 * jumps as u64);
+    }
+
+    #[test]
+    fn test_paging() {
+        const FLAGS: u64 = 0xcf; // V, R, W, X, A and D
+        let mut ram = Ram::new(0, 0x40_0000);
+        write_program(
+            &mut ram,
+            0x2000,
+            &[
+                0x00000097, // auipc x1, 0
+                0x0140a103, // lw x2, 20(x1)
+                0x00222623, // sw x2, 12(x4)
+                0x00100193, // addi x3, x0, 1
+                0x00000073, // ecall
+                0x00200193, // addi x3, x0, 2
+            ],
+        );
+        write_program(
+            &mut ram,
+            0x3000,
+            &[
+                0x12000073, // sfence.vma
+                0x00028067, // jalr x0, 0(x5)
+            ],
+        );
+        write_program(
+            &mut ram,
+            0x20_200c,
+            &[
+                0x00300193, // addi x3, x0, 3
+                0x00000073, // ecall
+            ],
+        );
+        // 0x4000_0000 maps to 0x0 through a megapage, and 0x8000_0000 through a gigapage.
+        let (root, table) = (0x1_0000, 0x1_1000);
+        ram.write(root + 8, &(table >> 12 << 10 | 1u64).to_le_bytes())
+            .unwrap();
+        ram.write(root + 16, &FLAGS.to_le_bytes()).unwrap();
+        ram.write(table, &FLAGS.to_le_bytes()).unwrap();
+
+        let mut hart = Rv64::new();
+        hart.write_csr(Csr::Satp, 8 << 60 | root >> 12);
+        hart.set_privilege(Privilege::Supervisor);
+        hart.reg_mut().set_rd(IRd::X4, 0x8000_2000);
+        hart.reg_mut().set_rd(IRd::X5, 0x4000_200c);
+
+        // Stores through another mapping of the same memory drop the block
+        hart.set_pc(0x4000_2000);
+        assert_eq!(hart.run(&mut ram, u64::MAX), Err(Error::EcallFromHSMode));
+        assert_eq!(hart.reg().get_rs1(IRs1::X3), 2);
+
+        // Remapping the page takes an `sfence.vma` to be seen
+        hart.set_pc(0x4000_200c);
+        assert_eq!(hart.run(&mut ram, u64::MAX), Err(Error::EcallFromHSMode));
+        let remapped = 0x20_0000 >> 12 << 10 | FLAGS;
+        ram.write(table, &remapped.to_le_bytes()).unwrap();
+        hart.set_pc(0x8000_3000);
+        assert_eq!(hart.run(&mut ram, u64::MAX), Err(Error::EcallFromHSMode));
+        assert_eq!(hart.reg().get_rs1(IRs1::X3), 3);
+    }
+}
@@ -0,0 +1,481 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Counters and performance monitoring (Zicntr, Zihpm and Sscofpmf).
+//!
+//! `cycle` advances once per retired instruction, just like `instret`, plus the cycles taken by
+//! the caches if [modelled](Hart::set_caches).
+//! `time` belongs to the platform and is set through [`Hart::set_time`].
+//!
+//! The 32 counters are stored by their index in `mcounteren`: 0 is `cycle`, 1 is `time`, 2 is
+//! `instret` and the rest are the `hpmcounter`s.
+
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::FRegType,
+    hart::{exec::Error, Hart, Privilege},
+    inst::{AmoKind, HKind, IKind, Instruction},
+    reg::RegType,
+};
+
+/// Events the `mhpmevent` registers can select, by the value of their lower 16 bits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Taken conditional branches
+    BranchTaken = 1,
+    /// Retired instructions that read memory, including atomics
+    Load = 2,
+    /// Retired instructions that write memory, including atomics
+    Store = 3,
+    // NOTE: Fetches from translated blocks reuse the walk made when translating the block.
+    /// Page table walks, which every translated access takes as there is no TLB
+    TlbMiss = 4,
+    /// Lines missing the L1 caches, if [modelled](Hart::set_caches)
+    CacheMiss = 5,
+    /// Exceptions raised by fetching or executing instructions
+    Trap = 6,
+}
+
+const EVENT: u64 = 0xffff;
+const OF: u64 = 1 << 63;
+const MINH: u64 = 1 << 62;
+const SINH: u64 = 1 << 61;
+const UINH: u64 = 1 << 60;
+
+/// Local counter-overflow interrupt pending
+const MIP_LCOFIP: u64 = 1 << 13;
+
+/// Whether `csr` is one of the read-only user views of the counters.
+fn user_view(csr: Csr) -> bool {
+    csr.offset_in(Csr::Cycle, Csr::Hpmcounter31h).is_some()
+}
+
+/// The index of the counter `csr` views, and whether it's the upper half.
+fn counter(csr: Csr) -> Option<(usize, bool)> {
+    // NOTE: There is no `mtime` CSR, so the machine counters skip index 1.
+    let machine = |index: usize| if index == 0 { 0 } else { index + 1 };
+    csr.offset_in(Csr::Cycle, Csr::Hpmcounter31)
+        .map(|index| (index, false))
+        .or_else(|| {
+            csr.offset_in(Csr::Cycleh, Csr::Hpmcounter31h)
+                .map(|index| (index, true))
+        })
+        .or_else(|| {
+            csr.offset_in(Csr::Mcycle, Csr::Mhpmcounter31)
+                .map(|i| (machine(i), false))
+        })
+        .or_else(|| {
+            csr.offset_in(Csr::Mcycleh, Csr::Mhpmcounter31h)
+                .map(|i| (machine(i), true))
+        })
+}
+
+fn event_selector(index: usize) -> Csr {
+    Csr::checked_from_u32(0x320 + index as u32).unwrap()
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Sets the value read from `time`, which is kept by the platform.
+    pub fn set_time(&mut self, time: u64) {
+        self.counters[1] = time;
+    }
+
+    /// Executes `inst` and accounts for it in the counters.
+    pub(super) fn execute_counted(
+        &mut self,
+        bus: &mut impl Bus,
+        inst: Instruction<I, E, M, A, F, X>,
+        len: u8,
+    ) -> Result<(), Error> {
+        let pc = self.pc;
+        self.fault = None;
+        self.counters_written = 0;
+        let result = match (self.caches.is_some(), self.tracing()) {
+            (true, _) => self.execute_timed(bus, inst, len),
+            (false, true) => self.execute_traced(bus, inst, len),
+            (false, false) => self.execute_debug(bus, inst, len),
+        };
+        match result {
+            Ok(true) => {
+                let next = pc.wrapping_add(I::from_u128(len as u128));
+                let taken = matches!(inst, Instruction::BType { .. }) && self.pc != next;
+                self.retire(&inst, taken);
+            }
+            // Halted in debug mode instead
+            Ok(false) => {}
+            Err(e) => self.exception(e),
+        }
+        result.map(|_| ())
+    }
+
+    /// Whether any `hpmcounter` counts events, which native code doesn't report.
+    pub(super) fn counting_events(&self) -> bool {
+        self.hpm_active != 0
+    }
+
+    /// Accounts for `count` instructions retired by native code.
+    pub(super) fn retire_native(&mut self, count: u64) {
+        let inhibit = self.csr.read(Csr::Mcountinhibit);
+        if inhibit & 0b001 == 0 {
+            self.counters[0] = self.counters[0].wrapping_add(count);
+        }
+        if inhibit & 0b100 == 0 {
+            self.counters[2] = self.counters[2].wrapping_add(count);
+        }
+    }
+
+    /// Accounts for `cycles` taken by the caches.
+    pub(super) fn stall(&mut self, cycles: u64) {
+        if self.csr.read(Csr::Mcountinhibit) & 0b001 == 0 {
+            self.counters[0] = self.counters[0].wrapping_add(cycles);
+        }
+    }
+
+    fn retire(&mut self, inst: &Instruction<I, E, M, A, F, X>, taken: bool) {
+        use Instruction::*;
+
+        // NOTE: A write to `mcycle` or `minstret` takes effect after the instruction retires, so
+        //       the next instruction reads the written value.
+        let (cycle, instret) = (self.counters[0], self.counters[2]);
+        self.retire_native(1);
+        if self.counters_written & 0b001 != 0 {
+            self.counters[0] = cycle;
+        }
+        if self.counters_written & 0b100 != 0 {
+            self.counters[2] = instret;
+        }
+        if !self.counting_events() {
+            return;
+        }
+
+        let (load, store) = match *inst {
+            IType { kind, .. } => {
+                let load = matches!(
+                    kind,
+                    IKind::Lb
+                        | IKind::Lh
+                        | IKind::Lw
+                        | IKind::Lbu
+                        | IKind::Lhu
+                        | IKind::Ld
+                        | IKind::Lwu
+                        | IKind::Lq
+                        | IKind::Ldu
+                );
+                (load, matches!(kind, IKind::CboZero))
+            }
+            SType { .. } | FStoreType { .. } => (false, true),
+            FLoadType { .. } => (true, false),
+            HypervisorType { kind, .. } => match kind {
+                HKind::HsvB | HKind::HsvH | HKind::HsvW | HKind::HsvD => (false, true),
+                HKind::HfenceVvma | HKind::HfenceGvma => (false, false),
+                _ => (true, false),
+            },
+            AmoType { kind, .. } => match kind {
+                AmoKind::Lrw | AmoKind::Lrd => (true, false),
+                AmoKind::Scw | AmoKind::Scd => (false, true),
+                _ => (true, true),
+            },
+            VMemType { mem, .. } => (!mem.store, mem.store),
+            _ => (false, false),
+        };
+        if load {
+            self.count_event(Event::Load);
+        }
+        if store {
+            self.count_event(Event::Store);
+        }
+        if taken {
+            self.count_event(Event::BranchTaken);
+        }
+    }
+
+    /// Counts `event` in every `hpmcounter` selecting it, raising overflow interrupts.
+    pub(crate) fn count_event(&mut self, event: Event) {
+        let inhibit = self.csr.read(Csr::Mcountinhibit);
+        let mode = match self.privilege {
+            Privilege::Machine => MINH,
+            Privilege::Supervisor => SINH,
+            Privilege::User => UINH,
+        };
+
+        let mut active = self.hpm_active;
+        while active != 0 {
+            let index = active.trailing_zeros() as usize;
+            active &= active - 1;
+
+            let csr = event_selector(index);
+            let selector = self.csr.read(csr);
+            if selector & EVENT != event as u64 || selector & mode != 0 || inhibit >> index & 1 != 0
+            {
+                continue;
+            }
+            let (value, overflow) = self.counters[index].overflowing_add(1);
+            self.counters[index] = value;
+            if overflow && selector & OF == 0 {
+                self.csr.write(csr, selector | OF);
+                let mip = self.csr.read(Csr::Mip);
+                self.csr.write(Csr::Mip, mip | MIP_LCOFIP);
+            }
+        }
+    }
+
+    /// Checks an access to a counter CSR, if `csr` is one.
+    pub(super) fn check_counter_csr(&self, csr: Csr, write: bool) -> Result<(), Error> {
+        if let Csr::Scountovf = csr {
+            if write || self.privilege == Privilege::User {
+                Err(Error::IllegalInstruction)?;
+            }
+            return Ok(());
+        }
+        let Some((index, upper)) = counter(csr) else {
+            return Ok(());
+        };
+
+        let enabled = |csr: Csr| self.csr.read(csr) >> index & 1 == 1;
+        let user = self.privilege < Privilege::Supervisor;
+        let illegal = match user_view(csr) {
+            true => write || (self.privilege < Privilege::Machine && !enabled(Csr::Mcounteren)),
+            false => self.privilege != Privilege::Machine,
+        };
+        // NOTE: Guests are held back by `hcounteren` first, so they trap to the hypervisor.
+        let virtual_instruction =
+            self.virt && (!enabled(Csr::Hcounteren) || (user && !enabled(Csr::Scounteren)));
+        let illegal = illegal || (!virtual_instruction && user && !enabled(Csr::Scounteren));
+        // NOTE: The upper halves only exist in RV32.
+        if illegal || (upper && I::BITS != 32) {
+            Err(Error::IllegalInstruction)?;
+        }
+        if virtual_instruction && user_view(csr) {
+            Err(Error::VirtualInstruction)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a counter CSR, or returns `None` if `csr` isn't one.
+    pub(super) fn read_counter(&self, csr: Csr) -> Option<u64> {
+        if let Csr::Scountovf = csr {
+            let overflow = (3..32)
+                .filter(|&index| self.csr.read(event_selector(index)) & OF != 0)
+                .fold(0, |overflow, index| overflow | 1 << index);
+            // NOTE: Supervisor mode only sees the overflow of counters it can read.
+            return Some(match self.privilege {
+                Privilege::Machine => overflow,
+                _ => overflow & self.csr.read(Csr::Mcounteren),
+            });
+        }
+
+        let (index, upper) = counter(csr)?;
+        let mut value = self.counters[index];
+        // NOTE: Guests see `time` shifted by `htimedelta`.
+        if index == 1 && self.virt {
+            let delta = match I::BITS {
+                32 => {
+                    let high = self.csr.read(Csr::Htimedeltah);
+                    self.csr.read(Csr::Htimedelta) & 0xffff_ffff | high << 32
+                }
+                _ => self.csr.read(Csr::Htimedelta),
+            };
+            value = value.wrapping_add(delta);
+        }
+        Some(if upper { value >> 32 } else { value })
+    }
+
+    /// Writes a counter or event selector, returning `false` if `csr` is neither.
+    pub(super) fn write_counter(&mut self, csr: Csr, value: u64) -> bool {
+        if let Some(index) = csr.offset_in(Csr::Mhpmevent3, Csr::Mhpmevent31) {
+            let index = index + 3;
+            self.csr.write(csr, value);
+            match value & EVENT {
+                0 => self.hpm_active &= !(1 << index),
+                _ => self.hpm_active |= 1 << index,
+            }
+            return true;
+        }
+
+        let Some((index, upper)) = counter(csr) else {
+            return false;
+        };
+        let old = self.counters[index];
+        self.counters_written |= 1 << index;
+        self.counters[index] = match (upper, I::BITS) {
+            (true, _) => old & 0xffff_ffff | value << 32,
+            (false, 32) => old & !0xffff_ffff | value & 0xffff_ffff,
+            (false, _) => value,
+        };
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bus::Bus,
+        csr::Csr,
+        hart::{
+            counters::Event,
+            exec::Error,
+            testing::{boot, load, BASE, DATA},
+            Hart, Privilege,
+        },
+        reg::{IRd, IRs1},
+    };
+
+    #[test]
+    fn test_csr_layout() {
+        // The counter views are looked up by their position in `Csr`
+        for i in 0..32 {
+            let (user, upper) = (0xC00 + i, 0xC80 + i);
+            let user = Csr::checked_from_u32(user).unwrap() as usize;
+            let upper = Csr::checked_from_u32(upper).unwrap() as usize;
+            assert_eq!(user, Csr::Cycle as usize + i as usize);
+            assert_eq!(upper, Csr::Cycleh as usize + i as usize);
+        }
+        for i in 3..32 {
+            let machine = Csr::checked_from_u32(0xB00 + i).unwrap() as usize;
+            let event = Csr::checked_from_u32(0x320 + i).unwrap() as usize;
+            assert_eq!(machine, Csr::Mhpmcounter3 as usize + i as usize - 3);
+            assert_eq!(event, Csr::Mhpmevent3 as usize + i as usize - 3);
+        }
+    }
+
+    #[test]
+    fn test_counters() {
+        let program = [
+            0x00052083u32, // lw x1, 0(x10)
+            0x00152223,    // sw x1, 4(x10)
+            0x00000463,    // beq x0, x0, 8
+            0x00000013,    // nop
+            0xc0202173,    // csrr x2, instret
+            0xc03021f3,    // csrr x3, hpmcounter3
+            0xc0402273,    // csrr x4, hpmcounter4
+            0xc8002373,    // csrr x6, cycleh
+            0xda0023f3,    // csrr x7, scountovf
+        ];
+        let mut ram = load(&program);
+
+        let mut hart: Hart<0, u32, false, false, false, (), false, false> = boot();
+        hart.reg_mut().set_rd(IRd::X10, 0x1800);
+        hart.write_csr(Csr::Mhpmevent3, Event::Load as u64);
+        hart.write_csr(Csr::Mhpmevent4, Event::Store as u64);
+        hart.write_csr(Csr::Mhpmevent5, Event::BranchTaken as u64);
+        // Two stores before overflowing
+        hart.write_csr(Csr::Mhpmcounter4, u32::MAX as u64 - 1);
+        hart.write_csr(Csr::Mhpmcounter4h, u32::MAX as u64);
+        for _ in 0..program.len() - 2 {
+            hart.step(&mut ram).unwrap();
+        }
+        hart.write_csr(Csr::Mhpmcounter4, u32::MAX as u64);
+        let (inst, len) = hart.fetch(&mut ram, 0x1004).unwrap();
+        hart.set_pc(0x1004);
+        hart.execute_counted(&mut ram, inst, len).unwrap();
+        hart.set_pc(0x1020);
+        hart.step(&mut ram).unwrap();
+
+        let reg = |x| hart.reg().get_rs1(x);
+        // The skipped `nop` never retires
+        assert_eq!(reg(IRs1::X2), 3);
+        assert_eq!(reg(IRs1::X3), 1);
+        assert_eq!(reg(IRs1::X4), u32::MAX);
+        assert_eq!(reg(IRs1::X6), 0);
+        assert_eq!(reg(IRs1::X7), 1 << 4);
+        assert_eq!(hart.read_csr(Csr::Mhpmcounter5), 1);
+        assert_eq!(hart.read_csr(Csr::Mhpmcounter4), 0);
+        assert_eq!(hart.read_csr(Csr::Mip), 1 << 13);
+        assert_eq!(hart.read_csr(Csr::Minstret), 9);
+
+        // User mode needs the counters enabled at every level above it
+        hart.set_privilege(Privilege::User);
+        hart.set_pc(0x1010);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.write_csr(Csr::Mcounteren, 0b100);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.write_csr(Csr::Scounteren, 0b100);
+        hart.step(&mut ram).unwrap();
+        // Traps are events too
+        hart.write_csr(Csr::Mhpmevent6, Event::Trap as u64);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        assert_eq!(hart.read_csr(Csr::Mhpmcounter6), 1);
+
+        // Inhibited counters stand still
+        hart.write_csr(Csr::Mcountinhibit, 0b101);
+        hart.set_privilege(Privilege::Machine);
+        hart.set_pc(0x100c);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.read_csr(Csr::Minstret), 10);
+    }
+
+    #[test]
+    fn test_counter_writes() {
+        let program = [
+            0xb0209073u32, // csrw minstret, x1
+            0xb0009073,    // csrw mcycle, x1
+            0xb0202173,    // csrr x2, minstret
+            0xb00021f3,    // csrr x3, mcycle
+        ];
+        let mut ram = load(&program);
+
+        let mut hart: Hart<0, u64, false, false, false, (), false, false> = boot();
+        hart.reg_mut().set_rd(IRd::X1, 100);
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+
+        // The writes hold, so neither counter counts the instruction writing it
+        assert_eq!(hart.reg().get_rs1(IRs1::X2), 101);
+        assert_eq!(hart.reg().get_rs1(IRs1::X3), 101);
+        assert_eq!(hart.read_csr(Csr::Minstret), 103);
+        assert_eq!(hart.read_csr(Csr::Mcycle), 102);
+    }
+
+    #[test]
+    fn test_page_walks() {
+        const FLAGS: u64 = 0xcf; // V, R, W, X, A and D
+        let mut ram = load(&[
+            0x00052083, // lw x1, 0(x10)
+            0x00000073, // ecall
+        ]);
+        // 0x0 maps to itself through a gigapage
+        ram.write(0x2000, &FLAGS.to_le_bytes()).unwrap();
+
+        let mut hart: Hart<0, u64, false, false, false, (), false, false> = boot();
+        hart.write_csr(Csr::Mhpmevent3, Event::TlbMiss as u64);
+        hart.reg_mut().set_rd(IRd::X10, DATA);
+
+        // Machine mode translates nothing
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.read_csr(Csr::Mhpmcounter3), 0);
+
+        // Both parcels of the fetch walk the page tables, and so does the load
+        hart.write_csr(Csr::Satp, 8 << 60 | 0x2000 >> 12);
+        hart.set_privilege(Privilege::Supervisor);
+        hart.set_pc(BASE);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.read_csr(Csr::Mhpmcounter3), 3);
+
+        // Translating a block walks them for every instruction it fetches, but not again for the
+        // pages the block lives in
+        hart.set_pc(BASE);
+        hart.run(&mut ram, 1).unwrap();
+        assert_eq!(hart.read_csr(Csr::Mhpmcounter3), 3 + 2 * 2 + 1);
+    }
+}
@@ -0,0 +1,462 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Primitives of the scalar cryptography extensions and the entropy source behind `seed`.
+//!
+//! Every function here is table-driven or plain bit twiddling and takes the same time for every
+//! input, like the instructions they implement are required to.
+
+/// Source of the entropy returned by the `seed` CSR.
+#[derive(Clone, Debug)]
+pub enum Entropy {
+    /// A pseudo-random sequence starting from the given state, for reproducible runs
+    Deterministic(u64),
+    /// Randomness from the operating system, like `/dev/urandom`
+    Host,
+}
+
+impl Default for Entropy {
+    fn default() -> Self {
+        Self::Deterministic(0)
+    }
+}
+
+impl Entropy {
+    /// Returns the next value of `seed`: 16 bits of entropy (ES16), or WAIT if the host has none
+    /// to give.
+    pub(super) fn seed(&mut self) -> u32 {
+        const ES16: u32 = 0b10 << 30;
+        const WAIT: u32 = 0b01 << 30;
+        match self {
+            // NOTE: SplitMix64, which unlike xorshift has no bad seeds.
+            Self::Deterministic(state) => {
+                *state = state.wrapping_add(0x9e3779b97f4a7c15);
+                let mut z = *state;
+                z = (z ^ z >> 30).wrapping_mul(0xbf58476d1ce4e5b9);
+                z = (z ^ z >> 27).wrapping_mul(0x94d049bb133111eb);
+                ES16 | (z ^ z >> 31) as u16 as u32
+            }
+            Self::Host => match getrandom::u32() {
+                Ok(bits) => ES16 | bits & 0xffff,
+                Err(_) => WAIT,
+            },
+        }
+    }
+}
+
+const AES_SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const AES_INV_SBOX: [u8; 256] = [
+    0x52, 0x09, 0x6a, 0xd5, 0x30, 0x36, 0xa5, 0x38, 0xbf, 0x40, 0xa3, 0x9e, 0x81, 0xf3, 0xd7, 0xfb,
+    0x7c, 0xe3, 0x39, 0x82, 0x9b, 0x2f, 0xff, 0x87, 0x34, 0x8e, 0x43, 0x44, 0xc4, 0xde, 0xe9, 0xcb,
+    0x54, 0x7b, 0x94, 0x32, 0xa6, 0xc2, 0x23, 0x3d, 0xee, 0x4c, 0x95, 0x0b, 0x42, 0xfa, 0xc3, 0x4e,
+    0x08, 0x2e, 0xa1, 0x66, 0x28, 0xd9, 0x24, 0xb2, 0x76, 0x5b, 0xa2, 0x49, 0x6d, 0x8b, 0xd1, 0x25,
+    0x72, 0xf8, 0xf6, 0x64, 0x86, 0x68, 0x98, 0x16, 0xd4, 0xa4, 0x5c, 0xcc, 0x5d, 0x65, 0xb6, 0x92,
+    0x6c, 0x70, 0x48, 0x50, 0xfd, 0xed, 0xb9, 0xda, 0x5e, 0x15, 0x46, 0x57, 0xa7, 0x8d, 0x9d, 0x84,
+    0x90, 0xd8, 0xab, 0x00, 0x8c, 0xbc, 0xd3, 0x0a, 0xf7, 0xe4, 0x58, 0x05, 0xb8, 0xb3, 0x45, 0x06,
+    0xd0, 0x2c, 0x1e, 0x8f, 0xca, 0x3f, 0x0f, 0x02, 0xc1, 0xaf, 0xbd, 0x03, 0x01, 0x13, 0x8a, 0x6b,
+    0x3a, 0x91, 0x11, 0x41, 0x4f, 0x67, 0xdc, 0xea, 0x97, 0xf2, 0xcf, 0xce, 0xf0, 0xb4, 0xe6, 0x73,
+    0x96, 0xac, 0x74, 0x22, 0xe7, 0xad, 0x35, 0x85, 0xe2, 0xf9, 0x37, 0xe8, 0x1c, 0x75, 0xdf, 0x6e,
+    0x47, 0xf1, 0x1a, 0x71, 0x1d, 0x29, 0xc5, 0x89, 0x6f, 0xb7, 0x62, 0x0e, 0xaa, 0x18, 0xbe, 0x1b,
+    0xfc, 0x56, 0x3e, 0x4b, 0xc6, 0xd2, 0x79, 0x20, 0x9a, 0xdb, 0xc0, 0xfe, 0x78, 0xcd, 0x5a, 0xf4,
+    0x1f, 0xdd, 0xa8, 0x33, 0x88, 0x07, 0xc7, 0x31, 0xb1, 0x12, 0x10, 0x59, 0x27, 0x80, 0xec, 0x5f,
+    0x60, 0x51, 0x7f, 0xa9, 0x19, 0xb5, 0x4a, 0x0d, 0x2d, 0xe5, 0x7a, 0x9f, 0x93, 0xc9, 0x9c, 0xef,
+    0xa0, 0xe0, 0x3b, 0x4d, 0xae, 0x2a, 0xf5, 0xb0, 0xc8, 0xeb, 0xbb, 0x3c, 0x83, 0x53, 0x99, 0x61,
+    0x17, 0x2b, 0x04, 0x7e, 0xba, 0x77, 0xd6, 0x26, 0xe1, 0x69, 0x14, 0x63, 0x55, 0x21, 0x0c, 0x7d,
+];
+
+const SM4_SBOX: [u8; 256] = [
+    0xd6, 0x90, 0xe9, 0xfe, 0xcc, 0xe1, 0x3d, 0xb7, 0x16, 0xb6, 0x14, 0xc2, 0x28, 0xfb, 0x2c, 0x05,
+    0x2b, 0x67, 0x9a, 0x76, 0x2a, 0xbe, 0x04, 0xc3, 0xaa, 0x44, 0x13, 0x26, 0x49, 0x86, 0x06, 0x99,
+    0x9c, 0x42, 0x50, 0xf4, 0x91, 0xef, 0x98, 0x7a, 0x33, 0x54, 0x0b, 0x43, 0xed, 0xcf, 0xac, 0x62,
+    0xe4, 0xb3, 0x1c, 0xa9, 0xc9, 0x08, 0xe8, 0x95, 0x80, 0xdf, 0x94, 0xfa, 0x75, 0x8f, 0x3f, 0xa6,
+    0x47, 0x07, 0xa7, 0xfc, 0xf3, 0x73, 0x17, 0xba, 0x83, 0x59, 0x3c, 0x19, 0xe6, 0x85, 0x4f, 0xa8,
+    0x68, 0x6b, 0x81, 0xb2, 0x71, 0x64, 0xda, 0x8b, 0xf8, 0xeb, 0x0f, 0x4b, 0x70, 0x56, 0x9d, 0x35,
+    0x1e, 0x24, 0x0e, 0x5e, 0x63, 0x58, 0xd1, 0xa2, 0x25, 0x22, 0x7c, 0x3b, 0x01, 0x21, 0x78, 0x87,
+    0xd4, 0x00, 0x46, 0x57, 0x9f, 0xd3, 0x27, 0x52, 0x4c, 0x36, 0x02, 0xe7, 0xa0, 0xc4, 0xc8, 0x9e,
+    0xea, 0xbf, 0x8a, 0xd2, 0x40, 0xc7, 0x38, 0xb5, 0xa3, 0xf7, 0xf2, 0xce, 0xf9, 0x61, 0x15, 0xa1,
+    0xe0, 0xae, 0x5d, 0xa4, 0x9b, 0x34, 0x1a, 0x55, 0xad, 0x93, 0x32, 0x30, 0xf5, 0x8c, 0xb1, 0xe3,
+    0x1d, 0xf6, 0xe2, 0x2e, 0x82, 0x66, 0xca, 0x60, 0xc0, 0x29, 0x23, 0xab, 0x0d, 0x53, 0x4e, 0x6f,
+    0xd5, 0xdb, 0x37, 0x45, 0xde, 0xfd, 0x8e, 0x2f, 0x03, 0xff, 0x6a, 0x72, 0x6d, 0x6c, 0x5b, 0x51,
+    0x8d, 0x1b, 0xaf, 0x92, 0xbb, 0xdd, 0xbc, 0x7f, 0x11, 0xd9, 0x5c, 0x41, 0x1f, 0x10, 0x5a, 0xd8,
+    0x0a, 0xc1, 0x31, 0x88, 0xa5, 0xcd, 0x7b, 0xbd, 0x2d, 0x74, 0xd0, 0x12, 0xb8, 0xe5, 0xb4, 0xb0,
+    0x89, 0x69, 0x97, 0x4a, 0x0c, 0x96, 0x77, 0x7e, 0x65, 0xb9, 0xf1, 0x09, 0xc5, 0x6e, 0xc6, 0x84,
+    0x18, 0xf0, 0x7d, 0xec, 0x3a, 0xdc, 0x4d, 0x20, 0x79, 0xee, 0x5f, 0x3e, 0xd7, 0xcb, 0x39, 0x48,
+];
+
+/// Round constants of the AES key schedule.
+const AES_RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+/// Multiplies by `x` in AES' GF(2^8).
+fn xtime(a: u8) -> u8 {
+    (a << 1) ^ ((a >> 7) * 0x1b)
+}
+
+/// Multiplies `a` by the 4-bit constant `b` in AES' GF(2^8).
+fn gfmul(a: u8, b: u8) -> u8 {
+    let (a2, a4, a8) = (xtime(a), xtime(xtime(a)), xtime(xtime(xtime(a))));
+    ((b & 1) * a) ^ ((b >> 1 & 1) * a2) ^ ((b >> 2 & 1) * a4) ^ ((b >> 3 & 1) * a8)
+}
+
+/// Applies `sbox` to every byte.
+fn sub_bytes<const N: usize>(bytes: [u8; N], sbox: &[u8; 256]) -> [u8; N] {
+    bytes.map(|b| sbox[b as usize])
+}
+
+fn mix_column(column: u32, coefficients: [u8; 4]) -> u32 {
+    let a = column.to_le_bytes();
+    let row = |r: usize| (0..4).fold(0, |acc, c| acc ^ gfmul(a[c], coefficients[(4 + c - r) % 4]));
+    u32::from_le_bytes([row(0), row(1), row(2), row(3)])
+}
+
+fn mix_column_fwd(column: u32) -> u32 {
+    mix_column(column, [2, 3, 1, 1])
+}
+
+fn mix_column_inv(column: u32) -> u32 {
+    mix_column(column, [14, 11, 13, 9])
+}
+
+fn mix_columns(value: u64, mix: fn(u32) -> u32) -> u64 {
+    mix(value as u32) as u64 | (mix((value >> 32) as u32) as u64) << 32
+}
+
+/// Lower half of the AES state `rs2:rs1` after (inverse) ShiftRows.
+fn shift_rows(rs1: u64, rs2: u64, inverse: bool) -> [u8; 8] {
+    let state = (rs1 as u128 | (rs2 as u128) << 64).to_le_bytes();
+    std::array::from_fn(|i| {
+        let (column, row) = (i / 4, i % 4);
+        let column = match inverse {
+            false => (column + row) % 4,
+            true => (column + 4 - row) % 4,
+        };
+        state[column * 4 + row]
+    })
+}
+
+/// `aes32esi`/`aes32esmi`/`aes32dsi`/`aes32dsmi`.
+pub(super) fn aes32(rs1: u32, rs2: u32, bs: u8, decrypt: bool, mix: bool) -> u32 {
+    let shamt = 8 * bs as u32;
+    let sbox = match decrypt {
+        false => &AES_SBOX,
+        true => &AES_INV_SBOX,
+    };
+    let so = sbox[(rs2 >> shamt) as u8 as usize];
+    let mixed = match (mix, decrypt) {
+        (false, _) => so as u32,
+        (true, false) => u32::from_le_bytes([gfmul(so, 2), so, so, gfmul(so, 3)]),
+        (true, true) => {
+            u32::from_le_bytes([gfmul(so, 14), gfmul(so, 9), gfmul(so, 13), gfmul(so, 11)])
+        }
+    };
+    rs1 ^ mixed.rotate_left(shamt)
+}
+
+/// `aes64es`/`aes64esm`.
+pub(super) fn aes64_encrypt(rs1: u64, rs2: u64, mix: bool) -> u64 {
+    let value = u64::from_le_bytes(sub_bytes(shift_rows(rs1, rs2, false), &AES_SBOX));
+    match mix {
+        false => value,
+        true => mix_columns(value, mix_column_fwd),
+    }
+}
+
+/// `aes64ds`/`aes64dsm`.
+pub(super) fn aes64_decrypt(rs1: u64, rs2: u64, mix: bool) -> u64 {
+    let value = u64::from_le_bytes(sub_bytes(shift_rows(rs1, rs2, true), &AES_INV_SBOX));
+    match mix {
+        false => value,
+        true => mix_columns(value, mix_column_inv),
+    }
+}
+
+/// `aes64im`.
+pub(super) fn aes64im(rs1: u64) -> u64 {
+    mix_columns(rs1, mix_column_inv)
+}
+
+/// `aes64ks1i`.
+pub(super) fn aes64ks1i(rs1: u64, rnum: u8) -> u64 {
+    let temp = (rs1 >> 32) as u32;
+    // NOTE: Round 0xA is used for AES-256 and skips the rotation and round constant.
+    let (temp, rcon) = match AES_RCON.get(rnum as usize) {
+        Some(&rcon) => (temp.rotate_right(8), rcon),
+        None => (temp, 0),
+    };
+    let word = u32::from_le_bytes(sub_bytes(temp.to_le_bytes(), &AES_SBOX)) ^ rcon as u32;
+    word as u64 | (word as u64) << 32
+}
+
+/// `aes64ks2`.
+pub(super) fn aes64ks2(rs1: u64, rs2: u64) -> u64 {
+    let w0 = (rs1 >> 32) as u32 ^ rs2 as u32;
+    let w1 = w0 ^ (rs2 >> 32) as u32;
+    w0 as u64 | (w1 as u64) << 32
+}
+
+pub(super) fn sha256sig0(x: u32) -> u32 {
+    x.rotate_right(7) ^ x.rotate_right(18) ^ x >> 3
+}
+
+pub(super) fn sha256sig1(x: u32) -> u32 {
+    x.rotate_right(17) ^ x.rotate_right(19) ^ x >> 10
+}
+
+pub(super) fn sha256sum0(x: u32) -> u32 {
+    x.rotate_right(2) ^ x.rotate_right(13) ^ x.rotate_right(22)
+}
+
+pub(super) fn sha256sum1(x: u32) -> u32 {
+    x.rotate_right(6) ^ x.rotate_right(11) ^ x.rotate_right(25)
+}
+
+pub(super) fn sha512sig0(x: u64) -> u64 {
+    x.rotate_right(1) ^ x.rotate_right(8) ^ x >> 7
+}
+
+pub(super) fn sha512sig1(x: u64) -> u64 {
+    x.rotate_right(19) ^ x.rotate_right(61) ^ x >> 6
+}
+
+pub(super) fn sha512sum0(x: u64) -> u64 {
+    x.rotate_right(28) ^ x.rotate_right(34) ^ x.rotate_right(39)
+}
+
+pub(super) fn sha512sum1(x: u64) -> u64 {
+    x.rotate_right(14) ^ x.rotate_right(18) ^ x.rotate_right(41)
+}
+
+// NOTE: The RV32 SHA-512 instructions each produce one half of a 64-bit result, with `rs1` and
+//       `rs2` holding the halves of the input.
+
+pub(super) fn sha512sum0r(rs1: u32, rs2: u32) -> u32 {
+    rs1 << 25 ^ rs1 << 30 ^ rs1 >> 28 ^ rs2 >> 7 ^ rs2 >> 2 ^ rs2 << 4
+}
+
+pub(super) fn sha512sum1r(rs1: u32, rs2: u32) -> u32 {
+    rs1 << 23 ^ rs1 >> 14 ^ rs1 >> 18 ^ rs2 >> 9 ^ rs2 << 18 ^ rs2 << 14
+}
+
+pub(super) fn sha512sig0l(rs1: u32, rs2: u32) -> u32 {
+    rs1 >> 1 ^ rs1 >> 7 ^ rs1 >> 8 ^ rs2 << 31 ^ rs2 << 25 ^ rs2 << 24
+}
+
+pub(super) fn sha512sig0h(rs1: u32, rs2: u32) -> u32 {
+    rs1 >> 1 ^ rs1 >> 7 ^ rs1 >> 8 ^ rs2 << 31 ^ rs2 << 24
+}
+
+pub(super) fn sha512sig1l(rs1: u32, rs2: u32) -> u32 {
+    rs1 << 3 ^ rs1 >> 6 ^ rs1 >> 19 ^ rs2 >> 29 ^ rs2 << 26 ^ rs2 << 13
+}
+
+pub(super) fn sha512sig1h(rs1: u32, rs2: u32) -> u32 {
+    rs1 << 3 ^ rs1 >> 6 ^ rs1 >> 19 ^ rs2 >> 29 ^ rs2 << 13
+}
+
+pub(super) fn sm3p0(x: u32) -> u32 {
+    x ^ x.rotate_left(9) ^ x.rotate_left(17)
+}
+
+pub(super) fn sm3p1(x: u32) -> u32 {
+    x ^ x.rotate_left(15) ^ x.rotate_left(23)
+}
+
+/// `sm4ed`/`sm4ks`.
+pub(super) fn sm4(rs1: u32, rs2: u32, bs: u8, key_schedule: bool) -> u32 {
+    let shamt = 8 * bs as u32;
+    let x = SM4_SBOX[(rs2 >> shamt) as u8 as usize] as u32;
+    // NOTE: The linear transforms L and L' of the standard, which distribute over the bytes.
+    let y = match key_schedule {
+        false => x ^ x.rotate_left(2) ^ x.rotate_left(10) ^ x.rotate_left(18) ^ x.rotate_left(24),
+        true => x ^ x.rotate_left(13) ^ x.rotate_left(23),
+    };
+    rs1 ^ y.rotate_left(shamt)
+}
+
+/// Reverses the bits of every byte.
+pub(super) fn brev8(x: u128) -> u128 {
+    u128::from_le_bytes(x.to_le_bytes().map(u8::reverse_bits))
+}
+
+/// Interleaves the lower and upper halves of `x`.
+pub(super) fn zip(x: u32) -> u32 {
+    (0..16).fold(0, |acc, i| {
+        acc | (x >> i & 1) << (2 * i) | (x >> (i + 16) & 1) << (2 * i + 1)
+    })
+}
+
+/// Inverse of [`zip`].
+pub(super) fn unzip(x: u32) -> u32 {
+    (0..16).fold(0, |acc, i| {
+        acc | (x >> (2 * i) & 1) << i | (x >> (2 * i + 1) & 1) << (i + 16)
+    })
+}
+
+/// Replaces every `width`-bit element of `indices` with the element of `table` it indexes, or 0
+/// when out of range.
+pub(super) fn xperm(table: u128, indices: u128, width: u32, bits: u32) -> u128 {
+    let mask = (1 << width) - 1;
+    (0..bits / width).fold(0, |acc, i| {
+        let index = (indices >> (i * width) & mask) as u32;
+        let element = match index < bits / width {
+            true => table >> (index * width) & mask,
+            false => 0,
+        };
+        acc | element << (i * width)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expands an AES-128 key the way software using `aes64ks1i`/`aes64ks2` would.
+    fn expand(key: [u64; 2]) -> Vec<[u64; 2]> {
+        let mut keys = vec![key];
+        for round in 0..10 {
+            let [lo, hi] = keys[round];
+            let lo = aes64ks2(aes64ks1i(hi, round as u8), lo);
+            let hi = aes64ks2(lo, hi);
+            keys.push([lo, hi]);
+        }
+        keys
+    }
+
+    fn bytes(hex: &str) -> [u64; 2] {
+        let value = u128::from_str_radix(hex, 16).unwrap().swap_bytes();
+        [value as u64, (value >> 64) as u64]
+    }
+
+    #[test]
+    fn test_aes64() {
+        // FIPS 197, appendix C.1
+        let keys = expand(bytes("000102030405060708090a0b0c0d0e0f"));
+        let plaintext = bytes("00112233445566778899aabbccddeeff");
+        let ciphertext = bytes("69c4e0d86a7b0430d8cdb78070b4c55a");
+
+        let mut state = [plaintext[0] ^ keys[0][0], plaintext[1] ^ keys[0][1]];
+        for (round, key) in keys.iter().enumerate().skip(1) {
+            let mix = round != 10;
+            let lo = aes64_encrypt(state[0], state[1], mix);
+            let hi = aes64_encrypt(state[1], state[0], mix);
+            state = [lo ^ key[0], hi ^ key[1]];
+        }
+        assert_eq!(state, ciphertext);
+
+        // Equivalent inverse cipher
+        let mut state = [state[0] ^ keys[10][0], state[1] ^ keys[10][1]];
+        for round in (0..10).rev() {
+            let mix = round != 0;
+            let lo = aes64_decrypt(state[0], state[1], mix);
+            let hi = aes64_decrypt(state[1], state[0], mix);
+            let key = match mix {
+                true => keys[round].map(aes64im),
+                false => keys[round],
+            };
+            state = [lo ^ key[0], hi ^ key[1]];
+        }
+        assert_eq!(state, plaintext);
+    }
+
+    #[test]
+    fn test_aes32() {
+        // The first column of the first round of FIPS 197, appendix C.1
+        let state = [0x00102030u32, 0x40506070, 0x8090a0b0, 0xc0d0e0f0].map(u32::swap_bytes);
+        let column = [0, 1, 2, 3]
+            .into_iter()
+            .fold(0, |acc, bs| aes32(acc, state[bs as usize], bs, false, true));
+        assert_eq!(column, 0x5f726415u32.swap_bytes());
+    }
+
+    #[test]
+    fn test_sha512_halves() {
+        let x = 0x0123456789abcdefu64;
+        let (lo, hi) = (x as u32, (x >> 32) as u32);
+        let halves = |value: u64| (value as u32, (value >> 32) as u32);
+        assert_eq!(
+            halves(sha512sum0(x)),
+            (sha512sum0r(lo, hi), sha512sum0r(hi, lo))
+        );
+        assert_eq!(
+            halves(sha512sum1(x)),
+            (sha512sum1r(lo, hi), sha512sum1r(hi, lo))
+        );
+        assert_eq!(
+            halves(sha512sig0(x)),
+            (sha512sig0l(lo, hi), sha512sig0h(hi, lo))
+        );
+        assert_eq!(
+            halves(sha512sig1(x)),
+            (sha512sig1l(lo, hi), sha512sig1h(hi, lo))
+        );
+    }
+
+    #[test]
+    fn test_sm4() {
+        // GB/T 32907-2016, appendix A.1
+        const FK: [u32; 4] = [0xa3b1bac6, 0x56aa3350, 0x677d9197, 0xb27022dc];
+        let key = [0x01234567u32, 0x89abcdef, 0xfedcba98, 0x76543210];
+
+        let round =
+            |x: u32, key_schedule: bool| (0..4).fold(0, |acc, bs| sm4(acc, x, bs, key_schedule));
+
+        let mut k: Vec<u32> = key.iter().zip(FK).map(|(k, fk)| k ^ fk).collect();
+        for i in 0..32 {
+            let ck = u32::from_be_bytes(std::array::from_fn(|j| ((4 * i + j) * 7) as u8));
+            let x = k[i + 1] ^ k[i + 2] ^ k[i + 3] ^ ck;
+            k.push(k[i] ^ round(x, true));
+        }
+
+        let mut x = key.to_vec();
+        for i in 0..32 {
+            let input = x[i + 1] ^ x[i + 2] ^ x[i + 3] ^ k[i + 4];
+            x.push(x[i] ^ round(input, false));
+        }
+        assert_eq!(
+            [x[35], x[34], x[33], x[32]],
+            [0x681edf34, 0xd206965e, 0x86b3e94f, 0x536e4246]
+        );
+    }
+
+    #[test]
+    fn test_zip() {
+        assert_eq!(zip(0xffff0000), 0xaaaaaaaa);
+        assert_eq!(unzip(zip(0x12345678)), 0x12345678);
+    }
+
+    #[test]
+    fn test_entropy() {
+        let mut first = Entropy::Deterministic(1);
+        let mut second = Entropy::Deterministic(1);
+        assert_eq!(first.seed(), second.seed());
+
+        // NOTE: Four draws of 16 bits from the host all being equal would be a 1 in 2^48 fluke.
+        let mut host = Entropy::Host;
+        let draws: Vec<_> = (0..4).map(|_| host.seed()).collect();
+        assert!(draws.iter().all(|seed| seed >> 30 == 0b10));
+        assert!(draws.iter().any(|&seed| seed != draws[0]));
+    }
+}
@@ -0,0 +1,337 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Debug mode (Sdext).
+//!
+//! The hart halts when a trigger or `ebreak` asks for it, after a single step, or when a
+//! debugger calls [`Hart::halt`].
+//! While halted, [`Hart::step`] and [`Hart::run`] do nothing and the hart only executes what a
+//! debugger hands it through [`Hart::execute_progbuf`].
+
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::FRegType,
+    hart::{exec::Error, Hart, Privilege},
+    inst::Instruction,
+    reg::RegType,
+};
+
+/// Why the hart entered debug mode, as reported in `dcsr.cause`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum Cause {
+    Ebreak = 1,
+    Trigger = 2,
+    HaltRequest = 3,
+    Step = 4,
+}
+
+const EBREAKVS: u64 = 1 << 17;
+const EBREAKVU: u64 = 1 << 16;
+const EBREAKM: u64 = 1 << 15;
+const EBREAKS: u64 = 1 << 13;
+const EBREAKU: u64 = 1 << 12;
+const STEPIE: u64 = 1 << 11;
+const STOPCOUNT: u64 = 1 << 10;
+const CAUSE: u64 = 0b111 << 6;
+const V: u64 = 1 << 5;
+const MPRVEN: u64 = 1 << 4;
+const STEP: u64 = 1 << 2;
+const PRV: u64 = 0b11;
+
+/// `dcsr` out of reset: external debug version 4 (1.0), and counters that stop in debug mode
+// NOTE: Instructions executed in debug mode are never counted, so `stopcount` is fixed.
+pub(super) const DCSR_RESET: u64 = 4 << 28 | STOPCOUNT | Privilege::Machine as u64;
+
+const MSTATUS_MPRV: u64 = 1 << 17;
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Whether the hart is halted in debug mode.
+    pub fn debug_mode(&self) -> bool {
+        self.debug
+    }
+
+    /// Halts in debug mode, as a debugger asks to.
+    ///
+    /// The hart is always between instructions when this can be called, so it halts right away.
+    pub fn halt(&mut self) {
+        if !self.debug {
+            self.enter_debug(self.pc.as_u64(), Cause::HaltRequest);
+        }
+    }
+
+    /// Resumes from debug mode like `dret` would, single-stepping if `dcsr.step` is set.
+    pub fn resume(&mut self) {
+        if self.debug {
+            self.pc = self.leave_debug();
+        }
+    }
+
+    /// Executes the instructions in `progbuf` in debug mode, stopping at the first `ebreak`.
+    ///
+    /// Instructions execute one after the other, so control flow within `progbuf` isn't
+    /// supported.
+    /// Does nothing outside debug mode.
+    pub fn execute_progbuf(&mut self, bus: &mut impl Bus, progbuf: &[u32]) -> Result<(), Error> {
+        if !self.debug {
+            return Ok(());
+        }
+
+        let pc = self.pc;
+        let mut result = Ok(());
+        for &raw32 in progbuf {
+            result = match Instruction::decode_raw32(raw32) {
+                Instruction::Ebreak => break,
+                // NOTE: Debuggers resume through the Debug Module instead.
+                Instruction::Mret | Instruction::Sret | Instruction::Dret => {
+                    Err(Error::IllegalInstruction)
+                }
+                inst => self.execute(bus, inst, 4),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        self.pc = pc;
+        result
+    }
+
+    /// Halts before the instruction at `pc`, recording `cause` in `dcsr`.
+    pub(super) fn enter_debug(&mut self, pc: u64, cause: Cause) {
+        let dcsr = self.csr.read(Csr::Dcsr) & !(CAUSE | V | PRV);
+        let v = if self.virt { V } else { 0 };
+        let dcsr = dcsr | (cause as u64) << 6 | v | self.privilege as u64;
+        self.csr.write(Csr::Dcsr, dcsr);
+        self.csr.write(Csr::Dpc, pc);
+        self.virt = false;
+        self.set_privilege(Privilege::Machine);
+        self.debug = true;
+        self.stepping = false;
+        self.step_halt = false;
+    }
+
+    /// Leaves debug mode for the mode in `dcsr`, returning `dpc`.
+    fn leave_debug(&mut self) -> I {
+        let dcsr = self.csr.read(Csr::Dcsr);
+        let privilege = match dcsr & PRV {
+            0b00 => Privilege::User,
+            0b01 => Privilege::Supervisor,
+            _ => Privilege::Machine,
+        };
+        // NOTE: Like `mret`, leaving for a less privileged mode clears `mstatus.MPRV`.
+        if privilege != Privilege::Machine {
+            let mstatus = self.csr.read(Csr::Mstatus);
+            self.csr.write(Csr::Mstatus, mstatus & !MSTATUS_MPRV);
+        }
+        self.debug = false;
+        self.set_privilege(privilege);
+        self.virt = X::H && dcsr & V != 0 && privilege != Privilege::Machine;
+        self.stepping = dcsr & STEP != 0;
+        I::from_u128(self.csr.read(Csr::Dpc) as u128)
+    }
+
+    /// Executes `dret`, returning the address to continue at.
+    pub(super) fn dret(&mut self) -> Result<I, Error> {
+        match self.debug {
+            true => Ok(self.leave_debug()),
+            false => Err(Error::IllegalInstruction),
+        }
+    }
+
+    /// Executes `ebreak`, which enters debug mode instead of raising a breakpoint exception if
+    /// `dcsr` says so for the current mode.
+    pub(super) fn ebreak(&mut self) -> Error {
+        let bit = match (self.privilege, self.virt) {
+            (Privilege::Machine, _) => EBREAKM,
+            (Privilege::Supervisor, false) => EBREAKS,
+            (Privilege::User, false) => EBREAKU,
+            (Privilege::Supervisor, true) => EBREAKVS,
+            (Privilege::User, true) => EBREAKVU,
+        };
+        if !self.debug && self.csr.read(Csr::Dcsr) & bit != 0 {
+            self.halt = Some(Cause::Ebreak);
+        }
+        Error::Breakpoint
+    }
+
+    pub(super) fn write_dcsr(&mut self, value: u64) {
+        let virt = if X::H { EBREAKVS | EBREAKVU | V } else { 0 };
+        let writable = EBREAKM | EBREAKS | EBREAKU | STEPIE | MPRVEN | STEP | virt;
+        let dcsr = self.csr.read(Csr::Dcsr);
+        // NOTE: There is no privilege mode 2.
+        let prv = match value & PRV {
+            0b10 => dcsr & PRV,
+            prv => prv,
+        };
+        let dcsr = dcsr & !(writable | PRV) | value & writable | prv;
+        self.csr.write(Csr::Dcsr, dcsr);
+    }
+
+    /// Checks an access to a debug mode CSR, if `csr` is one.
+    pub(super) fn check_debug_csr(&self, csr: Csr) -> Result<(), Error> {
+        let debug = matches!(csr, Csr::Dcsr | Csr::Dpc | Csr::Dscratch0 | Csr::Dscratch1);
+        match debug && !self.debug {
+            true => Err(Error::IllegalInstruction),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether `mstatus.MPRV` applies, which in debug mode depends on `dcsr.mprven`.
+    pub(super) fn mprv_enabled(&self) -> bool {
+        !self.debug || self.csr.read(Csr::Dcsr) & MPRVEN != 0
+    }
+
+    /// Executes `inst` like [`execute_triggered`](Self::execute_triggered), halting in debug mode
+    /// where asked to.
+    ///
+    /// Returns whether the instruction retired, rather than the hart halting before it.
+    pub(super) fn execute_debug(
+        &mut self,
+        bus: &mut impl Bus,
+        inst: Instruction<I, E, M, A, F, X>,
+        len: u8,
+    ) -> Result<bool, Error> {
+        let pc = self.pc.as_u64();
+        // A single step that raised an exception halts at the handler
+        if std::mem::take(&mut self.step_halt) {
+            self.enter_debug(pc, Cause::Step);
+            return Ok(false);
+        }
+
+        let stepping = std::mem::take(&mut self.stepping);
+        let result = self.execute_triggered(bus, inst, len);
+        // The breakpoint halts instead
+        if let Some(cause) = self.halt.take() {
+            self.enter_debug(pc, cause);
+            return Ok(false);
+        }
+        match result {
+            Ok(()) if stepping => self.enter_debug(self.pc.as_u64(), Cause::Step),
+            Ok(()) => {}
+            Err(_) => self.step_halt = stepping,
+        }
+        result.map(|()| true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bus::Ram,
+        csr::Csr,
+        hart::{
+            exec::Error,
+            testing::{boot, load},
+            Hart, Privilege,
+        },
+        reg::{IRd, IRs1},
+    };
+
+    type TestHart = Hart<0, u64, false, false, false, (), false, false>;
+
+    fn hart(program: &[u32]) -> (TestHart, Ram) {
+        (boot(), load(program))
+    }
+
+    #[test]
+    fn test_halt_and_step() {
+        let (mut hart, mut ram) = hart(&[
+            0x00108093, // addi x1, x1, 1
+            0x00108093, // addi x1, x1, 1
+            0x00000073, // ecall
+            0x00108093, // addi x1, x1, 1
+        ]);
+        hart.set_privilege(Privilege::User);
+        hart.step(&mut ram).unwrap();
+        hart.halt();
+        assert!(hart.debug_mode());
+        assert_eq!(hart.privilege(), Privilege::Machine);
+        assert_eq!(hart.read_csr(Csr::Dpc), 0x1004);
+        assert_eq!(hart.read_csr(Csr::Dcsr), 4 << 28 | 1 << 10 | 3 << 6);
+        // Halted harts don't run
+        hart.run(&mut ram, 8).unwrap();
+        assert_eq!(hart.reg().get_rs1(IRs1::X1), 1);
+
+        // Single steps halt after one instruction
+        hart.write_csr(Csr::Dcsr, 1 << 2);
+        hart.resume();
+        assert_eq!(hart.privilege(), Privilege::User);
+        hart.run(&mut ram, 8).unwrap();
+        assert!(hart.debug_mode());
+        assert_eq!(hart.read_csr(Csr::Dpc), 0x1008);
+        assert_eq!(hart.read_csr(Csr::Dcsr) >> 6 & 0b111, 4);
+        assert_eq!(hart.reg().get_rs1(IRs1::X1), 2);
+
+        // ...or before the handler of the exception the instruction raised
+        hart.resume();
+        assert_eq!(hart.step(&mut ram), Err(Error::EcallFromUOrVUMode));
+        hart.set_pc(0x100c);
+        hart.step(&mut ram).unwrap();
+        assert!(hart.debug_mode());
+        assert_eq!(hart.read_csr(Csr::Dpc), 0x100c);
+        assert_eq!(hart.reg().get_rs1(IRs1::X1), 2);
+    }
+
+    #[test]
+    fn test_ebreak_and_progbuf() {
+        let (mut hart, mut ram) = hart(&[
+            0x7b002573, // csrr x10, dcsr
+            0x00100073, // ebreak
+        ]);
+        // Debug CSRs are only reachable from debug mode
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.set_pc(0x1004);
+        assert_eq!(hart.step(&mut ram), Err(Error::Breakpoint));
+
+        hart.halt();
+        hart.write_csr(Csr::Dcsr, 1 << 15 | 3);
+        hart.resume();
+        hart.step(&mut ram).unwrap();
+        assert!(hart.debug_mode());
+        assert_eq!(hart.read_csr(Csr::Dcsr) >> 6 & 0b111, 1);
+
+        // The program buffer runs up to the first `ebreak`, leaving `pc` alone
+        hart.reg_mut().set_rd(IRd::X11, 0x8000);
+        let progbuf = [
+            0x7b002573, // csrr x10, dcsr
+            0x00a5b023, // sd x10, 0(x11)
+            0x00100073, // ebreak
+            0x00000513, // li x10, 0
+        ];
+        hart.execute_progbuf(&mut ram, &progbuf).unwrap();
+        assert_eq!(hart.pc(), 0x1004);
+        let dcsr = hart.read_csr(Csr::Dcsr);
+        assert_eq!(&ram.as_slice()[0x8000..0x8008], &dcsr.to_le_bytes());
+        assert_eq!(
+            hart.execute_progbuf(&mut ram, &[0x0005b503, 0x00000073]),
+            Err(Error::EcallFromMMode)
+        );
+
+        // `dret` leaves for the mode and address in `dcsr` and `dpc`
+        hart.write_csr(Csr::Dcsr, 0);
+        hart.write_csr(Csr::Dpc, 0x1000);
+        hart.execute_progbuf(&mut ram, &[0x7b200073]).unwrap_err();
+        hart.resume();
+        assert!(!hart.debug_mode());
+        assert_eq!(hart.privilege(), Privilege::User);
+        assert_eq!(hart.pc(), 0x1000);
+    }
+}
@@ -7,6 +7,18 @@
 //
 // Copyright (C) 2024 mumblingdrunkard
 
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::FRegType,
+    hart::{crypto, Hart, Privilege},
+    inst::{AmoKind, BKind, CsrKind, IKind, Instruction, RKind, SKind, UKind},
+    mmu::pmp::Access,
+    reg::{IRd, IRs1, RegType},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Error {
     InstructionAddressMisaligned = 0,
     InstructionAccessFault = 1,
@@ -35,3 +47,1057 @@ pub enum Error {
 
     StoreOrAmoGuestPageFault = 23,
 }
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Executes `inst`, which is `len` bytes long and located at `pc`.
+    ///
+    /// Architectural state is only modified when execution succeeds.
+    pub(crate) fn execute(
+        &mut self,
+        bus: &mut impl Bus,
+        inst: Instruction<I, E, M, A, F, X>,
+        len: u8,
+    ) -> Result<(), Error> {
+        use Instruction::*;
+
+        let pc = self.pc;
+        let mut next = pc.wrapping_add(I::from_u128(len as u128));
+
+        match inst {
+            UType { rd, u, kind } => {
+                let u = I::from_i128(u.i32() as i128);
+                let value = match kind {
+                    UKind::Lui => u,
+                    UKind::Auipc => pc.wrapping_add(u),
+                };
+                self.reg.set_rd(rd, value);
+            }
+
+            Jal { rd, j } => {
+                let target = pc.wrapping_add(I::from_i128(j.i32() as i128));
+                self.check_target(target)?;
+                self.reg.set_rd(rd, next);
+                next = target;
+            }
+
+            IType { rd, rs1, i, kind } => {
+                let src = self.reg.get_rs1(rs1);
+                let imm = I::from_i128(i.i32() as i128);
+                let shamt = i.i32() as u32 & (I::BITS - 1);
+
+                let value = match kind {
+                    IKind::Jalr => {
+                        let target = I::from_u128(src.wrapping_add(imm).as_u128() & !1);
+                        self.check_target(target)?;
+                        self.reg.set_rd(rd, next);
+                        next = target;
+                        None
+                    }
+
+                    IKind::Lb => Some(self.load::<1>(bus, src, imm, true)?),
+                    IKind::Lh => Some(self.load::<2>(bus, src, imm, true)?),
+                    IKind::Lw => Some(self.load::<4>(bus, src, imm, true)?),
+                    IKind::Lbu => Some(self.load::<1>(bus, src, imm, false)?),
+                    IKind::Lhu => Some(self.load::<2>(bus, src, imm, false)?),
+
+                    IKind::Addi => Some(src.wrapping_add(imm)),
+                    IKind::Slti => Some(I::from_u128(src.lt(imm) as u128)),
+                    IKind::Sltiu => Some(I::from_u128((src < imm) as u128)),
+                    IKind::Xori => Some(I::from_u128(src.as_u128() ^ imm.as_u128())),
+                    IKind::Ori => Some(I::from_u128(src.as_u128() | imm.as_u128())),
+                    IKind::Andi => Some(I::from_u128(src.as_u128() & imm.as_u128())),
+
+                    IKind::Slli => Some(src.shl(shamt)),
+                    IKind::Srli => Some(src.shr(shamt)),
+                    IKind::Srai => Some(src.sra(shamt)),
+
+                    IKind::Fencei => {
+                        if !ZIFENCEI {
+                            Err(Error::IllegalInstruction)?;
+                        }
+                        self.blocks.flush();
+                        None
+                    }
+
+                    IKind::Ld => Some(self.load::<8>(bus, src, imm, true)?),
+                    IKind::Lwu => Some(self.load::<4>(bus, src, imm, false)?),
+
+                    IKind::Addiw => Some(word(src, imm, u32::wrapping_add)),
+                    IKind::Slliw => Some(word(src, imm, |src, _| src.shl(shamt & 31))),
+                    IKind::Srliw => Some(word(src, imm, |src, _| src.shr(shamt & 31))),
+                    IKind::Sraiw => Some(word(src, imm, |src, _| src.sra(shamt & 31))),
+
+                    IKind::Lq => Some(self.load::<16>(bus, src, imm, true)?),
+                    IKind::Ldu => Some(self.load::<8>(bus, src, imm, false)?),
+
+                    IKind::Addid => Some(double(src, imm, u64::wrapping_add)),
+                    IKind::Sllid => Some(double(src, imm, |src, _| src.shl(shamt & 63))),
+                    IKind::Srlid => Some(double(src, imm, |src, _| src.shr(shamt & 63))),
+                    IKind::Sraid => Some(double(src, imm, |src, _| src.sra(shamt & 63))),
+
+                    IKind::SlliUw => Some(uw(src).shl(shamt)),
+
+                    IKind::Clz => Some(I::from_u128(src.clz() as u128)),
+                    IKind::Ctz => Some(I::from_u128(src.ctz() as u128)),
+                    IKind::Cpop => Some(I::from_u128(src.cpop() as u128)),
+                    IKind::Clzw => Some(word(src, imm, |src, _| src.clz())),
+                    IKind::Ctzw => Some(word(src, imm, |src, _| src.ctz())),
+                    IKind::Cpopw => Some(word(src, imm, |src, _| src.cpop())),
+                    IKind::SextB => Some(I::from_i128(src.as_u128() as i8 as i128)),
+                    IKind::SextH => Some(I::from_i128(src.as_u128() as i16 as i128)),
+                    IKind::OrcB => Some(src.orc_b()),
+                    IKind::Rev8 => Some(src.rev8()),
+                    IKind::Rori => Some(src.ror(shamt)),
+                    IKind::Roriw => Some(word(src, imm, |src, _| src.ror(shamt & 31))),
+
+                    IKind::Bclri => Some(I::from_u128(src.as_u128() & !(1 << shamt))),
+                    IKind::Bexti => Some(I::from_u128(src.as_u128() >> shamt & 1)),
+                    IKind::Binvi => Some(I::from_u128(src.as_u128() ^ 1 << shamt)),
+                    IKind::Bseti => Some(I::from_u128(src.as_u128() | 1 << shamt)),
+
+                    IKind::Brev8 => Some(I::from_u128(crypto::brev8(src.as_u128()))),
+                    IKind::Zip => Some(word(src, imm, |src, _| crypto::zip(src))),
+                    IKind::Unzip => Some(word(src, imm, |src, _| crypto::unzip(src))),
+
+                    IKind::Aes64im => Some(I::from_u128(crypto::aes64im(src.as_u64()) as u128)),
+                    IKind::Aes64ks1i => {
+                        let rnum = i.i32() as u8 & 0xf;
+                        Some(I::from_u128(crypto::aes64ks1i(src.as_u64(), rnum) as u128))
+                    }
+
+                    IKind::Sha256sig0 => Some(word(src, imm, |src, _| crypto::sha256sig0(src))),
+                    IKind::Sha256sig1 => Some(word(src, imm, |src, _| crypto::sha256sig1(src))),
+                    IKind::Sha256sum0 => Some(word(src, imm, |src, _| crypto::sha256sum0(src))),
+                    IKind::Sha256sum1 => Some(word(src, imm, |src, _| crypto::sha256sum1(src))),
+                    IKind::Sha512sig0 => {
+                        Some(I::from_u128(crypto::sha512sig0(src.as_u64()) as u128))
+                    }
+                    IKind::Sha512sig1 => {
+                        Some(I::from_u128(crypto::sha512sig1(src.as_u64()) as u128))
+                    }
+                    IKind::Sha512sum0 => {
+                        Some(I::from_u128(crypto::sha512sum0(src.as_u64()) as u128))
+                    }
+                    IKind::Sha512sum1 => {
+                        Some(I::from_u128(crypto::sha512sum1(src.as_u64()) as u128))
+                    }
+
+                    IKind::Sm3p0 => Some(word(src, imm, |src, _| crypto::sm3p0(src))),
+                    IKind::Sm3p1 => Some(word(src, imm, |src, _| crypto::sm3p1(src))),
+
+                    IKind::CboClean | IKind::CboFlush | IKind::CboInval | IKind::CboZero => {
+                        self.cbo(bus, src, kind)?;
+                        None
+                    }
+                };
+
+                if let Some(value) = value {
+                    self.reg.set_rd(rd, value);
+                }
+            }
+
+            BType { rs1, rs2, b, kind } => {
+                let lhs = self.reg.get_rs1(rs1);
+                let rhs = self.reg.get_rs2(rs2);
+                let taken = match kind {
+                    BKind::Beq => lhs == rhs,
+                    BKind::Bne => lhs != rhs,
+                    BKind::Blt => lhs.lt(rhs),
+                    BKind::Bge => !lhs.lt(rhs),
+                    BKind::Bltu => lhs < rhs,
+                    BKind::Bgeu => lhs >= rhs,
+                };
+                if taken {
+                    let target = pc.wrapping_add(I::from_i128(b.i32() as i128));
+                    self.check_target(target)?;
+                    next = target;
+                }
+            }
+
+            SType { rs1, rs2, s, kind } => {
+                let base = self.reg.get_rs1(rs1);
+                let offset = I::from_i128(s.i32() as i128);
+                let value = self.reg.get_rs2(rs2);
+                match kind {
+                    SKind::Sb => self.store::<1>(bus, base, offset, value)?,
+                    SKind::Sh => self.store::<2>(bus, base, offset, value)?,
+                    SKind::Sw => self.store::<4>(bus, base, offset, value)?,
+                    SKind::Sd => self.store::<8>(bus, base, offset, value)?,
+                    SKind::Sq => self.store::<16>(bus, base, offset, value)?,
+                }
+            }
+
+            RType { rd, rs1, rs2, kind } => {
+                let lhs = self.reg.get_rs1(rs1);
+                let rhs = self.reg.get_rs2(rs2);
+                let shamt = rhs.as_u128() as u32 & (I::BITS - 1);
+                let value = match kind {
+                    RKind::Add => lhs.wrapping_add(rhs),
+                    RKind::Sub => lhs.wrapping_sub(rhs),
+                    RKind::Sll => lhs.shl(shamt),
+                    RKind::Slt => I::from_u128(lhs.lt(rhs) as u128),
+                    RKind::Sltu => I::from_u128((lhs < rhs) as u128),
+                    RKind::Xor => I::from_u128(lhs.as_u128() ^ rhs.as_u128()),
+                    RKind::Srl => lhs.shr(shamt),
+                    RKind::Sra => lhs.sra(shamt),
+                    RKind::Or => I::from_u128(lhs.as_u128() | rhs.as_u128()),
+                    RKind::And => I::from_u128(lhs.as_u128() & rhs.as_u128()),
+
+                    RKind::Mul => lhs.wrapping_mul(rhs),
+                    RKind::Mulh => lhs.mulh(rhs),
+                    RKind::Mulhsu => lhs.mulhsu(rhs),
+                    RKind::Mulhu => lhs.mulhu(rhs),
+                    RKind::Div => lhs.div(rhs),
+                    RKind::Divu => lhs.divu(rhs),
+                    RKind::Rem => lhs.rem(rhs),
+                    RKind::Remu => lhs.remu(rhs),
+
+                    RKind::Addw => word(lhs, rhs, u32::wrapping_add),
+                    RKind::Subw => word(lhs, rhs, u32::wrapping_sub),
+                    RKind::Sllw => word(lhs, rhs, |lhs, rhs| lhs.shl(rhs & 31)),
+                    RKind::Srlw => word(lhs, rhs, |lhs, rhs| lhs.shr(rhs & 31)),
+                    RKind::Sraw => word(lhs, rhs, |lhs, rhs| lhs.sra(rhs & 31)),
+
+                    RKind::Mulw => word(lhs, rhs, u32::wrapping_mul),
+                    RKind::Divw => word(lhs, rhs, RegType::div),
+                    RKind::Divuw => word(lhs, rhs, RegType::divu),
+                    RKind::Remw => word(lhs, rhs, RegType::rem),
+                    RKind::Remuw => word(lhs, rhs, RegType::remu),
+
+                    RKind::Addd => double(lhs, rhs, u64::wrapping_add),
+                    RKind::Subd => double(lhs, rhs, u64::wrapping_sub),
+                    RKind::Slld => double(lhs, rhs, |lhs, rhs| lhs.shl(rhs as u32 & 63)),
+                    RKind::Srld => double(lhs, rhs, |lhs, rhs| lhs.shr(rhs as u32 & 63)),
+                    RKind::Srad => double(lhs, rhs, |lhs, rhs| lhs.sra(rhs as u32 & 63)),
+
+                    RKind::Muld => double(lhs, rhs, u64::wrapping_mul),
+                    RKind::Divd => double(lhs, rhs, RegType::div),
+                    RKind::Divud => double(lhs, rhs, RegType::divu),
+                    RKind::Remd => double(lhs, rhs, RegType::rem),
+                    RKind::Remud => double(lhs, rhs, RegType::remu),
+
+                    RKind::Sh1add => lhs.shl(1).wrapping_add(rhs),
+                    RKind::Sh2add => lhs.shl(2).wrapping_add(rhs),
+                    RKind::Sh3add => lhs.shl(3).wrapping_add(rhs),
+                    RKind::AddUw => uw(lhs).wrapping_add(rhs),
+                    RKind::Sh1addUw => uw(lhs).shl(1).wrapping_add(rhs),
+                    RKind::Sh2addUw => uw(lhs).shl(2).wrapping_add(rhs),
+                    RKind::Sh3addUw => uw(lhs).shl(3).wrapping_add(rhs),
+
+                    RKind::Andn => I::from_u128(lhs.as_u128() & !rhs.as_u128()),
+                    RKind::Orn => I::from_u128(lhs.as_u128() | !rhs.as_u128()),
+                    RKind::Xnor => I::from_u128(!(lhs.as_u128() ^ rhs.as_u128())),
+                    RKind::Min => {
+                        if lhs.lt(rhs) {
+                            lhs
+                        } else {
+                            rhs
+                        }
+                    }
+                    RKind::Minu => lhs.min(rhs),
+                    RKind::Max => {
+                        if lhs.lt(rhs) {
+                            rhs
+                        } else {
+                            lhs
+                        }
+                    }
+                    RKind::Maxu => lhs.max(rhs),
+                    RKind::Rol => lhs.rol(shamt),
+                    RKind::Ror => lhs.ror(shamt),
+                    RKind::Rolw => word(lhs, rhs, |lhs, rhs| lhs.rol(rhs & 31)),
+                    RKind::Rorw => word(lhs, rhs, |lhs, rhs| lhs.ror(rhs & 31)),
+                    RKind::ZextH => I::from_u128(lhs.as_u128() as u16 as u128),
+
+                    RKind::Clmul => lhs.clmul(rhs),
+                    RKind::Clmulh => lhs.clmulh(rhs),
+                    RKind::Clmulr => lhs.clmulr(rhs),
+
+                    RKind::Bclr => I::from_u128(lhs.as_u128() & !(1 << shamt)),
+                    RKind::Bext => I::from_u128(lhs.as_u128() >> shamt & 1),
+                    RKind::Binv => I::from_u128(lhs.as_u128() ^ 1 << shamt),
+                    RKind::Bset => I::from_u128(lhs.as_u128() | 1 << shamt),
+
+                    RKind::Pack => {
+                        let half = I::BITS / 2;
+                        let lower = |value: I| value.as_u128() & ((1 << half) - 1);
+                        I::from_u128(lower(lhs) | lower(rhs) << half)
+                    }
+                    RKind::Packh => I::from_u128(
+                        lhs.as_u128() as u8 as u128 | (rhs.as_u128() as u8 as u128) << 8,
+                    ),
+                    RKind::Packw => word(lhs, rhs, |lhs, rhs| lhs & 0xffff | rhs << 16),
+
+                    RKind::Xperm4 => {
+                        I::from_u128(crypto::xperm(lhs.as_u128(), rhs.as_u128(), 4, I::BITS))
+                    }
+                    RKind::Xperm8 => {
+                        I::from_u128(crypto::xperm(lhs.as_u128(), rhs.as_u128(), 8, I::BITS))
+                    }
+
+                    RKind::Aes32esi { bs } => word(lhs, rhs, |lhs, rhs| {
+                        crypto::aes32(lhs, rhs, bs, false, false)
+                    }),
+                    RKind::Aes32esmi { bs } => word(lhs, rhs, |lhs, rhs| {
+                        crypto::aes32(lhs, rhs, bs, false, true)
+                    }),
+                    RKind::Aes32dsi { bs } => word(lhs, rhs, |lhs, rhs| {
+                        crypto::aes32(lhs, rhs, bs, true, false)
+                    }),
+                    RKind::Aes32dsmi { bs } => {
+                        word(lhs, rhs, |lhs, rhs| crypto::aes32(lhs, rhs, bs, true, true))
+                    }
+                    RKind::Aes64es => {
+                        I::from_u128(
+                            crypto::aes64_encrypt(lhs.as_u64(), rhs.as_u64(), false) as u128
+                        )
+                    }
+                    RKind::Aes64esm => {
+                        I::from_u128(crypto::aes64_encrypt(lhs.as_u64(), rhs.as_u64(), true) as u128)
+                    }
+                    RKind::Aes64ds => {
+                        I::from_u128(
+                            crypto::aes64_decrypt(lhs.as_u64(), rhs.as_u64(), false) as u128
+                        )
+                    }
+                    RKind::Aes64dsm => {
+                        I::from_u128(crypto::aes64_decrypt(lhs.as_u64(), rhs.as_u64(), true) as u128)
+                    }
+                    RKind::Aes64ks2 => {
+                        I::from_u128(crypto::aes64ks2(lhs.as_u64(), rhs.as_u64()) as u128)
+                    }
+
+                    RKind::Sha512sum0r => word(lhs, rhs, crypto::sha512sum0r),
+                    RKind::Sha512sum1r => word(lhs, rhs, crypto::sha512sum1r),
+                    RKind::Sha512sig0l => word(lhs, rhs, crypto::sha512sig0l),
+                    RKind::Sha512sig0h => word(lhs, rhs, crypto::sha512sig0h),
+                    RKind::Sha512sig1l => word(lhs, rhs, crypto::sha512sig1l),
+                    RKind::Sha512sig1h => word(lhs, rhs, crypto::sha512sig1h),
+
+                    RKind::Sm4ed { bs } => {
+                        word(lhs, rhs, |lhs, rhs| crypto::sm4(lhs, rhs, bs, false))
+                    }
+                    RKind::Sm4ks { bs } => {
+                        word(lhs, rhs, |lhs, rhs| crypto::sm4(lhs, rhs, bs, true))
+                    }
+
+                    RKind::CzeroEqz => match rhs == I::default() {
+                        true => I::default(),
+                        false => lhs,
+                    },
+                    RKind::CzeroNez => match rhs == I::default() {
+                        true => lhs,
+                        false => I::default(),
+                    },
+                };
+                self.reg.set_rd(rd, value);
+            }
+
+            // NOTE: Memory is accessed in program order by a single hart, so there is nothing to
+            //       order.
+            Fence { rd, rs1, info } => {
+                if X::ZIHINTPAUSE && info.is_pause() && rd == IRd::X0 && rs1 == IRs1::X0 {
+                    std::hint::spin_loop();
+                }
+            }
+
+            Ecall => match (self.privilege, self.virt) {
+                (Privilege::User, _) => Err(Error::EcallFromUOrVUMode)?,
+                (Privilege::Supervisor, false) => Err(Error::EcallFromHSMode)?,
+                (Privilege::Supervisor, true) => Err(Error::EcallFromVSMode)?,
+                (Privilege::Machine, _) => Err(Error::EcallFromMMode)?,
+            },
+            Ebreak => Err(self.ebreak())?,
+            Mret => next = self.mret()?,
+            Sret => next = self.sret()?,
+            Dret => next = self.dret()?,
+            // NOTE: Waiting may stop at any time, and the embedder is the one taking interrupts.
+            Wfi => {}
+            SfenceVma { .. } => self.sfence_vma()?,
+
+            CsrType { rd, rs1, csr, kind } => {
+                let writes = matches!(kind, CsrKind::Csrrw | CsrKind::Csrrwi) || rs1 != IRs1::X0;
+                // NOTE: Bits 9:8 of the address are the least privileged mode that may access the
+                //       CSR, where HS-mode CSRs (0b10) belong to S-mode, and bits 11:10 are 0b11
+                //       for read-only CSRs.
+                let addr = csr.as_u32();
+                let level = match addr >> 8 & 0b11 {
+                    0b10 => Privilege::Supervisor as u32,
+                    level => level,
+                };
+                let csr = self.virtualize_csr(csr)?;
+                if (self.privilege as u32) < level || (writes && addr >> 10 == 0b11) {
+                    Err(Error::IllegalInstruction)?;
+                }
+                self.check_vector_csr(csr, writes)?;
+                self.check_float_csr(csr)?;
+                self.check_counter_csr(csr, writes)?;
+                self.check_pmp_csr(csr)?;
+                self.check_seed_csr(csr)?;
+                self.check_trigger_csr(csr)?;
+                self.check_debug_csr(csr)?;
+                let old = match csr {
+                    // NOTE: Reading `seed` consumes entropy, so read-only accesses are illegal.
+                    Csr::Seed if X::ZKR && writes => self.logged_seed() as u64,
+                    Csr::Seed => Err(Error::IllegalInstruction)?,
+                    Csr::Time | Csr::Timeh => self.logged_time(csr),
+                    _ => self.read_csr(csr),
+                };
+                let src = match kind {
+                    CsrKind::Csrrw | CsrKind::Csrrs | CsrKind::Csrrc => {
+                        self.reg.get_rs1(rs1).as_u64()
+                    }
+                    CsrKind::Csrrwi | CsrKind::Csrrsi | CsrKind::Csrrci => rs1 as u64,
+                };
+                // NOTE: Set and clear with `x0`/`0` as the source must not write the CSR.
+                let (new, write) = match kind {
+                    CsrKind::Csrrw | CsrKind::Csrrwi => (src, true),
+                    CsrKind::Csrrs | CsrKind::Csrrsi => (old | src, rs1 != IRs1::X0),
+                    CsrKind::Csrrc | CsrKind::Csrrci => (old & !src, rs1 != IRs1::X0),
+                };
+                if write {
+                    self.write_csr(csr, new);
+                }
+                self.reg.set_rd(rd, I::from_u128(old as u128));
+            }
+
+            AmoType {
+                rd, rs1, rs2, kind, ..
+            } => {
+                let value =
+                    self.logged_amo(bus, self.reg.get_rs1(rs1), self.reg.get_rs2(rs2), kind)?;
+                self.reg.set_rd(rd, value);
+            }
+
+            HypervisorType {
+                rd, rs1, rs2, kind, ..
+            } => self.hypervisor(bus, rd, rs1, rs2, kind)?,
+
+            FLoadType { rd, rs1, i, fmt } => {
+                self.fload(bus, rd, rs1, I::from_i128(i.i32() as i128), fmt)?
+            }
+            FStoreType { rs1, rs2, s, fmt } => {
+                self.fstore(bus, rs1, rs2, I::from_i128(s.i32() as i128), fmt)?
+            }
+            FmaType {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+                fmt,
+                kind,
+            } => self.fma(rd, rs1, rs2, rs3, rm, fmt, kind)?,
+            FpType {
+                rd,
+                rs1,
+                rs2,
+                rm,
+                fmt,
+                kind,
+            } => self.fp(rd, rs1, rs2, rm, fmt, kind)?,
+
+            VsetType { rd, kind } => self.vset(rd, kind)?,
+            VMemType { vd, rs1, vm, mem } => self.vmem(bus, vd, rs1, vm, mem)?,
+            VArithType {
+                vd,
+                vs2,
+                src,
+                vm,
+                kind,
+            } => self.varith(vd, vs2, src, vm, kind)?,
+
+            Illegal32 { .. } | Illegal16 { .. } | Unused { .. } => Err(Error::IllegalInstruction)?,
+        }
+
+        self.pc = next;
+        Ok(())
+    }
+
+    fn check_target(&self, target: I) -> Result<(), Error> {
+        let alignment = if C { 2 } else { 4 };
+        match target.as_u128() % alignment {
+            0 => Ok(()),
+            _ => Err(Error::InstructionAddressMisaligned),
+        }
+    }
+
+    /// Loads an `N`-byte value, sign- or zero-extending it to `XLEN`.
+    fn load<const N: usize>(
+        &mut self,
+        bus: &mut impl Bus,
+        base: I,
+        offset: I,
+        signed: bool,
+    ) -> Result<I, Error> {
+        let addr = base
+            .wrapping_add(offset)
+            .as_addr()
+            .ok_or(Error::LoadAccessFault)?;
+        let pa = self.translate_addr(bus, addr, N as u64, Access::Read)?;
+        let mut buf = [0; 16];
+        bus.read(pa, &mut buf[..N])
+            .map_err(|_| Error::LoadAccessFault)?;
+
+        let value = u128::from_le_bytes(buf);
+        self.match_data(addr, N as u64, Access::Read, value as u64)?;
+        let shift = 128 - 8 * N;
+        let value = match signed {
+            true => ((value << shift) as i128 >> shift) as u128,
+            false => value,
+        };
+        Ok(I::from_u128(value))
+    }
+
+    /// Stores the lower `N` bytes of `value`.
+    fn store<const N: usize>(
+        &mut self,
+        bus: &mut impl Bus,
+        base: I,
+        offset: I,
+        value: I,
+    ) -> Result<(), Error> {
+        let addr = base
+            .wrapping_add(offset)
+            .as_addr()
+            .ok_or(Error::StoreOrAmoAccessFault)?;
+        let pa = self.translate_addr(bus, addr, N as u64, Access::Write)?;
+        let data = value.as_u128() & u128::MAX >> (128 - 8 * N);
+        self.match_data(addr, N as u64, Access::Write, data as u64)?;
+        bus.write(pa, &value.as_u128().to_le_bytes()[..N])
+            .map_err(|_| Error::StoreOrAmoAccessFault)?;
+        self.blocks.invalidate(pa, N as u64);
+        Ok(())
+    }
+
+    /// Executes a cache-block operation on the block containing `addr`.
+    ///
+    /// Enables in `menvcfg` and `senvcfg` apply to the less privileged modes.
+    fn cbo(&mut self, bus: &mut impl Bus, addr: I, kind: IKind) -> Result<(), Error> {
+        const CBIE: u64 = 0b11 << 4;
+        const CBCFE: u64 = 1 << 6;
+        const CBZE: u64 = 1 << 7;
+
+        let envcfg = match self.privilege {
+            Privilege::Machine => u64::MAX,
+            Privilege::Supervisor => self.csr.read(Csr::Menvcfg),
+            Privilege::User => self.csr.read(Csr::Menvcfg) & self.csr.read(Csr::Senvcfg),
+        };
+        let enabled = match kind {
+            IKind::CboClean | IKind::CboFlush => envcfg & CBCFE != 0,
+            // NOTE: `cbo.inval` is turned into a flush, which the caches can do without knowing
+            //       what's dirty, but is illegal if either level has it disabled.
+            IKind::CboInval => envcfg & CBIE != 0,
+            _ => envcfg & CBZE != 0,
+        };
+        if !enabled {
+            Err(Error::IllegalInstruction)?;
+        }
+
+        let size = X::CBOZ_BLOCK_SIZE;
+        let base = addr.as_addr().ok_or(Error::StoreOrAmoAccessFault)? & !(size - 1);
+        // NOTE: Management operations only need one of the permissions a load or store would, and
+        //       report faults as stores.
+        let base = match kind {
+            IKind::CboZero => self.translate_addr(bus, base, size, Access::Write)?,
+            _ => self
+                .translate_addr(bus, base, size, Access::Read)
+                .or_else(|e| match e {
+                    // Watchpoints aren't faults
+                    Error::Breakpoint => Err(e),
+                    _ => self.translate_addr(bus, base, size, Access::Write),
+                })?,
+        };
+
+        // NOTE: The caches only model timing, so only `cbo.zero` changes memory.
+        if let (Some(caches), false) = (&mut self.caches, matches!(kind, IKind::CboZero)) {
+            caches.clean(base, size, !matches!(kind, IKind::CboClean));
+        }
+        if let IKind::CboZero = kind {
+            let zeros = [0; 64];
+            for offset in (0..size).step_by(zeros.len()) {
+                let len = (size - offset).min(zeros.len() as u64) as usize;
+                bus.write(base + offset, &zeros[..len])
+                    .map_err(|_| Error::StoreOrAmoAccessFault)?;
+            }
+            self.blocks.invalidate(base, size);
+        }
+        Ok(())
+    }
+
+    /// Executes an AMO, `lr` or `sc`, with `sc` succeeding as `outcome` says if given.
+    pub(super) fn amo(
+        &mut self,
+        bus: &mut impl Bus,
+        addr: I,
+        src: I,
+        kind: AmoKind,
+        outcome: Option<bool>,
+    ) -> Result<I, Error> {
+        use AmoKind::*;
+
+        let width = match kind {
+            Lrw | Scw | Amoswapw | Amoaddw | Amoxorw | Amoandw | Amoorw | Amominw | Amomaxw
+            | Amominuw | Amomaxuw => 4,
+            _ => 8,
+        };
+        let misaligned = match kind {
+            Lrw | Lrd => Error::LoadAddressMisaligned,
+            _ => Error::StoreOrAmoAddressMisaligned,
+        };
+        let fault = match kind {
+            Lrw | Lrd => Error::LoadAccessFault,
+            _ => Error::StoreOrAmoAccessFault,
+        };
+
+        let addr = addr.as_addr().ok_or(fault)?;
+        if addr % width != 0 {
+            Err(misaligned)?;
+        }
+        let width = width as usize;
+
+        let access = match kind {
+            Lrw | Lrd => Access::Read,
+            _ => Access::Write,
+        };
+        let addr = self.translate_addr(bus, addr, width as u64, access)?;
+        // NOTE: Everything but `lr` needs write permission, and everything but `sc` needs read.
+        let machine = self.data_context().privilege == Privilege::Machine;
+        if !matches!(kind, Scw | Scd) && !self.pmp.check(addr, width as u64, Access::Read, machine)
+        {
+            Err(fault)?;
+        }
+
+        let sext = |raw: u64| match width {
+            4 => raw as i32 as i64,
+            _ => raw as i64,
+        };
+
+        // Store-conditional is the only operation that might not read memory
+        if let Scw | Scd = kind {
+            let reserved = self.reservation.take() == Some(addr);
+            let success = outcome.unwrap_or(reserved);
+            if success {
+                let value = src.as_u64().to_le_bytes();
+                bus.write(addr, &value[..width]).map_err(|_| fault)?;
+                self.blocks.invalidate(addr, width as u64);
+            }
+            return Ok(I::from_u128(!success as u128));
+        }
+
+        let mut buf = [0; 8];
+        bus.read(addr, &mut buf[..width]).map_err(|_| fault)?;
+        let old = u64::from_le_bytes(buf);
+        let src = src.as_u64();
+
+        let new = match kind {
+            Lrw | Lrd => {
+                self.reservation = Some(addr);
+                None
+            }
+            Amoswapw | Amoswapd => Some(src),
+            Amoaddw | Amoaddd => Some(old.wrapping_add(src)),
+            Amoxorw | Amoxord => Some(old ^ src),
+            Amoandw | Amoandd => Some(old & src),
+            Amoorw | Amoord => Some(old | src),
+            Amominw | Amomind => Some(if sext(old) < sext(src) { old } else { src }),
+            Amomaxw | Amomaxd => Some(if sext(old) > sext(src) { old } else { src }),
+            Amominuw | Amominud => Some(old.min(src & mask(width))),
+            Amomaxuw | Amomaxud => Some(old.max(src & mask(width))),
+            Scw | Scd => unreachable!(),
+        };
+
+        if let Some(new) = new {
+            bus.write(addr, &new.to_le_bytes()[..width])
+                .map_err(|_| fault)?;
+            self.blocks.invalidate(addr, width as u64);
+        }
+
+        Ok(I::from_i128(sext(old) as i128))
+    }
+}
+
+/// Applies a 32-bit operation to the lower halves of `lhs` and `rhs`, sign-extending the result.
+fn word<I: RegType>(lhs: I, rhs: I, op: impl Fn(u32, u32) -> u32) -> I {
+    let value = op(lhs.as_u128() as u32, rhs.as_u128() as u32);
+    I::from_i128(value as i32 as i128)
+}
+
+/// Applies a 64-bit operation to the lower halves of `lhs` and `rhs`, sign-extending the result.
+fn double<I: RegType>(lhs: I, rhs: I, op: impl Fn(u64, u64) -> u64) -> I {
+    let value = op(lhs.as_u128() as u64, rhs.as_u128() as u64);
+    I::from_i128(value as i64 as i128)
+}
+
+/// Zero-extends the lower 32 bits of `value`.
+fn uw<I: RegType>(value: I) -> I {
+    I::from_u128(value.as_u128() as u32 as u128)
+}
+
+fn mask(width: usize) -> u64 {
+    u64::MAX >> (64 - 8 * width)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bus::Bus,
+        csr::Csr,
+        ext::Extensions,
+        hart::{
+            exec::Error,
+            testing::{boot, load, BASE},
+            Hart, Privilege,
+        },
+        reg::{IRd, IRs1},
+    };
+
+    #[test]
+    fn test_load_extension() {
+        let program = [
+            0x00000097u32, // auipc x1, 0
+            0x0140c103,    // lbu x2, 20(x1)
+            0x01408183,    // lb x3, 20(x1)
+            0x0140d203,    // lhu x4, 20(x1)
+            0x0140a283,    // lw x5, 20(x1)
+        ];
+
+        let mut ram = load(&[&program[..], &[0xfffff0f0]].concat());
+
+        let mut hart: Hart<0, u64, false, false, false, (), false, false> = boot();
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+
+        assert_eq!(hart.reg().get_rs1(IRs1::X2), 0xf0);
+        assert_eq!(hart.reg().get_rs1(IRs1::X3), 0xffff_ffff_ffff_fff0);
+        assert_eq!(hart.reg().get_rs1(IRs1::X4), 0xf0f0);
+        assert_eq!(hart.reg().get_rs1(IRs1::X5), 0xffff_ffff_ffff_f0f0);
+
+        // Execution runs into the data
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+    }
+
+    #[test]
+    fn test_rv64_word_ops() {
+        let program = [
+            0xfff00093u32, // addi x1, x0, -1
+            0x0010d093,    // srli x1, x1, 1
+            0x0010811b,    // addiw x2, x1, 1
+            0x02109193,    // slli x3, x1, 33
+            0x0010823b,    // addw x4, x1, x1
+            0x0042529b,    // srliw x5, x4, 4
+            0x0202433b,    // divw x6, x4, x0
+            0x0200939b,    // slliw x7, x1, 32
+        ];
+
+        let mut ram = load(&program);
+
+        let mut hart: Hart<0, u64, false, true, false, (), false, false> = boot();
+        for _ in 0..program.len() - 1 {
+            hart.step(&mut ram).unwrap();
+        }
+
+        assert_eq!(hart.reg().get_rs1(IRs1::X2), 0);
+        assert_eq!(hart.reg().get_rs1(IRs1::X3), 0xffff_fffe_0000_0000);
+        assert_eq!(hart.reg().get_rs1(IRs1::X4), 0xffff_ffff_ffff_fffe);
+        assert_eq!(hart.reg().get_rs1(IRs1::X5), 0x0fff_ffff);
+        assert_eq!(hart.reg().get_rs1(IRs1::X6), u64::MAX);
+
+        // 32-bit shifts only have a 5-bit shift amount
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+    }
+
+    #[test]
+    fn test_rv128() {
+        let program = [
+            0x00000197u32, // auipc x3, 0
+            0x04018193,    // addi x3, x3, 64
+            0x00100093,    // addi x1, x0, 1
+            0x06409093,    // slli x1, x1, 100
+            0x4630d113,    // srai x2, x1, 99
+            0x0011c023,    // sq x1, 0(x3)
+            0x0001a20f,    // lq x4, 0(x3)
+            0xfff082db,    // addid x5, x1, -1
+            0x0081f303,    // ldu x6, 8(x3)
+            0x0042d3db,    // srlid x7, x5, 4
+            0x00000073,    // ecall
+        ];
+
+        let mut ram = load(&program);
+
+        let mut hart: Hart<0, u128, false, false, false, (), false, false> = boot();
+        assert_eq!(hart.run(&mut ram, u64::MAX), Err(Error::EcallFromMMode));
+
+        assert_eq!(hart.reg().get_rs1(IRs1::X1), 1 << 100);
+        assert_eq!(hart.reg().get_rs1(IRs1::X2), 2);
+        assert_eq!(hart.reg().get_rs1(IRs1::X4), 1 << 100);
+        assert_eq!(hart.reg().get_rs1(IRs1::X5), u128::MAX);
+        assert_eq!(hart.reg().get_rs1(IRs1::X6), 1 << 36);
+        assert_eq!(hart.reg().get_rs1(IRs1::X7), 0x0fff_ffff_ffff_ffff);
+        assert_eq!(hart.read_csr(Csr::Misa) >> 62, 3);
+    }
+
+    #[test]
+    fn test_rv32e() {
+        let program = [
+            0x00100793u32, // addi x15, x0, 1
+            0x340fd173,    // csrrwi x2, mscratch, 31
+            0x301020f3,    // csrrs x1, misa, x0
+            0x00100813,    // addi x16, x0, 1
+        ];
+
+        let mut ram = load(&program);
+
+        let mut hart: Hart<0, u32, true, true, false, (), false, false> = boot();
+        for _ in 0..program.len() - 1 {
+            hart.step(&mut ram).unwrap();
+        }
+
+        assert_eq!(hart.reg().get_rs1(IRs1::X15), 1);
+        assert_eq!(hart.reg().get_rs1(IRs1::X1), 0x4000_1010);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+    }
+
+    #[test]
+    fn test_bit_manipulation() {
+        #[derive(Clone, Copy)]
+        struct Zb;
+
+        impl Extensions for Zb {
+            const ZBA: bool = true;
+            const ZBB: bool = true;
+            const ZBC: bool = true;
+            const ZBS: bool = true;
+        }
+
+        let program = [
+            0x0f000093u32, // addi x1, x0, 0xf0
+            0x2010c133,    // sh2add x2, x1, x1
+            0x60009193,    // clz x3, x1
+            0x60209213,    // cpop x4, x1
+            0x6b80d293,    // rev8 x5, x1
+            0x28715313,    // orc.b x6, x2
+            0x2bf01393,    // bseti x7, x0, 63
+            0x0a13c433,    // min x8, x7, x1
+            0x0a1094b3,    // clmul x9, x1, x1
+            0x6040d513,    // rori x10, x1, 4
+            0x401175b3,    // andn x11, x2, x1
+            0x60409613,    // sext.b x12, x1
+            0x0846169b,    // slli.uw x13, x12, 4
+            0x4bf3d713,    // bexti x14, x7, 63
+        ];
+
+        let mut ram = load(&program);
+
+        let mut hart: Hart<0, u64, false, false, false, (), false, false, Zb> = boot();
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+
+        let reg = |x| hart.reg().get_rs1(x);
+        assert_eq!(reg(IRs1::X2), 0x4b0);
+        assert_eq!(reg(IRs1::X3), 56);
+        assert_eq!(reg(IRs1::X4), 4);
+        assert_eq!(reg(IRs1::X5), 0xf000_0000_0000_0000);
+        assert_eq!(reg(IRs1::X6), 0xffff);
+        assert_eq!(reg(IRs1::X7), 1 << 63);
+        assert_eq!(reg(IRs1::X8), 1 << 63);
+        assert_eq!(reg(IRs1::X9), 0x5500);
+        assert_eq!(reg(IRs1::X10), 0xf);
+        assert_eq!(reg(IRs1::X11), 0x400);
+        assert_eq!(reg(IRs1::X12), 0xffff_ffff_ffff_fff0);
+        assert_eq!(reg(IRs1::X13), 0xf_ffff_ff00);
+        assert_eq!(reg(IRs1::X14), 1);
+
+        // Without the extensions, the same encodings are illegal
+        let mut hart = Hart::<0, u64, false, false, false, (), false, false>::new();
+        hart.set_pc(0x1004);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+    }
+
+    #[test]
+    fn test_scalar_crypto() {
+        #[derive(Clone, Copy)]
+        struct Zk;
+
+        impl Extensions for Zk {
+            const ZBKB: bool = true;
+            const ZBKX: bool = true;
+            const ZKNE: bool = true;
+            const ZKNH: bool = true;
+            const ZKSH: bool = true;
+            const ZKR: bool = true;
+        }
+
+        let program = [
+            0x0f000093u32, // addi x1, x0, 0xf0
+            0x6870d113,    // brev8 x2, x1
+            0x08f09193,    // zip x3, x1
+            0x0810c233,    // pack x4, x1, x1
+            0x2800c2b3,    // xperm8 x5, x1, x0
+            0x22000333,    // aes32esi x6, x0, x0, 0
+            0x10209393,    // sha256sig0 x7, x1
+            0x10809413,    // sm3p0 x8, x1
+            0x015014f3,    // csrrw x9, seed, x0
+            0x01502573,    // csrrs x10, seed, x0
+        ];
+
+        let mut ram = load(&program);
+
+        let mut hart: Hart<0, u32, false, false, false, (), false, false, Zk> = boot();
+        for _ in 0..program.len() - 1 {
+            hart.step(&mut ram).unwrap();
+        }
+        // Reading `seed` without writing it is illegal
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+
+        let reg = |x| hart.reg().get_rs1(x);
+        assert_eq!(reg(IRs1::X2), 0x0f);
+        assert_eq!(reg(IRs1::X3), 0x5500);
+        assert_eq!(reg(IRs1::X4), 0x00f0_00f0);
+        assert_eq!(reg(IRs1::X5), 0xf0f0_f0f0);
+        assert_eq!(reg(IRs1::X6), 0x63);
+        assert_eq!(reg(IRs1::X7), 0xe03c_001f);
+        assert_eq!(reg(IRs1::X8), 0x01e1_e0f0);
+        assert_eq!(reg(IRs1::X9) >> 30, 0b10);
+
+        // Less privileged modes read `seed` only where `mseccfg` allows them to
+        for (privilege, allow) in [(Privilege::User, 1 << 8), (Privilege::Supervisor, 1 << 9)] {
+            hart.set_privilege(privilege);
+            hart.write_csr(Csr::Mseccfg, 0);
+            hart.set_pc(BASE as u32 + 32);
+            assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+            hart.write_csr(Csr::Mseccfg, allow);
+            hart.step(&mut ram).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_zicond_and_cbo() {
+        #[derive(Clone, Copy)]
+        struct Rva23;
+
+        impl Extensions for Rva23 {
+            const ZICOND: bool = true;
+            const ZICBOM: bool = true;
+            const ZICBOZ: bool = true;
+            const ZIHINTPAUSE: bool = true;
+        }
+
+        let program = [
+            0x00500093u32, // addi x1, x0, 5
+            0x0e00d133,    // czero.eqz x2, x1, x0
+            0x0e00f1b3,    // czero.nez x3, x1, x0
+            0x0e10d233,    // czero.eqz x4, x1, x1
+            0x0045200f,    // cbo.zero (x10)
+            0x0015200f,    // cbo.clean (x10)
+            0x0100000f,    // pause
+            0x00156013,    // prefetch.r 0(x10)
+            0x00000073,    // ecall
+        ];
+
+        let mut ram = load(&program);
+        ram.write(0x1100, &[0xff; 0xc0]).unwrap();
+
+        let mut hart: Hart<0, u64, false, false, false, (), false, false, Rva23> = boot();
+        hart.reg_mut().set_rd(IRd::X10, 0x1150);
+        for _ in 0..6 {
+            hart.step(&mut ram).unwrap();
+        }
+        assert_eq!(hart.reg().get_rs1(IRs1::X2), 0);
+        assert_eq!(hart.reg().get_rs1(IRs1::X3), 5);
+        assert_eq!(hart.reg().get_rs1(IRs1::X4), 5);
+        // Only the aligned block containing the address is zeroed
+        let mem = ram.as_slice();
+        assert!(mem[0x1100..0x1140].iter().all(|&b| b == 0xff));
+        assert!(mem[0x1140..0x1180].iter().all(|&b| b == 0));
+        assert!(mem[0x1180..0x11c0].iter().all(|&b| b == 0xff));
+
+        // Less privileged modes need the operations enabled at every level above them
+        hart.set_privilege(Privilege::User);
+        hart.set_pc(0x1014);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.write_csr(Csr::Menvcfg, 1 << 6);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.write_csr(Csr::Senvcfg, 1 << 6);
+        for _ in 0..3 {
+            hart.step(&mut ram).unwrap();
+        }
+        assert_eq!(hart.step(&mut ram), Err(Error::EcallFromUOrVUMode));
+    }
+
+    #[test]
+    fn test_csr_access() {
+        let program = [
+            0x30002573u32, // csrr a0, mstatus
+            0x10002573,    // csrr a0, sstatus
+            0xf1402573,    // csrr a0, mhartid
+            0xf1451073,    // csrw mhartid, a0
+            0xf14525f3,    // csrrs a1, mhartid, a0
+            0x30151073,    // csrw misa, a0
+        ];
+        let mut ram = load(&program);
+        let mut hart = Hart::<3, u64, false, false, false, (), false, false>::new();
+        hart.reg_mut().set_rd(IRd::X10, 7);
+        hart.reg_mut().set_rd(IRd::X11, 7);
+
+        // Machine-level CSRs trap in less privileged modes, without touching `rd`
+        for privilege in [Privilege::User, Privilege::Supervisor] {
+            hart.set_privilege(privilege);
+            hart.set_pc(0x1000);
+            assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+            assert_eq!(hart.reg().get_rs1(IRs1::X10), 7);
+        }
+        hart.set_pc(0x1004);
+        hart.step(&mut ram).unwrap();
+        hart.set_privilege(Privilege::User);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+
+        // Read-only CSRs can be read, but not written, even when setting bits
+        hart.set_privilege(Privilege::Machine);
+        hart.set_pc(0x1008);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.reg().get_rs1(IRs1::X10), 3);
+        hart.reg_mut().set_rd(IRd::X10, 7);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.set_pc(0x1010);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        assert_eq!(hart.reg().get_rs1(IRs1::X11), 7);
+
+        // `misa` can be written, but the extensions stay as they are
+        let misa = hart.read_csr(Csr::Misa);
+        hart.set_pc(0x1014);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.read_csr(Csr::Misa), misa);
+    }
+
+    #[test]
+    fn test_wfi() {
+        let program = [
+            0x10500073u32, // wfi
+            0x00100093,    // addi x1, x0, 1
+        ];
+        let mut ram = load(&program);
+        let mut hart: Hart<0, u64, false, false, false, (), false, false> = boot();
+        hart.set_privilege(Privilege::User);
+
+        // Waiting ends straight away, in every mode
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+        assert_eq!(hart.reg().get_rs1(IRs1::X1), 1);
+        assert_eq!(hart.pc(), BASE + 8);
+    }
+}
@@ -0,0 +1,519 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Execution of the scalar floating-point extensions.
+//!
+//! Arithmetic goes through [`softfloat`] so results and exception flags don't depend on the host.
+//! Values narrower than `FLEN` are NaN-boxed.
+
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::{FRd, FRegType, FRs1, FRs2, FRs3},
+    hart::{exec::Error, Hart},
+    inst::float::{FFmt, FmaKind, FpKind},
+    mmu::pmp::Access,
+    reg::{IRd, IRs1, RegType},
+    softfloat::{self, Format, Rounding},
+};
+
+const MSTATUS_FS: u64 = 0b11 << 13;
+
+fn format(fmt: FFmt) -> Format {
+    match fmt {
+        FFmt::S => Format::SINGLE,
+        FFmt::D => Format::DOUBLE,
+        FFmt::H => Format::HALF,
+        FFmt::Q => Format::QUAD,
+    }
+}
+
+fn mask(bits: u32) -> u128 {
+    u128::MAX >> (128 - bits)
+}
+
+fn sext(value: u128, bits: u32) -> i128 {
+    (value << (128 - bits)) as i128 >> (128 - bits)
+}
+
+/// Reads a register as a `fmt` value, replacing improperly NaN-boxed values with the canonical NaN.
+fn unbox<F: FRegType>(value: F, fmt: Format) -> u128 {
+    let bits = value.to_bits();
+    let width = fmt.bits();
+    if width < F::BITS && bits >> width != mask(F::BITS - width) {
+        return fmt.canonical_nan();
+    }
+    bits & mask(width)
+}
+
+/// NaN-boxes a `fmt` value.
+fn boxed<F: FRegType>(bits: u128, fmt: Format) -> F {
+    F::from_bits(bits | !mask(fmt.bits()))
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Raises an illegal instruction unless floating-point state is accessible, marking it dirty
+    /// otherwise.
+    pub(super) fn float_enabled(&mut self) -> Result<(), Error> {
+        self.float_accessible()?;
+        self.float_dirty();
+        Ok(())
+    }
+
+    /// Raises an illegal instruction unless floating-point state is accessible.
+    pub(super) fn float_accessible(&self) -> Result<(), Error> {
+        if F::BITS == 0 || self.status_off(MSTATUS_FS) {
+            Err(Error::IllegalInstruction)?;
+        }
+        Ok(())
+    }
+
+    /// Marks floating-point state dirty, once nothing can raise an exception anymore.
+    pub(super) fn float_dirty(&mut self) {
+        self.status_dirty(MSTATUS_FS);
+    }
+
+    /// Checks an access to a floating-point CSR, if `csr` is one.
+    pub(super) fn check_float_csr(&mut self, csr: Csr) -> Result<(), Error> {
+        match csr {
+            Csr::Fflags | Csr::Frm | Csr::Fcsr => self.float_enabled(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Resolves the `rm` field, where 0b111 selects `frm`.
+    pub(super) fn rounding(&self, rm: u8) -> Result<Rounding, Error> {
+        let rm = match rm {
+            0b111 => self.csr.read(Csr::Frm) as u8,
+            rm => rm,
+        };
+        Rounding::from_rm(rm).ok_or(Error::IllegalInstruction)
+    }
+
+    pub(super) fn accrue(&mut self, flags: u8) {
+        if flags != 0 {
+            let fflags = self.csr.read(Csr::Fflags);
+            self.write_csr(Csr::Fflags, fflags | flags as u64);
+        }
+    }
+
+    pub(super) fn fload(
+        &mut self,
+        bus: &mut impl Bus,
+        rd: FRd,
+        rs1: IRs1,
+        offset: I,
+        fmt: FFmt,
+    ) -> Result<(), Error> {
+        self.float_enabled()?;
+        let fmt = format(fmt);
+        let addr = self
+            .reg
+            .get_rs1(rs1)
+            .wrapping_add(offset)
+            .as_addr()
+            .ok_or(Error::LoadAccessFault)?;
+        let addr = self.translate_addr(bus, addr, fmt.bits() as u64 / 8, Access::Read)?;
+        let mut buf = [0; 16];
+        bus.read(addr, &mut buf[..fmt.bits() as usize / 8])
+            .map_err(|_| Error::LoadAccessFault)?;
+        self.freg.set_rd(rd, boxed(u128::from_le_bytes(buf), fmt));
+        Ok(())
+    }
+
+    /// Stores the lower bits of `f[rs2]` as they are, without checking the NaN-boxing.
+    pub(super) fn fstore(
+        &mut self,
+        bus: &mut impl Bus,
+        rs1: IRs1,
+        rs2: FRs2,
+        offset: I,
+        fmt: FFmt,
+    ) -> Result<(), Error> {
+        self.float_enabled()?;
+        let width = format(fmt).bits() as usize / 8;
+        let addr = self
+            .reg
+            .get_rs1(rs1)
+            .wrapping_add(offset)
+            .as_addr()
+            .ok_or(Error::StoreOrAmoAccessFault)?;
+        let addr = self.translate_addr(bus, addr, width as u64, Access::Write)?;
+        let value = self.freg.get_rs2(rs2).to_bits().to_le_bytes();
+        bus.write(addr, &value[..width])
+            .map_err(|_| Error::StoreOrAmoAccessFault)?;
+        self.blocks.invalidate(addr, width as u64);
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn fma(
+        &mut self,
+        rd: FRd,
+        rs1: FRs1,
+        rs2: FRs2,
+        rs3: FRs3,
+        rm: u8,
+        fmt: FFmt,
+        kind: FmaKind,
+    ) -> Result<(), Error> {
+        self.float_accessible()?;
+        let rm = self.rounding(rm)?;
+        self.float_dirty();
+        let fmt = format(fmt);
+        let a = unbox(self.freg.get_rs1(rs1), fmt);
+        let b = unbox(self.freg.get_rs2(rs2), fmt);
+        let c = unbox(self.freg.get_rs3(rs3), fmt);
+        let (a, c) = match kind {
+            FmaKind::Fmadd => (a, c),
+            FmaKind::Fmsub => (a, fmt.negate(c)),
+            FmaKind::Fnmsub => (fmt.negate(a), c),
+            FmaKind::Fnmadd => (fmt.negate(a), fmt.negate(c)),
+        };
+
+        let mut flags = 0;
+        let value = softfloat::fma(fmt, a, b, c, rm, &mut flags);
+        self.accrue(flags);
+        self.freg.set_rd(rd, boxed(value, fmt));
+        Ok(())
+    }
+
+    /// Executes an OP-FP instruction, where `rd` and `rs1` are `x` or `f` registers depending on
+    /// `kind`.
+    pub(super) fn fp(
+        &mut self,
+        rd: u8,
+        rs1: u8,
+        rs2: FRs2,
+        rm: u8,
+        fmt: FFmt,
+        kind: FpKind,
+    ) -> Result<(), Error> {
+        use FpKind::*;
+
+        self.float_accessible()?;
+        // NOTE: A reserved rounding mode raises before anything is changed.
+        let rounds = matches!(
+            kind,
+            Fadd | Fsub
+                | Fmul
+                | Fdiv
+                | Fsqrt
+                | Fcvt { .. }
+                | FcvtBf16S
+                | FcvtSBf16
+                | FcvtXF { .. }
+                | FcvtFX { .. }
+        );
+        let rm = match rounds {
+            true => self.rounding(rm)?,
+            false => Rounding::NearestEven,
+        };
+        self.float_dirty();
+
+        let f = format(fmt);
+        let src = self.freg.get_rs1(FRs1::wrapping_from_u32(rs1 as u32));
+        let int = self
+            .reg
+            .get_rs1(IRs1::wrapping_from_u32(rs1 as u32))
+            .as_u128();
+        let (a, b) = (unbox(src, f), unbox(self.freg.get_rs2(rs2), f));
+        let sign = 1 << (f.bits() - 1);
+        let mut flags = 0;
+
+        if kind.int_rd() {
+            let value = match kind {
+                Feq => softfloat::eq(f, a, b, &mut flags) as i128,
+                Flt => softfloat::lt(f, a, b, &mut flags) as i128,
+                Fle => softfloat::le(f, a, b, &mut flags) as i128,
+                Fclass => softfloat::classify(f, a) as i128,
+                FmvXF => sext(src.to_bits(), f.bits()),
+                FcvtXF { signed, bits } => {
+                    sext(softfloat::to_int(f, a, signed, bits, rm, &mut flags), bits)
+                }
+                _ => unreachable!(),
+            };
+            self.accrue(flags);
+            let rd = IRd::checked_from_u32(rd as u32).unwrap();
+            self.reg.set_rd(rd, I::from_i128(value));
+            return Ok(());
+        }
+
+        let value = match kind {
+            Fadd => softfloat::add(f, a, b, rm, &mut flags),
+            Fsub => softfloat::sub(f, a, b, rm, &mut flags),
+            Fmul => softfloat::mul(f, a, b, rm, &mut flags),
+            Fdiv => softfloat::div(f, a, b, rm, &mut flags),
+            Fsqrt => softfloat::sqrt(f, a, rm, &mut flags),
+            Fsgnj => a & !sign | b & sign,
+            Fsgnjn => a & !sign | !b & sign,
+            Fsgnjx => a ^ b & sign,
+            Fmin => softfloat::min_max(f, a, b, false, &mut flags),
+            Fmax => softfloat::min_max(f, a, b, true, &mut flags),
+            Fcvt { from } => {
+                let from = format(from);
+                softfloat::convert(from, f, unbox(src, from), rm, &mut flags)
+            }
+            FcvtBf16S => {
+                let (from, to) = (Format::SINGLE, Format::BF16);
+                softfloat::convert(from, to, unbox(src, from), rm, &mut flags)
+            }
+            FcvtSBf16 => {
+                let (from, to) = (Format::BF16, Format::SINGLE);
+                softfloat::convert(from, to, unbox(src, from), rm, &mut flags)
+            }
+            FmvFX => int & mask(f.bits()),
+            FcvtFX { signed, bits } => {
+                let (negative, magnitude) = match signed {
+                    true => {
+                        let value = sext(int, bits);
+                        (value < 0, value.unsigned_abs())
+                    }
+                    false => (false, int & mask(bits)),
+                };
+                softfloat::from_int(f, negative, magnitude, rm, &mut flags)
+            }
+            Feq | Flt | Fle | Fclass | FmvXF | FcvtXF { .. } => unreachable!(),
+        };
+        self.accrue(flags);
+        let rd = FRd::checked_from_u32(rd as u32).unwrap();
+        self.freg.set_rd(rd, boxed(value, f));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bus::Bus,
+        csr::Csr,
+        ext::Extensions,
+        freg::{FRd, FRs1, F128},
+        hart::{
+            exec::Error,
+            testing::{boot, load, BASE, DATA},
+            Hart, Privilege,
+        },
+        reg::{IRd, IRs1},
+    };
+
+    #[derive(Clone, Copy)]
+    struct Zfh;
+
+    impl Extensions for Zfh {
+        const ZFH: bool = true;
+        const ZFBFMIN: bool = true;
+    }
+
+    type Rv64fd = Hart<0, u64, false, true, false, f64, false, false, Zfh>;
+    type Rv64q = Hart<0, u64, false, true, false, F128, false, false>;
+
+    fn hart() -> Rv64fd {
+        let mut hart: Rv64fd = boot();
+        hart.write_csr(Csr::Mstatus, 0b01 << 13);
+        hart.reg_mut().set_rd(IRd::X10, DATA);
+        hart
+    }
+
+    /// Bits of a NaN-boxed half-precision register.
+    fn half(hart: &Rv64fd, rs1: FRs1) -> u64 {
+        let bits = hart.freg().get_rs1(rs1).to_bits();
+        assert_eq!(bits >> 16, 0xffff_ffff_ffff);
+        bits & 0xffff
+    }
+
+    #[test]
+    fn test_disabled() {
+        let program = [
+            0x00051087u32, // flh f1, 0(x10)
+        ];
+        let mut ram = load(&program);
+        let mut hart = hart();
+        hart.write_csr(Csr::Mstatus, 0);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+    }
+
+    #[test]
+    fn test_supervisor_enable() {
+        let program = [
+            0x00051087u32, // flh f1, 0(x10)
+            0x1005a073,    // csrs sstatus, x11
+            0x00051087,    // flh f1, 0(x10)
+        ];
+        let mut ram = load(&program);
+        ram.write(DATA, &0x3e00u16.to_le_bytes()).unwrap();
+        let mut hart = hart();
+        hart.write_csr(Csr::Mstatus, 0);
+        hart.set_privilege(Privilege::Supervisor);
+        hart.reg_mut().set_rd(IRd::X11, 0b01 << 13);
+
+        // Kernels enable floating-point state for themselves through `sstatus`
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.set_pc(BASE + 4);
+        for _ in 0..2 {
+            hart.step(&mut ram).unwrap();
+        }
+        assert_eq!(half(&hart, FRs1::F1), 0x3e00);
+        assert_eq!(hart.read_csr(Csr::Sstatus), 1 << 63 | 0b11 << 13);
+    }
+
+    #[test]
+    fn test_guest_disabled() {
+        #[derive(Clone, Copy)]
+        struct Hypervisor;
+
+        impl Extensions for Hypervisor {
+            const H: bool = true;
+        }
+
+        let program = [
+            0x00053087u32, // fld f1, 0(x10)
+        ];
+        let mut ram = load(&program);
+        let mut hart: Hart<0, u64, false, true, false, f64, false, false, Hypervisor> = boot();
+        hart.write_csr(Csr::Mstatus, 0b01 << 13);
+        hart.set_privilege(Privilege::Supervisor);
+        hart.set_virtualized(true);
+
+        // Guests need floating-point state enabled in `vsstatus` as well
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.write_csr(Csr::Vsstatus, 0b01 << 13);
+        hart.reg_mut().set_rd(IRd::X10, DATA);
+        hart.step(&mut ram).unwrap();
+        // ...and dirty it in both
+        for csr in [Csr::Mstatus, Csr::Vsstatus] {
+            assert_eq!(hart.read_csr(csr), 1 << 63 | 0b11 << 13);
+        }
+    }
+
+    #[test]
+    fn test_reserved_rounding() {
+        let program = [
+            0x023150d3u32, // fadd.d f1, f2, f3, rm=0b101
+            0x023170d3,    // fadd.d f1, f2, f3, dyn
+        ];
+        let mut ram = load(&program);
+        let mut hart = hart();
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.set_pc(BASE + 4);
+        hart.write_csr(Csr::Frm, 0b101);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        // Nothing executed, so the state is still clean
+        assert_eq!(hart.read_csr(Csr::Mstatus) >> 13 & 0b11, 0b01);
+    }
+
+    #[test]
+    fn test_half_precision() {
+        let program = [
+            0x0021d073u32, // csrwi frm, 3
+            0x00051087,    // flh f1, 0(x10)
+            0x00251107,    // flh f2, 2(x10)
+            0x1c20f1d3,    // fdiv.h f3, f1, f2
+            0x00351227,    // fsh f3, 4(x10)
+            0xe40182d3,    // fmv.x.h x5, f3
+            0x40218253,    // fcvt.s.h f4, f3
+            0x448202d3,    // fcvt.bf16.s f5, f4
+            0xa4111353,    // flt.h x6, f2, f1
+            0xc40113d3,    // fcvt.w.h x7, f2, rtz
+            0x14208443,    // fmadd.h f8, f1, f2, f2, rne
+        ];
+        let mut ram = load(&program);
+        ram.write(DATA, &[0x00, 0x3c, 0x00, 0x42]).unwrap();
+        let mut hart = hart();
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+
+        // 1/3 rounded up
+        let mut buf = [0; 2];
+        ram.read(DATA + 4, &mut buf).unwrap();
+        assert_eq!(u16::from_le_bytes(buf), 0x3556);
+        assert_eq!(half(&hart, FRs1::F3), 0x3556);
+        assert_eq!(hart.reg().get_rs1(IRs1::X5), 0x3556);
+        assert_eq!(
+            hart.freg().get_rs1(FRs1::F4).to_bits(),
+            0xffff_ffff_3eaa_c000
+        );
+        assert_eq!(half(&hart, FRs1::F5), 0x3eab);
+        assert_eq!(hart.reg().get_rs1(IRs1::X6), 0);
+        assert_eq!(hart.reg().get_rs1(IRs1::X7), 3);
+        assert_eq!(half(&hart, FRs1::F8), 0x4600);
+        assert_eq!(hart.csr().read(Csr::Fflags), 0b00001);
+        assert_eq!(hart.csr().read(Csr::Fcsr), 0b011_00001);
+        // Executing floating-point instructions marks the state dirty
+        assert_eq!(hart.csr().read(Csr::Mstatus) >> 13 & 0b11, 0b11);
+    }
+
+    #[test]
+    fn test_nan_boxing() {
+        let program = [
+            0x04208053u32, // fadd.h f0, f1, f2, rne
+        ];
+        let mut ram = load(&program);
+        let mut hart = hart();
+        // A single-precision 1.0 isn't a properly boxed half-precision value
+        hart.freg_mut()
+            .set_rd(FRd::F1, f64::from_bits(0xffff_ffff_3f80_0000));
+        hart.step(&mut ram).unwrap();
+        assert_eq!(half(&hart, FRs1::F0), 0x7e00);
+        assert_eq!(hart.csr().read(Csr::Fflags), 0);
+    }
+
+    #[test]
+    fn test_quad_precision() {
+        let program = [
+            0x00054087u32, // flq f1, 0(x10)
+            0x01054107,    // flq f2, 16(x10)
+            0x1e20f1d3,    // fdiv.q f3, f1, f2
+            0x02354027,    // fsq f3, 32(x10)
+            0x4231f253,    // fcvt.d.q f4, f3
+            0xc62112d3,    // fcvt.l.q x5, f2, rtz
+            0xd622f3d3,    // fcvt.q.l f7, x5
+        ];
+        let mut ram = load(&program);
+        let one = F128::from(1.0).to_bits();
+        let three = F128::from(3.0).to_bits();
+        ram.write(DATA, &one.to_le_bytes()).unwrap();
+        ram.write(DATA + 16, &three.to_le_bytes()).unwrap();
+        let mut hart: Rv64q = boot();
+        hart.write_csr(Csr::Mstatus, 0b01 << 13);
+        hart.reg_mut().set_rd(IRd::X10, DATA);
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+
+        let third = 0x3ffd_5555_5555_5555_5555_5555_5555_5555;
+        let mut buf = [0; 16];
+        ram.read(DATA + 32, &mut buf).unwrap();
+        assert_eq!(u128::from_le_bytes(buf), third);
+        assert_eq!(hart.freg().get_rs1(FRs1::F3).to_bits(), third);
+        // Doubles are NaN-boxed in the 128-bit registers
+        let double = (1.0f64 / 3.0).to_bits() as u128;
+        assert_eq!(hart.freg().get_rs1(FRs1::F4).to_bits(), double | !0 << 64);
+        assert_eq!(hart.reg().get_rs1(IRs1::X5), 3);
+        assert_eq!(hart.freg().get_rs1(FRs1::F7).to_bits(), three);
+        assert_eq!(hart.csr().read(Csr::Fflags), 0b00001);
+        assert_eq!(
+            hart.csr().read(Csr::Misa) & (1 << (b'Q' - b'A')),
+            1 << (b'Q' - b'A')
+        );
+    }
+}
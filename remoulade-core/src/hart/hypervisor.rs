@@ -0,0 +1,337 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! The hypervisor extension: virtualization mode, VS CSR swapping and the virtual-machine load
+//! and store instructions.
+
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::FRegType,
+    hart::{exec::Error, translate::Context, Hart, Privilege},
+    inst::HKind,
+    mmu::pmp::Access,
+    reg::{IRd, IRs1, IRs2, RegType},
+};
+
+pub(super) const HSTATUS_GVA: u64 = 1 << 6;
+pub(super) const HSTATUS_SPV: u64 = 1 << 7;
+pub(super) const HSTATUS_SPVP: u64 = 1 << 8;
+const HSTATUS_HU: u64 = 1 << 9;
+pub(super) const HSTATUS_VTVM: u64 = 1 << 20;
+const HSTATUS_VTW: u64 = 1 << 21;
+pub(super) const HSTATUS_VTSR: u64 = 1 << 22;
+
+pub(super) const MSTATUS_TVM: u64 = 1 << 20;
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Whether the hart runs a guest, i.e. in VS- or VU-mode.
+    pub fn virtualized(&self) -> bool {
+        self.virt
+    }
+
+    /// Switches virtualization mode, e.g. to enter a guest without an `sret`.
+    ///
+    /// Ignored without the hypervisor extension.
+    pub fn set_virtualized(&mut self, virt: bool) {
+        self.virt = X::H && virt;
+    }
+
+    pub(super) fn write_hstatus(&mut self, value: u64) {
+        let writable = HSTATUS_GVA
+            | HSTATUS_SPV
+            | HSTATUS_SPVP
+            | HSTATUS_HU
+            | HSTATUS_VTVM
+            | HSTATUS_VTW
+            | HSTATUS_VTSR;
+        // NOTE: VSXL is fixed to the width of the hart.
+        let vsxl = match I::BITS {
+            32 => 0,
+            _ => 2 << 32,
+        };
+        self.csr.write(Csr::Hstatus, value & writable | vsxl);
+    }
+
+    /// Maps `csr` to the register an access from the current mode reaches.
+    ///
+    /// Guests reach the VS CSRs through the supervisor CSR addresses.
+    pub(super) fn virtualize_csr(&self, csr: Csr) -> Result<Csr, Error> {
+        let supervisor = csr.offset_in(Csr::Sstatus, Csr::Scontext).is_some();
+        let hypervisor = csr.offset_in(Csr::Hstatus, Csr::Vsatp).is_some();
+
+        if hypervisor && !X::H {
+            Err(Error::IllegalInstruction)?;
+        }
+        // NOTE: Accesses from guests that HS-mode could make trap to the hypervisor.
+        if self.virt && (hypervisor || supervisor && self.privilege == Privilege::User) {
+            Err(Error::VirtualInstruction)?;
+        }
+        if (hypervisor || supervisor) && self.privilege == Privilege::User {
+            Err(Error::IllegalInstruction)?;
+        }
+        let tvm = self.csr.read(Csr::Mstatus) & MSTATUS_TVM != 0;
+        if matches!(csr, Csr::Hgatp) && tvm && self.privilege == Privilege::Supervisor && !self.virt
+        {
+            Err(Error::IllegalInstruction)?;
+        }
+        if !self.virt {
+            return Ok(csr);
+        }
+
+        let csr = match csr {
+            Csr::Sstatus => Csr::Vsstatus,
+            Csr::Ssie => Csr::Vsie,
+            Csr::Stvec => Csr::Vstvec,
+            Csr::Sscratch => Csr::Vsscratch,
+            Csr::Sepc => Csr::Vsepc,
+            Csr::Scause => Csr::Vscause,
+            Csr::Stval => Csr::Vstval,
+            Csr::Sip => Csr::Vsip,
+            Csr::Satp if self.csr.read(Csr::Hstatus) & HSTATUS_VTVM != 0 => {
+                Err(Error::VirtualInstruction)?
+            }
+            Csr::Satp => Csr::Vsatp,
+            csr => csr,
+        };
+        Ok(csr)
+    }
+
+    /// Executes a hypervisor load, store or fence.
+    ///
+    /// Loads and stores are made as if from the guest mode in `hstatus.SPVP`.
+    pub(super) fn hypervisor(
+        &mut self,
+        bus: &mut impl Bus,
+        rd: IRd,
+        rs1: IRs1,
+        rs2: IRs2,
+        kind: HKind,
+    ) -> Result<(), Error> {
+        use HKind::*;
+
+        if self.virt {
+            Err(Error::VirtualInstruction)?;
+        }
+        let hstatus = self.csr.read(Csr::Hstatus);
+        let fence = matches!(kind, HfenceVvma | HfenceGvma);
+        let permitted = match self.privilege {
+            Privilege::Machine => true,
+            Privilege::Supervisor => {
+                kind != HfenceGvma || self.csr.read(Csr::Mstatus) & MSTATUS_TVM == 0
+            }
+            Privilege::User => !fence && hstatus & HSTATUS_HU != 0,
+        };
+        if !permitted {
+            Err(Error::IllegalInstruction)?;
+        }
+        // NOTE: Without a TLB, only translated blocks depend on the page tables.
+        if fence {
+            self.blocks.flush();
+            return Ok(());
+        }
+
+        let (width, signed, access) = match kind {
+            HlvB => (1, true, Access::Read),
+            HlvBu => (1, false, Access::Read),
+            HlvH => (2, true, Access::Read),
+            HlvHu | HlvxHu => (2, false, Access::Read),
+            HlvW => (4, true, Access::Read),
+            HlvWu | HlvxWu => (4, false, Access::Read),
+            HlvD => (8, true, Access::Read),
+            HsvB => (1, false, Access::Write),
+            HsvH => (2, false, Access::Write),
+            HsvW => (4, false, Access::Write),
+            HsvD => (8, false, Access::Write),
+            HfenceVvma | HfenceGvma => unreachable!(),
+        };
+        let context = Context {
+            privilege: match hstatus & HSTATUS_SPVP {
+                0 => Privilege::User,
+                _ => Privilege::Supervisor,
+            },
+            virt: true,
+            hlvx: matches!(kind, HlvxHu | HlvxWu),
+        };
+
+        let fault = match access {
+            Access::Write => Error::StoreOrAmoAccessFault,
+            _ => Error::LoadAccessFault,
+        };
+        let addr = self.reg.get_rs1(rs1).as_addr().ok_or(fault)?;
+        let addr = self.translate_addr_in(bus, addr, width, access, context)?;
+        let width = width as usize;
+
+        match access {
+            Access::Write => {
+                let value = self.reg.get_rs2(rs2).as_u128().to_le_bytes();
+                bus.write(addr, &value[..width]).map_err(|_| fault)?;
+                self.blocks.invalidate(addr, width as u64);
+            }
+            _ => {
+                let mut buf = [0; 16];
+                bus.read(addr, &mut buf[..width]).map_err(|_| fault)?;
+                let value = u128::from_le_bytes(buf);
+                let shift = 128 - 8 * width;
+                let value = match signed {
+                    true => ((value << shift) as i128 >> shift) as u128,
+                    false => value,
+                };
+                self.reg.set_rd(rd, I::from_u128(value));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bus::{Bus, Ram},
+        csr::Csr,
+        ext::Extensions,
+        hart::{
+            exec::Error,
+            testing::{boot, write_program},
+            translate::Fault,
+            Hart, Privilege,
+        },
+        reg::{IRd, IRs1},
+    };
+
+    #[derive(Clone, Copy)]
+    struct Hypervisor;
+
+    impl Extensions for Hypervisor {
+        const H: bool = true;
+    }
+
+    const V: u64 = 1 << 0;
+    const R: u64 = 1 << 1;
+    const W: u64 = 1 << 2;
+    const X: u64 = 1 << 3;
+    const U: u64 = 1 << 4;
+    const A: u64 = 1 << 6;
+    const D: u64 = 1 << 7;
+
+    fn pte(ram: &mut Ram, addr: u64, pte: u64) {
+        ram.write(addr, &pte.to_le_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_two_stage() {
+        let mut ram = Ram::new(0, 0x40_0000);
+        let program = [
+            0x0005a503u32, // lw x10, 0(x11)
+            0x00a5a223,    // sw x10, 4(x11)
+            0x18002673,    // csrr x12, satp
+            0x60002673,    // csrr x12, hstatus
+        ];
+        write_program(&mut ram, 0x10_0000, &program);
+        ram.write(0x20_0000, &0x1234_5678u32.to_le_bytes()).unwrap();
+
+        // G-stage: megapages mapping guest-physical 0x0 and 0x20_0000 to the same host-physical
+        // addresses, the latter without write permission
+        let (groot, gnext) = (0x1_0000, 0x1_4000);
+        pte(&mut ram, groot, gnext >> 12 << 10 | V);
+        pte(&mut ram, gnext, V | R | W | X | U | A | D);
+        pte(&mut ram, gnext + 8, 0x20_0000 >> 12 << 10 | V | R | U | A);
+        // VS-stage: guest-virtual 0x4000_0000 maps to guest-physical 0x0 as a gigapage
+        let vsroot = 0x2_0000;
+        pte(&mut ram, vsroot + 8, V | R | W | X | A | D);
+
+        let mut hart = Hart::<0, u64, false, false, false, (), false, false, Hypervisor>::new();
+        assert_eq!(hart.csr().read(Csr::Misa) >> 7 & 1, 1);
+        hart.write_csr(Csr::Hgatp, 8 << 60 | groot >> 12);
+        hart.write_csr(Csr::Vsatp, 8 << 60 | vsroot >> 12);
+        hart.set_privilege(Privilege::Supervisor);
+        hart.set_virtualized(true);
+        hart.set_pc(0x4010_0000);
+        hart.reg_mut().set_rd(IRd::X11, 0x4020_0000);
+
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.reg().get_rs1(IRs1::X10), 0x1234_5678);
+        // The store misses write permission in the G-stage
+        assert_eq!(hart.step(&mut ram), Err(Error::StoreOrAmoGuestPageFault));
+        let fault = Fault {
+            tval: 0x4020_0004,
+            gva: true,
+            tval2: 0x20_0004 >> 2,
+            tinst: 0,
+        };
+        assert_eq!(hart.fault(), Some(fault));
+
+        // Supervisor CSRs are swapped for their VS counterparts, and H CSRs are off limits
+        hart.set_pc(0x4010_0008);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.reg().get_rs1(IRs1::X12), 8 << 60 | vsroot >> 12);
+        assert_eq!(hart.step(&mut ram), Err(Error::VirtualInstruction));
+
+        // VS-stage tables are read through the G-stage
+        hart.write_csr(Csr::Vsatp, 8 << 60 | 0x20_0000 >> 12);
+        hart.set_pc(0x4010_0000);
+        assert_eq!(hart.step(&mut ram), Err(Error::InstructionPageFault));
+        hart.write_csr(Csr::Vsatp, 8 << 60 | 0x40_0000 >> 12);
+        assert_eq!(hart.step(&mut ram), Err(Error::InstructionGuestPageFault));
+        let fault = Fault {
+            tval: 0x4010_0000,
+            gva: true,
+            tval2: (0x40_0000 + 8) >> 2,
+            tinst: 0x3000,
+        };
+        assert_eq!(hart.fault(), Some(fault));
+    }
+
+    #[test]
+    fn test_hypervisor_loads() {
+        let mut ram = Ram::new(0, 0x10_0000);
+        let program = [
+            0x6815c573u32, // hlv.wu x10, (x11)
+            0x6ab5c073,    // hsv.w x11, (x11)
+            0x6835c573,    // hlvx.wu x10, (x11)
+            0x62000073,    // hfence.gvma
+        ];
+        write_program(&mut ram, 0x1000, &program);
+        ram.write(0x8000, &0xdead_beefu32.to_le_bytes()).unwrap();
+
+        let mut hart: Hart<0, u64, false, false, false, (), false, false, Hypervisor> = boot();
+        hart.reg_mut().set_rd(IRd::X11, 0x8000);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.reg().get_rs1(IRs1::X10), 0xdead_beef);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(&ram.as_slice()[0x8000..0x8004], &0x8000u32.to_le_bytes());
+
+        // Guest pages without execute permission can't be read by `hlvx`
+        let root = 0x2_0000;
+        pte(&mut ram, root, V | R | W | U | A | D);
+        hart.write_csr(Csr::Hgatp, 8 << 60 | root >> 12);
+        assert_eq!(hart.step(&mut ram), Err(Error::LoadGuestPageFault));
+
+        // User mode needs `hstatus.HU`, and guests can't use them at all
+        hart.set_pc(0x100c);
+        hart.set_privilege(Privilege::User);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.set_privilege(Privilege::Supervisor);
+        hart.set_virtualized(true);
+        hart.write_csr(Csr::Hgatp, 0);
+        assert_eq!(hart.step(&mut ram), Err(Error::VirtualInstruction));
+    }
+}
@@ -0,0 +1,576 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Translation of hot blocks into native x86-64 code.
+//!
+//! Only the leading run of instructions that can never trap is translated: integer computation,
+//! `LUI`/`AUIPC`, and branches and jumps whose targets are known to be aligned.
+//! The rest of the block, including every memory access, CSR access and anything that might raise
+//! an exception, is left to the interpreter, which picks up where the native code stops.
+//! Exceptions are therefore always raised by the interpreter with precise state.
+//!
+//! Native code has the signature `extern "sysv64" fn(regs: *mut u64) -> u64` where `regs` points
+//! at the [`RegFile`](crate::reg::RegFile) and the return value is the next `pc`.
+//! Only RV64 harts are translated.
+
+use std::{any::TypeId, ffi::c_void};
+
+use crate::{
+    ext::Extensions,
+    freg::FRegType,
+    hart::Hart,
+    inst::{BKind, IKind, Instruction, RKind, UKind},
+    reg::RegType,
+};
+
+/// Number of times a block must be entered before it is translated.
+pub(super) const HOT_THRESHOLD: u32 = 16;
+
+pub(super) struct Native {
+    code: ExecutableMemory,
+    /// Number of instructions at the start of the block the native code executes
+    covered: usize,
+}
+
+impl Native {
+    /// # Safety
+    ///
+    /// `regs` must point at a register file of 33 `u64`s.
+    unsafe fn call(&self, regs: *mut u64) -> u64 {
+        let f: extern "sysv64" fn(*mut u64) -> u64 = std::mem::transmute(self.code.ptr);
+        f(regs)
+    }
+}
+
+/// Translates the longest supported prefix of the block starting at `start`.
+pub(super) fn compile<
+    I: RegType,
+    const E: bool,
+    const M: bool,
+    const A: bool,
+    F: FRegType,
+    X: Extensions,
+>(
+    start: u64,
+    insts: &[(Instruction<I, E, M, A, F, X>, u8)],
+    alignment: u64,
+) -> Option<Native> {
+    if TypeId::of::<I>() != TypeId::of::<u64>() {
+        return None;
+    }
+
+    let mut e = Emitter::default();
+    let mut pc = start;
+    let mut covered = 0;
+    let mut exited = false;
+
+    for &(inst, len) in insts {
+        let next = pc.wrapping_add(len as u64);
+        match e.emit(inst, pc, next, alignment) {
+            Emitted::Unsupported => break,
+            Emitted::Continue => {}
+            Emitted::Exit => exited = true,
+        }
+        covered += 1;
+        pc = next;
+        if exited {
+            break;
+        }
+    }
+
+    if covered == 0 {
+        return None;
+    }
+    if !exited {
+        e.mov_imm(RAX, pc);
+        e.ret();
+    }
+
+    let code = ExecutableMemory::new(&e.code)?;
+    Some(Native { code, covered })
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Runs the native code for block `index` if it has been translated and fits in `budget`.
+    ///
+    /// Native code only covers the prefix of a block that can't trap, made of integer
+    /// computation, `LUI`/`AUIPC`, branches and `JAL`, and only on RV64.
+    /// It doesn't count events, check triggers, trace, model the caches or halt after single
+    /// steps either, so callers interpret the block instead whenever any of that is needed.
+    ///
+    /// Returns the number of instructions executed, after which the interpreter continues with
+    /// the rest of the block.
+    pub(super) fn run_native(&mut self, index: usize, budget: u64) -> usize {
+        let alignment = if C { 2 } else { 4 };
+        let Some(native) = self.blocks.native(index, alignment) else {
+            return 0;
+        };
+        if native.covered as u64 > budget {
+            return 0;
+        }
+
+        // SAFETY: `compile` only produces code for `u64` registers, and register files have 33.
+        let next = unsafe { native.call(self.reg.as_mut_ptr() as *mut u64) };
+        self.pc = I::from_u128(next as u128);
+        native.covered
+    }
+}
+
+enum Emitted {
+    Unsupported,
+    Continue,
+    /// The native code returns after this instruction
+    Exit,
+}
+
+type Scratch = u8;
+const RAX: Scratch = 0;
+const RCX: Scratch = 1;
+const RDX: Scratch = 2;
+
+// Condition codes for `setcc`/`cmovcc`
+const CC_B: u8 = 0x2;
+const CC_AE: u8 = 0x3;
+const CC_E: u8 = 0x4;
+const CC_NE: u8 = 0x5;
+const CC_L: u8 = 0xc;
+const CC_GE: u8 = 0xd;
+
+#[derive(Default)]
+struct Emitter {
+    code: Vec<u8>,
+}
+
+impl Emitter {
+    fn emit<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions>(
+        &mut self,
+        inst: Instruction<I, E, M, A, F, X>,
+        pc: u64,
+        next: u64,
+        alignment: u64,
+    ) -> Emitted {
+        use Instruction::*;
+        match inst {
+            UType { rd, u, kind } => {
+                let u = u.i32() as i64 as u64;
+                let value = match kind {
+                    UKind::Lui => u,
+                    UKind::Auipc => pc.wrapping_add(u),
+                };
+                self.mov_imm(RAX, value);
+                self.store(rd as usize, RAX);
+            }
+
+            IType { rd, rs1, i, kind } => {
+                let imm = i.i32() as i64 as u64;
+                let op = match kind {
+                    IKind::Addi => Op::Add,
+                    IKind::Slti => Op::Set(CC_L),
+                    IKind::Sltiu => Op::Set(CC_B),
+                    IKind::Xori => Op::Xor,
+                    IKind::Ori => Op::Or,
+                    IKind::Andi => Op::And,
+                    IKind::Slli => Op::Shift(4),
+                    IKind::Srli => Op::Shift(5),
+                    IKind::Srai => Op::Shift(7),
+                    _ => return Emitted::Unsupported,
+                };
+                self.load(RAX, rs1 as usize);
+                self.mov_imm(RCX, imm);
+                self.op(op);
+                self.store(rd as usize, RAX);
+            }
+
+            RType { rd, rs1, rs2, kind } => {
+                let op = match kind {
+                    RKind::Add => Op::Add,
+                    RKind::Sub => Op::Sub,
+                    RKind::Sll => Op::Shift(4),
+                    RKind::Slt => Op::Set(CC_L),
+                    RKind::Sltu => Op::Set(CC_B),
+                    RKind::Xor => Op::Xor,
+                    RKind::Srl => Op::Shift(5),
+                    RKind::Sra => Op::Shift(7),
+                    RKind::Or => Op::Or,
+                    RKind::And => Op::And,
+                    RKind::Mul => Op::Mul,
+                    _ => return Emitted::Unsupported,
+                };
+                self.load(RAX, rs1 as usize);
+                self.load(RCX, rs2 as usize);
+                self.op(op);
+                self.store(rd as usize, RAX);
+            }
+
+            BType { rs1, rs2, b, kind } => {
+                let target = pc.wrapping_add(b.i32() as i64 as u64);
+                if !target.is_multiple_of(alignment) {
+                    return Emitted::Unsupported;
+                }
+                let cc = match kind {
+                    BKind::Beq => CC_E,
+                    BKind::Bne => CC_NE,
+                    BKind::Blt => CC_L,
+                    BKind::Bge => CC_GE,
+                    BKind::Bltu => CC_B,
+                    BKind::Bgeu => CC_AE,
+                };
+                self.load(RAX, rs1 as usize);
+                self.load(RCX, rs2 as usize);
+                self.cmp();
+                self.mov_imm(RAX, next);
+                self.mov_imm(RDX, target);
+                self.cmov(cc);
+                self.ret();
+                return Emitted::Exit;
+            }
+
+            Jal { rd, j } => {
+                let target = pc.wrapping_add(j.i32() as i64 as u64);
+                if !target.is_multiple_of(alignment) {
+                    return Emitted::Unsupported;
+                }
+                self.mov_imm(RAX, next);
+                self.store(rd as usize, RAX);
+                self.mov_imm(RAX, target);
+                self.ret();
+                return Emitted::Exit;
+            }
+
+            _ => return Emitted::Unsupported,
+        }
+
+        Emitted::Continue
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// `mov reg, [rdi + 8 * x]`
+    fn load(&mut self, reg: Scratch, x: usize) {
+        self.bytes(&[0x48, 0x8b, 0x87 | reg << 3]);
+        self.bytes(&(8 * x as u32).to_le_bytes());
+    }
+
+    /// `mov [rdi + 8 * x], reg`
+    fn store(&mut self, x: usize, reg: Scratch) {
+        self.bytes(&[0x48, 0x89, 0x87 | reg << 3]);
+        self.bytes(&(8 * x as u32).to_le_bytes());
+    }
+
+    /// `movabs reg, value`
+    fn mov_imm(&mut self, reg: Scratch, value: u64) {
+        self.bytes(&[0x48, 0xb8 + reg]);
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// `rax = rax <op> rcx`
+    fn op(&mut self, op: Op) {
+        match op {
+            Op::Add => self.bytes(&[0x48, 0x01, 0xc8]),
+            Op::Sub => self.bytes(&[0x48, 0x29, 0xc8]),
+            Op::Xor => self.bytes(&[0x48, 0x31, 0xc8]),
+            Op::Or => self.bytes(&[0x48, 0x09, 0xc8]),
+            Op::And => self.bytes(&[0x48, 0x21, 0xc8]),
+            Op::Mul => self.bytes(&[0x48, 0x0f, 0xaf, 0xc1]),
+            // NOTE: x86-64 masks 64-bit shift amounts to 6 bits, just like RV64.
+            Op::Shift(ext) => self.bytes(&[0x48, 0xd3, 0xc0 | ext << 3]),
+            Op::Set(cc) => {
+                self.cmp();
+                // setcc al; movzx eax, al
+                self.bytes(&[0x0f, 0x90 | cc, 0xc0, 0x0f, 0xb6, 0xc0]);
+            }
+        }
+    }
+
+    /// `cmp rax, rcx`
+    fn cmp(&mut self) {
+        self.bytes(&[0x48, 0x39, 0xc8]);
+    }
+
+    /// `cmovcc rax, rdx`
+    fn cmov(&mut self, cc: u8) {
+        self.bytes(&[0x48, 0x0f, 0x40 | cc, 0xc2]);
+    }
+
+    fn ret(&mut self) {
+        self.bytes(&[0xc3]);
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Op {
+    Add,
+    Sub,
+    Xor,
+    Or,
+    And,
+    Mul,
+    /// Shift with the given `/digit` opcode extension
+    Shift(u8),
+    /// Compare, setting `rax` to 1 if the condition holds and 0 otherwise
+    Set(u8),
+}
+
+const PROT_READ: i32 = 0x1;
+const PROT_WRITE: i32 = 0x2;
+const PROT_EXEC: i32 = 0x4;
+const MAP_PRIVATE: i32 = 0x02;
+const MAP_ANONYMOUS: i32 = 0x20;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, off: i64)
+        -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+/// A private mapping that is writable while being filled and executable afterwards.
+///
+/// Each translated block owns one until it is invalidated or the cache is flushed, so no more
+/// than the cache's limit on blocks are mapped at once.
+struct ExecutableMemory {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+// SAFETY: The mapping is owned exclusively and never written after construction.
+unsafe impl Send for ExecutableMemory {}
+
+impl ExecutableMemory {
+    fn new(code: &[u8]) -> Option<Self> {
+        let len = code.len();
+        // SAFETY: Anonymous mappings don't alias anything, and the mapping is only made
+        //         executable after the copy into it.
+        unsafe {
+            let ptr = mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr as isize == -1 {
+                return None;
+            }
+            let mem = Self { ptr, len };
+            std::ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, len);
+            if mprotect(ptr, len, PROT_READ | PROT_EXEC) != 0 {
+                return None;
+            }
+            Some(mem)
+        }
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        // SAFETY: The mapping was created by `mmap` with this length.
+        unsafe {
+            munmap(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        csr::Csr,
+        hart::{
+            exec::Error,
+            testing::{boot, load, BASE},
+            Hart,
+        },
+        reg::{IRs1, RegType},
+    };
+
+    type Rv64 = Hart<0, u64, false, true, false, (), false, false>;
+
+    fn r(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32) -> u32 {
+        funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | 0b0110011
+    }
+
+    fn i(imm: i32, rs1: u32, funct3: u32, rd: u32) -> u32 {
+        (imm as u32 & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | 0b0010011
+    }
+
+    fn b(imm: i32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+        let imm = imm as u32;
+        (imm >> 12 & 1) << 31
+            | (imm >> 5 & 0x3f) << 25
+            | rs2 << 20
+            | rs1 << 15
+            | funct3 << 12
+            | (imm >> 1 & 0xf) << 8
+            | (imm >> 11 & 1) << 7
+            | 0b1100011
+    }
+
+    /// Small xorshift generator so failures are reproducible from the seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 32) as u32
+        }
+
+        fn below(&mut self, n: u32) -> u32 {
+            self.next() % n
+        }
+    }
+
+    /// Generates a loop over random computation on `x1..=x30`, counted down in `x31`.
+    fn program(rng: &mut Rng) -> Vec<u32> {
+        let mut program = vec![i(25, 0, 0b000, 31)];
+        for n in 0..24 {
+            let rd = 1 + rng.below(30);
+            let rs1 = rng.below(31);
+            let rs2 = rng.below(31);
+            // NOTE: A branch in the last slot would skip the loop counter decrement.
+            let kinds = if n == 23 { 5 } else { 6 };
+            let inst = match rng.below(kinds) {
+                0 => {
+                    let (funct7, funct3) = [
+                        (0, 0b000),
+                        (0b0100000, 0b000),
+                        (0, 0b001),
+                        (0, 0b010),
+                        (0, 0b011),
+                        (0, 0b100),
+                        (0, 0b101),
+                        (0b0100000, 0b101),
+                        (0, 0b110),
+                        (0, 0b111),
+                        (1, 0b000),
+                    ][rng.below(11) as usize];
+                    r(funct7, rs2, rs1, funct3, rd)
+                }
+                1 => {
+                    let funct3 = [0b000, 0b010, 0b011, 0b100, 0b110, 0b111][rng.below(6) as usize];
+                    i(rng.next() as i32 >> 20, rs1, funct3, rd)
+                }
+                2 => {
+                    let (funct7, funct3) =
+                        [(0, 0b001), (0, 0b101), (0b0100000, 0b101)][rng.below(3) as usize];
+                    i((funct7 << 5 | rng.below(32)) as i32, rs1, funct3, rd)
+                }
+                3 => rng.next() & 0xfffff000 | rd << 7 | 0b0110111,
+                4 => rng.next() & 0xfffff000 | rd << 7 | 0b0010111,
+                _ => {
+                    // Skip the next instruction
+                    let funct3 = [0b000, 0b001, 0b100, 0b101, 0b110, 0b111][rng.below(6) as usize];
+                    b(8, rs2, rs1, funct3)
+                }
+            };
+            program.push(inst);
+        }
+        program.push(i(-1, 31, 0b000, 31));
+        let offset = -4 * (program.len() as i32 - 1);
+        program.push(b(offset, 0, 31, 0b001));
+        program.push(0x00000073); // ecall
+        program
+    }
+
+    #[test]
+    fn test_differential() {
+        for seed in 1..=64 {
+            let mut rng = Rng(seed);
+            let program = program(&mut rng);
+
+            let mut interpreted: Rv64 = boot();
+            let mut ram = load(&program);
+            while interpreted.step(&mut ram).is_ok() {}
+
+            let mut translated: Rv64 = boot();
+            let mut ram = load(&program);
+            assert_eq!(
+                translated.run(&mut ram, u64::MAX),
+                Err(Error::EcallFromMMode)
+            );
+
+            assert_eq!(translated.pc(), interpreted.pc(), "seed {seed}");
+            for x in 0..32 {
+                let x = IRs1::wrapping_from_u32(x);
+                assert_eq!(
+                    translated.reg().get_rs1(x).as_u128(),
+                    interpreted.reg().get_rs1(x).as_u128(),
+                    "{x:?} differs for seed {seed}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_partial_budget() {
+        let program = [
+            i(1, 1, 0b000, 1),  // addi x1, x1, 1
+            i(2, 2, 0b000, 2),  // addi x2, x2, 2
+            b(-8, 0, 0, 0b000), // beq x0, x0, -8
+        ];
+
+        let mut hart: Rv64 = boot();
+        let mut ram = load(&program);
+
+        // Enough iterations to translate the loop, stopping in the middle of it
+        let budget = 3 * 2 * super::HOT_THRESHOLD as u64 + 1;
+        assert_eq!(hart.run(&mut ram, budget), Ok(()));
+        assert_eq!(
+            hart.reg().get_rs1(IRs1::X1),
+            2 * super::HOT_THRESHOLD as u64 + 1
+        );
+        assert_eq!(
+            hart.reg().get_rs1(IRs1::X2),
+            4 * super::HOT_THRESHOLD as u64
+        );
+        assert_eq!(hart.pc(), BASE + 4);
+    }
+
+    #[test]
+    fn test_single_step() {
+        let program = [
+            i(1, 1, 0b000, 1),  // addi x1, x1, 1
+            i(2, 2, 0b000, 2),  // addi x2, x2, 2
+            b(-8, 0, 0, 0b000), // beq x0, x0, -8
+        ];
+
+        let mut hart: Rv64 = boot();
+        let mut ram = load(&program);
+        let budget = 3 * 2 * super::HOT_THRESHOLD as u64;
+        assert_eq!(hart.run(&mut ram, budget), Ok(()));
+
+        // Stepping through the translated loop runs one instruction of it
+        hart.halt();
+        let dcsr = hart.read_csr(Csr::Dcsr);
+        hart.write_csr(Csr::Dcsr, dcsr | 1 << 2);
+        hart.resume();
+        assert_eq!(hart.run(&mut ram, 8), Ok(()));
+        assert!(hart.debug_mode());
+        assert_eq!(hart.read_csr(Csr::Dpc), BASE + 4);
+        assert_eq!(
+            hart.reg().get_rs1(IRs1::X1),
+            2 * super::HOT_THRESHOLD as u64 + 1
+        );
+    }
+}
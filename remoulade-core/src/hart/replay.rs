@@ -0,0 +1,93 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::FRegType,
+    hart::{exec::Error, Hart},
+    inst::AmoKind,
+    reg::RegType,
+    replay::{Event, Journal},
+};
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Records the nondeterministic inputs of this hart to `journal`, or replays them from it.
+    ///
+    /// Events are logged as happening on hart `ID`.
+    pub fn set_journal(&mut self, journal: Option<Journal>) {
+        self.journal = journal;
+    }
+
+    /// Reads `seed`, consuming entropy.
+    pub(super) fn logged_seed(&mut self) -> u32 {
+        let Some(journal) = self.journal.clone() else {
+            return self.entropy.seed();
+        };
+        let mut turn = journal.turn(ID);
+        let value = match turn.replayed() {
+            Some(&Event::Seed(value)) => value,
+            _ => self.entropy.seed(),
+        };
+        turn.commit(Event::Seed(value));
+        value
+    }
+
+    /// Reads `time` or `timeh` for an instruction.
+    pub(super) fn logged_time(&self, csr: Csr) -> u64 {
+        let Some(journal) = &self.journal else {
+            return self.read_csr(csr);
+        };
+        let mut turn = journal.turn(ID);
+        let value = match turn.replayed() {
+            Some(&Event::Time(value)) => value,
+            _ => self.read_csr(csr),
+        };
+        turn.commit(Event::Time(value));
+        value
+    }
+
+    /// Executes an AMO, `lr` or `sc`, ordered against those of the other harts.
+    pub(super) fn logged_amo(
+        &mut self,
+        bus: &mut impl Bus,
+        addr: I,
+        src: I,
+        kind: AmoKind,
+    ) -> Result<I, Error> {
+        let Some(journal) = self.journal.clone() else {
+            return self.amo(bus, addr, src, kind, None);
+        };
+        let mut turn = journal.turn(ID);
+        let sc = matches!(kind, AmoKind::Scw | AmoKind::Scd);
+        // NOTE: The outcome of `sc` is replayed as recorded, whatever the reservation says.
+        let outcome = match turn.replayed() {
+            Some(&Event::Sc(success)) if sc => Some(success),
+            _ => None,
+        };
+        let value = self.amo(bus, addr, src, kind, outcome)?;
+        turn.commit(match sc {
+            true => Event::Sc(value == I::default()),
+            false => Event::Atomic,
+        });
+        Ok(value)
+    }
+}
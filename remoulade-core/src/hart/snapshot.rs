@@ -0,0 +1,293 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+use crate::{
+    ext::Extensions,
+    freg::FRegType,
+    hart::{crypto::Entropy, debug::Cause, Hart, Privilege},
+    reg::RegType,
+    snapshot::{Reader, Snapshot, SnapshotError, Writer},
+};
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// The configuration a snapshot has to be restored into, as `(name, value)` pairs.
+    fn configuration() -> [(&'static str, u64); 34] {
+        [
+            ("XLEN", I::BITS as u64),
+            ("FLEN", F::BITS as u64),
+            ("E", E as u64),
+            ("M", M as u64),
+            ("A", A as u64),
+            ("C", C as u64),
+            ("ZIFENCEI", ZIFENCEI as u64),
+            ("ZBA", X::ZBA as u64),
+            ("ZBB", X::ZBB as u64),
+            ("ZBC", X::ZBC as u64),
+            ("ZBS", X::ZBS as u64),
+            ("ZBKB", X::ZBKB as u64),
+            ("ZBKC", X::ZBKC as u64),
+            ("ZBKX", X::ZBKX as u64),
+            ("ZKND", X::ZKND as u64),
+            ("ZKNE", X::ZKNE as u64),
+            ("ZKNH", X::ZKNH as u64),
+            ("ZKSED", X::ZKSED as u64),
+            ("ZKSH", X::ZKSH as u64),
+            ("ZKR", X::ZKR as u64),
+            ("ZICOND", X::ZICOND as u64),
+            ("ZICBOM", X::ZICBOM as u64),
+            ("ZICBOZ", X::ZICBOZ as u64),
+            ("CBOZ_BLOCK_SIZE", X::CBOZ_BLOCK_SIZE),
+            ("ZIHINTPAUSE", X::ZIHINTPAUSE as u64),
+            ("ZFH", X::ZFH as u64),
+            ("ZFHMIN", X::ZFHMIN as u64),
+            ("ZFBFMIN", X::ZFBFMIN as u64),
+            ("H", X::H as u64),
+            ("PMP_ENTRIES", X::PMP_ENTRIES as u64),
+            ("SMEPMP", X::SMEPMP as u64),
+            ("TRIGGERS", X::TRIGGERS as u64),
+            ("VLEN", if X::V { X::VLEN as u64 } else { 0 }),
+            ("ELEN", if X::V { X::ELEN as u64 } else { 0 }),
+        ]
+    }
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Snapshot for Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    fn save(&self, w: &mut Writer) {
+        for (_, value) in Self::configuration() {
+            w.u64(value);
+        }
+
+        w.u128(self.pc.as_u128());
+        w.u8(self.privilege as u8);
+        self.reg.save(w);
+        self.freg.save(w);
+        self.vreg.save(w);
+        self.csr.save(w);
+
+        w.bool(self.reservation.is_some());
+        w.u64(self.reservation.unwrap_or(0));
+        match self.entropy {
+            Entropy::Deterministic(state) => {
+                w.u8(0);
+                w.u64(state);
+            }
+            Entropy::Host => w.u8(1),
+        }
+        for &counter in &self.counters {
+            w.u64(counter);
+        }
+        w.u32(self.hpm_active);
+        self.pmp.save(w);
+        w.bool(self.virt);
+        self.triggers.save(w);
+
+        w.bool(self.debug);
+        w.u8(self.halt.map_or(0, |cause| cause as u8));
+        w.bool(self.stepping);
+        w.bool(self.step_halt);
+    }
+
+    /// Restores a hart saved with the same configuration.
+    ///
+    /// Translated code is dropped and tracing carries on as it was.
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        for (name, value) in Self::configuration() {
+            r.expect(name, value)?;
+        }
+
+        self.pc = I::from_u128(r.u128()?);
+        self.privilege = match r.u8()? {
+            0 => Privilege::User,
+            1 => Privilege::Supervisor,
+            3 => Privilege::Machine,
+            privilege => Err(SnapshotError::Invalid(format!(
+                "{privilege} is not a privilege mode"
+            )))?,
+        };
+        self.reg.restore(r)?;
+        self.freg.restore(r)?;
+        self.vreg.restore(r)?;
+        self.csr.restore(r)?;
+
+        let reserved = r.bool()?;
+        let reservation = r.u64()?;
+        self.reservation = reserved.then_some(reservation);
+        self.entropy = match r.u8()? {
+            0 => Entropy::Deterministic(r.u64()?),
+            1 => Entropy::Host,
+            entropy => Err(SnapshotError::Invalid(format!(
+                "{entropy} is not a source of entropy"
+            )))?,
+        };
+        for counter in &mut self.counters {
+            *counter = r.u64()?;
+        }
+        self.hpm_active = r.u32()?;
+        self.pmp.restore(r)?;
+        self.virt = r.bool()?;
+        self.triggers.restore(r)?;
+        if !self.triggers_legal() {
+            Err(SnapshotError::Invalid(
+                "a trigger is configured in a way `tdata1` can't be".to_string(),
+            ))?;
+        }
+
+        self.debug = r.bool()?;
+        self.halt = match r.u8()? {
+            0 => None,
+            1 => Some(Cause::Ebreak),
+            2 => Some(Cause::Trigger),
+            3 => Some(Cause::HaltRequest),
+            4 => Some(Cause::Step),
+            cause => Err(SnapshotError::Invalid(format!(
+                "{cause} is not a cause of entering debug mode"
+            )))?,
+        };
+        self.stepping = r.bool()?;
+        self.step_halt = r.bool()?;
+
+        self.fault = None;
+        self.blocks.flush();
+        if let Some(caches) = &mut self.caches {
+            caches.invalidate();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bus::Ram,
+        ext::Extensions,
+        hart::{testing::write_program, Hart},
+        inst::Instruction,
+        reg::IRs1,
+        snapshot::{self, SnapshotError},
+    };
+
+    type Inst = Instruction<u64, false, true, true, f64>;
+    type Rv64 = Hart<0, u64, false, true, true, f64, true, false>;
+
+    #[derive(Clone, Copy)]
+    struct Zba;
+
+    impl Extensions for Zba {
+        const ZBA: bool = true;
+    }
+
+    fn machine() -> (Rv64, Ram) {
+        let mut ram = Ram::new(0x8000_0000, 0x1000);
+        let program = Inst::assemble(
+            "
+                    li t0,0x2000
+                    csrs mstatus,t0
+                    auipc s0,0
+                    li a0,0
+            loop:   addi a0,a0,1
+                    sd a0,256(s0)
+                    fcvt.d.l fa0,a0
+                    csrw mscratch,a0
+                    j loop
+            ",
+        )
+        .unwrap();
+        write_program(&mut ram, 0x8000_0000, &program);
+        let mut hart = Rv64::new();
+        hart.set_pc(0x8000_0000);
+        (hart, ram)
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let (mut hart, mut ram) = machine();
+        hart.run(&mut ram, 100).unwrap();
+
+        let mut file = Vec::new();
+        snapshot::save(&mut file, &[("hart0", &hart), ("ram", &ram)]).unwrap();
+        hart.run(&mut ram, 100).unwrap();
+
+        // NOTE: The restored machine carries on exactly like the original one.
+        let (mut restored, mut restored_ram) = machine();
+        snapshot::restore(
+            &mut &file[..],
+            &mut [("ram", &mut restored_ram), ("hart0", &mut restored)],
+        )
+        .unwrap();
+        restored.run(&mut restored_ram, 100).unwrap();
+
+        assert_eq!(restored.pc(), hart.pc());
+        assert_eq!(
+            restored.reg().get_rs1(IRs1::X10),
+            hart.reg().get_rs1(IRs1::X10)
+        );
+        assert_eq!(restored_ram.as_slice(), ram.as_slice());
+        let (mut original, mut copy) = (Vec::new(), Vec::new());
+        snapshot::save(&mut original, &[("hart0", &hart)]).unwrap();
+        snapshot::save(&mut copy, &[("hart0", &restored)]).unwrap();
+        assert_eq!(original, copy);
+
+        // NOTE: Snapshots only restore into a machine of the same shape.
+        let error = snapshot::restore(&mut &file[..], &mut [("hart0", &mut restored)]);
+        assert!(matches!(error, Err(SnapshotError::Mismatch(_))));
+        let mut small = Ram::new(0x8000_0000, 0x100);
+        let error = snapshot::restore(
+            &mut &file[..],
+            &mut [("hart0", &mut restored), ("ram", &mut small)],
+        );
+        assert!(matches!(error, Err(SnapshotError::Mismatch(_))));
+        let mut rv32 = Hart::<0, u32, false, true, true, f64, true, false>::new();
+        let error = snapshot::restore(
+            &mut &file[..],
+            &mut [("hart0", &mut rv32), ("ram", &mut restored_ram)],
+        );
+        assert!(matches!(error, Err(SnapshotError::Mismatch(_))));
+        let mut no_zifencei = Hart::<0, u64, false, true, true, f64, false, false>::new();
+        let error = snapshot::restore(
+            &mut &file[..],
+            &mut [("hart0", &mut no_zifencei), ("ram", &mut restored_ram)],
+        );
+        assert!(matches!(error, Err(SnapshotError::Mismatch(_))));
+        let mut zba = Hart::<0, u64, false, true, true, f64, true, false, Zba>::new();
+        let error = snapshot::restore(
+            &mut &file[..],
+            &mut [("hart0", &mut zba), ("ram", &mut restored_ram)],
+        );
+        assert!(matches!(error, Err(SnapshotError::Mismatch(_))));
+
+        let mut truncated = &file[..file.len() - 1];
+        let error = snapshot::restore(
+            &mut truncated,
+            &mut [("hart0", &mut restored), ("ram", &mut restored_ram)],
+        );
+        assert!(matches!(error, Err(SnapshotError::Invalid(_))));
+    }
+}
@@ -0,0 +1,55 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Fixtures shared by the tests running programs on a hart.
+
+use crate::{
+    bus::{Bus, Ram},
+    ext::Extensions,
+    freg::FRegType,
+    hart::Hart,
+    reg::RegType,
+};
+
+/// Where programs are loaded and start.
+pub(crate) const BASE: u64 = 0x1000;
+/// Where programs keep their data, in the same page as `BASE`.
+pub(crate) const DATA: u64 = 0x1800;
+
+/// Writes `program` to `ram` from `addr` on.
+pub(crate) fn write_program(ram: &mut Ram, addr: u64, program: &[u32]) {
+    for (i, word) in program.iter().enumerate() {
+        ram.write(addr + 4 * i as u64, &word.to_le_bytes())
+            .expect("Program does not fit in memory");
+    }
+}
+
+/// The lower 64 KiB of memory, holding `program` at `BASE`.
+pub(crate) fn load(program: &[u32]) -> Ram {
+    let mut ram = Ram::new(0, 0x1_0000);
+    write_program(&mut ram, BASE, program);
+    ram
+}
+
+/// A new hart about to run the program at `BASE`.
+pub(crate) fn boot<
+    const ID: usize,
+    I: RegType,
+    const E: bool,
+    const M: bool,
+    const A: bool,
+    F: FRegType,
+    const ZIFENCEI: bool,
+    const C: bool,
+    X: Extensions,
+>() -> Hart<ID, I, E, M, A, F, ZIFENCEI, C, X> {
+    let mut hart = Hart::new();
+    hart.set_pc(I::from_u128(BASE as u128));
+    hart
+}
@@ -0,0 +1,209 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+use crate::{
+    bus::{AccessFault, Bus},
+    ext::Extensions,
+    freg::FRegType,
+    hart::{counters::Event, exec::Error, Hart},
+    inst::Instruction,
+    mmu::cache::Caches,
+    reg::RegType,
+};
+
+/// Records the accesses made through `bus`, merging those following on from the last.
+struct Timed<'a, B> {
+    bus: &'a mut B,
+    /// `(addr, len, write)`
+    accesses: Vec<(u64, u64, bool)>,
+}
+
+impl<B> Timed<'_, B> {
+    fn log(&mut self, addr: u64, len: u64, write: bool) {
+        match self.accesses.last_mut() {
+            Some((last, last_len, last_write))
+                if *last_write == write && *last + *last_len == addr =>
+            {
+                *last_len += len;
+            }
+            _ => self.accesses.push((addr, len, write)),
+        }
+    }
+}
+
+impl<B: Bus> Bus for Timed<'_, B> {
+    fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), AccessFault> {
+        self.bus.read(addr, buf)?;
+        self.log(addr, buf.len() as u64, false);
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u64, buf: &[u8]) -> Result<(), AccessFault> {
+        self.bus.write(addr, buf)?;
+        self.log(addr, buf.len() as u64, true);
+        Ok(())
+    }
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Models the caches of this hart, or stops modelling them.
+    ///
+    /// Every fetch and memory access then looks up the caches, and `cycle` advances by the
+    /// cycles they take on top of one per instruction.
+    /// Page table walks count against the cache of the access they translate, and every
+    /// address is cacheable, devices included.
+    /// Instructions [`run`](Self::run) from translated blocks are fetched without walking the
+    /// page tables again, as if the translation hit in a TLB, and native code doesn't run.
+    ///
+    /// The caches, the L2 included, are private to this hart.
+    /// Harts given caches each model their own, which don't see each other's accesses.
+    pub fn set_caches(&mut self, caches: Option<Caches>) {
+        self.caches = caches;
+    }
+
+    /// The modelled caches, with what they counted.
+    pub fn caches(&self) -> Option<&Caches> {
+        self.caches.as_ref()
+    }
+
+    pub fn caches_mut(&mut self) -> Option<&mut Caches> {
+        self.caches.as_mut()
+    }
+
+    /// Fetches the instruction at `pc` through the instruction cache.
+    pub(super) fn fetch_timed(
+        &mut self,
+        bus: &mut impl Bus,
+        pc: u64,
+    ) -> Result<(Instruction<I, E, M, A, F, X>, u8), Error> {
+        if self.caches.is_none() {
+            return self.fetch(bus, pc);
+        }
+        let mut timed = Timed {
+            bus,
+            accesses: Vec::new(),
+        };
+        let result = self.fetch(&mut timed, pc);
+        self.account(true, timed.accesses);
+        result
+    }
+
+    /// Executes `inst` with its accesses going through the data cache.
+    pub(super) fn execute_timed(
+        &mut self,
+        bus: &mut impl Bus,
+        inst: Instruction<I, E, M, A, F, X>,
+        len: u8,
+    ) -> Result<bool, Error> {
+        let mut timed = Timed {
+            bus,
+            accesses: Vec::new(),
+        };
+        let result = match self.tracing() {
+            true => self.execute_traced(&mut timed, inst, len),
+            false => self.execute_debug(&mut timed, inst, len),
+        };
+        self.account(false, timed.accesses);
+        result
+    }
+
+    /// Looks up the caches for `accesses`, stalling for the cycles they take.
+    pub(super) fn account(&mut self, fetch: bool, accesses: Vec<(u64, u64, bool)>) {
+        let Some(caches) = &mut self.caches else {
+            return;
+        };
+        let (mut cycles, mut misses) = (0, 0);
+        for (addr, len, write) in accesses {
+            let (taken, missed) = caches.access(fetch, addr, len, write);
+            cycles += taken;
+            misses += missed;
+        }
+        self.stall(cycles);
+        for _ in 0..misses {
+            self.count_event(Event::CacheMiss);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        csr::Csr,
+        hart::{
+            counters::Event,
+            testing::{boot, load},
+            Hart,
+        },
+        mmu::cache::{Cache, Caches, Replacement},
+        reg::IRd,
+    };
+
+    #[test]
+    fn test_caches() {
+        let program = [
+            0x00052083u32, // lw x1, 0(x10)
+            0x00450513,    // addi x10, x10, 4
+            0xfff58593,    // addi x11, x11, -1
+            0xfe059ae3,    // bnez x11, -12
+            0x0000006f,    // j .
+        ];
+        let mut ram = load(&program);
+
+        let l1 = || Cache::new(4, 4, 16, Replacement::Lru).latency(1);
+        let l2 = Cache::new(16, 8, 64, Replacement::Plru).latency(10);
+        let new = || {
+            let mut hart: Hart<0, u32, false, false, false, (), false, false> = boot();
+            hart.set_caches(Some(Caches::new(l1(), l1(), Some(l2.clone()), 100)));
+            hart.reg_mut().set_rd(IRd::X10, 0x1800);
+            hart.reg_mut().set_rd(IRd::X11, 16);
+            hart.write_csr(Csr::Mhpmevent3, Event::CacheMiss as u64);
+            hart
+        };
+        let mut hart = new();
+        hart.run(&mut ram, 4 * 16).unwrap();
+
+        // NOTE: The loop takes one line of code, and its loads four lines of data.
+        let caches = hart.caches().unwrap();
+        assert_eq!(caches.l1i().stats().misses, 1);
+        assert_eq!(caches.l1i().stats().hits, 63);
+        assert_eq!(caches.l1d().stats().misses, 4);
+        assert_eq!(caches.l1d().stats().hits, 12);
+        // The L2 lines hold all of the loop's code and data
+        assert_eq!(caches.l2().unwrap().stats().misses, 2);
+        assert_eq!(hart.read_csr(Csr::Mhpmcounter3), 5);
+        assert_eq!(hart.read_csr(Csr::Minstret), 64);
+        assert_eq!(
+            hart.read_csr(Csr::Mcycle),
+            64 + (64 + 16) + 5 * 10 + 2 * 100
+        );
+
+        // Translated blocks look up the caches like single steps do
+        let mut stepped = new();
+        for _ in 0..4 * 16 {
+            stepped.step(&mut ram).unwrap();
+        }
+        let stats = |hart: &Hart<0, u32, false, false, false, (), false, false>| {
+            let caches = hart.caches().unwrap();
+            [caches.l1i(), caches.l1d(), caches.l2().unwrap()].map(|cache| cache.stats())
+        };
+        assert_eq!(stats(&stepped), stats(&hart));
+        assert_eq!(stepped.read_csr(Csr::Mcycle), hart.read_csr(Csr::Mcycle));
+    }
+}
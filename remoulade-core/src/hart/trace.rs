@@ -0,0 +1,611 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Commit logs in the format of Spike's `--log-commits`.
+//!
+//! Every retired instruction is logged as
+//!
+//! ```text
+//! core   0: 3 0x0000000080000004 (0x02028593) x11 0x0000000080000020
+//! core   0: 3 0x0000000080000008 (0x00b53023) mem 0x0000000080001000 0x0000000080000020
+//! ```
+//!
+//! with the privilege mode it executed in, its address and encoding, and what it wrote.
+//! Loads only list their address and stores their address and the value stored.
+//! With [`Trace::disasm`], each instruction executed is also logged like Spike does with `-l`,
+//! using this crate's [disassembly](crate::inst::disasm::Disassembly::spike) in Spike's syntax.
+//!
+//! Destination registers are logged even when their value doesn't change, but other CSRs
+//! written as a side effect are only logged when they do.
+//! Vector registers aren't logged.
+//!
+//! Each line is a [`Commit`], which also parses from the logs Spike writes.
+
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+    io::Write,
+    str::FromStr,
+};
+
+use crate::{
+    bus::{AccessFault, Bus},
+    csr::Csr,
+    ext::Extensions,
+    freg::{FRegType, FRs1},
+    hart::{exec::Error, Hart},
+    inst::{vector::VKind, CsrKind, Instruction},
+    reg::{IRs1, RegType},
+};
+
+/// Where and how retired instructions are logged.
+pub struct Trace {
+    out: Box<dyn Write + Send>,
+    disasm: bool,
+    /// Data accesses translated by the current instruction, as `(virtual, physical, len)`
+    translated: Vec<(u64, u64, u64)>,
+    /// The last instruction retired, until taken
+    last: Option<Commit>,
+}
+
+impl Trace {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Box::new(out),
+            disasm: false,
+            translated: Vec::new(),
+            last: None,
+        }
+    }
+
+    /// Also logs the disassembly of every instruction, like Spike does with `-l`.
+    pub fn disasm(mut self, disasm: bool) -> Self {
+        self.disasm = disasm;
+        self
+    }
+
+    /// The virtual address `pa` was translated from by the current instruction.
+    fn virtual_addr(&self, pa: u64) -> Option<u64> {
+        self.translated
+            .iter()
+            .find(|&&(_, base, len)| (base..base + len).contains(&pa))
+            .map(|&(va, base, _)| va + (pa - base))
+    }
+}
+
+/// A retired instruction, as a line of the commit log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Commit {
+    pub core: usize,
+    /// The privilege mode the instruction executed in, if known
+    pub privilege: Option<u8>,
+    pub pc: u128,
+    pub insn: u32,
+    /// The length of `insn` in bytes
+    pub len: u8,
+    /// How wide addresses and integer registers are shown
+    pub xlen: u32,
+    pub effects: Vec<Effect>,
+}
+
+/// Something a retired instruction did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Effect {
+    X {
+        reg: u8,
+        value: u128,
+    },
+    /// `bits` is `FLEN`, which decides how wide `value` is shown
+    F {
+        reg: u8,
+        value: u128,
+        bits: u32,
+    },
+    Csr {
+        addr: u32,
+        value: u64,
+    },
+    Load {
+        addr: u64,
+    },
+    Store {
+        addr: u64,
+        data: Vec<u8>,
+    },
+}
+
+/// Why a line isn't a commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseCommitError {
+    pub message: String,
+}
+
+impl Display for ParseCommitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for ParseCommitError {}
+
+/// Formats `value` as a hexadecimal number with all `bits / 4` of its digits.
+fn hex(value: u128, bits: u32) -> String {
+    format!("0x{value:0width$x}", width = bits as usize / 4)
+}
+
+impl Display for Commit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let addr_bits = self.xlen.min(64);
+        let privilege = match self.privilege {
+            Some(privilege) => privilege.to_string(),
+            None => "?".to_string(),
+        };
+        write!(
+            f,
+            "core{:4}: {privilege} {} ({})",
+            self.core,
+            hex(self.pc, self.xlen),
+            hex(self.insn as u128, self.len as u32 * 8),
+        )?;
+        for effect in &self.effects {
+            match effect {
+                Effect::X { reg, value } => write!(f, " x{reg:<2} {}", hex(*value, self.xlen))?,
+                Effect::F { reg, value, bits } => write!(f, " f{reg:<2} {}", hex(*value, *bits))?,
+                Effect::Csr { addr, value } => {
+                    let name = match Csr::checked_from_u32(*addr) {
+                        Some(csr) => format!("{csr:?}").to_lowercase(),
+                        None => "unknown".to_string(),
+                    };
+                    write!(f, " c{addr}_{name} {}", hex(*value as u128, addr_bits))?
+                }
+                Effect::Load { addr } => write!(f, " mem {}", hex(*addr as u128, addr_bits))?,
+                Effect::Store { addr, data } => {
+                    let digits: String = data
+                        .iter()
+                        .rev()
+                        .map(|byte| format!("{byte:02x}"))
+                        .collect();
+                    write!(f, " mem {} 0x{digits}", hex(*addr as u128, addr_bits))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Commit {
+    type Err = ParseCommitError;
+
+    /// Parses a line of a commit log, which Spike writes with `--log-commits`.
+    ///
+    /// Vector register writes are skipped.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |message: String| ParseCommitError { message };
+        let number = |token: &str| {
+            token
+                .strip_prefix("0x")
+                .and_then(|digits| {
+                    u128::from_str_radix(digits, 16)
+                        .ok()
+                        .map(|value| (value, digits.len() as u32 * 4))
+                })
+                .ok_or_else(|| error(format!("invalid number `{token}`")))
+        };
+
+        let (core, rest) = s
+            .strip_prefix("core")
+            .and_then(|s| s.split_once(':'))
+            .ok_or_else(|| error(format!("not a commit: `{s}`")))?;
+        let core = core
+            .trim()
+            .parse()
+            .map_err(|_| error(format!("invalid core `{core}`")))?;
+        let mut tokens = rest.split_whitespace().peekable();
+        let mut next = || {
+            tokens
+                .next()
+                .ok_or_else(|| error(format!("truncated commit: `{s}`")))
+        };
+
+        let privilege = next()?;
+        let privilege = match privilege.parse() {
+            Ok(privilege @ 0..=3) => privilege,
+            _ => Err(error(format!("invalid privilege mode `{privilege}`")))?,
+        };
+        let (pc, xlen) = number(next()?)?;
+        let insn = next()?;
+        let (insn, bits) = insn
+            .strip_prefix('(')
+            .and_then(|insn| insn.strip_suffix(')'))
+            .ok_or_else(|| error(format!("invalid encoding `{insn}`")))
+            .and_then(number)?;
+
+        let mut effects = Vec::new();
+        while let Some(token) = tokens.next() {
+            let mut value = || {
+                tokens
+                    .next()
+                    .ok_or_else(|| error(format!("missing value after `{token}`")))
+                    .and_then(number)
+            };
+            let reg = |prefix| {
+                token
+                    .strip_prefix(prefix)
+                    .and_then(|reg| reg.parse::<u8>().ok())
+            };
+            let effect = if token == "mem" {
+                let (addr, _) = value()?;
+                match tokens.peek() {
+                    Some(data) if data.starts_with("0x") => {
+                        let (data, bits) = number(tokens.next().expect("Peeked"))?;
+                        let data = data.to_le_bytes()[..bits as usize / 8].to_vec();
+                        Effect::Store {
+                            addr: addr as u64,
+                            data,
+                        }
+                    }
+                    _ => Effect::Load { addr: addr as u64 },
+                }
+            } else if let Some(reg @ 0..32) = reg('x') {
+                Effect::X {
+                    reg,
+                    value: value()?.0,
+                }
+            } else if let Some(reg @ 0..32) = reg('f') {
+                let (value, bits) = value()?;
+                Effect::F { reg, value, bits }
+            } else if let Some((addr, _)) =
+                token.strip_prefix('c').and_then(|csr| csr.split_once('_'))
+            {
+                let addr = addr
+                    .parse()
+                    .map_err(|_| error(format!("invalid CSR `{token}`")))?;
+                Effect::Csr {
+                    addr,
+                    value: value()?.0 as u64,
+                }
+            } else if reg('v').is_some() {
+                value()?;
+                continue;
+            } else if token.starts_with(['e', 'm', 'l']) {
+                // `e32 m1 l4` describes `vtype` and `vl` before vector register writes
+                continue;
+            } else {
+                Err(error(format!("unknown write `{token}`")))?
+            };
+            effects.push(effect);
+        }
+
+        Ok(Self {
+            core,
+            privilege: Some(privilege),
+            pc,
+            insn: insn as u32,
+            len: bits as u8 / 8,
+            xlen,
+            effects,
+        })
+    }
+}
+
+/// Records the accesses made through `bus`.
+struct Recording<'a, B> {
+    bus: &'a mut B,
+    reads: Vec<u64>,
+    writes: Vec<(u64, Vec<u8>)>,
+}
+
+impl<B: Bus> Bus for Recording<'_, B> {
+    fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), AccessFault> {
+        self.bus.read(addr, buf)?;
+        self.reads.push(addr);
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u64, buf: &[u8]) -> Result<(), AccessFault> {
+        self.bus.write(addr, buf)?;
+        self.writes.push((addr, buf.to_vec()));
+        Ok(())
+    }
+}
+
+/// A register written by an instruction.
+enum Destination {
+    X(u8),
+    F(u8),
+}
+
+fn destination<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X>(
+    inst: &Instruction<I, E, M, A, F, X>,
+) -> Option<Destination>
+where
+    X: Extensions,
+{
+    use Instruction::*;
+
+    // NOTE: `IRd::X0` is 32, so registers are compared by their 5-bit encoding.
+    let x = |rd: u8| match rd & 0x1f {
+        0 => None,
+        rd => Some(Destination::X(rd)),
+    };
+    match *inst {
+        UType { rd, .. }
+        | Jal { rd, .. }
+        | IType { rd, .. }
+        | RType { rd, .. }
+        | AmoType { rd, .. }
+        | CsrType { rd, .. }
+        | HypervisorType { rd, .. }
+        | VsetType { rd, .. } => x(rd as u8),
+        FLoadType { rd, .. } | FmaType { rd, .. } => Some(Destination::F(rd as u8 & 0x1f)),
+        FpType { rd, kind, .. } if kind.int_rd() => x(rd),
+        FpType { rd, .. } => Some(Destination::F(rd & 0x1f)),
+        VArithType { vd, kind, .. } => match kind {
+            VKind::VmvXS | VKind::Vcpop | VKind::Vfirst => x(vd),
+            VKind::VfmvFS => Some(Destination::F(vd & 0x1f)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Logs every retired instruction to `trace`, or stops logging.
+    ///
+    /// Translated blocks run one instruction at a time while tracing.
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+
+    /// Takes the last instruction retired while tracing.
+    pub fn take_commit(&mut self) -> Option<Commit> {
+        self.trace.as_mut().and_then(|trace| trace.last.take())
+    }
+
+    /// Whether retired instructions are logged.
+    pub(crate) fn tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Notes that the current instruction accesses `len` bytes at `addr`, which is at `pa`.
+    pub(super) fn trace_translated(&mut self, addr: u64, pa: u64, len: u64) {
+        if let Some(trace) = &mut self.trace {
+            trace.translated.push((addr, pa, len));
+        }
+    }
+
+    /// Like [`execute_debug`](Self::execute_debug), but logging the instruction if it retires.
+    pub(super) fn execute_traced(
+        &mut self,
+        bus: &mut impl Bus,
+        inst: Instruction<I, E, M, A, F, X>,
+        len: u8,
+    ) -> Result<bool, Error> {
+        let pc = self.pc;
+        let privilege = self.privilege;
+        let before = self.csr.clone();
+        if let Some(trace) = &mut self.trace {
+            trace.translated.clear();
+        }
+
+        let mut recording = Recording {
+            bus,
+            reads: Vec::new(),
+            writes: Vec::new(),
+        };
+        let result = self.execute_debug(&mut recording, inst, len);
+        let Recording { reads, writes, .. } = recording;
+        let insn = match len {
+            2 => inst.encode_raw16().map(u32::from),
+            _ => inst.encode_raw32(),
+        };
+
+        // NOTE: Spike logs the instruction before executing it, so even if it traps, with the
+        //       address sign-extended and the encoding as a full word.
+        let trace = self.trace.as_mut().expect("Only called while tracing");
+        if trace.disasm && result != Ok(false) {
+            let _ = writeln!(
+                trace.out,
+                "core{ID:4}: 0x{:016x} (0x{:08x}) {}",
+                pc.as_i128() as u64,
+                insn.unwrap_or_default(),
+                inst.disassemble().spike(true),
+            );
+        }
+        if result != Ok(true) {
+            return result;
+        }
+
+        let xlen = I::BITS;
+        let mut commit = Commit {
+            core: ID,
+            privilege: Some(privilege as u8),
+            pc: pc.as_u128(),
+            insn: insn.unwrap_or_default(),
+            len,
+            xlen,
+            effects: Vec::new(),
+        };
+
+        match destination(&inst) {
+            Some(Destination::X(reg)) => {
+                let value = self.reg.get_rs1(IRs1::wrapping_from_u32(reg as u32));
+                let value = value.as_u128();
+                commit.effects.push(Effect::X { reg, value });
+            }
+            Some(Destination::F(reg)) => {
+                let value = self.freg.get_rs1(FRs1::wrapping_from_u32(reg as u32));
+                let (value, bits) = (value.to_bits(), F::BITS);
+                commit.effects.push(Effect::F { reg, value, bits });
+            }
+            None => {}
+        }
+
+        // NOTE: `fcsr` and `vcsr` only show up when written directly, like in Spike where they
+        //       have no storage of their own.
+        let explicit = match inst {
+            Instruction::CsrType { rs1, kind, csr, .. } => {
+                let writes =
+                    matches!(kind, CsrKind::Csrrw | CsrKind::Csrrwi) || rs1 as u8 & 0x1f != 0;
+                writes.then_some(csr)
+            }
+            _ => None,
+        };
+        for addr in 0..0x1000 {
+            let Some(csr) = Csr::checked_from_u32(addr) else {
+                continue;
+            };
+            let mirror = matches!(csr, Csr::Fcsr | Csr::Vcsr);
+            let changed = self.csr.read(csr) != before.read(csr);
+            if explicit == Some(csr) || (changed && !mirror) {
+                let value = self.read_csr(csr);
+                commit.effects.push(Effect::Csr { addr, value });
+            }
+        }
+
+        let trace = self.trace.as_mut().expect("Only called while tracing");
+        for pa in reads {
+            if let Some(addr) = trace.virtual_addr(pa) {
+                commit.effects.push(Effect::Load { addr });
+            }
+        }
+        for (pa, data) in writes {
+            if let Some(addr) = trace.virtual_addr(pa) {
+                commit.effects.push(Effect::Store { addr, data });
+            }
+        }
+
+        let _ = writeln!(trace.out, "{commit}");
+        trace.last = Some(commit);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        bus::Ram,
+        csr::Csr,
+        hart::{testing::write_program, Commit, Effect, Hart, Trace},
+        inst::Instruction,
+    };
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_commit_log() {
+        let program = Instruction::<u64, false, true, true, f64>::assemble(
+            "
+                auipc t0,0
+                addi a1,t0,32
+                sd a1,256(t0)
+                ld a2,256(t0)
+                csrw mscratch,a1
+                fmv.d.x ft1,a1
+                nop
+                ecall
+            ",
+        )
+        .unwrap();
+        let mut ram = Ram::new(0x8000_0000, 0x1000);
+        write_program(&mut ram, 0x8000_0000, &program);
+
+        let out = Shared::default();
+        let mut hart = Hart::<0, u64, false, true, true, f64, false, false>::new();
+        hart.set_pc(0x8000_0000);
+        hart.write_csr(Csr::Mstatus, 1 << 13);
+        hart.set_trace(Some(Trace::new(out.clone())));
+        assert!(hart.run(&mut ram, 100).is_err());
+
+        // NOTE: `ecall` traps instead of retiring.
+        let log = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            log.lines().collect::<Vec<_>>(),
+            [
+                "core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000",
+                "core   0: 3 0x0000000080000004 (0x02028593) x11 0x0000000080000020",
+                "core   0: 3 0x0000000080000008 (0x10b2b023) mem 0x0000000080000100 0x0000000080000020",
+                "core   0: 3 0x000000008000000c (0x1002b603) x12 0x0000000080000020 mem 0x0000000080000100",
+                "core   0: 3 0x0000000080000010 (0x34059073) c832_mscratch 0x0000000080000020",
+                "core   0: 3 0x0000000080000014 (0xf20580d3) f1  0x0000000080000020 c768_mstatus 0x8000000000006000",
+                "core   0: 3 0x0000000080000018 (0x00000013)",
+            ]
+        );
+
+        for line in log.lines() {
+            assert_eq!(line.parse::<Commit>().unwrap().to_string(), line);
+        }
+
+        let out = Shared::default();
+        hart.set_pc(0x8000_0004);
+        hart.set_trace(Some(Trace::new(out.clone()).disasm(true)));
+        hart.step(&mut ram).unwrap();
+        let log = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            log.lines().collect::<Vec<_>>(),
+            [
+                "core   0: 0x0000000080000004 (0x02028593) addi    a1, t0, 32",
+                "core   0: 3 0x0000000080000004 (0x02028593) x11 0x0000000080000020",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_commit() {
+        let line =
+            "core   1: 1 0x80000010 (0x4501) x10 0x00000000 e32 m1 l4 v8  0x0 mem 0x80001000";
+        let commit: Commit = line.parse().unwrap();
+        assert_eq!(commit.core, 1);
+        assert_eq!(commit.privilege, Some(1));
+        assert_eq!((commit.pc, commit.xlen), (0x8000_0010, 32));
+        assert_eq!((commit.insn, commit.len), (0x4501, 2));
+        assert_eq!(
+            commit.effects,
+            [
+                Effect::X { reg: 10, value: 0 },
+                Effect::Load { addr: 0x8000_1000 },
+            ]
+        );
+
+        assert!("core   0: 0x0000000080000004 (0x02028593) addi    a1,t0,32"
+            .parse::<Commit>()
+            .is_err());
+        assert!("core   0: 3 0x0000000080000004 (0x02028593) x11"
+            .parse::<Commit>()
+            .is_err());
+    }
+}
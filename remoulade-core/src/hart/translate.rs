@@ -0,0 +1,345 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Address translation through `satp`, or `vsatp` and `hgatp` when virtualized.
+//!
+//! There is no TLB, so every access walks the page tables, which the `hpmcounter`s count as TLB
+//! misses.
+
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::FRegType,
+    hart::{
+        block::Space,
+        counters::Event,
+        exec::Error,
+        hypervisor::{HSTATUS_VTVM, MSTATUS_TVM},
+        Hart, Privilege,
+    },
+    mmu::{
+        paging::{self, Request, Table, PAGE_SIZE},
+        pmp::Access,
+    },
+    reg::RegType,
+};
+
+const MSTATUS_MPRV: u64 = 1 << 17;
+const MSTATUS_SUM: u64 = 1 << 18;
+const MSTATUS_MXR: u64 = 1 << 19;
+const MSTATUS_MPV: u64 = 1 << 39;
+
+/// What a trap for the last translation or protection fault would report.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fault {
+    /// The faulting address, as reported in `mtval` or `stval`
+    pub tval: u64,
+    /// Whether `tval` is a guest virtual address, as reported in `mstatus.GVA` or `hstatus.GVA`
+    pub gva: bool,
+    /// The faulting guest-physical address shifted right by two, as reported in `mtval2` or
+    /// `htval`
+    pub tval2: u64,
+    /// The transformed instruction, as reported in `mtinst` or `htinst`
+    pub tinst: u64,
+}
+
+/// The mode an access is made in.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct Context {
+    pub(super) privilege: Privilege,
+    pub(super) virt: bool,
+    /// Whether reads need execute permission, as for `hlvx`
+    pub(super) hlvx: bool,
+}
+
+fn page_fault(access: Access, guest: bool) -> Error {
+    match (access, guest) {
+        (Access::Read, false) => Error::LoadPageFault,
+        (Access::Write, false) => Error::StoreOrAmoPageFault,
+        (Access::Execute, false) => Error::InstructionPageFault,
+        (Access::Read, true) => Error::LoadGuestPageFault,
+        (Access::Write, true) => Error::StoreOrAmoGuestPageFault,
+        (Access::Execute, true) => Error::InstructionGuestPageFault,
+    }
+}
+
+fn access_fault(access: Access) -> Error {
+    match access {
+        Access::Read => Error::LoadAccessFault,
+        Access::Write => Error::StoreOrAmoAccessFault,
+        Access::Execute => Error::InstructionAccessFault,
+    }
+}
+
+fn misaligned(access: Access) -> Error {
+    match access {
+        Access::Read => Error::LoadAddressMisaligned,
+        Access::Write => Error::StoreOrAmoAddressMisaligned,
+        Access::Execute => Error::InstructionAddressMisaligned,
+    }
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// What a trap for the page, guest-page or access fault raised by translation or PMP, or the
+    /// breakpoint raised by a trigger, in the last instruction would report.
+    ///
+    /// [`take_trap`](Self::take_trap) writes this to the trap CSRs.
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    fn fetch_context(&self) -> Context {
+        Context {
+            privilege: self.privilege,
+            virt: self.virt,
+            hlvx: false,
+        }
+    }
+
+    /// The mode loads and stores are made in, which `mstatus.MPRV` changes for machine mode.
+    pub(super) fn data_context(&self) -> Context {
+        let mstatus = self.csr.read(Csr::Mstatus);
+        if self.privilege != Privilege::Machine
+            || mstatus & MSTATUS_MPRV == 0
+            || !self.mprv_enabled()
+        {
+            return self.fetch_context();
+        }
+
+        let privilege = match mstatus >> 11 & 0b11 {
+            0b00 => Privilege::User,
+            0b01 => Privilege::Supervisor,
+            _ => Privilege::Machine,
+        };
+        // NOTE: MPV lives in `mstatush` on RV32.
+        let mpv = match I::BITS {
+            32 => self.csr.read(Csr::Mstatush) << 32 & MSTATUS_MPV != 0,
+            _ => mstatus & MSTATUS_MPV != 0,
+        };
+        Context {
+            privilege,
+            virt: X::H && mpv && privilege != Privilege::Machine,
+            hlvx: false,
+        }
+    }
+
+    /// The single-stage or VS-stage table, and the G-stage table for accesses in `context`.
+    fn tables(&self, context: Context) -> (Option<Table>, Option<Table>) {
+        if context.privilege == Privilege::Machine {
+            return (None, None);
+        }
+        let atp = |csr: Csr, widened: bool| Table::from_atp(I::BITS, self.csr.read(csr), widened);
+        match context.virt {
+            false => (atp(Csr::Satp, false), None),
+            true => (atp(Csr::Vsatp, false), atp(Csr::Hgatp, true)),
+        }
+    }
+
+    /// The translation environment instructions are fetched in.
+    pub(super) fn fetch_space(&self) -> Space {
+        let atp = |table: Option<Table>, csr: Csr| table.map_or(0, |_| self.csr.read(csr));
+        let (first, second) = self.tables(self.fetch_context());
+        Space {
+            privilege: self.privilege,
+            virt: self.virt,
+            atp: atp(first, if self.virt { Csr::Vsatp } else { Csr::Satp }),
+            gatp: atp(second, Csr::Hgatp),
+        }
+    }
+
+    /// Executes `sfence.vma`, which drops every translated block as there is no TLB to flush.
+    pub(super) fn sfence_vma(&mut self) -> Result<(), Error> {
+        let tvm = self.csr.read(Csr::Mstatus) & MSTATUS_TVM != 0;
+        let vtvm = self.csr.read(Csr::Hstatus) & HSTATUS_VTVM != 0;
+        match (self.privilege, self.virt) {
+            (Privilege::User, false) => Err(Error::IllegalInstruction)?,
+            (Privilege::User, true) => Err(Error::VirtualInstruction)?,
+            (Privilege::Supervisor, false) if tvm => Err(Error::IllegalInstruction)?,
+            (Privilege::Supervisor, true) if vtvm => Err(Error::VirtualInstruction)?,
+            _ => {}
+        }
+        self.blocks.flush();
+        Ok(())
+    }
+
+    /// Translates `len` bytes at `addr` and checks them against PMP, returning the physical
+    /// address.
+    pub(super) fn translate_addr(
+        &mut self,
+        bus: &mut impl Bus,
+        addr: u64,
+        len: u64,
+        access: Access,
+    ) -> Result<u64, Error> {
+        let context = match access {
+            Access::Execute => self.fetch_context(),
+            _ => self.data_context(),
+        };
+        self.translate_addr_in(bus, addr, len, access, context)
+    }
+
+    /// Where instructions at `addr` are fetched from, looked up without counting, tracing or
+    /// reporting the walk like a fetch would.
+    pub(super) fn fetch_address(&self, bus: &mut impl Bus, addr: u64) -> Result<u64, Error> {
+        let context = self.fetch_context();
+        self.walk(
+            bus,
+            addr,
+            1,
+            Access::Execute,
+            context,
+            &mut Fault::default(),
+        )
+    }
+
+    /// Like [`translate_addr`](Self::translate_addr), but for an access made in `context`.
+    pub(super) fn translate_addr_in(
+        &mut self,
+        bus: &mut impl Bus,
+        addr: u64,
+        len: u64,
+        access: Access,
+        context: Context,
+    ) -> Result<u64, Error> {
+        // NOTE: Execute triggers match whole instructions rather than fetches.
+        if access != Access::Execute {
+            self.match_access(addr, len, access, None, context)?;
+        }
+
+        if self.counting_events() {
+            if let (Some(_), _) | (_, Some(_)) = self.tables(context) {
+                self.count_event(Event::TlbMiss);
+            }
+        }
+
+        let mut fault = Fault {
+            tval: addr,
+            gva: context.virt,
+            tval2: 0,
+            tinst: 0,
+        };
+        let result = self.walk(bus, addr, len, access, context, &mut fault);
+        match result {
+            Ok(pa) => self.trace_translated(addr, pa, len),
+            Err(_) => self.fault = Some(fault),
+        }
+        result
+    }
+
+    fn walk(
+        &self,
+        bus: &mut impl Bus,
+        addr: u64,
+        len: u64,
+        access: Access,
+        context: Context,
+        fault: &mut Fault,
+    ) -> Result<u64, Error> {
+        let (first, second) = self.tables(context);
+        // NOTE: Accesses crossing pages would need two translations, so software handles them.
+        let last = addr.saturating_add(len - 1);
+        if (first.is_some() || second.is_some()) && addr / PAGE_SIZE != last / PAGE_SIZE {
+            Err(misaligned(access))?;
+        }
+
+        let mstatus = self.csr.read(Csr::Mstatus);
+        let vsstatus = self.csr.read(Csr::Vsstatus);
+        let status = if context.virt { vsstatus } else { mstatus };
+        let request = Request {
+            access,
+            user: context.privilege == Privilege::User,
+            sum: status & MSTATUS_SUM != 0,
+            mxr: mstatus & MSTATUS_MXR != 0 || context.virt && vsstatus & MSTATUS_MXR != 0,
+            hlvx: context.hlvx,
+        };
+        // NOTE: The G-stage treats every access as coming from user mode, and only listens to
+        //       the MXR of `mstatus`.
+        let guest = Request {
+            user: true,
+            mxr: mstatus & MSTATUS_MXR != 0,
+            ..request
+        };
+
+        let gpa = match first {
+            None => addr,
+            Some(table) => {
+                let bytes = table.pte_bytes();
+                let entry = |pte: u64| {
+                    let implicit = Request {
+                        access: Access::Read,
+                        hlvx: false,
+                        ..guest
+                    };
+                    let pa = self.guest(bus, second, pte, implicit, access);
+                    if pa == Err(page_fault(access, true)) {
+                        fault.tval2 = pte >> 2;
+                        // Pseudoinstructions for the implicit 32- or 64-bit read
+                        fault.tinst = if bytes == 4 { 0x2000 } else { 0x3000 };
+                    }
+                    self.read_pte(bus, pa?, bytes).ok_or(access_fault(access))
+                };
+                paging::walk(table, addr, request, page_fault(access, false), entry)?
+            }
+        };
+
+        let pa = self.guest(bus, second, gpa, guest, access);
+        if pa == Err(page_fault(access, true)) {
+            fault.tval2 = gpa >> 2;
+        }
+        let pa = pa?;
+
+        let machine = context.privilege == Privilege::Machine;
+        if !self.pmp.check(pa, len, access, machine) {
+            Err(access_fault(access))?;
+        }
+        Ok(pa)
+    }
+
+    /// Translates guest-physical `gpa` through the G-stage `table`, if any.
+    ///
+    /// Faults are reported for the original `access`, which may differ from the request's when
+    /// reading VS-stage page tables.
+    fn guest(
+        &self,
+        bus: &mut impl Bus,
+        table: Option<Table>,
+        gpa: u64,
+        request: Request,
+        access: Access,
+    ) -> Result<u64, Error> {
+        let Some(table) = table else {
+            return Ok(gpa);
+        };
+        let bytes = table.pte_bytes();
+        let entry = |pte| self.read_pte(bus, pte, bytes).ok_or(access_fault(access));
+        paging::walk(table, gpa, request, page_fault(access, true), entry)
+    }
+
+    /// Reads a `bytes`-wide page table entry, which PMP checks like a supervisor-mode read.
+    fn read_pte(&self, bus: &mut impl Bus, pa: u64, bytes: u64) -> Option<u64> {
+        if !self.pmp.check(pa, bytes, Access::Read, false) {
+            return None;
+        }
+        let mut buf = [0; 8];
+        bus.read(pa, &mut buf[..bytes as usize]).ok()?;
+        Some(u64::from_le_bytes(buf))
+    }
+}
@@ -0,0 +1,477 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Trap entry and `mret`/`sret`.
+//!
+//! Exceptions are returned to the embedder rather than taken, which it can do with
+//! [`Hart::take_trap`] to have them delegated and switch virtualization mode the way the
+//! privileged specification describes.
+
+use crate::{
+    csr::Csr,
+    ext::Extensions,
+    freg::FRegType,
+    hart::{
+        exec::Error,
+        hypervisor::{HSTATUS_GVA, HSTATUS_SPV, HSTATUS_SPVP, HSTATUS_VTSR},
+        Hart, Privilege,
+    },
+    reg::RegType,
+};
+
+const MSTATUS_SIE: u64 = 1 << 1;
+const MSTATUS_MIE: u64 = 1 << 3;
+const MSTATUS_SPIE: u64 = 1 << 5;
+const MSTATUS_MPIE: u64 = 1 << 7;
+const MSTATUS_SPP: u64 = 1 << 8;
+const MSTATUS_MPP: u64 = 0b11 << 11;
+const MSTATUS_MPRV: u64 = 1 << 17;
+const MSTATUS_TSR: u64 = 1 << 22;
+const MSTATUS_GVA: u64 = 1 << 38;
+const MSTATUS_MPV: u64 = 1 << 39;
+const MSTATUS_UBE: u64 = 1 << 6;
+const MSTATUS_VS: u64 = 0b11 << 9;
+const MSTATUS_FS: u64 = 0b11 << 13;
+const MSTATUS_XS: u64 = 0b11 << 15;
+const MSTATUS_SUM: u64 = 1 << 18;
+const MSTATUS_MXR: u64 = 1 << 19;
+const MSTATUS_UXL: u64 = 0b11 << 32;
+
+/// The fields of `mstatus` supervisor mode writes through `sstatus`.
+const SSTATUS_WRITABLE: u64 =
+    MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_VS | MSTATUS_FS | MSTATUS_SUM | MSTATUS_MXR;
+/// The fields of `mstatus` supervisor mode reads through `sstatus`, besides SD.
+const SSTATUS: u64 = SSTATUS_WRITABLE | MSTATUS_UBE | MSTATUS_XS | MSTATUS_UXL;
+
+/// The supervisor software, timer, external and counter overflow interrupts.
+const SUPERVISOR_INTERRUPTS: u64 = 1 << 1 | 1 << 5 | 1 << 9 | 1 << 13;
+/// The pending bits of those software clears, where the rest come from the platform.
+const SIP_WRITABLE: u64 = 1 << 1 | 1 << 13;
+
+/// Disables interrupts in `status`, saving whether they were enabled in the `pie` bit.
+fn enter(status: u64, ie: u64, pie: u64) -> u64 {
+    let pie = if status & ie != 0 { pie } else { 0 };
+    status & !(ie | pie) | pie
+}
+
+/// Restores the interrupt enable in `status` from the `pie` bit, which is set.
+fn leave(status: u64, ie: u64, pie: u64) -> u64 {
+    let ie = if status & pie != 0 { ie } else { 0 };
+    status & !ie | ie | pie
+}
+
+fn privilege(mpp: u64) -> Privilege {
+    match mpp & 0b11 {
+        0b00 => Privilege::User,
+        0b01 => Privilege::Supervisor,
+        _ => Privilege::Machine,
+    }
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// `mstatus`, with `mstatush` in the upper half on RV32.
+    fn mstatus(&self) -> u64 {
+        match I::BITS {
+            32 => self.csr.read(Csr::Mstatus) | self.csr.read(Csr::Mstatush) << 32,
+            _ => self.csr.read(Csr::Mstatus),
+        }
+    }
+
+    fn set_mstatus(&mut self, value: u64) {
+        match I::BITS {
+            32 => {
+                self.csr.write(Csr::Mstatus, value & 0xffff_ffff);
+                self.csr.write(Csr::Mstatush, value >> 32);
+            }
+            _ => self.csr.write(Csr::Mstatus, value),
+        }
+    }
+
+    /// `status` with SD set if, and only if, FS, VS or XS is dirty.
+    pub(super) fn summarize(status: u64) -> u64 {
+        let dirty = |field| status & field == field;
+        let dirty = dirty(MSTATUS_FS) || dirty(MSTATUS_VS) || dirty(MSTATUS_XS);
+        match dirty {
+            true => status | Self::xlen_msb(),
+            false => status & !Self::xlen_msb(),
+        }
+    }
+
+    /// Whether `field` is Off in `mstatus`, or in `vsstatus` while running a guest.
+    pub(super) fn status_off(&self, field: u64) -> bool {
+        let off = |csr| self.csr.read(csr) & field == 0;
+        off(Csr::Mstatus) || self.virt && off(Csr::Vsstatus)
+    }
+
+    /// Marks `field` dirty in `mstatus`, and in `vsstatus` too while running a guest.
+    pub(super) fn status_dirty(&mut self, field: u64) {
+        let dirty = |status| status | field | Self::xlen_msb();
+        self.csr
+            .write(Csr::Mstatus, dirty(self.csr.read(Csr::Mstatus)));
+        if self.virt {
+            self.csr
+                .write(Csr::Vsstatus, dirty(self.csr.read(Csr::Vsstatus)));
+        }
+    }
+
+    /// The supervisor interrupts `mideleg` delegates, which are all `sie` and `sip` show.
+    fn delegated_interrupts(&self) -> u64 {
+        self.csr.read(Csr::Mideleg) & SUPERVISOR_INTERRUPTS
+    }
+
+    /// Reads `sstatus`, `sie` or `sip`, which show supervisor mode its part of `mstatus`, `mie`
+    /// and `mip`.
+    pub(super) fn read_supervisor(&self, csr: Csr) -> u64 {
+        match csr {
+            Csr::Sstatus => self.csr.read(Csr::Mstatus) & (SSTATUS | Self::xlen_msb()),
+            Csr::Ssie => self.csr.read(Csr::Mie) & self.delegated_interrupts(),
+            _ => self.csr.read(Csr::Mip) & self.delegated_interrupts(),
+        }
+    }
+
+    /// Writes `sstatus`, `sie` or `sip` through to the machine-level CSR it shows part of.
+    pub(super) fn write_supervisor(&mut self, csr: Csr, value: u64) {
+        let (alias, mask) = match csr {
+            Csr::Sstatus => (Csr::Mstatus, SSTATUS_WRITABLE),
+            Csr::Ssie => (Csr::Mie, self.delegated_interrupts()),
+            _ => (Csr::Mip, self.delegated_interrupts() & SIP_WRITABLE),
+        };
+        let value = self.csr.read(alias) & !mask | value & mask;
+        self.write_csr(alias, value);
+    }
+
+    /// Takes the trap for exception `e`, raised by the instruction at `pc`.
+    ///
+    /// The trap goes to M-mode, to HS-mode if `medeleg` delegates it, or to VS-mode if `hedeleg`
+    /// also does while running a guest.
+    /// What [`fault`](Self::fault) reports is written to `xtval` and `htval`/`htinst` or
+    /// `mtval2`/`mtinst`, and the mode the trap came from to `xstatus` and `hstatus`.
+    pub fn take_trap(&mut self, e: Error) {
+        let cause = e as u64;
+        let fault = self.fault.take().unwrap_or_default();
+        let epc = self.pc.as_u128() as u64;
+        let (privilege, virt) = (self.privilege, self.virt);
+        let spp = match privilege {
+            Privilege::User => 0,
+            _ => MSTATUS_SPP,
+        };
+        let hs = privilege != Privilege::Machine && self.csr.read(Csr::Medeleg) >> cause & 1 != 0;
+        let vs = hs && virt && self.csr.read(Csr::Hedeleg) >> cause & 1 != 0;
+
+        let tvec = if vs {
+            self.csr.write(Csr::Vsepc, epc);
+            self.csr.write(Csr::Vscause, cause);
+            self.csr.write(Csr::Vstval, fault.tval);
+            let vsstatus = enter(self.csr.read(Csr::Vsstatus), MSTATUS_SIE, MSTATUS_SPIE);
+            self.csr.write(Csr::Vsstatus, vsstatus & !MSTATUS_SPP | spp);
+            self.csr.read(Csr::Vstvec)
+        } else if hs {
+            self.csr.write(Csr::Sepc, epc);
+            self.csr.write(Csr::Scause, cause);
+            self.csr.write(Csr::Stval, fault.tval);
+            if X::H {
+                self.csr.write(Csr::Htval, fault.tval2);
+                self.csr.write(Csr::Htinst, fault.tinst);
+                let mut hstatus = self.csr.read(Csr::Hstatus) & !(HSTATUS_SPV | HSTATUS_GVA);
+                if virt {
+                    hstatus = hstatus & !HSTATUS_SPVP | HSTATUS_SPV;
+                    if privilege == Privilege::Supervisor {
+                        hstatus |= HSTATUS_SPVP;
+                    }
+                }
+                if fault.gva {
+                    hstatus |= HSTATUS_GVA;
+                }
+                self.csr.write(Csr::Hstatus, hstatus);
+            }
+            let mstatus = enter(self.mstatus(), MSTATUS_SIE, MSTATUS_SPIE);
+            self.set_mstatus(mstatus & !MSTATUS_SPP | spp);
+            self.csr.read(Csr::Stvec)
+        } else {
+            self.csr.write(Csr::Mepc, epc);
+            self.csr.write(Csr::Mcause, cause);
+            self.csr.write(Csr::Mtval, fault.tval);
+            let mut mstatus = enter(self.mstatus(), MSTATUS_MIE, MSTATUS_MPIE);
+            mstatus = mstatus & !MSTATUS_MPP | (privilege as u64) << 11;
+            if X::H {
+                self.csr.write(Csr::Mtval2, fault.tval2);
+                self.csr.write(Csr::Mtinst, fault.tinst);
+                mstatus &= !(MSTATUS_MPV | MSTATUS_GVA);
+                if virt {
+                    mstatus |= MSTATUS_MPV;
+                }
+                if fault.gva {
+                    mstatus |= MSTATUS_GVA;
+                }
+            }
+            self.set_mstatus(mstatus);
+            self.csr.read(Csr::Mtvec)
+        };
+
+        self.privilege = match hs {
+            true => Privilege::Supervisor,
+            false => Privilege::Machine,
+        };
+        self.virt = vs;
+        // NOTE: Only interrupts are vectored.
+        self.pc = I::from_u128((tvec & !0b11) as u128);
+    }
+
+    /// The address an `xret` returns to, from `epc`.
+    fn epc(epc: u64) -> I {
+        I::from_u128((epc & if C { !0b1 } else { !0b11 }) as u128)
+    }
+
+    /// Executes `mret`, returning to the mode in `mstatus.MPP` and `mstatus.MPV`.
+    pub(super) fn mret(&mut self) -> Result<I, Error> {
+        if self.privilege != Privilege::Machine {
+            Err(Error::IllegalInstruction)?;
+        }
+        let mstatus = self.mstatus();
+        let mpp = privilege(mstatus >> 11);
+        let mut status = leave(mstatus, MSTATUS_MIE, MSTATUS_MPIE) & !(MSTATUS_MPP | MSTATUS_MPV);
+        if mpp != Privilege::Machine {
+            status &= !MSTATUS_MPRV;
+        }
+        self.set_mstatus(status);
+        self.privilege = mpp;
+        self.virt = X::H && mstatus & MSTATUS_MPV != 0 && mpp != Privilege::Machine;
+        Ok(Self::epc(self.csr.read(Csr::Mepc)))
+    }
+
+    /// Executes `sret`, returning to the mode in `sstatus.SPP` and `hstatus.SPV`, or within the
+    /// guest in `vsstatus.SPP`.
+    pub(super) fn sret(&mut self) -> Result<I, Error> {
+        let tsr = self.mstatus() & MSTATUS_TSR != 0;
+        let vtsr = self.csr.read(Csr::Hstatus) & HSTATUS_VTSR != 0;
+        match (self.privilege, self.virt) {
+            (Privilege::User, false) => Err(Error::IllegalInstruction)?,
+            (Privilege::User, true) => Err(Error::VirtualInstruction)?,
+            (Privilege::Supervisor, false) if tsr => Err(Error::IllegalInstruction)?,
+            (Privilege::Supervisor, true) if vtsr => Err(Error::VirtualInstruction)?,
+            _ => {}
+        }
+
+        if self.virt {
+            let vsstatus = self.csr.read(Csr::Vsstatus);
+            let status = leave(vsstatus, MSTATUS_SIE, MSTATUS_SPIE) & !MSTATUS_SPP;
+            self.csr.write(Csr::Vsstatus, status);
+            self.privilege = privilege(vsstatus >> 8 & 1);
+            return Ok(Self::epc(self.csr.read(Csr::Vsepc)));
+        }
+
+        let mstatus = self.mstatus();
+        let status = leave(mstatus, MSTATUS_SIE, MSTATUS_SPIE) & !(MSTATUS_SPP | MSTATUS_MPRV);
+        self.set_mstatus(status);
+        self.privilege = privilege(mstatus >> 8 & 1);
+        if X::H {
+            let hstatus = self.csr.read(Csr::Hstatus);
+            self.csr.write(Csr::Hstatus, hstatus & !HSTATUS_SPV);
+            self.virt = hstatus & HSTATUS_SPV != 0;
+        }
+        Ok(Self::epc(self.csr.read(Csr::Sepc)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MSTATUS_MPP, MSTATUS_MPRV, MSTATUS_SUM};
+    use crate::{
+        bus::{Bus, Ram},
+        csr::Csr,
+        ext::Extensions,
+        hart::{
+            exec::Error,
+            testing::{boot, load, write_program, BASE, DATA},
+            Hart, Privilege,
+        },
+        reg::{IRd, IRs1},
+    };
+
+    #[derive(Clone, Copy)]
+    struct Hypervisor;
+
+    impl Extensions for Hypervisor {
+        const H: bool = true;
+    }
+
+    const HSTATUS_GVA: u64 = 1 << 6;
+    const HSTATUS_SPV: u64 = 1 << 7;
+    const HSTATUS_SPVP: u64 = 1 << 8;
+
+    #[test]
+    fn test_guest_page_fault() {
+        let mut ram = Ram::new(0, 0x40_0000);
+        // The guest
+        write_program(
+            &mut ram,
+            0x1000,
+            &[
+                0x0005a503, // lw x10, 0(x11)
+                0x00000073, // ecall
+            ],
+        );
+        // The hypervisor's trap handler, and the machine's
+        write_program(
+            &mut ram,
+            0x3000,
+            &[
+                0x64302673, // csrr x12, htval
+                0x10200073, // sret
+                0x30200073, // mret
+            ],
+        );
+        write_program(&mut ram, 0x20_0000, &[0x1234_5678]);
+
+        // G-stage: guest-physical 0x0 maps to the same host-physical address as a megapage, and
+        // 0x20_0000 isn't mapped until the hypervisor handles the fault
+        const FLAGS: u64 = 0xdf; // V, R, W, X, U, A and D
+        let (root, table) = (0x1_0000, 0x1_4000);
+        ram.write(root, &(table >> 12 << 10 | 1u64).to_le_bytes())
+            .unwrap();
+        ram.write(table, &FLAGS.to_le_bytes()).unwrap();
+
+        let mut hart = Hart::<0, u64, false, false, false, (), false, false, Hypervisor>::new();
+        hart.write_csr(Csr::Hgatp, 8 << 60 | root >> 12);
+        hart.write_csr(Csr::Medeleg, 1 << Error::LoadGuestPageFault as u64);
+        hart.write_csr(Csr::Stvec, 0x3000);
+        hart.write_csr(Csr::Mtvec, 0x3008);
+        hart.reg_mut().set_rd(IRd::X11, 0x20_0000);
+
+        // The hypervisor enters the guest with an `sret`
+        hart.set_privilege(Privilege::Supervisor);
+        hart.write_csr(Csr::Mstatus, 1 << 8);
+        hart.write_csr(Csr::Hstatus, HSTATUS_SPV | HSTATUS_SPVP);
+        hart.write_csr(Csr::Sepc, 0x1000);
+        hart.set_pc(0x3004);
+        let e = hart.run(&mut ram, 10).unwrap_err();
+        assert_eq!(e, Error::LoadGuestPageFault);
+        assert!(hart.virtualized());
+        assert_eq!(hart.pc(), 0x1000);
+
+        // The fault is delegated to the hypervisor, leaving the guest
+        hart.take_trap(e);
+        assert_eq!(
+            (hart.privilege(), hart.virtualized()),
+            (Privilege::Supervisor, false)
+        );
+        assert_eq!(hart.pc(), 0x3000);
+        assert_eq!(hart.read_csr(Csr::Scause), 21);
+        assert_eq!(hart.read_csr(Csr::Sepc), 0x1000);
+        assert_eq!(hart.read_csr(Csr::Stval), 0x20_0000);
+        assert_eq!(hart.read_csr(Csr::Htval), 0x20_0000 >> 2);
+        let hstatus = HSTATUS_GVA | HSTATUS_SPV | HSTATUS_SPVP;
+        assert_eq!(hart.read_csr(Csr::Hstatus) & hstatus, hstatus);
+
+        // It maps the page and returns to the guest, which retries the load
+        ram.write(table + 8, &(0x20_0000 >> 12 << 10 | FLAGS).to_le_bytes())
+            .unwrap();
+        assert_eq!(hart.run(&mut ram, 10), Err(Error::EcallFromVSMode));
+        assert_eq!(hart.reg().get_rs1(IRs1::X12), 0x20_0000 >> 2);
+        assert_eq!(hart.reg().get_rs1(IRs1::X10), 0x1234_5678);
+        assert!(hart.virtualized());
+
+        // Environment calls aren't delegated, so they go to the machine, which returns with
+        // `mret`
+        hart.take_trap(Error::EcallFromVSMode);
+        assert_eq!(
+            (hart.privilege(), hart.virtualized()),
+            (Privilege::Machine, false)
+        );
+        assert_eq!(hart.read_csr(Csr::Mcause), 10);
+        assert_eq!(hart.read_csr(Csr::Mepc), 0x1004);
+        let mstatus = hart.read_csr(Csr::Mstatus);
+        assert_eq!(
+            (mstatus >> 11 & 0b11, mstatus >> 39 & 1, mstatus >> 38 & 1),
+            (1, 1, 0)
+        );
+        hart.write_csr(Csr::Mepc, 0x1008);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(
+            (hart.privilege(), hart.virtualized()),
+            (Privilege::Supervisor, true)
+        );
+        assert_eq!(hart.pc(), 0x1008);
+    }
+
+    #[test]
+    fn test_sstatus() {
+        let mut ram = load(&[
+            0x00052083, // lw x1, 0(x10)
+            0x1005a073, // csrs sstatus, x11
+            0x00052083, // lw x1, 0(x10)
+            0x10062073, // csrs sstatus, x12
+            0x100026f3, // csrr x13, sstatus
+        ]);
+        ram.write(DATA, &0x1234_5678u32.to_le_bytes()).unwrap();
+        // Gigapages mapping 0x0 for supervisor mode, and 0x4000_0000 to it for user mode
+        const FLAGS: u64 = 0xcf; // V, R, W, X, A and D
+        const USER: u64 = 1 << 4;
+        ram.write(0x2000, &FLAGS.to_le_bytes()).unwrap();
+        ram.write(0x2008, &(FLAGS | USER).to_le_bytes()).unwrap();
+
+        let mut hart: Hart<0, u64, false, false, false, (), false, false> = boot();
+        hart.write_csr(Csr::Satp, 8 << 60 | 0x2000 >> 12);
+        hart.set_privilege(Privilege::Supervisor);
+        hart.reg_mut().set_rd(IRd::X10, 0x4000_0000 + DATA);
+        hart.reg_mut().set_rd(IRd::X11, MSTATUS_SUM);
+        hart.reg_mut().set_rd(IRd::X12, MSTATUS_MPP);
+
+        // Supervisor mode only reads user memory with SUM set, which `sstatus` writes through
+        assert_eq!(hart.step(&mut ram), Err(Error::LoadPageFault));
+        hart.set_pc(BASE + 4);
+        for _ in 0..2 {
+            hart.step(&mut ram).unwrap();
+        }
+        assert_eq!(hart.reg().get_rs1(IRs1::X1), 0x1234_5678);
+        assert_eq!(hart.read_csr(Csr::Mstatus), MSTATUS_SUM);
+
+        // ...but not to the fields of machine mode, which it doesn't see either
+        hart.write_csr(Csr::Mstatus, MSTATUS_SUM | MSTATUS_MPRV);
+        for _ in 0..2 {
+            hart.step(&mut ram).unwrap();
+        }
+        assert_eq!(hart.read_csr(Csr::Mstatus), MSTATUS_SUM | MSTATUS_MPRV);
+        assert_eq!(hart.reg().get_rs1(IRs1::X13), MSTATUS_SUM);
+    }
+
+    #[test]
+    fn test_sie_and_sip() {
+        let mut hart: Hart<0, u64, false, false, false, (), false, false> = boot();
+        hart.write_csr(Csr::Mie, 1 << 7);
+        hart.write_csr(Csr::Mip, 1 << 9);
+
+        // Only the interrupts delegated to supervisor mode show
+        assert_eq!(hart.read_csr(Csr::Ssie), 0);
+        assert_eq!(hart.read_csr(Csr::Sip), 0);
+        hart.write_csr(Csr::Ssie, !0);
+        assert_eq!(hart.read_csr(Csr::Mie), 1 << 7);
+
+        hart.write_csr(Csr::Mideleg, 1 << 1 | 1 << 5 | 1 << 9);
+        assert_eq!(hart.read_csr(Csr::Sip), 1 << 9);
+        hart.write_csr(Csr::Ssie, !0);
+        assert_eq!(hart.read_csr(Csr::Mie), 1 << 1 | 1 << 5 | 1 << 7 | 1 << 9);
+        assert_eq!(hart.read_csr(Csr::Ssie), 1 << 1 | 1 << 5 | 1 << 9);
+
+        // ...of which software only sets and clears the software interrupt
+        hart.write_csr(Csr::Sip, 1 << 1 | 1 << 5);
+        assert_eq!(hart.read_csr(Csr::Mip), 1 << 1 | 1 << 9);
+    }
+}
@@ -0,0 +1,688 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! The Sdtrig trigger module: `mcontrol6` breakpoints and watchpoints, and the `icount`,
+//! `itrigger` and `etrigger` triggers.
+//!
+//! Triggers with action 0 raise [`Error::Breakpoint`], reporting the address through
+//! [`Hart::fault`].
+//! Triggers with action 1 enter debug mode instead, and can only be configured from debug mode.
+
+use std::ops::Range;
+
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::FRegType,
+    hart::{debug::Cause, exec::Error, translate::Context, translate::Fault, Hart, Privilege},
+    inst::Instruction,
+    mmu::pmp::Access,
+    reg::RegType,
+    snapshot::{Reader, Snapshot, SnapshotError, Writer},
+};
+
+const ICOUNT: u64 = 3;
+const ITRIGGER: u64 = 4;
+const ETRIGGER: u64 = 5;
+const MCONTROL6: u64 = 6;
+const DISABLED: u64 = 15;
+
+/// `tinfo.version` for version 1.0 of Sdtrig
+const VERSION: u64 = 1 << 24;
+
+// Fields of `mcontrol6`
+const VS6: u64 = 1 << 24;
+const VU6: u64 = 1 << 23;
+const HIT0: u64 = 1 << 22;
+const SELECT: u64 = 1 << 21;
+const ACTION6: u64 = 0xf << 12;
+const CHAIN: u64 = 1 << 11;
+const MATCH: u64 = 0xf << 7;
+const M6: u64 = 1 << 6;
+const S6: u64 = 1 << 4;
+const U6: u64 = 1 << 3;
+const EXECUTE: u64 = 1 << 2;
+const STORE: u64 = 1 << 1;
+const LOAD: u64 = 1 << 0;
+
+// Fields of `icount`, `itrigger` and `etrigger`
+const VS3: u64 = 1 << 26;
+const VU3: u64 = 1 << 25;
+const HIT3: u64 = 1 << 24;
+const COUNT: u64 = 0x3fff << 10;
+const PENDING: u64 = 1 << 8;
+const VS4: u64 = 1 << 12;
+const VU4: u64 = 1 << 11;
+const MMODE: u64 = 1 << 9;
+const SMODE: u64 = 1 << 7;
+const UMODE: u64 = 1 << 6;
+const ACTION: u64 = 0x3f;
+
+const MSTATUS_MIE: u64 = 1 << 3;
+
+/// Configuration and state of the triggers.
+pub(super) struct Triggers {
+    tdata1: Vec<u64>,
+    tdata2: Vec<u64>,
+    /// Triggers that matched a trap and fire before the next instruction
+    pending: Vec<bool>,
+}
+
+impl Triggers {
+    pub(super) fn new(count: usize, xlen: u32) -> Self {
+        Self {
+            tdata1: vec![DISABLED << (xlen - 4); count],
+            tdata2: vec![0; count],
+            pending: vec![false; count],
+        }
+    }
+}
+
+impl Snapshot for Triggers {
+    fn save(&self, w: &mut Writer) {
+        w.u64(self.tdata1.len() as u64);
+        for index in 0..self.tdata1.len() {
+            w.u64(self.tdata1[index]);
+            w.u64(self.tdata2[index]);
+            w.bool(self.pending[index]);
+        }
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        r.expect("number of triggers", self.tdata1.len() as u64)?;
+        for index in 0..self.tdata1.len() {
+            self.tdata1[index] = r.u64()?;
+            self.tdata2[index] = r.u64()?;
+            self.pending[index] = r.bool()?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `value` satisfies the `match` condition `kind` against `tdata2`.
+fn compare(kind: u64, value: u64, tdata2: u64, xlen: u32) -> bool {
+    let half = xlen / 2;
+    let low = (1 << half) - 1;
+    let mask = tdata2 >> half & low;
+    let matched = match kind & 0b111 {
+        0 => value == tdata2,
+        // The trailing ones of `tdata2` and the zero above them are ignored
+        1 => {
+            let bits = (tdata2.trailing_ones() + 1).min(xlen);
+            value.checked_shr(bits).unwrap_or(0) == tdata2.checked_shr(bits).unwrap_or(0)
+        }
+        2 => value >= tdata2,
+        3 => value < tdata2,
+        4 => value & low & mask == tdata2 & low & mask,
+        5 => value >> half & low & mask == tdata2 & low & mask,
+        _ => false,
+    };
+    // NOTE: The upper bit negates the condition.
+    matched != (kind & 0b1000 != 0)
+}
+
+/// Whether a trigger of type `kind` applies in `privilege`, with or without virtualization.
+fn enabled(kind: u64, tdata1: u64, privilege: Privilege, virt: bool) -> bool {
+    let [m, s, u, vs, vu] = match kind {
+        MCONTROL6 => [M6, S6, U6, VS6, VU6],
+        ICOUNT => [MMODE, SMODE, UMODE, VS3, VU3],
+        _ => [MMODE, SMODE, UMODE, VS4, VU4],
+    };
+    let bit = match (privilege, virt) {
+        (Privilege::Machine, _) => m,
+        (Privilege::Supervisor, false) => s,
+        (Privilege::User, false) => u,
+        (Privilege::Supervisor, true) => vs,
+        (Privilege::User, true) => vu,
+    };
+    tdata1 & bit != 0
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    fn xlen() -> u32 {
+        I::BITS.min(64)
+    }
+
+    fn trigger_type(tdata1: u64) -> u64 {
+        tdata1 >> (Self::xlen() - 4)
+    }
+
+    fn dmode() -> u64 {
+        1 << (Self::xlen() - 5)
+    }
+
+    fn hit(tdata1: u64) -> u64 {
+        match Self::trigger_type(tdata1) {
+            MCONTROL6 => HIT0,
+            ICOUNT => HIT3,
+            _ => 1 << (Self::xlen() - 6),
+        }
+    }
+
+    fn action(tdata1: u64) -> u64 {
+        match Self::trigger_type(tdata1) {
+            MCONTROL6 => (tdata1 & ACTION6) >> 12,
+            _ => tdata1 & ACTION,
+        }
+    }
+
+    /// Whether any trigger is configured, which native code doesn't check.
+    pub(super) fn triggers_armed(&self) -> bool {
+        self.triggers
+            .tdata1
+            .iter()
+            .any(|&tdata1| Self::trigger_type(tdata1) != DISABLED)
+    }
+
+    /// Reads a trigger CSR, returning `None` if `csr` isn't one.
+    pub(super) fn read_trigger(&self, csr: Csr) -> Option<u64> {
+        let index = self.csr.read(Csr::Tselect) as usize;
+        let value = match csr {
+            // NOTE: Without triggers, `tdata1` reads as type 0 and `tinfo` as only supporting it.
+            Csr::Tdata1 | Csr::Tdata2 | Csr::Tdata3 if X::TRIGGERS == 0 => 0,
+            Csr::Tinfo if X::TRIGGERS == 0 => 1,
+            Csr::Tdata1 => self.triggers.tdata1[index],
+            Csr::Tdata2 => self.triggers.tdata2[index],
+            // NOTE: There are no `textra` conditions.
+            Csr::Tdata3 => 0,
+            Csr::Tinfo => {
+                let types = [ICOUNT, ITRIGGER, ETRIGGER, MCONTROL6, DISABLED];
+                types.iter().fold(VERSION, |info, kind| info | 1 << kind)
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Writes a trigger CSR, returning `false` if `csr` isn't one.
+    pub(super) fn write_trigger(&mut self, csr: Csr, value: u64) -> bool {
+        let index = self.csr.read(Csr::Tselect) as usize;
+        match csr {
+            Csr::Tselect => {
+                if (value as usize) < X::TRIGGERS {
+                    self.csr.write(csr, value);
+                }
+            }
+            Csr::Tdata1 | Csr::Tdata2 | Csr::Tdata3 | Csr::Tinfo if X::TRIGGERS == 0 => {}
+            // NOTE: Triggers belonging to debug mode can only be changed from debug mode.
+            Csr::Tdata1 | Csr::Tdata2 | Csr::Tdata3
+                if self.triggers.tdata1[index] & Self::dmode() != 0 && !self.debug => {}
+            Csr::Tdata1 => {
+                self.triggers.tdata1[index] = self.legalize_tdata1(index, value, self.debug);
+                self.triggers.pending[index] = false;
+            }
+            Csr::Tdata2 => self.triggers.tdata2[index] = value,
+            Csr::Tdata3 | Csr::Tinfo => {}
+            _ => return false,
+        }
+        true
+    }
+
+    /// Checks an access to a trigger CSR, if `csr` is one.
+    pub(super) fn check_trigger_csr(&self, csr: Csr) -> Result<(), Error> {
+        let trigger = matches!(
+            csr,
+            Csr::Tselect | Csr::Tdata1 | Csr::Tdata2 | Csr::Tdata3 | Csr::Tinfo
+        );
+        match trigger && self.privilege != Privilege::Machine {
+            true => Err(Error::IllegalInstruction),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether every `tdata1` holds a value it could have been written, which restored snapshots
+    /// are checked for.
+    pub(super) fn triggers_legal(&self) -> bool {
+        (0..X::TRIGGERS).all(|index| {
+            let tdata1 = self.triggers.tdata1[index];
+            self.legalize_tdata1(index, tdata1, true) == tdata1
+        })
+    }
+
+    /// The value `tdata1` of trigger `index` takes when `value` is written to it, in debug mode
+    /// if `debug`.
+    fn legalize_tdata1(&self, index: usize, value: u64, debug: bool) -> u64 {
+        let dmode = match debug {
+            true => value & Self::dmode(),
+            false => 0,
+        };
+        let virt = |vs, vu| if X::H { vs | vu } else { 0 };
+
+        let kind = Self::trigger_type(value);
+        let mut fields = match kind {
+            MCONTROL6 => {
+                let fields = HIT0 | SELECT | ACTION6 | CHAIN | MATCH | M6 | S6 | U6;
+                let mut fields = value & (fields | EXECUTE | STORE | LOAD | virt(VS6, VU6));
+                if (fields & MATCH) >> 7 & 0b111 > 5 {
+                    fields &= !MATCH;
+                }
+                // NOTE: Instruction encodings aren't matched, so execute triggers only take
+                //       addresses.
+                if fields & SELECT != 0 {
+                    fields &= !EXECUTE;
+                }
+                // The last trigger has nothing to chain to
+                if index + 1 == X::TRIGGERS {
+                    fields &= !CHAIN;
+                }
+                fields
+            }
+            ICOUNT => {
+                value & (HIT3 | COUNT | MMODE | PENDING | SMODE | UMODE | ACTION | virt(VS3, VU3))
+            }
+            ITRIGGER | ETRIGGER => {
+                let hit = 1 << (Self::xlen() - 6);
+                value & (hit | MMODE | SMODE | UMODE | ACTION | virt(VS4, VU4))
+            }
+            _ => return DISABLED << (Self::xlen() - 4) | dmode,
+        };
+
+        // NOTE: Only triggers belonging to debug mode may enter it, and there are no other
+        //       actions.
+        let action = Self::action(kind << (Self::xlen() - 4) | fields);
+        if !(action == 0 || action == 1 && dmode != 0) {
+            fields &= match kind {
+                MCONTROL6 => !ACTION6,
+                _ => !ACTION,
+            };
+        }
+        kind << (Self::xlen() - 4) | dmode | fields
+    }
+
+    /// Fires the triggers in `chain` with the action of the last one.
+    fn fire(&mut self, chain: Range<usize>, tval: u64) -> Result<(), Error> {
+        let action = Self::action(self.triggers.tdata1[chain.end - 1]);
+        // NOTE: A breakpoint in machine mode would clobber the state of a trap handler that's
+        //       still running, so they're held off while `mstatus.MIE` is clear.
+        let mie = self.csr.read(Csr::Mstatus) & MSTATUS_MIE != 0;
+        if action == 0 && self.privilege == Privilege::Machine && !mie {
+            return Ok(());
+        }
+
+        for index in chain {
+            let tdata1 = self.triggers.tdata1[index];
+            self.triggers.tdata1[index] = tdata1 | Self::hit(tdata1);
+        }
+        if action == 1 {
+            self.halt = Some(Cause::Trigger);
+        } else {
+            self.fault = Some(Fault {
+                tval,
+                gva: self.virt,
+                tval2: 0,
+                tinst: 0,
+            });
+        }
+        Err(Error::Breakpoint)
+    }
+
+    /// Whether trigger `index` is an `mcontrol6` matching an access of `len` bytes at `addr`.
+    fn matches(
+        &self,
+        index: usize,
+        access: u64,
+        addr: u64,
+        len: u64,
+        data: Option<u64>,
+        context: Context,
+    ) -> bool {
+        let tdata1 = self.triggers.tdata1[index];
+        let tdata2 = self.triggers.tdata2[index];
+        if Self::trigger_type(tdata1) != MCONTROL6
+            || tdata1 & access == 0
+            || !enabled(MCONTROL6, tdata1, context.privilege, context.virt)
+        {
+            return false;
+        }
+
+        let compare = |value| compare((tdata1 & MATCH) >> 7, value, tdata2, Self::xlen());
+        match (tdata1 & SELECT != 0, data) {
+            (true, Some(data)) => compare(data),
+            (true, None) => false,
+            // Any of the accessed bytes can match
+            (false, _) => (addr..addr.saturating_add(len)).any(compare),
+        }
+    }
+
+    /// Fires the `mcontrol6` triggers matching an access of `len` bytes at `addr` made in
+    /// `context`.
+    ///
+    /// Without `data`, only chains of address triggers are considered; with it, only chains
+    /// including a data trigger.
+    pub(super) fn match_access(
+        &mut self,
+        addr: u64,
+        len: u64,
+        access: Access,
+        data: Option<u64>,
+        context: Context,
+    ) -> Result<(), Error> {
+        if X::TRIGGERS == 0 || self.debug {
+            return Ok(());
+        }
+        let access = match access {
+            Access::Read => LOAD,
+            Access::Write => STORE,
+            Access::Execute => EXECUTE,
+        };
+
+        let chained = |tdata1: u64| Self::trigger_type(tdata1) == MCONTROL6 && tdata1 & CHAIN != 0;
+        let mut start = 0;
+        while start < X::TRIGGERS {
+            let mut end = start + 1;
+            while chained(self.triggers.tdata1[end - 1]) {
+                end += 1;
+            }
+            let chain = start..end;
+            start = end;
+
+            let data_chain = chain
+                .clone()
+                .any(|index| self.triggers.tdata1[index] & SELECT != 0);
+            let matched = chain
+                .clone()
+                .all(|index| self.matches(index, access, addr, len, data, context));
+            if matched && data_chain == data.is_some() {
+                self.fire(chain, addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fires the data triggers matching `data` being accessed at `addr` in the mode loads and
+    /// stores are made in.
+    pub(super) fn match_data(
+        &mut self,
+        addr: u64,
+        len: u64,
+        access: Access,
+        data: u64,
+    ) -> Result<(), Error> {
+        match X::TRIGGERS {
+            0 => Ok(()),
+            _ => self.match_access(addr, len, access, Some(data), self.data_context()),
+        }
+    }
+
+    /// Marks the `etrigger`s or `itrigger`s matching a trap with `cause` from the current mode,
+    /// which fire before the next instruction.
+    fn match_trap(&mut self, kind: u64, cause: u64) {
+        if self.debug || cause >= Self::xlen() as u64 {
+            return;
+        }
+        for index in 0..X::TRIGGERS {
+            let tdata1 = self.triggers.tdata1[index];
+            if Self::trigger_type(tdata1) == kind
+                && enabled(kind, tdata1, self.privilege, self.virt)
+                && self.triggers.tdata2[index] >> cause & 1 != 0
+            {
+                self.triggers.pending[index] = true;
+            }
+        }
+    }
+
+    /// Marks the `etrigger`s matching exception `e`, which the embedder takes as a trap.
+    pub(super) fn match_exception(&mut self, e: Error) {
+        self.match_trap(ETRIGGER, e as u64);
+    }
+
+    /// Tells the triggers that the embedder is taking interrupt `code`, so the matching
+    /// `itrigger`s fire before the first instruction of the handler.
+    ///
+    /// Must be called before switching to the privilege mode of the handler.
+    pub fn interrupt_taken(&mut self, code: u64) {
+        self.match_trap(ITRIGGER, code);
+    }
+
+    /// Fires the triggers that matched earlier and wait for an instruction boundary.
+    fn fire_pending(&mut self) -> Result<(), Error> {
+        let pc = self.pc.as_u64();
+        for index in 0..X::TRIGGERS {
+            let tdata1 = self.triggers.tdata1[index];
+            let icount = Self::trigger_type(tdata1) == ICOUNT
+                && tdata1 & PENDING != 0
+                && enabled(ICOUNT, tdata1, self.privilege, self.virt);
+            if icount {
+                self.triggers.tdata1[index] &= !PENDING;
+            }
+            if icount || std::mem::take(&mut self.triggers.pending[index]) {
+                self.fire(index..index + 1, pc)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts an instruction retired in `privilege` against the `icount` triggers.
+    fn count_instruction(&mut self, privilege: Privilege, virt: bool) {
+        for tdata1 in &mut self.triggers.tdata1 {
+            let count = (*tdata1 & COUNT) >> 10;
+            if Self::trigger_type(*tdata1) == ICOUNT
+                && count > 0
+                && enabled(ICOUNT, *tdata1, privilege, virt)
+            {
+                *tdata1 = *tdata1 & !COUNT | (count - 1) << 10;
+                if count == 1 {
+                    *tdata1 |= PENDING;
+                }
+            }
+        }
+    }
+
+    /// Executes `inst` like [`execute`](Self::execute), firing the triggers that match it.
+    pub(super) fn execute_triggered(
+        &mut self,
+        bus: &mut impl Bus,
+        inst: Instruction<I, E, M, A, F, X>,
+        len: u8,
+    ) -> Result<(), Error> {
+        if X::TRIGGERS == 0 || self.debug {
+            return self.execute(bus, inst, len);
+        }
+
+        let (privilege, virt) = (self.privilege, self.virt);
+        let context = Context {
+            privilege,
+            virt,
+            hlvx: false,
+        };
+        self.fire_pending()?;
+        self.match_access(self.pc.as_u64(), len as u64, Access::Execute, None, context)?;
+        self.execute(bus, inst, len)?;
+        self.count_instruction(privilege, virt);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ETRIGGER, ICOUNT, MCONTROL6};
+    use crate::{
+        bus::{Bus, Ram},
+        csr::Csr,
+        ext::Extensions,
+        hart::{
+            exec::Error,
+            testing::{boot, load},
+            translate::Fault,
+            Hart, Privilege,
+        },
+        reg::{IRd, IRs1},
+        snapshot::{self, SnapshotError},
+    };
+
+    #[derive(Clone, Copy)]
+    struct Triggers;
+
+    impl Extensions for Triggers {
+        const TRIGGERS: usize = 4;
+    }
+
+    type TestHart = Hart<0, u64, false, false, false, (), false, false, Triggers>;
+
+    fn hart(program: &[u32]) -> (TestHart, Ram) {
+        (boot(), load(program))
+    }
+
+    fn trigger(hart: &mut TestHart, index: u64, tdata1: u64, tdata2: u64) {
+        hart.write_csr(Csr::Tselect, index);
+        hart.write_csr(Csr::Tdata1, tdata1);
+        hart.write_csr(Csr::Tdata2, tdata2);
+    }
+
+    const U6: u64 = 1 << 3;
+    const UMODE: u64 = 1 << 6;
+
+    #[test]
+    fn test_csrs() {
+        let mut hart = TestHart::new();
+        assert_eq!(hart.read_csr(Csr::Tinfo), 1 << 24 | 0b1000_0000_0111_1000);
+        assert_eq!(hart.read_csr(Csr::Tdata1), 15 << 60);
+
+        hart.write_csr(Csr::Tselect, 4);
+        assert_eq!(hart.read_csr(Csr::Tselect), 0);
+        hart.write_csr(Csr::Tselect, 3);
+        assert_eq!(hart.read_csr(Csr::Tselect), 3);
+
+        // Neither `dmode` nor entering debug mode can be set outside debug mode, and the last
+        // trigger can't chain
+        hart.write_csr(
+            Csr::Tdata1,
+            MCONTROL6 << 60 | 1 << 59 | 1 << 12 | 1 << 11 | 0b111,
+        );
+        assert_eq!(hart.read_csr(Csr::Tdata1), MCONTROL6 << 60 | 0b111);
+        // Unsupported types and `match` values
+        hart.write_csr(Csr::Tdata1, 2 << 60);
+        assert_eq!(hart.read_csr(Csr::Tdata1), 15 << 60);
+        hart.write_csr(Csr::Tdata1, MCONTROL6 << 60 | 7 << 7);
+        assert_eq!(hart.read_csr(Csr::Tdata1), MCONTROL6 << 60);
+
+        // Debug mode owns the triggers it configures
+        hart.debug = true;
+        hart.write_csr(Csr::Tdata1, ICOUNT << 60 | 1 << 59 | 1 << 10 | 1);
+        hart.debug = false;
+        hart.write_csr(Csr::Tdata1, 15 << 60);
+        assert_eq!(
+            hart.read_csr(Csr::Tdata1),
+            ICOUNT << 60 | 1 << 59 | 1 << 10 | 1
+        );
+
+        let mut none = Hart::<0, u64, false, false, false, (), false, false>::new();
+        none.write_csr(Csr::Tdata1, MCONTROL6 << 60);
+        assert_eq!(none.read_csr(Csr::Tdata1), 0);
+        assert_eq!(none.read_csr(Csr::Tinfo), 1);
+
+        // Snapshots can't hold configurations the CSRs can't, like the last trigger chaining
+        let mut file = Vec::new();
+        snapshot::save(&mut file, &[("hart", &hart)]).unwrap();
+        snapshot::restore(&mut &file[..], &mut [("hart", &mut TestHart::new())]).unwrap();
+        hart.triggers.tdata1[3] = MCONTROL6 << 60 | 1 << 11;
+        let mut file = Vec::new();
+        snapshot::save(&mut file, &[("hart", &hart)]).unwrap();
+        let error = snapshot::restore(&mut &file[..], &mut [("hart", &mut TestHart::new())]);
+        assert!(matches!(error, Err(SnapshotError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let (mut hart, mut ram) = hart(&[
+            0x00a5a023, // sw x10, 0(x11)
+            0x0005a603, // lw x12, 0(x11)
+            0x0005a603, // lw x12, 0(x11)
+        ]);
+        hart.set_privilege(Privilege::User);
+        hart.reg_mut().set_rd(IRd::X10, 0x1234);
+        hart.reg_mut().set_rd(IRd::X11, 0x8000);
+
+        // Stores to any byte of 0x8000..0x8010, matched with `match` 1 (NAPOT)
+        trigger(&mut hart, 0, MCONTROL6 << 60 | 1 << 7 | U6 | 0b010, 0x8007);
+        assert_eq!(hart.step(&mut ram), Err(Error::Breakpoint));
+        assert_eq!(hart.pc(), 0x1000);
+        assert_eq!(&ram.as_slice()[0x8000..0x8004], &[0; 4]);
+        let fault = Fault {
+            tval: 0x8000,
+            gva: false,
+            tval2: 0,
+            tinst: 0,
+        };
+        assert_eq!(hart.fault(), Some(fault));
+        assert_eq!(hart.read_csr(Csr::Tdata1) >> 22 & 1, 1);
+
+        // Loads from 0x8000 of the value 0x1234, chaining an address and a data trigger
+        trigger(&mut hart, 0, MCONTROL6 << 60 | 1 << 11 | U6 | 0b001, 0x8000);
+        trigger(&mut hart, 1, MCONTROL6 << 60 | 1 << 21 | U6 | 0b001, 0x1234);
+        hart.set_pc(0x1004);
+        hart.step(&mut ram).unwrap();
+        ram.write(0x8000, &0x1234u32.to_le_bytes()).unwrap();
+        assert_eq!(hart.step(&mut ram), Err(Error::Breakpoint));
+        assert_eq!(hart.reg().get_rs1(IRs1::X12), 0);
+
+        // Machine mode isn't matched
+        hart.set_privilege(Privilege::Machine);
+        hart.step(&mut ram).unwrap();
+    }
+
+    #[test]
+    fn test_execute_and_count() {
+        let program = [0x00108093; 4]; // addi x1, x1, 1
+        let (mut hart, mut ram) = hart(&program);
+        hart.set_privilege(Privilege::User);
+
+        trigger(&mut hart, 0, MCONTROL6 << 60 | U6 | 0b100, 0x100c);
+        trigger(&mut hart, 1, ICOUNT << 60 | 2 << 10 | UMODE, 0);
+        hart.step(&mut ram).unwrap();
+        hart.step(&mut ram).unwrap();
+        // `icount` fires once its count runs out, before the next instruction
+        assert_eq!(hart.step(&mut ram), Err(Error::Breakpoint));
+        assert_eq!(hart.read_csr(Csr::Tdata1), ICOUNT << 60 | 1 << 24 | UMODE);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.run(&mut ram, 8), Err(Error::Breakpoint));
+        assert_eq!(hart.pc(), 0x100c);
+        assert_eq!(hart.reg().get_rs1(IRs1::X1), 3);
+    }
+
+    #[test]
+    fn test_traps_and_debug_mode() {
+        let (mut hart, mut ram) = hart(&[
+            0x00000073, // ecall
+            0x00108093, // addi x1, x1, 1
+        ]);
+        hart.set_privilege(Privilege::User);
+
+        // Environment calls from user mode
+        trigger(&mut hart, 0, ETRIGGER << 60 | UMODE, 1 << 8);
+        assert_eq!(hart.step(&mut ram), Err(Error::EcallFromUOrVUMode));
+        hart.set_pc(0x1004);
+        assert_eq!(hart.step(&mut ram), Err(Error::Breakpoint));
+        assert_eq!(hart.read_csr(Csr::Tdata1) >> 58 & 1, 1);
+
+        // Debug mode is entered before the instruction executes
+        hart.debug = true;
+        let tdata1 = MCONTROL6 << 60 | 1 << 59 | 1 << 12 | U6 | 0b100;
+        trigger(&mut hart, 0, tdata1, 0x1004);
+        hart.debug = false;
+        hart.run(&mut ram, 8).unwrap();
+        assert!(hart.debug_mode());
+        assert_eq!(hart.privilege(), Privilege::Machine);
+        assert_eq!(hart.read_csr(Csr::Dpc), 0x1004);
+        assert_eq!(hart.read_csr(Csr::Dcsr) & 0b1_1100_0011, 2 << 6);
+        assert_eq!(hart.reg().get_rs1(IRs1::X1), 0);
+    }
+}
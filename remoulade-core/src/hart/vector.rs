@@ -0,0 +1,1129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Execution of the vector extension.
+//!
+//! Elements are at most 64 bits wide and are handled as zero-extended `u64`s.
+//!
+//! Agnostic tail and inactive elements are overwritten with all ones rather than left undisturbed,
+//! which is the behaviour most likely to break software that wrongly relies on them.
+
+mod mixed;
+mod permute;
+
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::{FRd, FRegType, FRs1},
+    hart::{exec::Error, Hart},
+    inst::vector::{VAddressing, VKind, VMem, VSrc, VsetKind},
+    mmu::pmp::Access,
+    reg::{IRd, IRs1, RegType},
+    softfloat::{self, Format, Rounding},
+};
+
+const MSTATUS_VS: u64 = 0b11 << 9;
+
+/// Decoded `vtype`.
+#[derive(Copy, Clone, Debug)]
+struct VType {
+    /// Element width in bits
+    sew: u32,
+    /// `log2(LMUL)`
+    lmul: i32,
+    ta: bool,
+    ma: bool,
+}
+
+impl VType {
+    /// Returns `None` for settings that would set `vill`.
+    fn decode(vtype: u64, elen: u32) -> Option<Self> {
+        // NOTE: This also catches `vill` itself.
+        if vtype >> 8 != 0 || vtype >> 3 & 0b111 > 0b011 {
+            None?
+        }
+        let sew = 8 << (vtype >> 3 & 0b111);
+        let lmul = match vtype & 0b111 {
+            0b100 => None?,
+            lmul @ 0b000..=0b011 => lmul as i32,
+            lmul => lmul as i32 - 8,
+        };
+        // NOTE: Fractional LMUL must leave room for at least one element of each supported width.
+        if sew > elen || (lmul < 0 && sew > elen >> -lmul) {
+            None?
+        }
+        Some(Self {
+            sew,
+            lmul,
+            ta: vtype >> 6 & 1 == 1,
+            ma: vtype >> 7 & 1 == 1,
+        })
+    }
+
+    fn vlmax(self, vlen: u32) -> usize {
+        scale(vlen as usize / self.sew as usize, self.lmul)
+    }
+
+    /// Number of elements up to the end of the register group, including the tail past `VLMAX`
+    /// in fractional groups.
+    fn group_len(self, vlen: u32) -> usize {
+        scale(vlen as usize / self.sew as usize, self.lmul.max(0))
+    }
+}
+
+/// An arithmetic instruction, along with the state it executes under.
+#[derive(Copy, Clone, Debug)]
+struct Arith {
+    vd: u8,
+    vs2: u8,
+    src: VSrc,
+    vm: bool,
+    kind: VKind,
+    vtype: VType,
+    /// The scalar operand truncated to `SEW`, or 0 for vector operands
+    scalar: u64,
+    rm: Rounding,
+}
+
+/// Multiplies `n` by `2^log2`.
+fn scale(n: usize, log2: i32) -> usize {
+    match log2 {
+        0.. => n << log2,
+        _ => n >> -log2,
+    }
+}
+
+/// Number of registers in a group with `EMUL = 2^emul`.
+fn regs(emul: i32) -> u8 {
+    1 << emul.max(0)
+}
+
+fn mask(bits: u32) -> u64 {
+    u64::MAX >> (64 - bits)
+}
+
+fn sext(value: u64, bits: u32) -> i64 {
+    (value << (64 - bits)) as i64 >> (64 - bits)
+}
+
+/// Shifts `value` right by `d` bits, rounding according to `vxrm`.
+fn roundoff(value: i128, d: u32, vxrm: u64) -> i128 {
+    if d == 0 {
+        return value;
+    }
+    let bit = |n: u32| value >> n & 1;
+    let lower = value & ((1 << d) - 1);
+    let round = match vxrm {
+        // Round-to-nearest-up
+        0b00 => bit(d - 1),
+        // Round-to-nearest-even
+        0b01 => bit(d - 1) & ((value & ((1 << (d - 1)) - 1) != 0) as i128 | bit(d)),
+        // Round-down
+        0b10 => 0,
+        // Round-to-odd
+        _ => (bit(d) == 0 && lower != 0) as i128,
+    };
+    (value >> d) + round
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Position of `mstatus.SD` and `vtype.vill`.
+    pub(super) fn xlen_msb() -> u64 {
+        1 << (I::BITS.min(64) - 1)
+    }
+
+    /// Raises an illegal instruction unless vector state is accessible, marking it dirty otherwise.
+    fn vector_enabled(&mut self) -> Result<(), Error> {
+        if !X::V || self.status_off(MSTATUS_VS) {
+            Err(Error::IllegalInstruction)?;
+        }
+        // NOTE: Always assuming modification is allowed, and we rarely know better before
+        //       executing the instruction.
+        self.status_dirty(MSTATUS_VS);
+        Ok(())
+    }
+
+    /// Checks an access to a vector CSR, if `csr` is one.
+    pub(super) fn check_vector_csr(&mut self, csr: Csr, write: bool) -> Result<(), Error> {
+        match csr {
+            Csr::Vstart | Csr::Vxsat | Csr::Vxrm | Csr::Vcsr => self.vector_enabled(),
+            Csr::Vl | Csr::Vtype | Csr::Vlenb if write => Err(Error::IllegalInstruction),
+            Csr::Vl | Csr::Vtype | Csr::Vlenb => self.vector_enabled(),
+            _ => Ok(()),
+        }
+    }
+
+    fn vtype(&self) -> Result<VType, Error> {
+        VType::decode(self.csr.read(Csr::Vtype), X::ELEN).ok_or(Error::IllegalInstruction)
+    }
+
+    fn vl(&self) -> usize {
+        self.csr.read(Csr::Vl) as usize
+    }
+
+    fn vstart(&self) -> usize {
+        self.csr.read(Csr::Vstart) as usize
+    }
+
+    fn active(&self, vm: bool, index: usize) -> bool {
+        vm || self.vreg.mask(0, index)
+    }
+
+    pub(super) fn vset(&mut self, rd: IRd, kind: VsetKind) -> Result<(), Error> {
+        self.vector_enabled()?;
+
+        // NOTE: `None` requests `VLMAX`.
+        let avl = |hart: &Self, rs1: IRs1| match (rs1, rd) {
+            (IRs1::X0, IRd::X0) => Some(hart.vl() as u64),
+            (IRs1::X0, _) => None,
+            _ => Some(u64::try_from(hart.reg.get_rs1(rs1).as_u128()).unwrap_or(u64::MAX)),
+        };
+        let (avl, vtype) = match kind {
+            VsetKind::Vsetvli { rs1, vtypei } => (avl(self, rs1), vtypei as u64),
+            VsetKind::Vsetivli { uimm, vtypei } => (Some(uimm as u64), vtypei as u64),
+            VsetKind::Vsetvl { rs1, rs2 } => (avl(self, rs1), self.reg.get_rs2(rs2).as_u64()),
+        };
+
+        let (vtype, vl) = match VType::decode(vtype, X::ELEN) {
+            Some(decoded) => {
+                let vlmax = decoded.vlmax(X::VLEN) as u64;
+                (vtype, avl.map_or(vlmax, |avl| avl.min(vlmax)))
+            }
+            None => (Self::xlen_msb(), 0),
+        };
+
+        self.csr.write(Csr::Vtype, vtype);
+        self.csr.write(Csr::Vl, vl);
+        self.csr.write(Csr::Vstart, 0);
+        self.reg.set_rd(rd, I::from_u128(vl as u128));
+        Ok(())
+    }
+
+    /// Executes a vector load or store.
+    ///
+    /// When an element faults, the elements before it have been accessed and `vstart` points at
+    /// it so the instruction can be resumed.
+    pub(super) fn vmem(
+        &mut self,
+        bus: &mut impl Bus,
+        vd: u8,
+        rs1: IRs1,
+        vm: bool,
+        mem: VMem,
+    ) -> Result<(), Error> {
+        self.vector_enabled()?;
+
+        let base = self.reg.get_rs1(rs1);
+        let vlenb = X::VLEN as usize / 8;
+        let nf = mem.nf as usize;
+        let vstart = self.vstart();
+
+        if mem.addressing == VAddressing::Whole {
+            let width = mem.eew as usize / 8;
+            if !(vd as usize).is_multiple_of(nf) {
+                Err(Error::IllegalInstruction)?;
+            }
+            for i in vstart..nf * vlenb / width {
+                let addr = base.wrapping_add(I::from_u128((i * width) as u128));
+                self.velement(bus, mem.store, addr, vd, i, mem.eew)
+                    .inspect_err(|_| self.csr.write(Csr::Vstart, i as u64))?;
+            }
+            self.csr.write(Csr::Vstart, 0);
+            return Ok(());
+        }
+
+        let vtype = self.vtype()?;
+        let vl = self.vl();
+
+        if mem.addressing == VAddressing::Mask {
+            let evl = vl.div_ceil(8);
+            for i in vstart..evl {
+                let addr = base.wrapping_add(I::from_u128(i as u128));
+                self.velement(bus, mem.store, addr, vd, i, 8)
+                    .inspect_err(|_| self.csr.write(Csr::Vstart, i as u64))?;
+            }
+            // NOTE: Mask loads are always tail agnostic.
+            if !mem.store {
+                self.vreg.reg_mut(vd)[evl..].fill(0xff);
+            }
+            self.csr.write(Csr::Vstart, 0);
+            return Ok(());
+        }
+
+        // NOTE: Indexed accesses use `eew` for the indices, everything else for the data.
+        let log2 = |bits: u32| bits.trailing_zeros() as i32;
+        let index_emul = vtype.lmul + log2(mem.eew) - log2(vtype.sew);
+        let (data_eew, data_emul) = match mem.addressing {
+            VAddressing::Indexed { .. } => (vtype.sew, vtype.lmul),
+            _ => (mem.eew, index_emul),
+        };
+        let data_regs = regs(data_emul);
+        if !(-3..=3).contains(&index_emul)
+            || nf * data_regs as usize > 8
+            || vd as usize + nf * data_regs as usize > 32
+            || !vd.is_multiple_of(data_regs)
+            || (!vm && vd == 0 && !mem.store)
+        {
+            Err(Error::IllegalInstruction)?;
+        }
+        if let VAddressing::Indexed { vs2, .. } = mem.addressing {
+            if !vs2.is_multiple_of(regs(index_emul)) {
+                Err(Error::IllegalInstruction)?;
+            }
+        }
+
+        let width = data_eew as usize / 8;
+        for i in vstart..vl {
+            if !self.active(vm, i) {
+                if vtype.ma && !mem.store {
+                    for f in 0..nf {
+                        let reg = vd + (f as u8) * data_regs;
+                        self.vreg.set(reg, i, data_eew, u64::MAX);
+                    }
+                }
+                continue;
+            }
+
+            let mut faulted = None;
+            for f in 0..nf {
+                let reg = vd + (f as u8) * data_regs;
+                let offset = match mem.addressing {
+                    VAddressing::Unit | VAddressing::UnitFaultFirst => {
+                        I::from_u128(((i * nf + f) * width) as u128)
+                    }
+                    VAddressing::Strided { rs2 } => self
+                        .reg
+                        .get_rs2(rs2)
+                        .wrapping_mul(I::from_u128(i as u128))
+                        .wrapping_add(I::from_u128((f * width) as u128)),
+                    VAddressing::Indexed { vs2, .. } => {
+                        let index = self.vreg.get(vs2, i, mem.eew);
+                        I::from_u128(index as u128 + (f * width) as u128)
+                    }
+                    VAddressing::Whole | VAddressing::Mask => unreachable!(),
+                };
+                let addr = base.wrapping_add(offset);
+                if let Err(e) = self.velement(bus, mem.store, addr, reg, i, data_eew) {
+                    faulted = Some(e);
+                    break;
+                }
+            }
+
+            match faulted {
+                None => {}
+                Some(_) if mem.addressing == VAddressing::UnitFaultFirst && i > 0 => {
+                    self.csr.write(Csr::Vl, i as u64);
+                    break;
+                }
+                Some(e) => {
+                    self.csr.write(Csr::Vstart, i as u64);
+                    Err(e)?
+                }
+            }
+        }
+
+        let vl = self.vl();
+        if !mem.store && vtype.ta && vstart < vl {
+            let group_len = scale(vlenb * 8 / data_eew as usize, data_emul.max(0));
+            for f in 0..nf {
+                let reg = vd + (f as u8) * data_regs;
+                for i in vl..group_len {
+                    self.vreg.set(reg, i, data_eew, u64::MAX);
+                }
+            }
+        }
+
+        self.csr.write(Csr::Vstart, 0);
+        Ok(())
+    }
+
+    /// Moves element `index` of the `eew`-bit elements of `reg` to or from memory.
+    fn velement(
+        &mut self,
+        bus: &mut impl Bus,
+        store: bool,
+        addr: I,
+        reg: u8,
+        index: usize,
+        eew: u32,
+    ) -> Result<(), Error> {
+        let width = eew as usize / 8;
+        match store {
+            true => {
+                let addr = addr.as_addr().ok_or(Error::StoreOrAmoAccessFault)?;
+                let addr = self.translate_addr(bus, addr, width as u64, Access::Write)?;
+                let value = self.vreg.get(reg, index, eew).to_le_bytes();
+                bus.write(addr, &value[..width])
+                    .map_err(|_| Error::StoreOrAmoAccessFault)?;
+                self.blocks.invalidate(addr, width as u64);
+            }
+            false => {
+                let addr = addr.as_addr().ok_or(Error::LoadAccessFault)?;
+                let addr = self.translate_addr(bus, addr, width as u64, Access::Read)?;
+                let mut buf = [0; 8];
+                bus.read(addr, &mut buf[..width])
+                    .map_err(|_| Error::LoadAccessFault)?;
+                self.vreg.set(reg, index, eew, u64::from_le_bytes(buf));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads `f[rs1]` as a `sew`-bit value, replacing improperly NaN-boxed values with the
+    /// canonical NaN.
+    fn float_operand(&self, rs1: FRs1, sew: u32) -> Result<u64, Error> {
+        if sew > F::BITS {
+            Err(Error::IllegalInstruction)?;
+        }
+        // NOTE: Every bit above `sew` must be set.
+        let bits = self.freg.get_rs1(rs1).to_bits();
+        if bits | mask(sew) as u128 != u128::MAX >> (128 - F::BITS) {
+            return Ok(canonical_nan(sew));
+        }
+        Ok(bits as u64 & mask(sew))
+    }
+
+    pub(super) fn varith(
+        &mut self,
+        vd: u8,
+        vs2: u8,
+        src: VSrc,
+        vm: bool,
+        kind: VKind,
+    ) -> Result<(), Error> {
+        use VKind::*;
+
+        self.vector_enabled()?;
+
+        let vlenb = X::VLEN as usize / 8;
+        let vstart = self.vstart();
+
+        // NOTE: Whole register moves don't depend on `vtype`.
+        if kind == Vmvr {
+            let VSrc::Imm(imm) = src else { unreachable!() };
+            let nr = imm as u8 + 1;
+            if !vd.is_multiple_of(nr) || !vs2.is_multiple_of(nr) {
+                Err(Error::IllegalInstruction)?;
+            }
+            for r in 0..nr {
+                let source = self.vreg.reg(vs2 + r).to_vec();
+                self.vreg.reg_mut(vd + r).copy_from_slice(&source);
+            }
+            self.csr.write(Csr::Vstart, 0);
+            return Ok(());
+        }
+
+        let vtype = self.vtype()?;
+        let sew = vtype.sew;
+        let vl = self.vl();
+        let vlmax = vtype.vlmax(X::VLEN);
+        let group = regs(vtype.lmul);
+        let vxrm = self.csr.read(Csr::Vxrm);
+
+        let float = kind.float();
+        // NOTE: These check their own register groups, with operands of other widths than `SEW`
+        //       or that aren't element-wise.
+        let mixed = mixed::shape(kind).is_some();
+        let permute = matches!(
+            kind,
+            Vcompress | Viota | Vmsbf | Vmsof | Vmsif | Vrgatherei16
+        );
+        // NOTE: Vector floating point uses the scalar floating-point state, rounding as `frm`
+        //       says and accruing `fflags`.
+        let mut rm = Rounding::NearestEven;
+        if float {
+            if !mixed && (sew < 32 || sew > F::BITS) {
+                Err(Error::IllegalInstruction)?;
+            }
+            self.float_accessible()?;
+            rm = self.rounding(0b111)?;
+            self.float_dirty();
+        }
+        let mut flags = 0;
+
+        let reduction = matches!(
+            kind,
+            Vredsum
+                | Vredand
+                | Vredor
+                | Vredxor
+                | Vredminu
+                | Vredmin
+                | Vredmaxu
+                | Vredmax
+                | Vfredusum
+                | Vfredosum
+                | Vfredmin
+                | Vfredmax
+        );
+        let mask_logical = matches!(
+            kind,
+            Vmandn | Vmand | Vmor | Vmxor | Vmorn | Vmnand | Vmnor | Vmxnor
+        );
+
+        // Register group constraints
+        let vector_vd = !kind.writes_mask() && !kind.writes_scalar() && !reduction;
+        let grouped_vs2 = !mask_logical && !matches!(kind, Vcpop | Vfirst | VmvXS | VfmvFS);
+        let grouped_vs1 = !mask_logical && !reduction && kind.selector().is_none();
+        let misaligned = (vector_vd && !vd.is_multiple_of(group))
+            || (grouped_vs2 && !vs2.is_multiple_of(group))
+            || matches!(src, VSrc::Vector(vs1) if grouped_vs1 && !vs1.is_multiple_of(group));
+        let overlaps_mask = !vm && vd == 0 && vector_vd && !matches!(kind, Vmerge | Vfmerge);
+        if !mixed && !permute && (misaligned || overlaps_mask) {
+            Err(Error::IllegalInstruction)?;
+        }
+
+        // Scalar operand as a full-width offset and truncated to `SEW`
+        let (offset, scalar) = match src {
+            VSrc::Vector(_) => (0, 0),
+            VSrc::Scalar(rs1) => {
+                let value = self.reg.get_rs1(rs1);
+                let offset = u64::try_from(value.as_u128()).unwrap_or(u64::MAX);
+                (offset, value.as_i128() as u64 & mask(sew))
+            }
+            VSrc::Imm(imm) => (imm as u64, imm as i64 as u64 & mask(sew)),
+            VSrc::Float(rs1) => (0, self.float_operand(rs1, sew)?),
+        };
+        let operand = |hart: &Self, i: usize| match src {
+            VSrc::Vector(vs1) => hart.vreg.get(vs1, i, sew),
+            _ => scalar,
+        };
+
+        match kind {
+            VmvXS => {
+                let value = sext(self.vreg.get(vs2, 0, sew), sew);
+                let rd = IRd::checked_from_u32(vd as u32).unwrap();
+                self.reg.set_rd(rd, I::from_i128(value as i128));
+            }
+
+            VfmvFS => {
+                let boxed = self.vreg.get(vs2, 0, sew) as u128 | u128::MAX << sew;
+                let rd = FRd::checked_from_u32(vd as u32).unwrap();
+                self.freg.set_rd(rd, F::from_bits(boxed));
+            }
+
+            Vcpop | Vfirst => {
+                if vstart != 0 {
+                    Err(Error::IllegalInstruction)?;
+                }
+                let mut set = (0..vl).filter(|&i| self.active(vm, i) && self.vreg.mask(vs2, i));
+                let value = match kind {
+                    Vcpop => set.count() as i128,
+                    _ => set.next().map_or(-1, |i| i as i128),
+                };
+                let rd = IRd::checked_from_u32(vd as u32).unwrap();
+                self.reg.set_rd(rd, I::from_i128(value));
+            }
+
+            _ if vstart >= vl => {}
+
+            _ if mixed || permute => {
+                let op = Arith {
+                    vd,
+                    vs2,
+                    src,
+                    vm,
+                    kind,
+                    vtype,
+                    scalar,
+                    rm,
+                };
+                match mixed {
+                    true => self.vmixed(op, &mut flags)?,
+                    false => self.vpermute(op)?,
+                }
+            }
+
+            VmvSX | VfmvSF => {
+                self.vreg.set(vd, 0, sew, scalar);
+                if vtype.ta {
+                    for i in 1..vlenb * 8 / sew as usize {
+                        self.vreg.set(vd, i, sew, u64::MAX);
+                    }
+                }
+            }
+
+            _ if mask_logical => {
+                for i in vstart..vl {
+                    let VSrc::Vector(vs1) = src else {
+                        unreachable!()
+                    };
+                    let (a, b) = (self.vreg.mask(vs2, i), self.vreg.mask(vs1, i));
+                    let value = match kind {
+                        Vmandn => a & !b,
+                        Vmand => a & b,
+                        Vmor => a | b,
+                        Vmxor => a ^ b,
+                        Vmorn => a | !b,
+                        Vmnand => !(a & b),
+                        Vmnor => !(a | b),
+                        _ => !(a ^ b),
+                    };
+                    self.vreg.set_mask(vd, i, value);
+                }
+                // NOTE: Mask destinations are always tail agnostic.
+                for i in vl..vlenb * 8 {
+                    self.vreg.set_mask(vd, i, true);
+                }
+            }
+
+            _ if kind.writes_mask() => {
+                // NOTE: `v0` is the carry in rather than a mask for these.
+                let carry = matches!(kind, Vmadc | Vmsbc);
+                let mut results = Vec::with_capacity(vl);
+                for i in vstart..vl {
+                    if !carry && !self.active(vm, i) {
+                        results.push((i, vtype.ma.then_some(true)));
+                        continue;
+                    }
+                    let (a, b) = (self.vreg.get(vs2, i, sew), operand(self, i));
+                    let value = match kind {
+                        _ if float => fp_op(kind, sew, a, b, 0, rm, &mut flags) == 1,
+                        Vmadc | Vmsbc => {
+                            let carry = !vm && self.vreg.mask(0, i);
+                            carry_out(kind, sew, a, b, carry)
+                        }
+                        _ => compare(kind, sew, a, b),
+                    };
+                    results.push((i, Some(value)));
+                }
+                for (i, value) in results {
+                    if let Some(value) = value {
+                        self.vreg.set_mask(vd, i, value);
+                    }
+                }
+                for i in vl..vlenb * 8 {
+                    self.vreg.set_mask(vd, i, true);
+                }
+            }
+
+            _ if reduction => {
+                if vstart != 0 {
+                    Err(Error::IllegalInstruction)?;
+                }
+                let mut acc = operand(self, 0);
+                let op = match kind {
+                    Vredsum => Vadd,
+                    Vredand => Vand,
+                    Vredor => Vor,
+                    Vredxor => Vxor,
+                    Vredminu => Vminu,
+                    Vredmin => Vmin,
+                    Vredmaxu => Vmaxu,
+                    Vredmax => Vmax,
+                    // NOTE: Unordered sums are allowed to be computed in order.
+                    Vfredusum | Vfredosum => Vfadd,
+                    Vfredmin => Vfmin,
+                    _ => Vfmax,
+                };
+                for i in (0..vl).filter(|&i| self.active(vm, i)) {
+                    let element = self.vreg.get(vs2, i, sew);
+                    acc = match float {
+                        true => fp_op(op, sew, acc, element, 0, rm, &mut flags),
+                        false => int_op(op, sew, acc, element, 0, vxrm).0,
+                    };
+                }
+                self.vreg.set(vd, 0, sew, acc);
+                if vtype.ta {
+                    for i in 1..vlenb * 8 / sew as usize {
+                        self.vreg.set(vd, i, sew, u64::MAX);
+                    }
+                }
+            }
+
+            _ => {
+                let mut saturated = false;
+                let mut results = Vec::with_capacity(vl);
+                for i in vstart..vl {
+                    let merge = matches!(kind, Vmerge | Vfmerge | Vadc | Vsbc);
+                    if !merge && !self.active(vm, i) {
+                        results.push((i, vtype.ma.then_some(u64::MAX)));
+                        continue;
+                    }
+
+                    let a = self.vreg.get(vs2, i, sew);
+                    let b = operand(self, i);
+                    let d = self.vreg.get(vd, i, sew);
+                    let get = |j: usize| self.vreg.get(vs2, j, sew);
+                    let offset = match src {
+                        VSrc::Vector(_) => b as usize,
+                        _ => offset as usize,
+                    };
+
+                    let value = match kind {
+                        Vrgather => match offset < vlmax {
+                            true => get(offset),
+                            false => 0,
+                        },
+                        Vslideup if i < offset => d,
+                        Vslideup => get(i - offset),
+                        Vslidedown => match i.checked_add(offset) {
+                            Some(j) if j < vlmax => get(j),
+                            _ => 0,
+                        },
+                        Vslide1up | Vfslide1up if i == 0 => scalar,
+                        Vslide1up | Vfslide1up => get(i - 1),
+                        Vslide1down | Vfslide1down if i == vl - 1 => scalar,
+                        Vslide1down | Vfslide1down => get(i + 1),
+                        Vmerge | Vfmerge if self.vreg.mask(0, i) => b,
+                        Vmerge | Vfmerge => a,
+                        Vmv | Vfmv => b,
+                        Vid => i as u64 & mask(sew),
+                        Vadc | Vsbc => {
+                            let carry = self.vreg.mask(0, i) as u64;
+                            let value = match kind {
+                                Vadc => a.wrapping_add(b).wrapping_add(carry),
+                                _ => a.wrapping_sub(b).wrapping_sub(carry),
+                            };
+                            value & mask(sew)
+                        }
+                        _ if float => fp_op(kind, sew, a, b, d, rm, &mut flags),
+                        _ => {
+                            let (value, sat) = int_op(kind, sew, a, b, d, vxrm);
+                            saturated |= sat;
+                            value
+                        }
+                    };
+                    results.push((i, Some(value)));
+                }
+
+                for (i, value) in results {
+                    if let Some(value) = value {
+                        self.vreg.set(vd, i, sew, value);
+                    }
+                }
+                if vtype.ta {
+                    for i in vl..vtype.group_len(X::VLEN) {
+                        self.vreg.set(vd, i, sew, u64::MAX);
+                    }
+                }
+                if saturated {
+                    self.write_csr(Csr::Vxsat, 1);
+                }
+            }
+        }
+
+        self.accrue(flags);
+        self.csr.write(Csr::Vstart, 0);
+        Ok(())
+    }
+}
+
+/// Whether `a + b + carry`, or `a - b - carry` for borrows, carries out of `sew` bits.
+fn carry_out(kind: VKind, sew: u32, a: u64, b: u64, carry: bool) -> bool {
+    let (a, b, carry) = (a as u128, b as u128, carry as u128);
+    match kind {
+        VKind::Vmadc => a + b + carry > mask(sew) as u128,
+        _ => a < b + carry,
+    }
+}
+
+fn compare(kind: VKind, sew: u32, a: u64, b: u64) -> bool {
+    use VKind::*;
+    let (sa, sb) = (sext(a, sew), sext(b, sew));
+    match kind {
+        Vmseq => a == b,
+        Vmsne => a != b,
+        Vmsltu => a < b,
+        Vmslt => sa < sb,
+        Vmsleu => a <= b,
+        Vmsle => sa <= sb,
+        Vmsgtu => a > b,
+        Vmsgt => sa > sb,
+        _ => unreachable!(),
+    }
+}
+
+/// Computes an integer element, returning whether the result saturated.
+///
+/// `a` is from `vs2`, `b` from `vs1`/`rs1`/the immediate and `d` from `vd`.
+fn int_op(kind: VKind, sew: u32, a: u64, b: u64, d: u64, vxrm: u64) -> (u64, bool) {
+    use VKind::*;
+
+    let (sa, sb) = (sext(a, sew) as i128, sext(b, sew) as i128);
+    let (min, max) = (-(1i128 << (sew - 1)), (1i128 << (sew - 1)) - 1);
+    let shamt = (b & (sew as u64 - 1)) as u32;
+    let clamp = |value: i128| (value.clamp(min, max) as u64, value < min || value > max);
+
+    let (value, saturated) = match kind {
+        Vadd => (a.wrapping_add(b), false),
+        Vsub => (a.wrapping_sub(b), false),
+        Vrsub => (b.wrapping_sub(a), false),
+        Vminu => (a.min(b), false),
+        Vmin => (if sa < sb { a } else { b }, false),
+        Vmaxu => (a.max(b), false),
+        Vmax => (if sa < sb { b } else { a }, false),
+        Vand => (a & b, false),
+        Vor => (a | b, false),
+        Vxor => (a ^ b, false),
+
+        Vsaddu => {
+            let sum = a as u128 + b as u128;
+            (sum.min(mask(sew) as u128) as u64, sum > mask(sew) as u128)
+        }
+        Vsadd => clamp(sa + sb),
+        Vssubu => match a.checked_sub(b) {
+            Some(value) => (value, false),
+            None => (0, true),
+        },
+        Vssub => clamp(sa - sb),
+        Vsmul if sa == min && sb == min => (max as u64, true),
+        Vsmul => (roundoff(sa * sb, sew - 1, vxrm) as u64, false),
+
+        Vsll => (a << shamt, false),
+        Vsrl => (a >> shamt, false),
+        Vsra => ((sa >> shamt) as u64, false),
+        Vssrl => (roundoff(a as i128, shamt, vxrm) as u64, false),
+        Vssra => (roundoff(sa, shamt, vxrm) as u64, false),
+
+        Vaaddu => (roundoff(a as i128 + b as i128, 1, vxrm) as u64, false),
+        Vaadd => (roundoff(sa + sb, 1, vxrm) as u64, false),
+        Vasubu => (roundoff(a as i128 - b as i128, 1, vxrm) as u64, false),
+        Vasub => (roundoff(sa - sb, 1, vxrm) as u64, false),
+
+        Vdivu => (a.checked_div(b).unwrap_or(u64::MAX), false),
+        Vdiv => match sb {
+            0 => (u64::MAX, false),
+            _ => ((sa / sb) as u64, false),
+        },
+        Vremu => (a.checked_rem(b).unwrap_or(a), false),
+        Vrem => match sb {
+            0 => (a, false),
+            _ => ((sa % sb) as u64, false),
+        },
+        Vmulhu => (((a as u128 * b as u128) >> sew) as u64, false),
+        Vmul => (a.wrapping_mul(b), false),
+        Vmulhsu => (((sa * b as i128) >> sew) as u64, false),
+        Vmulh => (((sa * sb) >> sew) as u64, false),
+        Vmadd => (b.wrapping_mul(d).wrapping_add(a), false),
+        Vnmsub => (a.wrapping_sub(b.wrapping_mul(d)), false),
+        Vmacc => (b.wrapping_mul(a).wrapping_add(d), false),
+        Vnmsac => (d.wrapping_sub(b.wrapping_mul(a)), false),
+
+        _ => unreachable!(),
+    };
+    (value & mask(sew), saturated)
+}
+
+fn canonical_nan(sew: u32) -> u64 {
+    format(sew).canonical_nan() as u64
+}
+
+/// The floating-point format of `bits`-wide elements.
+fn format(bits: u32) -> Format {
+    match bits {
+        16 => Format::HALF,
+        32 => Format::SINGLE,
+        _ => Format::DOUBLE,
+    }
+}
+
+/// Computes a floating-point element, or a comparison result as 0 or 1, accruing exception
+/// flags to `flags`.
+///
+/// Operands are as for [`int_op`].
+fn fp_op(kind: VKind, sew: u32, a: u64, b: u64, d: u64, rm: Rounding, flags: &mut u8) -> u64 {
+    use VKind::*;
+
+    let fmt = format(sew);
+    let (a, b, d) = (a as u128, b as u128, d as u128);
+    let neg = |value: u128| fmt.negate(value);
+    let sign = 1 << (fmt.bits() - 1);
+    let value = match kind {
+        // Sign injection only moves bits around
+        Vfsgnj => a & !sign | b & sign,
+        Vfsgnjn => a & !sign | !b & sign,
+        Vfsgnjx => a ^ b & sign,
+
+        Vfadd => softfloat::add(fmt, a, b, rm, flags),
+        Vfsub => softfloat::sub(fmt, a, b, rm, flags),
+        Vfrsub => softfloat::sub(fmt, b, a, rm, flags),
+        Vfmul => softfloat::mul(fmt, a, b, rm, flags),
+        Vfdiv => softfloat::div(fmt, a, b, rm, flags),
+        Vfrdiv => softfloat::div(fmt, b, a, rm, flags),
+        Vfmin => softfloat::min_max(fmt, a, b, false, flags),
+        Vfmax => softfloat::min_max(fmt, a, b, true, flags),
+        Vfmacc => softfloat::fma(fmt, b, a, d, rm, flags),
+        Vfnmacc => softfloat::fma(fmt, neg(b), a, neg(d), rm, flags),
+        Vfmsac => softfloat::fma(fmt, b, a, neg(d), rm, flags),
+        Vfnmsac => softfloat::fma(fmt, neg(b), a, d, rm, flags),
+        Vfmadd => softfloat::fma(fmt, b, d, a, rm, flags),
+        Vfnmadd => softfloat::fma(fmt, neg(b), d, neg(a), rm, flags),
+        Vfmsub => softfloat::fma(fmt, b, d, neg(a), rm, flags),
+        Vfnmsub => softfloat::fma(fmt, neg(b), d, a, rm, flags),
+
+        Vmfeq => softfloat::eq(fmt, a, b, flags) as u128,
+        Vmfne => !softfloat::eq(fmt, a, b, flags) as u128,
+        Vmflt => softfloat::lt(fmt, a, b, flags) as u128,
+        Vmfle => softfloat::le(fmt, a, b, flags) as u128,
+        Vmfgt => softfloat::lt(fmt, b, a, flags) as u128,
+        Vmfge => softfloat::le(fmt, b, a, flags) as u128,
+
+        Vfsqrt => softfloat::sqrt(fmt, a, rm, flags),
+        Vfclass => softfloat::classify(fmt, a) as u128,
+        VfcvtXuFV | VfcvtXFV | VfcvtFXuV | VfcvtFXV | VfcvtRtzXuFV | VfcvtRtzXFV => {
+            mixed::convert(kind, sew, sew, a as u64, rm, flags) as u128
+        }
+        _ => unreachable!(),
+    };
+    value as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bus::Bus,
+        csr::Csr,
+        ext::Extensions,
+        freg::{FRd, FRs1},
+        hart::{
+            exec::Error,
+            testing::{boot, load, BASE, DATA},
+            Hart,
+        },
+        reg::{IRd, IRs1},
+    };
+
+    #[derive(Clone, Copy)]
+    struct V;
+
+    impl Extensions for V {
+        const V: bool = true;
+    }
+
+    type Rv64v = Hart<0, u64, false, true, false, f64, false, false, V>;
+
+    fn hart() -> Rv64v {
+        let mut hart: Rv64v = boot();
+        hart.write_csr(Csr::Mstatus, 0b01 << 9);
+        hart.reg_mut().set_rd(IRd::X10, DATA);
+        hart
+    }
+
+    #[test]
+    fn test_disabled() {
+        let program = [
+            0x0d0072d7u32, // vsetvli x5, x0, e32, m1, ta, ma
+        ];
+        let mut ram = load(&program);
+        let mut hart = hart();
+        hart.write_csr(Csr::Mstatus, 0);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+    }
+
+    #[test]
+    fn test_vsetvli() {
+        let program = [
+            0x0d0072d7u32, // vsetvli x5, x0, e32, m1, ta, ma
+            0x0d307357,    // vsetvli x6, x0, e32, m8, ta, ma
+            0x0c3873d7,    // vsetvli x7, x16, e8, m8, ta, ma
+            0x0e8077d7,    // vsetvli x15, x0, e256, m1, ta, ma
+        ];
+        let mut ram = load(&program);
+        let mut hart = hart();
+        hart.reg_mut().set_rd(IRd::X16, 1000);
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+        assert_eq!(hart.reg().get_rs1(IRs1::X5), 4);
+        assert_eq!(hart.reg().get_rs1(IRs1::X6), 32);
+        assert_eq!(hart.reg().get_rs1(IRs1::X7), 128);
+        // Unsupported element width sets `vill`
+        assert_eq!(hart.reg().get_rs1(IRs1::X15), 0);
+        assert_eq!(hart.csr().read(Csr::Vtype), 1 << 63);
+        assert_eq!(hart.csr().read(Csr::Vlenb), 16);
+    }
+
+    #[test]
+    fn test_integer_kernel() {
+        let program = [
+            0x0d05f2d7u32, // vsetvli x5, x11, e32, m1, ta, ma
+            0x02056087,    // vle32.v v1, (x10)
+            0x5e02b157,    // vmv.v.i v2, 5
+            0x961121d7,    // vmul.vv v3, v1, v2
+            0x0211a257,    // vredsum.vs v4, v1, v3
+            0x76113057,    // vmsle.vi v0, v1, 2
+            0x0032b2d7,    // vadd.vi v5, v3, 5, v0.t
+            0x020562a7,    // vse32.v v5, (x10)
+            0x42402357,    // vmv.x.s x6, v4
+        ];
+        let mut ram = load(&program);
+        for (i, value) in [1u32, 2, 3, 4].iter().enumerate() {
+            ram.write(DATA + 4 * i as u64, &value.to_le_bytes())
+                .unwrap();
+        }
+        let mut hart = hart();
+        hart.reg_mut().set_rd(IRd::X11, 4);
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+
+        // sum(1..=4) + 1 * 5
+        assert_eq!(hart.reg().get_rs1(IRs1::X6), 15);
+        let mut buf = [0; 16];
+        ram.read(DATA, &mut buf).unwrap();
+        let stored: Vec<u32> = buf
+            .chunks(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        // Inactive elements are agnostic
+        assert_eq!(stored, [10, 15, u32::MAX, u32::MAX]);
+    }
+
+    #[test]
+    fn test_fixed_point() {
+        let program = [
+            0x0c8072d7u32, // vsetvli x5, x0, e16, m1, ta, ma
+            0x5e0fb0d7,    // vmv.v.i v1, -1
+            0x00a05073,    // csrwi vxrm, 0
+            0x8210b157,    // vsaddu.vi v2, v1, 1
+            0xaa10b1d7,    // vssrl.vi v3, v1, 1
+        ];
+        let mut ram = load(&program);
+        let mut hart = hart();
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+        assert_eq!(hart.vreg().get(2, 0, 16), 0xffff);
+        // Round-to-nearest-up rounds 0x7fff.8 up
+        assert_eq!(hart.vreg().get(3, 0, 16), 0x8000);
+        assert_eq!(hart.csr().read(Csr::Vxsat), 1);
+        assert_eq!(hart.csr().read(Csr::Vcsr), 1);
+    }
+
+    #[test]
+    fn test_float() {
+        let program = [
+            0x0d8072d7u32, // vsetvli x5, x0, e64, m1, ta, ma
+            0x5e05d0d7,    // vfmv.v.f v1, f11
+            0x02109157,    // vfadd.vv v2, v1, v1
+            0x422011d7,    // vfmv.f.s f3, v2
+        ];
+        let mut ram = load(&program);
+        let mut hart = hart();
+        hart.freg_mut().set_rd(FRd::F11, 1.5);
+        hart.write_csr(Csr::Mstatus, 0b01 << 9 | 0b01 << 13);
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+        assert_eq!(hart.freg().get_rs1(FRs1::F3), 3.0);
+    }
+
+    #[test]
+    fn test_widening() {
+        let program = [
+            0xcd027057u32, // vsetivli x0, 4, e32, m1, ta, ma
+            0xc210a157,    // vwaddu.vv v2, v1, v1
+            0xc610a257,    // vwadd.vv v4, v1, v1
+            0xba203357,    // vnclipu.wi v6, v2, 0
+            0x4a159457,    // vfwcvt.f.x.v v8, v1
+        ];
+        let mut ram = load(&program);
+        let mut hart = hart();
+        hart.write_csr(Csr::Mstatus, 0b01 << 9 | 0b01 << 13);
+        for (i, value) in [1, 0xffff_ffff, 3, 0x8000_0000].into_iter().enumerate() {
+            hart.vreg_mut().set(1, i, 32, value);
+        }
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+
+        let elements = |reg: u8, eew: u32| -> Vec<u64> {
+            (0..4).map(|i| hart.vreg().get(reg, i, eew)).collect()
+        };
+        assert_eq!(elements(2, 64), [2, 0x1_ffff_fffe, 6, 0x1_0000_0000]);
+        assert_eq!(
+            elements(4, 64),
+            [2, -2i64 as u64, 6, -0x1_0000_0000i64 as u64]
+        );
+        assert_eq!(elements(6, 32), [2, 0xffff_ffff, 6, 0xffff_ffff]);
+        assert_eq!(hart.csr().read(Csr::Vxsat), 1);
+        let converted = [1.0, -1.0, 3.0, -2147483648.0f64].map(f64::to_bits);
+        assert_eq!(elements(8, 64), converted);
+    }
+
+    #[test]
+    fn test_permute() {
+        let program = [
+            0xcc047057u32, // vsetivli x0, 8, e8, m1, ta, ma
+            0x5e102157,    // vcompress.vm v2, v1, v0
+            0x520821d7,    // viota.m v3, v0
+            0x5200a257,    // vmsbf.m v4, v0
+            0x401082d7,    // vadc.vvm v5, v1, v1, v0
+            0x3a1403d7,    // vrgatherei16.vv v7, v1, v8
+        ];
+        let mut ram = load(&program);
+        let mut hart = hart();
+        for i in 0..8 {
+            hart.vreg_mut().set(1, i, 8, 10 + i as u64);
+            hart.vreg_mut().set(8, i, 16, 7 - i as u64);
+        }
+        hart.vreg_mut().set(0, 0, 8, 0b1011_0010);
+        for _ in 0..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+
+        let elements = |reg: u8, len: usize| -> Vec<u64> {
+            (0..len).map(|i| hart.vreg().get(reg, i, 8)).collect()
+        };
+        // NOTE: The tail past the packed elements is agnostic.
+        assert_eq!(elements(2, 5), [11, 14, 15, 17, 0xff]);
+        assert_eq!(elements(3, 8), [0, 0, 1, 1, 1, 2, 3, 3]);
+        assert_eq!(elements(4, 1), [0b0000_0001]);
+        assert_eq!(elements(5, 8), [20, 23, 24, 26, 29, 31, 32, 35]);
+        assert_eq!(elements(7, 8), [17, 16, 15, 14, 13, 12, 11, 10]);
+    }
+
+    #[test]
+    fn test_float_state() {
+        let program = [
+            0x0d8072d7u32, // vsetvli x5, x0, e64, m1, ta, ma
+            0x5e05d0d7,    // vfmv.v.f v1, f11
+            0x5e0652d7,    // vfmv.v.f v5, f12
+            0x82129257,    // vfdiv.vv v4, v1, v5
+            0x42401257,    // vfmv.f.s f4, v4
+        ];
+        let mut ram = load(&program);
+        let mut hart = hart();
+        hart.freg_mut().set_rd(FRd::F11, 1.0);
+        hart.freg_mut().set_rd(FRd::F12, 3.0);
+
+        // NOTE: Vector floating point is off along with scalar floating point.
+        assert_eq!(hart.step(&mut ram), Ok(()));
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+
+        // Rounds up as `frm` says, and reports the result as inexact
+        hart.write_csr(Csr::Mstatus, 0b01 << 9 | 0b01 << 13);
+        hart.write_csr(Csr::Frm, 0b011);
+        for _ in 1..program.len() {
+            hart.step(&mut ram).unwrap();
+        }
+        let quotient = hart.freg().get_rs1(FRs1::F4).to_bits();
+        assert_eq!(quotient, (1.0f64 / 3.0).to_bits() + 1);
+        assert_eq!(hart.read_csr(Csr::Fflags), 0b00001);
+        assert_eq!(hart.read_csr(Csr::Mstatus) >> 13 & 0b11, 0b11);
+
+        // A reserved `frm` is illegal
+        hart.write_csr(Csr::Frm, 0b101);
+        hart.set_pc(BASE + 12);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+    }
+}
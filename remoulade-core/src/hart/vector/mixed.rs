@@ -0,0 +1,312 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Vector arithmetic with operands of other widths than `SEW`.
+
+use super::{format, mask, regs, roundoff, scale, sext, Arith};
+use crate::{
+    csr::Csr,
+    ext::Extensions,
+    freg::FRegType,
+    hart::{exec::Error, Hart},
+    inst::vector::{VKind, VSrc},
+    reg::RegType,
+    softfloat::{self, Rounding, INEXACT},
+};
+
+/// The widths of `vd` and `vs2` as `log2` of their ratio to `SEW`, or `None` if both are `SEW`.
+///
+/// `vs1` is `SEW` wide, except for reductions where it's as wide as `vd`.
+pub(super) fn shape(kind: VKind) -> Option<(i32, i32)> {
+    use VKind::*;
+
+    let shape = match kind {
+        Vwaddu | Vwadd | Vwsubu | Vwsub | Vwmulu | Vwmulsu | Vwmul | Vwmaccu | Vwmacc
+        | Vwmaccus | Vwmaccsu => (1, 0),
+        VwadduW | VwaddW | VwsubuW | VwsubW => (1, 1),
+        Vnsrl | Vnsra | Vnclipu | Vnclip => (0, 1),
+        Vwredsumu | Vwredsum | Vfwredusum | Vfwredosum => (1, 0),
+        VzextVf2 | VsextVf2 => (0, -1),
+        VzextVf4 | VsextVf4 => (0, -2),
+        VzextVf8 | VsextVf8 => (0, -3),
+        Vfwadd | Vfwsub | Vfwmul | Vfwmacc | Vfwnmacc | Vfwmsac | Vfwnmsac => (1, 0),
+        VfwaddW | VfwsubW => (1, 1),
+        VfwcvtXuFV | VfwcvtXFV | VfwcvtFXuV | VfwcvtFXV | VfwcvtFFV | VfwcvtRtzXuFV
+        | VfwcvtRtzXFV => (1, 0),
+        VfncvtXuFW | VfncvtXFW | VfncvtFXuW | VfncvtFXW | VfncvtFFW | VfncvtRodFFW
+        | VfncvtRtzXuFW | VfncvtRtzXFW => (0, 1),
+        _ => None?,
+    };
+    Some(shape)
+}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Executes an instruction with a [`shape`].
+    pub(super) fn vmixed(&mut self, op: Arith, flags: &mut u8) -> Result<(), Error> {
+        use VKind::*;
+
+        let Arith {
+            vd,
+            vs2,
+            src,
+            vm,
+            kind,
+            vtype,
+            scalar,
+            rm,
+        } = op;
+        let (wide_vd, wide_vs2) = shape(kind).unwrap();
+        let sew = vtype.sew;
+        let (dew, eew) = (
+            scale(sew as usize, wide_vd) as u32,
+            scale(sew as usize, wide_vs2) as u32,
+        );
+        let (demul, emul) = (vtype.lmul + wide_vd, vtype.lmul + wide_vs2);
+        let vl = self.vl();
+        let reduction = matches!(kind, Vwredsumu | Vwredsum | Vfwredusum | Vfwredosum);
+
+        // Which of `SEW` and `2 * SEW` hold floating-point values
+        let (narrow_float, wide_float) = match kind {
+            VfwcvtXuFV | VfwcvtXFV | VfwcvtRtzXuFV | VfwcvtRtzXFV | VfncvtFXuW | VfncvtFXW => {
+                (true, false)
+            }
+            VfwcvtFXuV | VfwcvtFXV | VfncvtXuFW | VfncvtXFW | VfncvtRtzXuFW | VfncvtRtzXFW => {
+                (false, true)
+            }
+            _ => (kind.float(), kind.float()),
+        };
+        let supported = |bits: u32| matches!(bits, 32 | 64) && bits <= F::BITS;
+
+        let group = |reg: u8, emul: i32| (-3..=3).contains(&emul) && reg.is_multiple_of(regs(emul));
+        let vs1 = match src {
+            VSrc::Vector(vs1) if kind.selector().is_none() => Some(vs1),
+            _ => None,
+        };
+        let legal = dew.max(eew) <= X::ELEN
+            && eew >= 8
+            && group(vs2, emul)
+            && (reduction || group(vd, demul))
+            && (reduction || vs1.is_none_or(|vs1| group(vs1, vtype.lmul)))
+            && (reduction || vm || vd != 0)
+            && (!narrow_float || supported(sew))
+            && (!wide_float || supported(2 * sew));
+        if !legal {
+            Err(Error::IllegalInstruction)?;
+        }
+
+        if reduction {
+            if self.vstart() != 0 {
+                Err(Error::IllegalInstruction)?;
+            }
+            let Some(vs1) = vs1 else { unreachable!() };
+            let mut acc = self.vreg.get(vs1, 0, dew);
+            for i in (0..vl).filter(|&i| self.active(vm, i)) {
+                let element = self.vreg.get(vs2, i, sew);
+                acc = match kind {
+                    Vwredsumu => acc.wrapping_add(element),
+                    Vwredsum => acc.wrapping_add(sext(element, sew) as u64),
+                    // NOTE: Unordered sums are allowed to be computed in order.
+                    _ => {
+                        let (narrow, wide) = (format(sew), format(dew));
+                        let element = softfloat::convert(narrow, wide, element as u128, rm, flags);
+                        softfloat::add(wide, acc as u128, element, rm, flags) as u64
+                    }
+                } & mask(dew);
+            }
+            self.vreg.set(vd, 0, dew, acc);
+            if vtype.ta {
+                for i in 1..X::VLEN as usize / dew as usize {
+                    self.vreg.set(vd, i, dew, u64::MAX);
+                }
+            }
+            return Ok(());
+        }
+
+        let vxrm = self.csr.read(Csr::Vxrm);
+        let mut saturated = false;
+        let mut results = Vec::with_capacity(vl);
+        for i in self.vstart()..vl {
+            if !self.active(vm, i) {
+                results.push((i, vtype.ma.then_some(u64::MAX)));
+                continue;
+            }
+
+            let a = self.vreg.get(vs2, i, eew);
+            let b = match vs1 {
+                Some(vs1) => self.vreg.get(vs1, i, sew),
+                None => scalar,
+            };
+            let d = self.vreg.get(vd, i, dew);
+            let value = match kind {
+                VzextVf2 | VzextVf4 | VzextVf8 => a,
+                VsextVf2 | VsextVf4 | VsextVf8 => sext(a, eew) as u64,
+                Vnsrl | Vnsra | Vnclipu | Vnclip => {
+                    let (value, sat) = narrow_int(kind, sew, a, b, vxrm);
+                    saturated |= sat;
+                    value
+                }
+                _ if kind.selector().is_some() => convert(kind, eew, dew, a, rm, flags),
+                _ if kind.float() => widen_fp(kind, sew, a, b, d, rm, flags),
+                _ => widen_int(kind, sew, a, b, d),
+            };
+            results.push((i, Some(value & mask(dew))));
+        }
+
+        for (i, value) in results {
+            if let Some(value) = value {
+                self.vreg.set(vd, i, dew, value);
+            }
+        }
+        if vtype.ta {
+            let group_len = scale(X::VLEN as usize / dew as usize, demul.max(0));
+            for i in vl..group_len {
+                self.vreg.set(vd, i, dew, u64::MAX);
+            }
+        }
+        if saturated {
+            self.write_csr(Csr::Vxsat, 1);
+        }
+        Ok(())
+    }
+}
+
+/// Computes a `2 * sew`-bit integer element.
+///
+/// `a` is from `vs2`, and `2 * sew` bits wide for `.w` forms.
+fn widen_int(kind: VKind, sew: u32, a: u64, b: u64, d: u64) -> u64 {
+    use VKind::*;
+
+    let (sa, sb) = (sext(a, sew) as i128, sext(b, sew) as i128);
+    let wa = sext(a, 2 * sew) as i128;
+    let (a, b, d) = (a as i128, b as i128, d as i128);
+    let value = match kind {
+        Vwaddu | VwadduW => a + b,
+        Vwadd => sa + sb,
+        VwaddW => wa + sb,
+        Vwsubu | VwsubuW => a - b,
+        Vwsub => sa - sb,
+        VwsubW => wa - sb,
+        Vwmulu => a * b,
+        Vwmulsu => sa * b,
+        Vwmul => sa * sb,
+        Vwmaccu => d + b * a,
+        Vwmacc => d + sb * sa,
+        Vwmaccsu => d + sb * a,
+        Vwmaccus => d + b * sa,
+        _ => unreachable!(),
+    };
+    value as u64
+}
+
+/// Computes a `sew`-bit element from the `2 * sew`-bit `a`, returning whether it saturated.
+fn narrow_int(kind: VKind, sew: u32, a: u64, b: u64, vxrm: u64) -> (u64, bool) {
+    let wide = 2 * sew;
+    let shamt = (b & (wide as u64 - 1)) as u32;
+    match kind {
+        VKind::Vnsrl => (a >> shamt, false),
+        VKind::Vnsra => ((sext(a, wide) >> shamt) as u64, false),
+        VKind::Vnclipu => {
+            let value = roundoff(a as i128, shamt, vxrm);
+            let max = mask(sew) as i128;
+            (value.min(max) as u64, value > max)
+        }
+        _ => {
+            let value = roundoff(sext(a, wide) as i128, shamt, vxrm);
+            let (min, max) = (-(1i128 << (sew - 1)), (1i128 << (sew - 1)) - 1);
+            (value.clamp(min, max) as u64, value < min || value > max)
+        }
+    }
+}
+
+/// Computes a `2 * sew`-bit floating-point element.
+///
+/// Operands are as for [`widen_int`], with `d` from `vd`.
+fn widen_fp(kind: VKind, sew: u32, a: u64, b: u64, d: u64, rm: Rounding, flags: &mut u8) -> u64 {
+    use VKind::*;
+
+    let (narrow, wide) = (format(sew), format(2 * sew));
+    // NOTE: Widening is exact, but signaling NaNs still raise invalid.
+    let wb = softfloat::convert(narrow, wide, b as u128, rm, flags);
+    let wa = match kind {
+        VfwaddW | VfwsubW => a as u128,
+        _ => softfloat::convert(narrow, wide, a as u128, rm, flags),
+    };
+    let (d, neg) = (d as u128, |value: u128| wide.negate(value));
+    let value = match kind {
+        Vfwadd | VfwaddW => softfloat::add(wide, wa, wb, rm, flags),
+        Vfwsub | VfwsubW => softfloat::sub(wide, wa, wb, rm, flags),
+        Vfwmul => softfloat::mul(wide, wa, wb, rm, flags),
+        Vfwmacc => softfloat::fma(wide, wb, wa, d, rm, flags),
+        Vfwnmacc => softfloat::fma(wide, neg(wb), wa, neg(d), rm, flags),
+        Vfwmsac => softfloat::fma(wide, wb, wa, neg(d), rm, flags),
+        Vfwnmsac => softfloat::fma(wide, neg(wb), wa, d, rm, flags),
+        _ => unreachable!(),
+    };
+    value as u64
+}
+
+/// Converts `a` from `from` bits to `to` bits, for all of the vector conversions.
+pub(super) fn convert(
+    kind: VKind,
+    from: u32,
+    to: u32,
+    a: u64,
+    rm: Rounding,
+    flags: &mut u8,
+) -> u64 {
+    use VKind::*;
+
+    let rm = match kind {
+        VfcvtRtzXuFV | VfcvtRtzXFV | VfwcvtRtzXuFV | VfwcvtRtzXFV | VfncvtRtzXuFW
+        | VfncvtRtzXFW => Rounding::TowardZero,
+        _ => rm,
+    };
+    let value = match kind {
+        VfcvtXuFV | VfcvtRtzXuFV | VfwcvtXuFV | VfwcvtRtzXuFV | VfncvtXuFW | VfncvtRtzXuFW => {
+            softfloat::to_int(format(from), a as u128, false, to, rm, flags)
+        }
+        VfcvtXFV | VfcvtRtzXFV | VfwcvtXFV | VfwcvtRtzXFV | VfncvtXFW | VfncvtRtzXFW => {
+            softfloat::to_int(format(from), a as u128, true, to, rm, flags)
+        }
+        VfcvtFXuV | VfwcvtFXuV | VfncvtFXuW => {
+            softfloat::from_int(format(to), false, a as u128, rm, flags)
+        }
+        VfcvtFXV | VfwcvtFXV | VfncvtFXW => {
+            let a = sext(a, from);
+            softfloat::from_int(format(to), a < 0, a.unsigned_abs() as u128, rm, flags)
+        }
+        VfwcvtFFV | VfncvtFFW => softfloat::convert(format(from), format(to), a as u128, rm, flags),
+        VfncvtRodFFW => {
+            // Rounds to odd by truncating and setting the lowest bit of inexact results
+            let mut raised = 0;
+            let value = softfloat::convert(
+                format(from),
+                format(to),
+                a as u128,
+                Rounding::TowardZero,
+                &mut raised,
+            );
+            *flags |= raised;
+            value | (raised & INEXACT != 0) as u128
+        }
+        _ => unreachable!(),
+    };
+    value as u64
+}
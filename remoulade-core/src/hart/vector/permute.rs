@@ -0,0 +1,183 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Vector permutations and mask instructions where elements depend on other elements.
+
+use super::{regs, Arith};
+use crate::{
+    ext::Extensions,
+    freg::FRegType,
+    hart::{exec::Error, Hart},
+    inst::vector::{VKind, VSrc},
+    reg::RegType,
+};
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Executes `vrgatherei16.vv`, `vcompress.vm`, `viota.m`, `vmsbf.m`, `vmsif.m` or `vmsof.m`.
+    pub(super) fn vpermute(&mut self, op: Arith) -> Result<(), Error> {
+        use VKind::*;
+
+        let Arith {
+            vd,
+            vs2,
+            src,
+            vm,
+            kind,
+            vtype,
+            ..
+        } = op;
+        let sew = vtype.sew;
+        let vl = self.vl();
+        let vstart = self.vstart();
+        let group = regs(vtype.lmul);
+        let in_group = |reg: u8, base: u8, len: u8| (base..base + len).contains(&reg);
+
+        // NOTE: Only gathers can be resumed from a `vstart` other than 0.
+        if kind != Vrgatherei16 && vstart != 0 {
+            Err(Error::IllegalInstruction)?;
+        }
+
+        match kind {
+            Vrgatherei16 => {
+                let VSrc::Vector(vs1) = src else {
+                    unreachable!()
+                };
+                // NOTE: The indices are 16 bits wide regardless of `SEW`.
+                let index_emul = vtype.lmul + 4 - sew.trailing_zeros() as i32;
+                if !(-3..=3).contains(&index_emul)
+                    || !vs1.is_multiple_of(regs(index_emul))
+                    || !vd.is_multiple_of(group)
+                    || !vs2.is_multiple_of(group)
+                    || (!vm && vd == 0)
+                {
+                    Err(Error::IllegalInstruction)?;
+                }
+                let vlmax = vtype.vlmax(X::VLEN);
+                let mut results = Vec::with_capacity(vl);
+                for i in vstart..vl {
+                    if !self.active(vm, i) {
+                        results.push((i, vtype.ma.then_some(u64::MAX)));
+                        continue;
+                    }
+                    let index = self.vreg.get(vs1, i, 16) as usize;
+                    let value = match index < vlmax {
+                        true => self.vreg.get(vs2, index, sew),
+                        false => 0,
+                    };
+                    results.push((i, Some(value)));
+                }
+                for (i, value) in results {
+                    if let Some(value) = value {
+                        self.vreg.set(vd, i, sew, value);
+                    }
+                }
+                if vtype.ta {
+                    for i in vl..vtype.group_len(X::VLEN) {
+                        self.vreg.set(vd, i, sew, u64::MAX);
+                    }
+                }
+            }
+
+            Vcompress => {
+                let VSrc::Vector(vs1) = src else {
+                    unreachable!()
+                };
+                if !vd.is_multiple_of(group)
+                    || !vs2.is_multiple_of(group)
+                    || in_group(vs1, vd, group)
+                    || in_group(vs2, vd, group)
+                {
+                    Err(Error::IllegalInstruction)?;
+                }
+                let packed: Vec<u64> = (0..vl)
+                    .filter(|&i| self.vreg.mask(vs1, i))
+                    .map(|i| self.vreg.get(vs2, i, sew))
+                    .collect();
+                for (i, &value) in packed.iter().enumerate() {
+                    self.vreg.set(vd, i, sew, value);
+                }
+                // NOTE: Everything past the packed elements is tail.
+                if vtype.ta {
+                    for i in packed.len()..vtype.group_len(X::VLEN) {
+                        self.vreg.set(vd, i, sew, u64::MAX);
+                    }
+                }
+            }
+
+            Viota => {
+                if !vd.is_multiple_of(group) || in_group(vs2, vd, group) || (!vm && vd == 0) {
+                    Err(Error::IllegalInstruction)?;
+                }
+                let mut count = 0;
+                let mut results = Vec::with_capacity(vl);
+                for i in 0..vl {
+                    if !self.active(vm, i) {
+                        results.push((i, vtype.ma.then_some(u64::MAX)));
+                        continue;
+                    }
+                    results.push((i, Some(count)));
+                    count += self.vreg.mask(vs2, i) as u64;
+                }
+                for (i, value) in results {
+                    if let Some(value) = value {
+                        self.vreg.set(vd, i, sew, value);
+                    }
+                }
+                if vtype.ta {
+                    for i in vl..vtype.group_len(X::VLEN) {
+                        self.vreg.set(vd, i, sew, u64::MAX);
+                    }
+                }
+            }
+
+            // Set-before-first, set-including-first and set-only-first
+            _ => {
+                if vd == vs2 || (!vm && vd == 0) {
+                    Err(Error::IllegalInstruction)?;
+                }
+                let mut found = false;
+                let mut results = Vec::with_capacity(vl);
+                for i in 0..vl {
+                    if !self.active(vm, i) {
+                        results.push((i, vtype.ma.then_some(true)));
+                        continue;
+                    }
+                    let set = self.vreg.mask(vs2, i);
+                    let value = match kind {
+                        Vmsbf => !found && !set,
+                        Vmsif => !found,
+                        _ => !found && set,
+                    };
+                    found |= set;
+                    results.push((i, Some(value)));
+                }
+                for (i, value) in results {
+                    if let Some(value) = value {
+                        self.vreg.set_mask(vd, i, value);
+                    }
+                }
+                for i in vl..X::VLEN as usize {
+                    self.vreg.set_mask(vd, i, true);
+                }
+            }
+        }
+        Ok(())
+    }
+}
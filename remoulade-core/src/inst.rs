@@ -7,16 +7,25 @@
 //
 // Copyright (C) 2024 mumblingdrunkard
 
+pub mod asm;
+pub mod disasm;
+pub mod float;
 pub mod imm;
+pub mod vector;
 
 use std::{any::TypeId, marker::PhantomData};
 
 use crate::{
     csr::Csr,
-    freg::FRegType,
-    inst::imm::{
-        AmoAqrl, BTypeImmediate, FenceInfo, ITypeImmediate, JTypeImmediate, STypeImmediate,
-        UTypeImmediate,
+    ext::Extensions,
+    freg::{FRd, FRegType, FRs1, FRs2, FRs3},
+    inst::{
+        float::{FFmt, FmaKind, FpKind},
+        imm::{
+            AmoAqrl, BTypeImmediate, FenceInfo, ITypeImmediate, JTypeImmediate, STypeImmediate,
+            UTypeImmediate,
+        },
+        vector::{VAddressing, VKind, VMem, VSrc, VsetKind},
     },
     reg::{IRd, IRs1, IRs2, RegType},
 };
@@ -40,10 +49,13 @@ enum Opcode {
     Nmsub,
     Nmadd,
     Opfp,
+    Opv,
+    Opimm64,
     Branch,
     Jalr,
     Jal,
     System,
+    Op64,
     Invalid,
 }
 
@@ -68,10 +80,13 @@ impl Opcode {
             0b1001011 => Nmsub,
             0b1001111 => Nmadd,
             0b1010011 => Opfp,
+            0b1010111 => Opv,
+            0b1011011 => Opimm64,
             0b1100011 => Branch,
             0b1100111 => Jalr,
             0b1101111 => Jal,
             0b1110011 => System,
+            0b1111011 => Op64,
             _ => Invalid,
         }
     }
@@ -198,6 +213,220 @@ pub enum RKind {
     Divu,
     Rem,
     Remu,
+
+    // RV64
+    Addw,
+    Subw,
+    Sllw,
+    Srlw,
+    Sraw,
+
+    Mulw,
+    Divw,
+    Divuw,
+    Remw,
+    Remuw,
+
+    // RV128
+    Addd,
+    Subd,
+    Slld,
+    Srld,
+    Srad,
+
+    Muld,
+    Divd,
+    Divud,
+    Remd,
+    Remud,
+
+    // Zba
+    Sh1add,
+    Sh2add,
+    Sh3add,
+    AddUw,
+    Sh1addUw,
+    Sh2addUw,
+    Sh3addUw,
+
+    // Zbb
+    Andn,
+    Orn,
+    Xnor,
+    Min,
+    Minu,
+    Max,
+    Maxu,
+    Rol,
+    Ror,
+    Rolw,
+    Rorw,
+    ZextH,
+
+    // Zbc
+    Clmul,
+    Clmulh,
+    Clmulr,
+
+    // Zbs
+    Bclr,
+    Bext,
+    Binv,
+    Bset,
+
+    // Zbkb
+    Pack,
+    Packh,
+    Packw,
+
+    // Zbkx
+    Xperm4,
+    Xperm8,
+
+    // Zknd, Zkne
+    Aes32dsi { bs: u8 },
+    Aes32dsmi { bs: u8 },
+    Aes32esi { bs: u8 },
+    Aes32esmi { bs: u8 },
+    Aes64ds,
+    Aes64dsm,
+    Aes64es,
+    Aes64esm,
+    Aes64ks2,
+
+    // Zknh
+    Sha512sum0r,
+    Sha512sum1r,
+    Sha512sig0l,
+    Sha512sig0h,
+    Sha512sig1l,
+    Sha512sig1h,
+
+    // Zksed
+    Sm4ed { bs: u8 },
+    Sm4ks { bs: u8 },
+
+    // Zicond
+    CzeroEqz,
+    CzeroNez,
+}
+
+impl RKind {
+    /// The major opcode, `funct3` and `funct7` of this kind.
+    fn fields<I: RegType>(self) -> (u32, u32, u32) {
+        use RKind::*;
+
+        const OP: u32 = 0b0110011;
+        const OP_32: u32 = 0b0111011;
+        const OP_64: u32 = 0b1111011;
+
+        match self {
+            Add => (OP, 0b000, 0b0000000),
+            Sub => (OP, 0b000, 0b0100000),
+            Sll => (OP, 0b001, 0b0000000),
+            Slt => (OP, 0b010, 0b0000000),
+            Sltu => (OP, 0b011, 0b0000000),
+            Xor => (OP, 0b100, 0b0000000),
+            Srl => (OP, 0b101, 0b0000000),
+            Sra => (OP, 0b101, 0b0100000),
+            Or => (OP, 0b110, 0b0000000),
+            And => (OP, 0b111, 0b0000000),
+
+            Mul => (OP, 0b000, 0b0000001),
+            Mulh => (OP, 0b001, 0b0000001),
+            Mulhsu => (OP, 0b010, 0b0000001),
+            Mulhu => (OP, 0b011, 0b0000001),
+            Div => (OP, 0b100, 0b0000001),
+            Divu => (OP, 0b101, 0b0000001),
+            Rem => (OP, 0b110, 0b0000001),
+            Remu => (OP, 0b111, 0b0000001),
+
+            Addw => (OP_32, 0b000, 0b0000000),
+            Subw => (OP_32, 0b000, 0b0100000),
+            Sllw => (OP_32, 0b001, 0b0000000),
+            Srlw => (OP_32, 0b101, 0b0000000),
+            Sraw => (OP_32, 0b101, 0b0100000),
+
+            Mulw => (OP_32, 0b000, 0b0000001),
+            Divw => (OP_32, 0b100, 0b0000001),
+            Divuw => (OP_32, 0b101, 0b0000001),
+            Remw => (OP_32, 0b110, 0b0000001),
+            Remuw => (OP_32, 0b111, 0b0000001),
+
+            Addd => (OP_64, 0b000, 0b0000000),
+            Subd => (OP_64, 0b000, 0b0100000),
+            Slld => (OP_64, 0b001, 0b0000000),
+            Srld => (OP_64, 0b101, 0b0000000),
+            Srad => (OP_64, 0b101, 0b0100000),
+
+            Muld => (OP_64, 0b000, 0b0000001),
+            Divd => (OP_64, 0b100, 0b0000001),
+            Divud => (OP_64, 0b101, 0b0000001),
+            Remd => (OP_64, 0b110, 0b0000001),
+            Remud => (OP_64, 0b111, 0b0000001),
+
+            Sh1add => (OP, 0b010, 0b0010000),
+            Sh2add => (OP, 0b100, 0b0010000),
+            Sh3add => (OP, 0b110, 0b0010000),
+            AddUw => (OP_32, 0b000, 0b0000100),
+            Sh1addUw => (OP_32, 0b010, 0b0010000),
+            Sh2addUw => (OP_32, 0b100, 0b0010000),
+            Sh3addUw => (OP_32, 0b110, 0b0010000),
+
+            Andn => (OP, 0b111, 0b0100000),
+            Orn => (OP, 0b110, 0b0100000),
+            Xnor => (OP, 0b100, 0b0100000),
+            Min => (OP, 0b100, 0b0000101),
+            Minu => (OP, 0b101, 0b0000101),
+            Max => (OP, 0b110, 0b0000101),
+            Maxu => (OP, 0b111, 0b0000101),
+            Rol => (OP, 0b001, 0b0110000),
+            Ror => (OP, 0b101, 0b0110000),
+            Rolw => (OP_32, 0b001, 0b0110000),
+            Rorw => (OP_32, 0b101, 0b0110000),
+            ZextH if I::BITS == 32 => (OP, 0b100, 0b0000100),
+            ZextH => (OP_32, 0b100, 0b0000100),
+
+            Clmul => (OP, 0b001, 0b0000101),
+            Clmulh => (OP, 0b011, 0b0000101),
+            Clmulr => (OP, 0b010, 0b0000101),
+
+            Bclr => (OP, 0b001, 0b0100100),
+            Bext => (OP, 0b101, 0b0100100),
+            Binv => (OP, 0b001, 0b0110100),
+            Bset => (OP, 0b001, 0b0010100),
+
+            Pack => (OP, 0b100, 0b0000100),
+            Packh => (OP, 0b111, 0b0000100),
+            Packw => (OP_32, 0b100, 0b0000100),
+
+            Xperm4 => (OP, 0b010, 0b0010100),
+            Xperm8 => (OP, 0b100, 0b0010100),
+
+            Aes32dsi { bs } => (OP, 0b000, (bs as u32) << 5 | 0b10101),
+            Aes32dsmi { bs } => (OP, 0b000, (bs as u32) << 5 | 0b10111),
+            Aes32esi { bs } => (OP, 0b000, (bs as u32) << 5 | 0b10001),
+            Aes32esmi { bs } => (OP, 0b000, (bs as u32) << 5 | 0b10011),
+            Aes64ds => (OP, 0b000, 0b0011101),
+            Aes64dsm => (OP, 0b000, 0b0011111),
+            Aes64es => (OP, 0b000, 0b0011001),
+            Aes64esm => (OP, 0b000, 0b0011011),
+            Aes64ks2 => (OP, 0b000, 0b0111111),
+
+            Sha512sum0r => (OP, 0b000, 0b0101000),
+            Sha512sum1r => (OP, 0b000, 0b0101001),
+            Sha512sig0l => (OP, 0b000, 0b0101010),
+            Sha512sig0h => (OP, 0b000, 0b0101110),
+            Sha512sig1l => (OP, 0b000, 0b0101011),
+            Sha512sig1h => (OP, 0b000, 0b0101111),
+
+            Sm4ed { bs } => (OP, 0b000, (bs as u32) << 5 | 0b11000),
+            Sm4ks { bs } => (OP, 0b000, (bs as u32) << 5 | 0b11010),
+
+            CzeroEqz => (OP, 0b101, 0b0000111),
+            CzeroNez => (OP, 0b111, 0b0000111),
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -238,6 +467,177 @@ pub enum IKind {
     Srai,
 
     Fencei,
+
+    // RV64
+    Ld,
+    Lwu,
+
+    Addiw,
+    Slliw,
+    Srliw,
+    Sraiw,
+
+    // RV128
+    Lq,
+    Ldu,
+
+    Addid,
+    Sllid,
+    Srlid,
+    Sraid,
+
+    // Zba
+    SlliUw,
+
+    // Zbb
+    Clz,
+    Ctz,
+    Cpop,
+    Clzw,
+    Ctzw,
+    Cpopw,
+    SextB,
+    SextH,
+    OrcB,
+    Rev8,
+    Rori,
+    Roriw,
+
+    // Zbs
+    Bclri,
+    Bexti,
+    Binvi,
+    Bseti,
+
+    // Zbkb
+    Brev8,
+    Zip,
+    Unzip,
+
+    // Zknd, Zkne
+    Aes64im,
+    Aes64ks1i,
+
+    // Zknh
+    Sha256sig0,
+    Sha256sig1,
+    Sha256sum0,
+    Sha256sum1,
+    Sha512sig0,
+    Sha512sig1,
+    Sha512sum0,
+    Sha512sum1,
+
+    // Zksh
+    Sm3p0,
+    Sm3p1,
+
+    // Zicbom
+    CboClean,
+    CboFlush,
+    CboInval,
+
+    // Zicboz
+    CboZero,
+}
+
+impl IKind {
+    /// The major opcode and `funct3` of this kind, along with the upper immediate bits and the
+    /// mask of the shift amount or other operand beneath them, for kinds that use the immediate
+    /// as a discriminant.
+    fn fields<I: RegType>(self) -> (u32, u32, Option<(u32, u32)>) {
+        use IKind::*;
+
+        const LOAD: u32 = 0b0000011;
+        const MISC_MEM: u32 = 0b0001111;
+        const OP_IMM: u32 = 0b0010011;
+        const OP_IMM_32: u32 = 0b0011011;
+        const OP_IMM_64: u32 = 0b1011011;
+        const JALR: u32 = 0b1100111;
+
+        let shamt = I::BITS - 1;
+        let (opcode, funct3, upper) = match self {
+            Jalr => (JALR, 0b000, None),
+
+            Lb => (LOAD, 0b000, None),
+            Lh => (LOAD, 0b001, None),
+            Lw => (LOAD, 0b010, None),
+            Lbu => (LOAD, 0b100, None),
+            Lhu => (LOAD, 0b101, None),
+            Ld => (LOAD, 0b011, None),
+            Lwu => (LOAD, 0b110, None),
+            Lq => (MISC_MEM, 0b010, None),
+            Ldu => (LOAD, 0b111, None),
+
+            Addi => (OP_IMM, 0b000, None),
+            Slti => (OP_IMM, 0b010, None),
+            Sltiu => (OP_IMM, 0b011, None),
+            Xori => (OP_IMM, 0b100, None),
+            Ori => (OP_IMM, 0b110, None),
+            Andi => (OP_IMM, 0b111, None),
+            Slli => (OP_IMM, 0b001, Some((0x000, shamt))),
+            Srli => (OP_IMM, 0b101, Some((0x000, shamt))),
+            Srai => (OP_IMM, 0b101, Some((0x400, shamt))),
+
+            Fencei => (MISC_MEM, 0b001, None),
+
+            Addiw => (OP_IMM_32, 0b000, None),
+            Slliw => (OP_IMM_32, 0b001, Some((0x000, 31))),
+            Srliw => (OP_IMM_32, 0b101, Some((0x000, 31))),
+            Sraiw => (OP_IMM_32, 0b101, Some((0x400, 31))),
+
+            Addid => (OP_IMM_64, 0b000, None),
+            Sllid => (OP_IMM_64, 0b001, Some((0x000, 63))),
+            Srlid => (OP_IMM_64, 0b101, Some((0x000, 63))),
+            Sraid => (OP_IMM_64, 0b101, Some((0x400, 63))),
+
+            SlliUw => (OP_IMM_32, 0b001, Some((0x080, 63))),
+
+            Clz => (OP_IMM, 0b001, Some((0x600, 0))),
+            Ctz => (OP_IMM, 0b001, Some((0x601, 0))),
+            Cpop => (OP_IMM, 0b001, Some((0x602, 0))),
+            Clzw => (OP_IMM_32, 0b001, Some((0x600, 0))),
+            Ctzw => (OP_IMM_32, 0b001, Some((0x601, 0))),
+            Cpopw => (OP_IMM_32, 0b001, Some((0x602, 0))),
+            SextB => (OP_IMM, 0b001, Some((0x604, 0))),
+            SextH => (OP_IMM, 0b001, Some((0x605, 0))),
+            OrcB => (OP_IMM, 0b101, Some((0x287, 0))),
+            Rev8 => (OP_IMM, 0b101, Some((0x680 | (I::BITS - 8), 0))),
+            Rori => (OP_IMM, 0b101, Some((0x600, shamt))),
+            Roriw => (OP_IMM_32, 0b101, Some((0x600, 31))),
+
+            Bclri => (OP_IMM, 0b001, Some((0x480, shamt))),
+            Bexti => (OP_IMM, 0b101, Some((0x480, shamt))),
+            Binvi => (OP_IMM, 0b001, Some((0x680, shamt))),
+            Bseti => (OP_IMM, 0b001, Some((0x280, shamt))),
+
+            Brev8 => (OP_IMM, 0b101, Some((0x687, 0))),
+            Zip => (OP_IMM, 0b001, Some((0x08f, 0))),
+            Unzip => (OP_IMM, 0b101, Some((0x08f, 0))),
+
+            Aes64im => (OP_IMM, 0b001, Some((0x300, 0))),
+            // NOTE: The round number takes the place of the shift amount.
+            Aes64ks1i => (OP_IMM, 0b001, Some((0x310, 0xf))),
+
+            Sha256sum0 => (OP_IMM, 0b001, Some((0x100, 0))),
+            Sha256sum1 => (OP_IMM, 0b001, Some((0x101, 0))),
+            Sha256sig0 => (OP_IMM, 0b001, Some((0x102, 0))),
+            Sha256sig1 => (OP_IMM, 0b001, Some((0x103, 0))),
+            Sha512sum0 => (OP_IMM, 0b001, Some((0x104, 0))),
+            Sha512sum1 => (OP_IMM, 0b001, Some((0x105, 0))),
+            Sha512sig0 => (OP_IMM, 0b001, Some((0x106, 0))),
+            Sha512sig1 => (OP_IMM, 0b001, Some((0x107, 0))),
+
+            Sm3p0 => (OP_IMM, 0b001, Some((0x108, 0))),
+            Sm3p1 => (OP_IMM, 0b001, Some((0x109, 0))),
+
+            CboInval => (MISC_MEM, 0b010, Some((0x000, 0))),
+            CboClean => (MISC_MEM, 0b010, Some((0x001, 0))),
+            CboFlush => (MISC_MEM, 0b010, Some((0x002, 0))),
+            CboZero => (MISC_MEM, 0b010, Some((0x004, 0))),
+        };
+        (opcode, funct3, upper)
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -257,6 +657,12 @@ pub enum SKind {
     Sb,
     Sh,
     Sw,
+
+    // RV64
+    Sd,
+
+    // RV128
+    Sq,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -289,6 +695,62 @@ pub enum AmoKind {
     Amomaxud,
 }
 
+impl AmoKind {
+    /// The `funct3` and `funct5` of this kind.
+    fn fields(self) -> (u32, u32) {
+        use AmoKind::*;
+
+        match self {
+            Lrw => (0b010, 0b00010),
+            Scw => (0b010, 0b00011),
+            Amoswapw => (0b010, 0b00001),
+            Amoaddw => (0b010, 0b00000),
+            Amoxorw => (0b010, 0b00100),
+            Amoandw => (0b010, 0b01100),
+            Amoorw => (0b010, 0b01000),
+            Amominw => (0b010, 0b10000),
+            Amomaxw => (0b010, 0b10100),
+            Amominuw => (0b010, 0b11000),
+            Amomaxuw => (0b010, 0b11100),
+
+            Lrd => (0b011, 0b00010),
+            Scd => (0b011, 0b00011),
+            Amoswapd => (0b011, 0b00001),
+            Amoaddd => (0b011, 0b00000),
+            Amoxord => (0b011, 0b00100),
+            Amoandd => (0b011, 0b01100),
+            Amoord => (0b011, 0b01000),
+            Amomind => (0b011, 0b10000),
+            Amomaxd => (0b011, 0b10100),
+            Amominud => (0b011, 0b11000),
+            Amomaxud => (0b011, 0b11100),
+        }
+    }
+}
+
+/// Hypervisor virtual-machine loads, stores and fences.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HKind {
+    HlvB,
+    HlvBu,
+    HlvH,
+    HlvHu,
+    HlvxHu,
+    HlvW,
+    HlvxWu,
+    HsvB,
+    HsvH,
+    HsvW,
+
+    // RV64
+    HlvWu,
+    HlvD,
+    HsvD,
+
+    HfenceVvma,
+    HfenceGvma,
+}
+
 #[repr(align(4))]
 pub enum Compressed {
     Cr { rd_rs1: IRd, rs2: IRs2 },
@@ -296,7 +758,14 @@ pub enum Compressed {
 
 #[repr(align(8))]
 #[derive(Clone, Copy, Debug)]
-pub enum Instruction<I: RegType, const M: bool, const A: bool, F: FRegType> {
+pub enum Instruction<
+    I: RegType,
+    const E: bool,
+    const M: bool,
+    const A: bool,
+    F: FRegType,
+    X: Extensions = (),
+> {
     UType {
         rd: IRd,
         u: UTypeImmediate,
@@ -337,6 +806,14 @@ pub enum Instruction<I: RegType, const M: bool, const A: bool, F: FRegType> {
     },
     Ecall,
     Ebreak,
+    Mret,
+    Sret,
+    Dret,
+    Wfi,
+    SfenceVma {
+        rs1: IRs1,
+        rs2: IRs2,
+    },
     CsrType {
         rd: IRd,
         rs1: IRs1,
@@ -350,6 +827,63 @@ pub enum Instruction<I: RegType, const M: bool, const A: bool, F: FRegType> {
         aqrl: AmoAqrl,
         kind: AmoKind,
     },
+    HypervisorType {
+        rd: IRd,
+        rs1: IRs1,
+        rs2: IRs2,
+        kind: HKind,
+    },
+
+    FLoadType {
+        rd: FRd,
+        rs1: IRs1,
+        i: ITypeImmediate,
+        fmt: FFmt,
+    },
+    FStoreType {
+        rs1: IRs1,
+        rs2: FRs2,
+        s: STypeImmediate,
+        fmt: FFmt,
+    },
+    FmaType {
+        rd: FRd,
+        rs1: FRs1,
+        rs2: FRs2,
+        rs3: FRs3,
+        rm: u8,
+        fmt: FFmt,
+        kind: FmaKind,
+    },
+    FpType {
+        /// `x` or `f` register depending on `kind`
+        rd: u8,
+        /// `x` or `f` register depending on `kind`
+        rs1: u8,
+        rs2: FRs2,
+        rm: u8,
+        fmt: FFmt,
+        kind: FpKind,
+    },
+
+    VsetType {
+        rd: IRd,
+        kind: VsetKind,
+    },
+    VMemType {
+        /// `vs3` for stores
+        vd: u8,
+        rs1: IRs1,
+        vm: bool,
+        mem: VMem,
+    },
+    VArithType {
+        vd: u8,
+        vs2: u8,
+        src: VSrc,
+        vm: bool,
+        kind: VKind,
+    },
 
     Illegal32 {
         raw32: u32,
@@ -362,10 +896,19 @@ pub enum Instruction<I: RegType, const M: bool, const A: bool, F: FRegType> {
     Unused {
         _pdi: PhantomData<I>,
         _pdf: PhantomData<F>,
+        _pdx: PhantomData<X>,
     },
 }
 
-impl<I: 'static + RegType, const M: bool, const A: bool, F: FRegType> Instruction<I, M, A, F> {
+impl<
+        I: 'static + RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        X: Extensions,
+    > Instruction<I, E, M, A, F, X>
+{
     /// Tries to decode a 32-bit RISC-V instruction.
     /// Returns `Some(Instruction)` when decode is successful.
     /// Returns `None` when decoding produces an illegal instruction.
@@ -395,6 +938,9 @@ impl<I: 'static + RegType, const M: bool, const A: bool, F: FRegType> Instructio
                     0b010 => IKind::Lw,
                     0b100 => IKind::Lbu,
                     0b101 => IKind::Lhu,
+                    0b011 if rv64 => IKind::Ld,
+                    0b110 if rv64 => IKind::Lwu,
+                    0b111 if rv128 => IKind::Ldu,
                     _ => None?,
                 };
                 IType { rd, rs1, i, kind }
@@ -408,21 +954,97 @@ impl<I: 'static + RegType, const M: bool, const A: bool, F: FRegType> Instructio
                         let kind = IKind::Fencei;
                         IType { rd, rs1, i, kind }
                     }
+                    0b010 if rv128 => {
+                        let kind = IKind::Lq;
+                        IType { rd, rs1, i, kind }
+                    }
+                    0b010 if rd == IRd::X0 => {
+                        let kind = match raw32.funct12() {
+                            0b000 if X::ZICBOM => IKind::CboInval,
+                            0b001 if X::ZICBOM => IKind::CboClean,
+                            0b010 if X::ZICBOM => IKind::CboFlush,
+                            0b100 if X::ZICBOZ => IKind::CboZero,
+                            _ => None?,
+                        };
+                        IType { rd, rs1, i, kind }
+                    }
                     _ => None?,
                 }
             }
 
             Opcode::Opimm => {
+                // NOTE: Only shifts and the bit-manipulation instructions sharing their encoding
+                //       space use the upper immediate bits as a discriminant.
+                //       The shift amount grows to 6 and 7 bits in RV64 and RV128, leaving 6 and 5
+                //       bits for the discriminant, which we shift back into `funct7` position.
+                let funct7 = match (rv64, rv128) {
+                    (_, true) => raw32 >> 27 << 2,
+                    (true, false) => raw32 >> 26 << 1,
+                    (false, false) => funct7,
+                };
+                // NOTE: The Zicbop prefetches are `ori` with `rd` set to `x0`, which already
+                //       executes as a no-op.
                 let kind = match (funct3, funct7) {
-                    (0b000, 0b0000000) => IKind::Addi,
-                    (0b010, 0b0000000) => IKind::Slti,
-                    (0b011, 0b0000000) => IKind::Sltiu,
-                    (0b100, 0b0000000) => IKind::Xori,
-                    (0b110, 0b0000000) => IKind::Ori,
-                    (0b111, 0b0000000) => IKind::Andi,
+                    (0b000, _) => IKind::Addi,
+                    (0b010, _) => IKind::Slti,
+                    (0b011, _) => IKind::Sltiu,
+                    (0b100, _) => IKind::Xori,
+                    (0b110, _) => IKind::Ori,
+                    (0b111, _) => IKind::Andi,
                     (0b001, 0b0000000) => IKind::Slli,
                     (0b101, 0b0000000) => IKind::Srli,
                     (0b101, 0b0100000) => IKind::Srai,
+
+                    (0b001, 0b0110000) if X::ZBB => match raw32.funct12() {
+                        0x600 => IKind::Clz,
+                        0x601 => IKind::Ctz,
+                        0x602 => IKind::Cpop,
+                        0x604 => IKind::SextB,
+                        0x605 => IKind::SextH,
+                        _ => None?,
+                    },
+                    (0b101, 0b0110000) if X::ZBB || X::ZBKB => IKind::Rori,
+                    (0b101, 0b0010100) if X::ZBB && raw32.funct12() == 0x287 => IKind::OrcB,
+                    // NOTE: The shift amount of `rev8` is `XLEN - 8`.
+                    (0b101, 0b0110100) if X::ZBB || X::ZBKB => match raw32.funct12() {
+                        f if f == 0x680 | (I::BITS - 8) => IKind::Rev8,
+                        0x687 if X::ZBKB => IKind::Brev8,
+                        _ => None?,
+                    },
+
+                    (0b001, 0b0100100) if X::ZBS => IKind::Bclri,
+                    (0b101, 0b0100100) if X::ZBS => IKind::Bexti,
+                    (0b001, 0b0110100) if X::ZBS => IKind::Binvi,
+                    (0b001, 0b0010100) if X::ZBS => IKind::Bseti,
+
+                    (0b001, 0b0000100) if X::ZBKB && !rv64 && raw32.funct12() == 0x08f => {
+                        IKind::Zip
+                    }
+                    (0b101, 0b0000100) if X::ZBKB && !rv64 && raw32.funct12() == 0x08f => {
+                        IKind::Unzip
+                    }
+
+                    (0b001, 0b0011000) if I::BITS == 64 => match raw32.funct12() {
+                        0x300 if X::ZKND => IKind::Aes64im,
+                        // NOTE: Round numbers past 0xA are reserved.
+                        0x310..=0x31a if X::ZKND || X::ZKNE => IKind::Aes64ks1i,
+                        _ => None?,
+                    },
+
+                    (0b001, 0b0001000) => match raw32.funct12() {
+                        0x100 if X::ZKNH => IKind::Sha256sum0,
+                        0x101 if X::ZKNH => IKind::Sha256sum1,
+                        0x102 if X::ZKNH => IKind::Sha256sig0,
+                        0x103 if X::ZKNH => IKind::Sha256sig1,
+                        0x104 if X::ZKNH && I::BITS == 64 => IKind::Sha512sum0,
+                        0x105 if X::ZKNH && I::BITS == 64 => IKind::Sha512sum1,
+                        0x106 if X::ZKNH && I::BITS == 64 => IKind::Sha512sig0,
+                        0x107 if X::ZKNH && I::BITS == 64 => IKind::Sha512sig1,
+                        0x108 if X::ZKSH => IKind::Sm3p0,
+                        0x109 if X::ZKSH => IKind::Sm3p1,
+                        _ => None?,
+                    },
+
                     _ => None?,
                 };
                 IType { rd, rs1, i, kind }
@@ -438,12 +1060,14 @@ impl<I: 'static + RegType, const M: bool, const A: bool, F: FRegType> Instructio
                     0b000 => SKind::Sb,
                     0b001 => SKind::Sh,
                     0b010 => SKind::Sw,
+                    0b011 if rv64 => SKind::Sd,
+                    0b100 if rv128 => SKind::Sq,
                     _ => None?,
                 };
                 SType { rs1, rs2, s, kind }
             }
 
-            Opcode::Amo => {
+            Opcode::Amo if A => {
                 let aqrl = raw32.aqrl();
                 let funct5 = raw32.funct5();
                 let kind = match funct3 {
@@ -461,6 +1085,20 @@ impl<I: 'static + RegType, const M: bool, const A: bool, F: FRegType> Instructio
                         0b11100 => AmoKind::Amomaxuw,
                         _ => None?,
                     },
+                    0b011 if rv64 => match funct5 {
+                        0b00010 if rs2 == IRs2::X0 => AmoKind::Lrd,
+                        0b00011 => AmoKind::Scd,
+                        0b00001 => AmoKind::Amoswapd,
+                        0b00000 => AmoKind::Amoaddd,
+                        0b00100 => AmoKind::Amoxord,
+                        0b01100 => AmoKind::Amoandd,
+                        0b01000 => AmoKind::Amoord,
+                        0b10000 => AmoKind::Amomind,
+                        0b10100 => AmoKind::Amomaxd,
+                        0b11000 => AmoKind::Amominud,
+                        0b11100 => AmoKind::Amomaxud,
+                        _ => None?,
+                    },
                     _ => None?,
                 };
                 AmoType {
@@ -485,14 +1123,167 @@ impl<I: 'static + RegType, const M: bool, const A: bool, F: FRegType> Instructio
                     (0b110, 0b0000000) => RKind::Or,
                     (0b111, 0b0000000) => RKind::And,
 
-                    (0b000, 0b0000001) => RKind::Mul,
-                    (0b001, 0b0000001) => RKind::Mulh,
-                    (0b010, 0b0000001) => RKind::Mulhsu,
-                    (0b011, 0b0000001) => RKind::Mulhu,
-                    (0b100, 0b0000001) => RKind::Div,
-                    (0b101, 0b0000001) => RKind::Divu,
-                    (0b110, 0b0000001) => RKind::Rem,
-                    (0b111, 0b0000001) => RKind::Remu,
+                    (0b000, 0b0000001) if M => RKind::Mul,
+                    (0b001, 0b0000001) if M => RKind::Mulh,
+                    (0b010, 0b0000001) if M => RKind::Mulhsu,
+                    (0b011, 0b0000001) if M => RKind::Mulhu,
+                    (0b100, 0b0000001) if M => RKind::Div,
+                    (0b101, 0b0000001) if M => RKind::Divu,
+                    (0b110, 0b0000001) if M => RKind::Rem,
+                    (0b111, 0b0000001) if M => RKind::Remu,
+
+                    (0b010, 0b0010000) if X::ZBA => RKind::Sh1add,
+                    (0b100, 0b0010000) if X::ZBA => RKind::Sh2add,
+                    (0b110, 0b0010000) if X::ZBA => RKind::Sh3add,
+
+                    (0b111, 0b0100000) if X::ZBB || X::ZBKB => RKind::Andn,
+                    (0b110, 0b0100000) if X::ZBB || X::ZBKB => RKind::Orn,
+                    (0b100, 0b0100000) if X::ZBB || X::ZBKB => RKind::Xnor,
+                    (0b100, 0b0000101) if X::ZBB => RKind::Min,
+                    (0b101, 0b0000101) if X::ZBB => RKind::Minu,
+                    (0b110, 0b0000101) if X::ZBB => RKind::Max,
+                    (0b111, 0b0000101) if X::ZBB => RKind::Maxu,
+                    (0b001, 0b0110000) if X::ZBB || X::ZBKB => RKind::Rol,
+                    (0b101, 0b0110000) if X::ZBB || X::ZBKB => RKind::Ror,
+                    // NOTE: RV64 moves `zext.h` to OP-32.
+                    (0b100, 0b0000100) if X::ZBB && !rv64 && rs2 == IRs2::X0 => RKind::ZextH,
+
+                    (0b001, 0b0000101) if X::ZBC || X::ZBKC => RKind::Clmul,
+                    (0b010, 0b0000101) if X::ZBC => RKind::Clmulr,
+                    (0b011, 0b0000101) if X::ZBC || X::ZBKC => RKind::Clmulh,
+
+                    (0b001, 0b0100100) if X::ZBS => RKind::Bclr,
+                    (0b101, 0b0100100) if X::ZBS => RKind::Bext,
+                    (0b001, 0b0110100) if X::ZBS => RKind::Binv,
+                    (0b001, 0b0010100) if X::ZBS => RKind::Bset,
+
+                    // NOTE: `zext.h` is `pack` with `x0` in RV32.
+                    (0b100, 0b0000100) if X::ZBKB => RKind::Pack,
+                    (0b111, 0b0000100) if X::ZBKB => RKind::Packh,
+
+                    (0b010, 0b0010100) if X::ZBKX => RKind::Xperm4,
+                    (0b100, 0b0010100) if X::ZBKX => RKind::Xperm8,
+
+                    (0b101, 0b0000111) if X::ZICOND => RKind::CzeroEqz,
+                    (0b111, 0b0000111) if X::ZICOND => RKind::CzeroNez,
+
+                    // NOTE: The upper two bits of `funct7` select a byte of `rs2`.
+                    (0b000, f) if f & 0x1f == 0b10001 && X::ZKNE && !rv64 => {
+                        RKind::Aes32esi { bs: (f >> 5) as u8 }
+                    }
+                    (0b000, f) if f & 0x1f == 0b10011 && X::ZKNE && !rv64 => {
+                        RKind::Aes32esmi { bs: (f >> 5) as u8 }
+                    }
+                    (0b000, f) if f & 0x1f == 0b10101 && X::ZKND && !rv64 => {
+                        RKind::Aes32dsi { bs: (f >> 5) as u8 }
+                    }
+                    (0b000, f) if f & 0x1f == 0b10111 && X::ZKND && !rv64 => {
+                        RKind::Aes32dsmi { bs: (f >> 5) as u8 }
+                    }
+                    (0b000, 0b0011001) if X::ZKNE && I::BITS == 64 => RKind::Aes64es,
+                    (0b000, 0b0011011) if X::ZKNE && I::BITS == 64 => RKind::Aes64esm,
+                    (0b000, 0b0011101) if X::ZKND && I::BITS == 64 => RKind::Aes64ds,
+                    (0b000, 0b0011111) if X::ZKND && I::BITS == 64 => RKind::Aes64dsm,
+                    (0b000, 0b0111111) if (X::ZKND || X::ZKNE) && I::BITS == 64 => RKind::Aes64ks2,
+
+                    (0b000, 0b0101000) if X::ZKNH && !rv64 => RKind::Sha512sum0r,
+                    (0b000, 0b0101001) if X::ZKNH && !rv64 => RKind::Sha512sum1r,
+                    (0b000, 0b0101010) if X::ZKNH && !rv64 => RKind::Sha512sig0l,
+                    (0b000, 0b0101110) if X::ZKNH && !rv64 => RKind::Sha512sig0h,
+                    (0b000, 0b0101011) if X::ZKNH && !rv64 => RKind::Sha512sig1l,
+                    (0b000, 0b0101111) if X::ZKNH && !rv64 => RKind::Sha512sig1h,
+
+                    (0b000, f) if f & 0x1f == 0b11000 && X::ZKSED => {
+                        RKind::Sm4ed { bs: (f >> 5) as u8 }
+                    }
+                    (0b000, f) if f & 0x1f == 0b11010 && X::ZKSED => {
+                        RKind::Sm4ks { bs: (f >> 5) as u8 }
+                    }
+
+                    _ => None?,
+                };
+                RType { rd, rs1, rs2, kind }
+            }
+
+            Opcode::Opimm32 if rv64 => {
+                let kind = match (funct3, funct7) {
+                    (0b000, _) => IKind::Addiw,
+                    (0b001, 0b0000000) => IKind::Slliw,
+                    (0b101, 0b0000000) => IKind::Srliw,
+                    (0b101, 0b0100000) => IKind::Sraiw,
+
+                    // NOTE: `slli.uw` takes a 6-bit shift amount.
+                    (0b001, 0b0000100 | 0b0000101) if X::ZBA => IKind::SlliUw,
+
+                    (0b001, 0b0110000) if X::ZBB => match raw32.funct12() {
+                        0x600 => IKind::Clzw,
+                        0x601 => IKind::Ctzw,
+                        0x602 => IKind::Cpopw,
+                        _ => None?,
+                    },
+                    (0b101, 0b0110000) if X::ZBB || X::ZBKB => IKind::Roriw,
+
+                    _ => None?,
+                };
+                IType { rd, rs1, i, kind }
+            }
+
+            Opcode::Op32 if rv64 => {
+                let kind = match (funct3, funct7) {
+                    (0b000, 0b0000000) => RKind::Addw,
+                    (0b000, 0b0100000) => RKind::Subw,
+                    (0b001, 0b0000000) => RKind::Sllw,
+                    (0b101, 0b0000000) => RKind::Srlw,
+                    (0b101, 0b0100000) => RKind::Sraw,
+
+                    (0b000, 0b0000001) if M => RKind::Mulw,
+                    (0b100, 0b0000001) if M => RKind::Divw,
+                    (0b101, 0b0000001) if M => RKind::Divuw,
+                    (0b110, 0b0000001) if M => RKind::Remw,
+                    (0b111, 0b0000001) if M => RKind::Remuw,
+
+                    (0b000, 0b0000100) if X::ZBA => RKind::AddUw,
+                    (0b010, 0b0010000) if X::ZBA => RKind::Sh1addUw,
+                    (0b100, 0b0010000) if X::ZBA => RKind::Sh2addUw,
+                    (0b110, 0b0010000) if X::ZBA => RKind::Sh3addUw,
+
+                    (0b100, 0b0000100) if X::ZBB && rs2 == IRs2::X0 => RKind::ZextH,
+                    (0b001, 0b0110000) if X::ZBB || X::ZBKB => RKind::Rolw,
+                    (0b101, 0b0110000) if X::ZBB || X::ZBKB => RKind::Rorw,
+
+                    (0b100, 0b0000100) if X::ZBKB => RKind::Packw,
+
+                    _ => None?,
+                };
+                RType { rd, rs1, rs2, kind }
+            }
+
+            Opcode::Opimm64 if rv128 => {
+                // NOTE: Like the RV64 shifts in OP-IMM, the shift amount is 6 bits wide.
+                let funct7 = raw32 >> 26 << 1;
+                let kind = match (funct3, funct7) {
+                    (0b000, _) => IKind::Addid,
+                    (0b001, 0b0000000) => IKind::Sllid,
+                    (0b101, 0b0000000) => IKind::Srlid,
+                    (0b101, 0b0100000) => IKind::Sraid,
+                    _ => None?,
+                };
+                IType { rd, rs1, i, kind }
+            }
+
+            Opcode::Op64 if rv128 => {
+                let kind = match (funct3, funct7) {
+                    (0b000, 0b0000000) => RKind::Addd,
+                    (0b000, 0b0100000) => RKind::Subd,
+                    (0b001, 0b0000000) => RKind::Slld,
+                    (0b101, 0b0000000) => RKind::Srld,
+                    (0b101, 0b0100000) => RKind::Srad,
+
+                    (0b000, 0b0000001) if M => RKind::Muld,
+                    (0b100, 0b0000001) if M => RKind::Divd,
+                    (0b101, 0b0000001) if M => RKind::Divud,
+                    (0b110, 0b0000001) if M => RKind::Remd,
+                    (0b111, 0b0000001) if M => RKind::Remud,
 
                     _ => None?,
                 };
@@ -518,6 +1309,9 @@ impl<I: 'static + RegType, const M: bool, const A: bool, F: FRegType> Instructio
             }
 
             Opcode::Jalr => {
+                if funct3 != 0 {
+                    None?
+                }
                 let kind = IKind::Jalr;
                 IType { rd, rs1, i, kind }
             }
@@ -526,12 +1320,54 @@ impl<I: 'static + RegType, const M: bool, const A: bool, F: FRegType> Instructio
 
             Opcode::System => {
                 match funct3 {
+                    // NOTE: The fences share their encoding space with `ecall` and the other
+                    //       privileged instructions.
+                    0b000 if rd == IRd::X0 && raw32.funct7() == 0b0001001 => SfenceVma { rs1, rs2 },
+
+                    0b000
+                        if X::H
+                            && rd == IRd::X0
+                            && matches!(raw32.funct7(), 0b0010001 | 0b0110001) =>
+                    {
+                        let kind = match raw32.funct7() {
+                            0b0010001 => HKind::HfenceVvma,
+                            _ => HKind::HfenceGvma,
+                        };
+                        HypervisorType { rd, rs1, rs2, kind }
+                    }
+
                     0b000 if rd == IRd::X0 && rs1 == IRs1::X0 => match raw32.funct12() {
                         0b000000000000 => Ecall,
                         0b000000000001 => Ebreak,
+                        0b001100000010 => Mret,
+                        0b000100000010 => Sret,
+                        0b011110110010 => Dret,
+                        0b000100000101 => Wfi,
                         _ => None?,
                     },
 
+                    0b100 if X::H => {
+                        let rv64 = I::BITS >= 64;
+                        let store = rd == IRd::X0;
+                        let kind = match (raw32.funct7(), raw32 >> 20 & 0x1f) {
+                            (0b0110000, 0b00000) => HKind::HlvB,
+                            (0b0110000, 0b00001) => HKind::HlvBu,
+                            (0b0110010, 0b00000) => HKind::HlvH,
+                            (0b0110010, 0b00001) => HKind::HlvHu,
+                            (0b0110010, 0b00011) => HKind::HlvxHu,
+                            (0b0110100, 0b00000) => HKind::HlvW,
+                            (0b0110100, 0b00001) if rv64 => HKind::HlvWu,
+                            (0b0110100, 0b00011) => HKind::HlvxWu,
+                            (0b0110110, 0b00000) if rv64 => HKind::HlvD,
+                            (0b0110001, _) if store => HKind::HsvB,
+                            (0b0110011, _) if store => HKind::HsvH,
+                            (0b0110101, _) if store => HKind::HsvW,
+                            (0b0110111, _) if store && rv64 => HKind::HsvD,
+                            _ => None?,
+                        };
+                        HypervisorType { rd, rs1, rs2, kind }
+                    }
+
                     0b001..=0b011 | 0b101..=0b111 => {
                         // NOTE: "Attempts to access a non-existent CSR raise an illegal instruction exception."
                         //       --- RISC-V Privileged specification, p. 6
@@ -545,8 +1381,7 @@ impl<I: 'static + RegType, const M: bool, const A: bool, F: FRegType> Instructio
                             0b101 => CsrKind::Csrrwi,
                             0b110 => CsrKind::Csrrsi,
                             0b111 => CsrKind::Csrrci,
-                            // NOTE: Unreachable because we've already matched both ranges
-                            _ => unsafe { std::hint::unreachable_unchecked() },
+                            _ => None?,
                         };
                         CsrType { rd, rs1, csr, kind }
                     }
@@ -555,29 +1390,366 @@ impl<I: 'static + RegType, const M: bool, const A: bool, F: FRegType> Instructio
                 }
             }
 
-            Opcode::Invalid => None?,
-            Opcode::Loadfp => todo!(),
-            Opcode::Opimm32 => todo!(),
-            Opcode::Storefp => todo!(),
-            Opcode::Op32 => todo!(),
-            Opcode::Madd => todo!(),
-            Opcode::Msub => todo!(),
-            Opcode::Nmsub => todo!(),
-            Opcode::Nmadd => todo!(),
-            Opcode::Opfp => todo!(),
+            Opcode::Invalid
+            | Opcode::Amo
+            | Opcode::Opimm32
+            | Opcode::Op32
+            | Opcode::Opimm64
+            | Opcode::Op64 => None?,
+            // NOTE: The vector element widths fill the gaps between the scalar floating-point
+            //       widths.
+            Opcode::Loadfp if X::V && matches!(funct3, 0b000 | 0b101..=0b111) => {
+                Self::decode_vmem(raw32, false)?
+            }
+            Opcode::Storefp if X::V && matches!(funct3, 0b000 | 0b101..=0b111) => {
+                Self::decode_vmem(raw32, true)?
+            }
+            Opcode::Opv if X::V => Self::decode_opv(raw32)?,
+            Opcode::Opv => None?,
+            Opcode::Loadfp
+            | Opcode::Storefp
+            | Opcode::Madd
+            | Opcode::Msub
+            | Opcode::Nmsub
+            | Opcode::Nmadd
+            | Opcode::Opfp => Self::decode_fp(raw32)?,
         };
 
+        // NOTE: RV32E and RV64E only have x0-x15, so any encoding naming x16-x31 is reserved.
+        if E && result.uses_upper_regs() {
+            None?
+        }
+
         Some(result)
     }
 
+    /// Whether this instruction reads or writes any of x16-x31.
+    fn uses_upper_regs(&self) -> bool {
+        use Instruction::*;
+
+        // NOTE: `IRd::X0` is 32, so registers are compared by their 5-bit encoding.
+        let upper = |reg: u8| reg & 0x1f > 15;
+        match *self {
+            UType { rd, .. } | Jal { rd, .. } => upper(rd as u8),
+            IType { rd, rs1, .. } => upper(rd as u8) || upper(rs1 as u8),
+            BType { rs1, rs2, .. } | SType { rs1, rs2, .. } => upper(rs1 as u8) || upper(rs2 as u8),
+            RType { rd, rs1, rs2, .. }
+            | AmoType { rd, rs1, rs2, .. }
+            | HypervisorType { rd, rs1, rs2, .. } => {
+                upper(rd as u8) || upper(rs1 as u8) || upper(rs2 as u8)
+            }
+            SfenceVma { rs1, rs2 } => upper(rs1 as u8) || upper(rs2 as u8),
+            CsrType { rd, rs1, kind, .. } => {
+                let reads_rs1 = matches!(kind, CsrKind::Csrrw | CsrKind::Csrrs | CsrKind::Csrrc);
+                upper(rd as u8) || (reads_rs1 && upper(rs1 as u8))
+            }
+            FLoadType { rs1, .. } | FStoreType { rs1, .. } => upper(rs1 as u8),
+            FmaType { .. } => false,
+            FpType { rd, rs1, kind, .. } => {
+                (kind.int_rd() && upper(rd)) || (kind.int_rs1() && upper(rs1))
+            }
+            VsetType { rd, kind } => {
+                upper(rd as u8)
+                    || match kind {
+                        VsetKind::Vsetvli { rs1, .. } => upper(rs1 as u8),
+                        VsetKind::Vsetivli { .. } => false,
+                        VsetKind::Vsetvl { rs1, rs2 } => upper(rs1 as u8) || upper(rs2 as u8),
+                    }
+            }
+            VMemType { rs1, mem, .. } => {
+                upper(rs1 as u8)
+                    || matches!(mem.addressing, VAddressing::Strided { rs2 } if upper(rs2 as u8))
+            }
+            VArithType { vd, src, kind, .. } => {
+                let rd = matches!(kind, VKind::VmvXS | VKind::Vcpop | VKind::Vfirst) && upper(vd);
+                rd || matches!(src, VSrc::Scalar(rs1) if upper(rs1 as u8))
+            }
+            // NOTE: The register fields of FENCE are reserved and ignored.
+            Fence { .. } | Ecall | Ebreak | Mret | Sret | Dret | Wfi => false,
+            Illegal32 { .. } | Illegal16 { .. } | Unused { .. } => false,
+        }
+    }
+
     pub fn decode_raw32(raw32: u32) -> Self {
         let default = Self::Illegal32 { raw32 };
         Self::decode_raw32_inner(raw32).unwrap_or(default)
     }
+
+    /// Encodes this instruction as a 32-bit word.
+    ///
+    /// Returns `None` for 16-bit instructions.
+    /// Fields the instruction doesn't use keep whatever they had when decoded, so decoding and
+    /// encoding round-trips.
+    pub fn encode_raw32(&self) -> Option<u32> {
+        use Instruction::*;
+
+        let reg = |reg: u8| reg as u32 & 0x1f;
+        let rd = |rd: IRd| reg(rd as u8) << 7;
+        let rs1 = |rs1: IRs1| reg(rs1 as u8) << 15;
+        let rs2 = |rs2: IRs2| reg(rs2 as u8) << 20;
+
+        let raw32 = match *self {
+            UType { rd: d, u, kind } => {
+                let opcode = match kind {
+                    UKind::Lui => 0b0110111,
+                    UKind::Auipc => 0b0010111,
+                };
+                u.encode_raw32() | rd(d) | opcode
+            }
+            Jal { rd: d, j } => j.encode_raw32() | rd(d) | 0b1101111,
+            IType {
+                rd: d,
+                rs1: s1,
+                i,
+                kind,
+            } => {
+                let (opcode, funct3, upper) = kind.fields::<I>();
+                // NOTE: Instructions with a discriminant in the immediate only take the bits of
+                //       it that are left over as operands.
+                let operand = match upper {
+                    Some((funct12, shamt_bits)) => funct12 | i.encode_raw32() >> 20 & shamt_bits,
+                    None => i.encode_raw32() >> 20,
+                };
+                operand << 20 | rs1(s1) | funct3 << 12 | rd(d) | opcode
+            }
+            BType {
+                rs1: s1,
+                rs2: s2,
+                b,
+                kind,
+            } => {
+                let funct3 = match kind {
+                    BKind::Beq => 0b000,
+                    BKind::Bne => 0b001,
+                    BKind::Blt => 0b100,
+                    BKind::Bge => 0b101,
+                    BKind::Bltu => 0b110,
+                    BKind::Bgeu => 0b111,
+                };
+                b.encode_raw32() | rs2(s2) | rs1(s1) | funct3 << 12 | 0b1100011
+            }
+            SType {
+                rs1: s1,
+                rs2: s2,
+                s,
+                kind,
+            } => {
+                let funct3 = match kind {
+                    SKind::Sb => 0b000,
+                    SKind::Sh => 0b001,
+                    SKind::Sw => 0b010,
+                    SKind::Sd => 0b011,
+                    SKind::Sq => 0b100,
+                };
+                s.encode_raw32() | rs2(s2) | rs1(s1) | funct3 << 12 | 0b0100011
+            }
+            RType {
+                rd: d,
+                rs1: s1,
+                rs2: s2,
+                kind,
+            } => {
+                let (opcode, funct3, funct7) = kind.fields::<I>();
+                let s2 = match kind {
+                    RKind::ZextH => 0,
+                    _ => rs2(s2),
+                };
+                funct7 << 25 | s2 | rs1(s1) | funct3 << 12 | rd(d) | opcode
+            }
+            Fence {
+                rd: d,
+                rs1: s1,
+                info,
+            } => info.encode_raw32() | rs1(s1) | rd(d) | 0b0001111,
+            Ecall => 0x00000073,
+            Ebreak => 0x00100073,
+            Mret => 0x30200073,
+            Sret => 0x10200073,
+            Dret => 0x7b200073,
+            Wfi => 0x10500073,
+            SfenceVma { rs1: s1, rs2: s2 } => 0b0001001 << 25 | rs2(s2) | rs1(s1) | 0b1110011,
+            CsrType {
+                rd: d,
+                rs1: s1,
+                csr,
+                kind,
+            } => {
+                let funct3 = match kind {
+                    CsrKind::Csrrw => 0b001,
+                    CsrKind::Csrrs => 0b010,
+                    CsrKind::Csrrc => 0b011,
+                    CsrKind::Csrrwi => 0b101,
+                    CsrKind::Csrrsi => 0b110,
+                    CsrKind::Csrrci => 0b111,
+                };
+                csr.encode_raw32() | rs1(s1) | funct3 << 12 | rd(d) | 0b1110011
+            }
+            AmoType {
+                rd: d,
+                rs1: s1,
+                rs2: s2,
+                aqrl,
+                kind,
+            } => {
+                let (funct3, funct5) = kind.fields();
+                let s2 = match kind {
+                    AmoKind::Lrw | AmoKind::Lrd => 0,
+                    _ => rs2(s2),
+                };
+                funct5 << 27 | aqrl.encode_raw32() | s2 | rs1(s1) | funct3 << 12 | rd(d) | 0b0101111
+            }
+            HypervisorType {
+                rd: d,
+                rs1: s1,
+                rs2: s2,
+                kind,
+            } => {
+                let (funct3, funct7, operand) = match kind {
+                    HKind::HlvB => (0b100, 0b0110000, Some(0b00000)),
+                    HKind::HlvBu => (0b100, 0b0110000, Some(0b00001)),
+                    HKind::HlvH => (0b100, 0b0110010, Some(0b00000)),
+                    HKind::HlvHu => (0b100, 0b0110010, Some(0b00001)),
+                    HKind::HlvxHu => (0b100, 0b0110010, Some(0b00011)),
+                    HKind::HlvW => (0b100, 0b0110100, Some(0b00000)),
+                    HKind::HlvWu => (0b100, 0b0110100, Some(0b00001)),
+                    HKind::HlvxWu => (0b100, 0b0110100, Some(0b00011)),
+                    HKind::HlvD => (0b100, 0b0110110, Some(0b00000)),
+                    HKind::HsvB => (0b100, 0b0110001, None),
+                    HKind::HsvH => (0b100, 0b0110011, None),
+                    HKind::HsvW => (0b100, 0b0110101, None),
+                    HKind::HsvD => (0b100, 0b0110111, None),
+                    HKind::HfenceVvma => (0b000, 0b0010001, None),
+                    HKind::HfenceGvma => (0b000, 0b0110001, None),
+                };
+                // NOTE: Loads select their variant with `rs2`, while stores and fences have no
+                //       `rd`.
+                let (s2, d) = match operand {
+                    Some(operand) => (operand << 20, rd(d)),
+                    None => (rs2(s2), 0),
+                };
+                funct7 << 25 | s2 | rs1(s1) | funct3 << 12 | d | 0b1110011
+            }
+            FLoadType { .. } | FStoreType { .. } | FmaType { .. } | FpType { .. } => {
+                self.encode_fp()
+            }
+            VsetType { .. } | VArithType { .. } => self.encode_opv()?,
+            VMemType { .. } => self.encode_vmem(),
+            Illegal32 { raw32 } => raw32,
+            Illegal16 { .. } | Unused { .. } => None?,
+        };
+
+        Some(raw32)
+    }
+
+    /// Encodes this instruction as a 16-bit parcel.
+    ///
+    /// Returns `None` for 32-bit instructions.
+    pub fn encode_raw16(&self) -> Option<u16> {
+        match *self {
+            Self::Illegal16 { raw16 } => Some(raw16),
+            _ => None,
+        }
+    }
 }
 
-impl<I: RegType, const M: bool, const A: bool, F: FRegType> Default for Instruction<I, M, A, F> {
+impl<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions> Default
+    for Instruction<I, E, M, A, F, X>
+{
     fn default() -> Self {
         Self::Illegal32 { raw32: 0 }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::Instruction;
+    use crate::{
+        ext::Extensions,
+        freg::{FRegType, F128},
+        reg::RegType,
+    };
+
+    #[derive(Clone, Copy)]
+    struct All;
+
+    impl Extensions for All {
+        const ZBA: bool = true;
+        const ZBB: bool = true;
+        const ZBC: bool = true;
+        const ZBS: bool = true;
+        const ZBKB: bool = true;
+        const ZBKC: bool = true;
+        const ZBKX: bool = true;
+        const ZKND: bool = true;
+        const ZKNE: bool = true;
+        const ZKNH: bool = true;
+        const ZKSED: bool = true;
+        const ZKSH: bool = true;
+        const ZKR: bool = true;
+        const ZICOND: bool = true;
+        const ZICBOM: bool = true;
+        const ZICBOZ: bool = true;
+        const ZIHINTPAUSE: bool = true;
+        const ZFH: bool = true;
+        const ZFHMIN: bool = true;
+        const ZFBFMIN: bool = true;
+        const H: bool = true;
+        const V: bool = true;
+    }
+
+    /// Decodes `raw32`, checks that it encodes back to the same word and that it disassembles.
+    fn check<
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        X: Extensions,
+    >(
+        raw32: u32,
+    ) -> Result<(), TestCaseError> {
+        let inst = Instruction::<I, E, M, A, F, X>::decode_raw32(raw32);
+        let text = inst.to_string();
+        prop_assert_eq!(inst.encode_raw32(), Some(raw32), "{}", text);
+        Ok(())
+    }
+
+    macro_rules! configs {
+        ($($name:ident: $i:ty, $e:literal, $m:literal, $a:literal, $f:ty, $x:ty;)*) => {
+            proptest! {
+                $(
+                    #[test]
+                    fn $name(raw32 in any::<u32>(), raw16 in any::<u16>()) {
+                        check::<$i, $e, $m, $a, $f, $x>(raw32)?;
+                        // NOTE: Compressed parcels must come out illegal, not panic.
+                        check::<$i, $e, $m, $a, $f, $x>(raw16 as u32)?;
+                    }
+                )*
+            }
+        };
+    }
+
+    configs! {
+        test_decode_rv32i: u32, false, false, false, (), ();
+        test_decode_rv32e: u32, true, false, false, (), ();
+        test_decode_rv32imaf: u32, false, true, true, f32, ();
+        test_decode_rv32_all: u32, false, true, true, f64, All;
+        test_decode_rv64e: u64, true, true, true, (), ();
+        test_decode_rv64imafd: u64, false, true, true, f64, ();
+        test_decode_rv64_all: u64, false, true, true, f64, All;
+        test_decode_rv128_all: u128, false, true, true, F128, All;
+    }
+
+    proptest! {
+        #[test]
+        fn test_decode_opcodes(upper in any::<u32>(), opcode in 0u32..32) {
+            // NOTE: Uniform words are mostly illegal, so every major opcode is hit directly too.
+            let raw32 = upper << 7 | opcode << 2 | 0b11;
+            check::<u64, false, true, true, f64, All>(raw32)?;
+            check::<u32, false, true, true, f64, All>(raw32)?;
+            check::<u128, false, true, true, F128, All>(raw32)?;
+        }
+    }
+}
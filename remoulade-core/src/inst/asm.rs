@@ -0,0 +1,1175 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! A small assembler for the syntax of the [disassembler](super::disasm).
+//!
+//! ```text
+//! loop:   addi a0,a0,-1   # comments start with `#`
+//!         bnez a0,loop
+//!         .word 0x00100073
+//! ```
+//!
+//! Branches and jumps take a label or an offset from the instruction.
+//! Vector instructions aren't supported.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+    str::FromStr,
+};
+
+use crate::{
+    csr::Csr,
+    ext::Extensions,
+    freg::{FRd, FRegType, FRs1, FRs2, FRs3},
+    inst::{
+        disasm::{dotted, ABI_NAMES, FABI_NAMES, ROUNDING_MODES},
+        float::{FFmt, FmaKind, FpKind},
+        imm::{
+            AmoAqrl, BTypeImmediate, FenceInfo, ITypeImmediate, JTypeImmediate, STypeImmediate,
+            UTypeImmediate,
+        },
+        AmoKind, BKind, CsrKind, HKind, IKind, Instruction, RKind, SKind, UKind,
+    },
+    reg::{IRd, IRs1, IRs2, RegType},
+};
+
+/// Why a line didn't assemble.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    /// Counting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+const RKINDS: &[RKind] = {
+    use RKind::*;
+    &[
+        Add,
+        Sub,
+        Sll,
+        Slt,
+        Sltu,
+        Xor,
+        Srl,
+        Sra,
+        Or,
+        And,
+        Mul,
+        Mulh,
+        Mulhsu,
+        Mulhu,
+        Div,
+        Divu,
+        Rem,
+        Remu,
+        Addw,
+        Subw,
+        Sllw,
+        Srlw,
+        Sraw,
+        Mulw,
+        Divw,
+        Divuw,
+        Remw,
+        Remuw,
+        Addd,
+        Subd,
+        Slld,
+        Srld,
+        Srad,
+        Muld,
+        Divd,
+        Divud,
+        Remd,
+        Remud,
+        Sh1add,
+        Sh2add,
+        Sh3add,
+        AddUw,
+        Sh1addUw,
+        Sh2addUw,
+        Sh3addUw,
+        Andn,
+        Orn,
+        Xnor,
+        Min,
+        Minu,
+        Max,
+        Maxu,
+        Rol,
+        Ror,
+        Rolw,
+        Rorw,
+        ZextH,
+        Clmul,
+        Clmulh,
+        Clmulr,
+        Bclr,
+        Bext,
+        Binv,
+        Bset,
+        Pack,
+        Packh,
+        Packw,
+        Xperm4,
+        Xperm8,
+        Aes64ds,
+        Aes64dsm,
+        Aes64es,
+        Aes64esm,
+        Aes64ks2,
+        Sha512sum0r,
+        Sha512sum1r,
+        Sha512sig0l,
+        Sha512sig0h,
+        Sha512sig1l,
+        Sha512sig1h,
+        CzeroEqz,
+        CzeroNez,
+    ]
+};
+
+/// Kinds taking a byte select as their last operand.
+const BS_RKINDS: &[fn(u8) -> RKind] = &[
+    |bs| RKind::Aes32dsi { bs },
+    |bs| RKind::Aes32dsmi { bs },
+    |bs| RKind::Aes32esi { bs },
+    |bs| RKind::Aes32esmi { bs },
+    |bs| RKind::Sm4ed { bs },
+    |bs| RKind::Sm4ks { bs },
+];
+
+const LOADS: &[IKind] = {
+    use IKind::*;
+    &[Lb, Lh, Lw, Lbu, Lhu, Ld, Lwu, Lq, Ldu]
+};
+
+const IMMEDIATES: &[IKind] = {
+    use IKind::*;
+    &[
+        Addi, Slti, Sltiu, Xori, Ori, Andi, Slli, Srli, Srai, Addiw, Slliw, Srliw, Sraiw, Addid,
+        Sllid, Srlid, Sraid, SlliUw, Rori, Roriw, Bclri, Bexti, Binvi, Bseti, Aes64ks1i,
+    ]
+};
+
+const UNARY: &[IKind] = {
+    use IKind::*;
+    &[
+        Clz, Ctz, Cpop, Clzw, Ctzw, Cpopw, SextB, SextH, OrcB, Rev8, Brev8, Zip, Unzip, Aes64im,
+        Sha256sig0, Sha256sig1, Sha256sum0, Sha256sum1, Sha512sig0, Sha512sig1, Sha512sum0,
+        Sha512sum1, Sm3p0, Sm3p1,
+    ]
+};
+
+const CBO: &[IKind] = &[
+    IKind::CboClean,
+    IKind::CboFlush,
+    IKind::CboInval,
+    IKind::CboZero,
+];
+
+const BKINDS: &[BKind] = {
+    use BKind::*;
+    &[Beq, Bne, Blt, Bge, Bltu, Bgeu]
+};
+
+const SKINDS: &[SKind] = {
+    use SKind::*;
+    &[Sb, Sh, Sw, Sd, Sq]
+};
+
+const CSRKINDS: &[CsrKind] = {
+    use CsrKind::*;
+    &[Csrrw, Csrrs, Csrrc, Csrrwi, Csrrsi, Csrrci]
+};
+
+const AMOKINDS: &[AmoKind] = {
+    use AmoKind::*;
+    &[
+        Lrw, Scw, Amoswapw, Amoaddw, Amoxorw, Amoandw, Amoorw, Amominw, Amomaxw, Amominuw,
+        Amomaxuw, Lrd, Scd, Amoswapd, Amoaddd, Amoxord, Amoandd, Amoord, Amomind, Amomaxd,
+        Amominud, Amomaxud,
+    ]
+};
+
+const HKINDS: &[HKind] = {
+    use HKind::*;
+    &[
+        HlvB, HlvBu, HlvH, HlvHu, HlvxHu, HlvW, HlvxWu, HsvB, HsvH, HsvW, HlvWu, HlvD, HsvD,
+        HfenceVvma, HfenceGvma,
+    ]
+};
+
+const FMAKINDS: &[FmaKind] = {
+    use FmaKind::*;
+    &[Fmadd, Fmsub, Fnmsub, Fnmadd]
+};
+
+fn number(s: &str) -> Result<i64, String> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    };
+    let value = value.map_err(|_| format!("invalid number `{s}`"))?;
+    Ok(if negative { -value } else { value })
+}
+
+fn xreg(s: &str) -> Result<u32, String> {
+    let abi = ABI_NAMES.iter().position(|&name| name == s);
+    let numeric = s.strip_prefix('x').and_then(|n| n.parse().ok());
+    match (s, abi, numeric) {
+        ("fp", ..) => Ok(8),
+        (_, Some(reg), _) => Ok(reg as u32),
+        (_, _, Some(reg @ 0..32)) => Ok(reg),
+        _ => Err(format!("invalid integer register `{s}`")),
+    }
+}
+
+fn freg(s: &str) -> Result<u32, String> {
+    let abi = FABI_NAMES.iter().position(|&name| name == s);
+    let numeric = s.strip_prefix('f').and_then(|n| n.parse().ok());
+    match (abi, numeric) {
+        (Some(reg), _) => Ok(reg as u32),
+        (_, Some(reg @ 0..32)) => Ok(reg),
+        _ => Err(format!("invalid floating-point register `{s}`")),
+    }
+}
+
+/// An `offset(base)` operand, where the offset may be left out.
+fn memory(s: &str) -> Result<(i64, u32), String> {
+    let (offset, base) = s
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .ok_or_else(|| format!("invalid memory operand `{s}`"))?;
+    let offset = match offset.trim() {
+        "" => 0,
+        offset => number(offset)?,
+    };
+    Ok((offset, xreg(base.trim())?))
+}
+
+fn csr(s: &str) -> Result<Csr, String> {
+    let csr = match number(s) {
+        Ok(addr) => Csr::checked_from_u32(addr as u32),
+        Err(_) => (0..0x1000)
+            .filter_map(Csr::checked_from_u32)
+            .find(|csr| format!("{csr:?}").to_lowercase() == s),
+    };
+    csr.ok_or_else(|| format!("unknown CSR `{s}`"))
+}
+
+fn rounding(s: Option<&&str>) -> Result<u8, String> {
+    match s {
+        None => Ok(0b111),
+        Some(s) => match ROUNDING_MODES.iter().position(|mode| mode == s) {
+            Some(rm @ (0..=4 | 7)) => Ok(rm as u8),
+            _ => Err(format!("invalid rounding mode `{s}`")),
+        },
+    }
+}
+
+fn fmt(s: &str) -> Option<FFmt> {
+    match s {
+        "s" => Some(FFmt::S),
+        "d" => Some(FFmt::D),
+        "h" => Some(FFmt::H),
+        "q" => Some(FFmt::Q),
+        _ => None,
+    }
+}
+
+/// The format of loads, stores and moves, where single precision is `w`.
+fn width(s: &str) -> Option<FFmt> {
+    match s {
+        "w" => Some(FFmt::S),
+        "s" => None,
+        _ => fmt(s),
+    }
+}
+
+/// The integer of a conversion, as signedness and width.
+fn int(s: &str) -> Option<(bool, u32)> {
+    match s {
+        "w" => Some((true, 32)),
+        "wu" => Some((false, 32)),
+        "l" => Some((true, 64)),
+        "lu" => Some((false, 64)),
+        _ => None,
+    }
+}
+
+/// The `pred` or `succ` of a fence.
+fn fence_set(s: &str) -> Result<u32, String> {
+    if s == "0" {
+        return Ok(0);
+    }
+    s.chars().try_fold(0, |set, c| match "iorw".find(c) {
+        Some(i) => Ok(set | 8 >> i),
+        None => Err(format!("invalid fence operand `{s}`")),
+    })
+}
+
+fn imm<T>(value: i64, from: impl Fn(i32) -> Option<T>) -> Result<T, String> {
+    i32::try_from(value)
+        .ok()
+        .and_then(from)
+        .ok_or_else(|| format!("immediate {value} out of range"))
+}
+
+impl<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions>
+    Instruction<I, E, M, A, F, X>
+{
+    /// Assembles `source` into instruction words placed from address 0.
+    pub fn assemble(source: &str) -> Result<Vec<u32>, AsmError> {
+        // NOTE: The first pass only finds the address of every label, which doesn't depend on
+        //       what the labels resolve to.
+        let mut labels = HashMap::new();
+        let mut words = Vec::new();
+        for pass in [false, true] {
+            words.clear();
+            for (i, line) in source.lines().enumerate() {
+                let error = |message| AsmError {
+                    line: i + 1,
+                    message,
+                };
+
+                let mut line = line.split('#').next().unwrap_or_default().trim();
+                while let Some((label, rest)) = line.split_once(':') {
+                    let label = label.trim();
+                    let valid = label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "_.$".contains(c));
+                    if label.is_empty() || !valid {
+                        Err(error(format!("invalid label `{label}`")))?
+                    }
+                    let pc = words.len() as u64 * 4;
+                    if !pass && labels.insert(label.to_string(), pc).is_some() {
+                        Err(error(format!("duplicate label `{label}`")))?
+                    }
+                    line = rest.trim();
+                }
+                if line.is_empty() {
+                    continue;
+                }
+
+                let (mnemonic, operands) = split(line);
+                if mnemonic == ".word" {
+                    for operand in operands {
+                        words.push(number(operand).map_err(error)? as u32);
+                    }
+                    continue;
+                }
+                let pc = words.len() as u64 * 4;
+                let labels = pass.then_some(&labels);
+                for inst in Self::parse(mnemonic, &operands, pc, labels).map_err(error)? {
+                    words.extend(inst.encode_raw32());
+                }
+            }
+        }
+        Ok(words)
+    }
+
+    /// Parses one line, which may expand to several instructions.
+    ///
+    /// Labels resolve to 0 when `labels` is `None`.
+    fn parse(
+        mnemonic: &str,
+        ops: &[&str],
+        pc: u64,
+        labels: Option<&HashMap<String, u64>>,
+    ) -> Result<Vec<Self>, String> {
+        use Instruction::*;
+
+        let arity = |counts: &[usize]| match counts.contains(&ops.len()) {
+            true => Ok(()),
+            false => Err(format!("wrong number of operands to `{mnemonic}`")),
+        };
+        let rd = |i: usize| xreg(ops[i]).map(IRd::wrapping_from_u32);
+        let rs1 = |i: usize| xreg(ops[i]).map(IRs1::wrapping_from_u32);
+        let rs2 = |i: usize| xreg(ops[i]).map(IRs2::wrapping_from_u32);
+        let mem =
+            |i: usize| memory(ops[i]).map(|(offset, base)| (offset, IRs1::wrapping_from_u32(base)));
+        let target = |i: usize| -> Result<i64, String> {
+            match (number(ops[i]), labels) {
+                (Ok(offset), _) => Ok(offset),
+                (Err(_), None) => Ok(0),
+                (Err(_), Some(labels)) => match labels.get(ops[i]) {
+                    Some(&addr) => Ok(addr.wrapping_sub(pc) as i64),
+                    None => Err(format!("unknown label `{}`", ops[i])),
+                },
+            }
+        };
+        let uimm = |i: usize| match number(ops[i])? {
+            uimm @ 0..32 => Ok(IRs1::wrapping_from_u32(uimm as u32)),
+            uimm => Err(format!("immediate {uimm} out of range")),
+        };
+
+        let itype = |rd: IRd, rs1: IRs1, value: i64, kind: IKind| -> Result<Self, String> {
+            let i = imm(value, ITypeImmediate::checked_from_i32)?;
+            Ok(IType { rd, rs1, i, kind })
+        };
+        let branch = |rs1: IRs1, rs2: IRs2, offset: i64, kind: BKind| -> Result<Self, String> {
+            let b = imm(offset, BTypeImmediate::checked_from_i32)?;
+            Ok(BType { rs1, rs2, b, kind })
+        };
+        let rtype = |rd: IRd, rs1: IRs1, rs2: IRs2, kind: RKind| RType { rd, rs1, rs2, kind };
+        let csrtype = |rd: IRd, rs1: IRs1, csr: Csr, kind: CsrKind| CsrType { rd, rs1, csr, kind };
+        let fence = |raw32: u32| Fence {
+            rd: IRd::X0,
+            rs1: IRs1::X0,
+            info: FenceInfo::decode_raw32(raw32),
+        };
+        let (x0, rs1_x0, rs2_x0) = (IRd::X0, IRs1::X0, IRs2::X0);
+
+        let inst = match mnemonic {
+            "nop" => {
+                arity(&[0])?;
+                itype(x0, rs1_x0, 0, IKind::Addi)?
+            }
+            "li" => {
+                arity(&[2])?;
+                let (rd, value) = (rd(0)?, number(ops[1])?);
+                // NOTE: RV32 takes unsigned 32-bit values as well.
+                let value = match I::BITS {
+                    32 if (0..=u32::MAX as i64).contains(&value) => value as u32 as i32 as i64,
+                    _ => value,
+                };
+                if (-0x800..0x800).contains(&value) {
+                    return Ok(vec![itype(rd, rs1_x0, value, IKind::Addi)?]);
+                }
+                let value = i32::try_from(value).map_err(|_| format!("`li` of {value}"))?;
+                let hi = value.wrapping_add(0x800) & !0xfff;
+                let lo = value.wrapping_sub(hi);
+                let u = UTypeImmediate::checked_from_i32(hi).expect("The low bits are cleared");
+                let lui = UType {
+                    rd,
+                    u,
+                    kind: UKind::Lui,
+                };
+                let addi = match I::BITS {
+                    32 => IKind::Addi,
+                    _ => IKind::Addiw,
+                };
+                let rs1 = IRs1::wrapping_from_u32(rd as u32);
+                return match lo {
+                    0 => Ok(vec![lui]),
+                    _ => Ok(vec![lui, itype(rd, rs1, lo as i64, addi)?]),
+                };
+            }
+            "mv" | "not" | "sext.w" | "seqz" | "zext.b" => {
+                arity(&[2])?;
+                let (value, kind) = match mnemonic {
+                    "mv" => (0, IKind::Addi),
+                    "not" => (-1, IKind::Xori),
+                    "sext.w" => (0, IKind::Addiw),
+                    "seqz" => (1, IKind::Sltiu),
+                    _ => (0xff, IKind::Andi),
+                };
+                itype(rd(0)?, rs1(1)?, value, kind)?
+            }
+            "neg" | "negw" | "snez" | "sgtz" => {
+                arity(&[2])?;
+                let kind = match mnemonic {
+                    "neg" => RKind::Sub,
+                    "negw" => RKind::Subw,
+                    "snez" => RKind::Sltu,
+                    _ => RKind::Slt,
+                };
+                rtype(rd(0)?, rs1_x0, rs2(1)?, kind)
+            }
+            "sltz" | "zext.w" => {
+                arity(&[2])?;
+                let kind = match mnemonic {
+                    "sltz" => RKind::Slt,
+                    _ => RKind::AddUw,
+                };
+                rtype(rd(0)?, rs1(1)?, rs2_x0, kind)
+            }
+
+            "j" | "jal" => {
+                arity(&[1, 2])?;
+                let rd = match (mnemonic, ops.len()) {
+                    ("j", 1) => x0,
+                    ("jal", 1) => IRd::X1,
+                    ("jal", _) => rd(0)?,
+                    _ => arity(&[1]).map(|_| x0)?,
+                };
+                let j = imm(target(ops.len() - 1)?, JTypeImmediate::checked_from_i32)?;
+                Jal { rd, j }
+            }
+            "jr" | "jalr" => {
+                arity(&[1, 2])?;
+                let link = match mnemonic {
+                    "jr" => x0,
+                    _ => IRd::X1,
+                };
+                let (rd, (offset, rs1)) = match ops.len() {
+                    1 => match xreg(ops[0]) {
+                        Ok(rs1) => (link, (0, IRs1::wrapping_from_u32(rs1))),
+                        Err(_) => (link, mem(0)?),
+                    },
+                    _ => (rd(0)?, mem(1)?),
+                };
+                itype(rd, rs1, offset, IKind::Jalr)?
+            }
+            "ret" => {
+                arity(&[0])?;
+                itype(x0, IRs1::X1, 0, IKind::Jalr)?
+            }
+
+            "beqz" | "bnez" | "bgez" | "bltz" => {
+                arity(&[2])?;
+                let kind = match mnemonic {
+                    "beqz" => BKind::Beq,
+                    "bnez" => BKind::Bne,
+                    "bgez" => BKind::Bge,
+                    _ => BKind::Blt,
+                };
+                branch(rs1(0)?, rs2_x0, target(1)?, kind)?
+            }
+            "blez" | "bgtz" => {
+                arity(&[2])?;
+                let kind = match mnemonic {
+                    "blez" => BKind::Bge,
+                    _ => BKind::Blt,
+                };
+                let rs2 = IRs2::wrapping_from_u32(xreg(ops[0])?);
+                branch(rs1_x0, rs2, target(1)?, kind)?
+            }
+            "bgt" | "ble" | "bgtu" | "bleu" => {
+                arity(&[3])?;
+                let kind = match mnemonic {
+                    "bgt" => BKind::Blt,
+                    "ble" => BKind::Bge,
+                    "bgtu" => BKind::Bltu,
+                    _ => BKind::Bgeu,
+                };
+                let (rs1, rs2) = (rs1(1)?, IRs2::wrapping_from_u32(xreg(ops[0])?));
+                branch(rs1, rs2, target(2)?, kind)?
+            }
+
+            "csrr" => {
+                arity(&[2])?;
+                csrtype(rd(0)?, rs1_x0, csr(ops[1])?, CsrKind::Csrrs)
+            }
+            "csrw" | "csrs" | "csrc" => {
+                arity(&[2])?;
+                let kind = match mnemonic {
+                    "csrw" => CsrKind::Csrrw,
+                    "csrs" => CsrKind::Csrrs,
+                    _ => CsrKind::Csrrc,
+                };
+                csrtype(x0, rs1(1)?, csr(ops[0])?, kind)
+            }
+            "csrwi" | "csrsi" | "csrci" => {
+                arity(&[2])?;
+                let kind = match mnemonic {
+                    "csrwi" => CsrKind::Csrrwi,
+                    "csrsi" => CsrKind::Csrrsi,
+                    _ => CsrKind::Csrrci,
+                };
+                csrtype(x0, uimm(1)?, csr(ops[0])?, kind)
+            }
+            "rdcycle" | "rdtime" | "rdinstret" | "rdcycleh" | "rdtimeh" | "rdinstreth" => {
+                arity(&[1])?;
+                csrtype(rd(0)?, rs1_x0, csr(&mnemonic[2..])?, CsrKind::Csrrs)
+            }
+            "frflags" | "frrm" | "frcsr" => {
+                arity(&[1])?;
+                let csr = csr(&format!("f{}", &mnemonic[2..]))?;
+                csrtype(rd(0)?, rs1_x0, csr, CsrKind::Csrrs)
+            }
+            "fsflags" | "fsrm" | "fscsr" => {
+                arity(&[1, 2])?;
+                let csr = csr(&format!("f{}", &mnemonic[2..]))?;
+                let rd = match ops.len() {
+                    1 => x0,
+                    _ => rd(0)?,
+                };
+                csrtype(rd, rs1(ops.len() - 1)?, csr, CsrKind::Csrrw)
+            }
+            "fsflagsi" | "fsrmi" => {
+                arity(&[1])?;
+                let csr = csr(&format!("f{}", &mnemonic[2..mnemonic.len() - 1]))?;
+                csrtype(x0, uimm(0)?, csr, CsrKind::Csrrwi)
+            }
+            "unimp" => {
+                arity(&[0])?;
+                csrtype(x0, rs1_x0, Csr::Cycle, CsrKind::Csrrw)
+            }
+
+            "fence" => {
+                arity(&[0, 2])?;
+                match ops {
+                    [pred, succ] => fence(fence_set(pred)? << 24 | fence_set(succ)? << 20),
+                    _ => fence(0x0ff00000),
+                }
+            }
+            "fence.tso" => {
+                arity(&[0])?;
+                fence(0x83300000)
+            }
+            "pause" => {
+                arity(&[0])?;
+                fence(0x01000000)
+            }
+            "fence.i" => {
+                arity(&[0])?;
+                itype(x0, rs1_x0, 0, IKind::Fencei)?
+            }
+            "sfence.vma" => {
+                arity(&[0, 1, 2])?;
+                let rs1 = ops.first().map(|_| rs1(0)).transpose()?;
+                let rs2 = ops.get(1).map(|_| rs2(1)).transpose()?;
+                SfenceVma {
+                    rs1: rs1.unwrap_or(IRs1::X0),
+                    rs2: rs2.unwrap_or(IRs2::X0),
+                }
+            }
+            "ecall" | "ebreak" | "mret" | "sret" | "dret" | "wfi" => {
+                arity(&[0])?;
+                match mnemonic {
+                    "ecall" => Ecall,
+                    "ebreak" => Ebreak,
+                    "mret" => Mret,
+                    "sret" => Sret,
+                    "dret" => Dret,
+                    _ => Wfi,
+                }
+            }
+
+            "lui" | "auipc" => {
+                arity(&[2])?;
+                let value = number(ops[1])?;
+                if !(-0x80000..0x100000).contains(&value) {
+                    Err(format!("immediate {value} out of range"))?
+                }
+                let u = UTypeImmediate::checked_from_i32((value as i32) << 12)
+                    .expect("The low bits are cleared");
+                let kind = match mnemonic {
+                    "lui" => UKind::Lui,
+                    _ => UKind::Auipc,
+                };
+                UType {
+                    rd: rd(0)?,
+                    u,
+                    kind,
+                }
+            }
+
+            _ => return Self::parse_kind(mnemonic, ops, target).map(|inst| vec![inst]),
+        };
+
+        Ok(vec![inst])
+    }
+
+    /// Parses the instructions named after their kind.
+    fn parse_kind(
+        mnemonic: &str,
+        ops: &[&str],
+        target: impl Fn(usize) -> Result<i64, String>,
+    ) -> Result<Self, String> {
+        use Instruction::*;
+
+        let arity = |count: usize| match ops.len() == count {
+            true => Ok(()),
+            false => Err(format!("wrong number of operands to `{mnemonic}`")),
+        };
+        let rd = |i: usize| xreg(ops[i]).map(IRd::wrapping_from_u32);
+        let rs1 = |i: usize| xreg(ops[i]).map(IRs1::wrapping_from_u32);
+        let rs2 = |i: usize| xreg(ops[i]).map(IRs2::wrapping_from_u32);
+        let mem =
+            |i: usize| memory(ops[i]).map(|(offset, base)| (offset, IRs1::wrapping_from_u32(base)));
+
+        if let Some(kind) = find(RKINDS, mnemonic) {
+            return match kind {
+                RKind::ZextH => {
+                    arity(2)?;
+                    Ok(RType {
+                        rd: rd(0)?,
+                        rs1: rs1(1)?,
+                        rs2: IRs2::X0,
+                        kind,
+                    })
+                }
+                _ => {
+                    arity(3)?;
+                    Ok(RType {
+                        rd: rd(0)?,
+                        rs1: rs1(1)?,
+                        rs2: rs2(2)?,
+                        kind,
+                    })
+                }
+            };
+        }
+        let bs_kind = BS_RKINDS.iter().find(|kind| dotted(kind(0)) == mnemonic);
+        if let Some(kind) = bs_kind {
+            arity(4)?;
+            let bs = match number(ops[3])? {
+                bs @ 0..4 => bs as u8,
+                bs => Err(format!("byte select {bs} out of range"))?,
+            };
+            return Ok(RType {
+                rd: rd(0)?,
+                rs1: rs1(1)?,
+                rs2: rs2(2)?,
+                kind: kind(bs),
+            });
+        }
+        if let Some(kind) = find(LOADS, mnemonic) {
+            arity(2)?;
+            let (offset, rs1) = mem(1)?;
+            let i = imm(offset, ITypeImmediate::checked_from_i32)?;
+            return Ok(IType {
+                rd: rd(0)?,
+                rs1,
+                i,
+                kind,
+            });
+        }
+        if let Some(kind) = find(IMMEDIATES, mnemonic) {
+            arity(3)?;
+            let value = number(ops[2])?;
+            // NOTE: Shift amounts and round numbers are checked against the bits left for them.
+            if let (_, _, Some((_, mask))) = kind.fields::<I>() {
+                if value < 0 || value as u64 & !(mask as u64) != 0 {
+                    Err(format!("immediate {value} out of range"))?
+                }
+            }
+            let i = imm(value, ITypeImmediate::checked_from_i32)?;
+            return Ok(IType {
+                rd: rd(0)?,
+                rs1: rs1(1)?,
+                i,
+                kind,
+            });
+        }
+        if let Some(kind) = find(UNARY, mnemonic) {
+            arity(2)?;
+            return Ok(IType {
+                rd: rd(0)?,
+                rs1: rs1(1)?,
+                i: ITypeImmediate::decode_raw32(0),
+                kind,
+            });
+        }
+        if let Some(kind) = find(CBO, mnemonic) {
+            arity(1)?;
+            let (_, rs1) = mem(0)?;
+            return Ok(IType {
+                rd: IRd::X0,
+                rs1,
+                i: ITypeImmediate::decode_raw32(0),
+                kind,
+            });
+        }
+        if let Some(kind) = find(BKINDS, mnemonic) {
+            arity(3)?;
+            let b = imm(target(2)?, BTypeImmediate::checked_from_i32)?;
+            return Ok(BType {
+                rs1: rs1(0)?,
+                rs2: rs2(1)?,
+                b,
+                kind,
+            });
+        }
+        if let Some(kind) = find(SKINDS, mnemonic) {
+            arity(2)?;
+            let (offset, rs1) = mem(1)?;
+            let s = imm(offset, STypeImmediate::checked_from_i32)?;
+            return Ok(SType {
+                rs1,
+                rs2: rs2(0)?,
+                s,
+                kind,
+            });
+        }
+        if let Some(kind) = find(CSRKINDS, mnemonic) {
+            arity(3)?;
+            let rs1 = match kind {
+                CsrKind::Csrrw | CsrKind::Csrrs | CsrKind::Csrrc => rs1(2)?,
+                _ => match number(ops[2])? {
+                    uimm @ 0..32 => IRs1::wrapping_from_u32(uimm as u32),
+                    uimm => Err(format!("immediate {uimm} out of range"))?,
+                },
+            };
+            return Ok(CsrType {
+                rd: rd(0)?,
+                rs1,
+                csr: csr(ops[1])?,
+                kind,
+            });
+        }
+
+        // NOTE: Atomics are `amoadd.w` for `Amoaddw`, with ordering suffixes.
+        let (base, aq, rl) = match mnemonic.rsplit_once('.') {
+            Some((base, "aqrl")) => (base, true, true),
+            Some((base, "aq")) => (base, true, false),
+            Some((base, "rl")) => (base, false, true),
+            _ => (mnemonic, false, false),
+        };
+        let amo = AMOKINDS
+            .iter()
+            .copied()
+            .find(|&kind| dotted(kind) == base.replace('.', ""));
+        if let Some(kind) = amo {
+            let aqrl = AmoAqrl::decode_raw32(((aq as u32) << 1 | rl as u32) << 25);
+            let (rs2, addr) = match kind {
+                AmoKind::Lrw | AmoKind::Lrd => {
+                    arity(2)?;
+                    (IRs2::X0, 1)
+                }
+                _ => {
+                    arity(3)?;
+                    (rs2(1)?, 2)
+                }
+            };
+            let (_, rs1) = mem(addr)?;
+            return Ok(AmoType {
+                rd: rd(0)?,
+                rs1,
+                rs2,
+                aqrl,
+                kind,
+            });
+        }
+
+        if let Some(kind) = find(HKINDS, mnemonic) {
+            let (rd, rs1, rs2) = match kind {
+                HKind::HfenceVvma | HKind::HfenceGvma => {
+                    let rs1 = ops.first().map(|_| rs1(0)).transpose()?;
+                    let rs2 = ops.get(1).map(|_| rs2(1)).transpose()?;
+                    if ops.len() > 2 {
+                        arity(2)?;
+                    }
+                    (IRd::X0, rs1.unwrap_or(IRs1::X0), rs2.unwrap_or(IRs2::X0))
+                }
+                HKind::HsvB | HKind::HsvH | HKind::HsvW | HKind::HsvD => {
+                    arity(2)?;
+                    (IRd::X0, mem(1)?.1, rs2(0)?)
+                }
+                _ => {
+                    arity(2)?;
+                    (rd(0)?, mem(1)?.1, IRs2::X0)
+                }
+            };
+            return Ok(HypervisorType { rd, rs1, rs2, kind });
+        }
+
+        Self::parse_fp(mnemonic, ops)
+    }
+
+    /// Parses the scalar floating-point instructions.
+    fn parse_fp(mnemonic: &str, ops: &[&str]) -> Result<Self, String> {
+        use FpKind::*;
+
+        let unknown = || format!("unknown instruction `{mnemonic}`");
+        let arity = |count: usize| match ops.len() == count {
+            true => Ok(()),
+            false => Err(format!("wrong number of operands to `{mnemonic}`")),
+        };
+        // NOTE: Rounding modes are an optional last operand.
+        let arity_rm = |count: usize| match ops.len() == count || ops.len() == count + 1 {
+            true => rounding(ops.get(count)),
+            false => Err(format!("wrong number of operands to `{mnemonic}`")),
+        };
+        let f = |i: usize| freg(ops[i]);
+        let x = |i: usize| xreg(ops[i]);
+
+        let parts: Vec<_> = mnemonic.split('.').collect();
+        let fp = |kind: FpKind, fmt: FFmt, rd: u32, rs1: u32, rs2: u32, rm: u8| Self::FpType {
+            rd: rd as u8,
+            rs1: rs1 as u8,
+            rs2: FRs2::wrapping_from_u32(rs2),
+            rm,
+            fmt,
+            kind,
+        };
+
+        let inst = match parts[..] {
+            [load @ ("flw" | "fld" | "flh" | "flq")] => {
+                arity(2)?;
+                let (offset, base) = memory(ops[1])?;
+                Self::FLoadType {
+                    rd: FRd::wrapping_from_u32(f(0)?),
+                    rs1: IRs1::wrapping_from_u32(base),
+                    i: imm(offset, ITypeImmediate::checked_from_i32)?,
+                    fmt: width(&load[2..]).ok_or_else(unknown)?,
+                }
+            }
+            [store @ ("fsw" | "fsd" | "fsh" | "fsq")] => {
+                arity(2)?;
+                let (offset, base) = memory(ops[1])?;
+                Self::FStoreType {
+                    rs1: IRs1::wrapping_from_u32(base),
+                    rs2: FRs2::wrapping_from_u32(f(0)?),
+                    s: imm(offset, STypeImmediate::checked_from_i32)?,
+                    fmt: width(&store[2..]).ok_or_else(unknown)?,
+                }
+            }
+            [name, suffix] if FMAKINDS.iter().any(|&kind| dotted(kind) == name) => {
+                let rm = arity_rm(4)?;
+                let kind = *FMAKINDS
+                    .iter()
+                    .find(|&&kind| dotted(kind) == name)
+                    .expect("The kind was found above");
+                Self::FmaType {
+                    rd: FRd::wrapping_from_u32(f(0)?),
+                    rs1: FRs1::wrapping_from_u32(f(1)?),
+                    rs2: FRs2::wrapping_from_u32(f(2)?),
+                    rs3: FRs3::wrapping_from_u32(f(3)?),
+                    rm,
+                    fmt: fmt(suffix).ok_or_else(unknown)?,
+                    kind,
+                }
+            }
+            [name @ ("fadd" | "fsub" | "fmul" | "fdiv"), suffix] => {
+                let rm = arity_rm(3)?;
+                let kind = match name {
+                    "fadd" => Fadd,
+                    "fsub" => Fsub,
+                    "fmul" => Fmul,
+                    _ => Fdiv,
+                };
+                let fmt = fmt(suffix).ok_or_else(unknown)?;
+                fp(kind, fmt, f(0)?, f(1)?, f(2)?, rm)
+            }
+            ["fsqrt", suffix] => {
+                let rm = arity_rm(2)?;
+                fp(Fsqrt, fmt(suffix).ok_or_else(unknown)?, f(0)?, f(1)?, 0, rm)
+            }
+            [name @ ("fsgnj" | "fsgnjn" | "fsgnjx" | "fmin" | "fmax"), suffix] => {
+                arity(3)?;
+                let kind = match name {
+                    "fsgnj" => Fsgnj,
+                    "fsgnjn" => Fsgnjn,
+                    "fsgnjx" => Fsgnjx,
+                    "fmin" => Fmin,
+                    _ => Fmax,
+                };
+                let fmt = fmt(suffix).ok_or_else(unknown)?;
+                fp(kind, fmt, f(0)?, f(1)?, f(2)?, 0)
+            }
+            [name @ ("fmv" | "fneg" | "fabs"), suffix] => {
+                arity(2)?;
+                let kind = match name {
+                    "fmv" => Fsgnj,
+                    "fneg" => Fsgnjn,
+                    _ => Fsgnjx,
+                };
+                let fmt = fmt(suffix).ok_or_else(unknown)?;
+                fp(kind, fmt, f(0)?, f(1)?, f(1)?, 0)
+            }
+            [name @ ("feq" | "flt" | "fle"), suffix] => {
+                arity(3)?;
+                let kind = match name {
+                    "feq" => Feq,
+                    "flt" => Flt,
+                    _ => Fle,
+                };
+                let fmt = fmt(suffix).ok_or_else(unknown)?;
+                fp(kind, fmt, x(0)?, f(1)?, f(2)?, 0)
+            }
+            ["fclass", suffix] => {
+                arity(2)?;
+                fp(Fclass, fmt(suffix).ok_or_else(unknown)?, x(0)?, f(1)?, 0, 0)
+            }
+            ["fmv", "x", suffix] => {
+                arity(2)?;
+                fp(
+                    FmvXF,
+                    width(suffix).ok_or_else(unknown)?,
+                    x(0)?,
+                    f(1)?,
+                    0,
+                    0,
+                )
+            }
+            ["fmv", suffix, "x"] => {
+                arity(2)?;
+                fp(
+                    FmvFX,
+                    width(suffix).ok_or_else(unknown)?,
+                    f(0)?,
+                    x(1)?,
+                    0,
+                    0,
+                )
+            }
+            ["fcvt", "bf16", "s"] => {
+                let rm = arity_rm(2)?;
+                fp(FcvtBf16S, FFmt::H, f(0)?, f(1)?, 0, rm)
+            }
+            ["fcvt", "s", "bf16"] => {
+                let rm = arity_rm(2)?;
+                fp(FcvtSBf16, FFmt::S, f(0)?, f(1)?, 0, rm)
+            }
+            ["fcvt", to, from] => {
+                let rm = arity_rm(2)?;
+                match (int(to), int(from), fmt(to), fmt(from)) {
+                    (Some((signed, bits)), _, _, Some(fmt)) => {
+                        fp(FcvtXF { signed, bits }, fmt, x(0)?, f(1)?, 0, rm)
+                    }
+                    (_, Some((signed, bits)), Some(fmt), _) => {
+                        fp(FcvtFX { signed, bits }, fmt, f(0)?, x(1)?, 0, rm)
+                    }
+                    (_, _, Some(fmt), Some(from)) => fp(Fcvt { from }, fmt, f(0)?, f(1)?, 0, rm),
+                    _ => Err(unknown())?,
+                }
+            }
+            _ => Err(unknown())?,
+        };
+
+        Ok(inst)
+    }
+}
+
+/// The kind in `kinds` named `mnemonic`.
+fn find<K: Copy + Debug>(kinds: &[K], mnemonic: &str) -> Option<K> {
+    kinds.iter().copied().find(|&kind| dotted(kind) == mnemonic)
+}
+
+/// Splits a line into its mnemonic and operands.
+fn split(line: &str) -> (&str, Vec<&str>) {
+    let (mnemonic, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let operands = match operands.trim() {
+        "" => Vec::new(),
+        operands => operands.split(',').map(str::trim).collect(),
+    };
+    (mnemonic, operands)
+}
+
+impl<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions> FromStr
+    for Instruction<I, E, M, A, F, X>
+{
+    type Err = AsmError;
+
+    /// Parses a single instruction, with branch and jump targets as offsets.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |message| AsmError { line: 1, message };
+        let (mnemonic, operands) = split(s.trim());
+        let insts = Self::parse(mnemonic, &operands, 0, Some(&HashMap::new())).map_err(error)?;
+        match insts[..] {
+            [inst] => Ok(inst),
+            _ => Err(error(format!("`{s}` is more than one instruction"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ext::Extensions, inst::Instruction};
+
+    #[derive(Clone, Copy)]
+    struct All;
+
+    impl Extensions for All {
+        const ZBA: bool = true;
+        const ZBB: bool = true;
+        const ZBS: bool = true;
+        const ZBKB: bool = true;
+        const ZKND: bool = true;
+        const ZKNE: bool = true;
+        const ZKNH: bool = true;
+        const ZICOND: bool = true;
+        const ZICBOM: bool = true;
+        const ZICBOZ: bool = true;
+        const ZIHINTPAUSE: bool = true;
+        const ZFH: bool = true;
+        const ZFBFMIN: bool = true;
+        const H: bool = true;
+        const V: bool = true;
+    }
+
+    type Inst = Instruction<u64, false, true, true, f64, All>;
+    type Inst32 = Instruction<u32, false, true, true, f64, All>;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            # Sums 1 to 10
+                    li a0,10
+                    li a1,0
+            loop:   add a1,a1,a0
+                    addi a0,a0,-1
+                    bnez a0,loop
+                    j end
+                    .word 0xffffffff
+            end:    ebreak
+        ";
+        let words = Inst::assemble(source).unwrap();
+        assert_eq!(
+            words,
+            [
+                0x00a00513, 0x00000593, 0x00a585b3, 0xfff50513, 0xfe051ce3, 0x0080006f, 0xffffffff,
+                0x00100073,
+            ]
+        );
+
+        assert_eq!(
+            Inst::assemble("li a0,0x12345fff").unwrap(),
+            [0x12346537, 0xfff5051b]
+        );
+        assert_eq!(Inst32::assemble("li a0,0xffffffff").unwrap(), [0xfff00513]);
+
+        let error = Inst::assemble("nop\n  beqz a0,nowhere").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(Inst::assemble("addi a0,a0,2048").is_err());
+        assert!(Inst::assemble("slli a0,a0,64").is_err());
+        assert!(Inst32::assemble("slli a0,a0,32").is_err());
+        assert!(Inst::assemble("add a0,a0").is_err());
+        assert!(Inst::assemble("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // NOTE: Every instruction is encoded, disassembled and assembled again.
+        let words = [
+            0xff010113, 0x00113423, 0x00813083, 0x00000013, 0x00500513, 0x00058513, 0xfff54513,
+            0x40a00533, 0x0005051b, 0x00153513, 0x00a03533, 0x0ff57513, 0x00008067, 0x00050067,
+            0x000500e7, 0x004282e7, 0x123455b7, 0x03f51513, 0x40355513, 0x00c0006f, 0xff9ff0ef,
+            0x00050463, 0x00a04463, 0x00b54463, 0x080505bb, 0xf1402573, 0x30051073, 0x30046073,
+            0x34151573, 0xc0002573, 0x00102573, 0x00251073, 0xc0001073, 0x0ff0000f, 0x0230000f,
+            0x8330000f, 0x0100000f, 0x0000100f, 0x00000073, 0x00100073, 0x7b200073, 0x10500073,
+            0x1005252f, 0x06b5352f, 0x6005c573, 0x22058073, 0x0010a00f, 0x00852507, 0x00a53427,
+            0x02b57553, 0x02b50553, 0x22b58553, 0xc2059553, 0x4205f553, 0xe2058553, 0xa2b52553,
+            0x62b5f543, 0x60051513, 0x6b855513, 0x20b52533, 0x08b54533, 0x0805151b, 0x0ab55533,
+            0x4005d513, 0xf0058553, 0x5805f553, 0x00b56033, 0x6002e073,
+        ];
+        for raw32 in words {
+            let inst = Inst::decode_raw32(raw32);
+            assert!(
+                !matches!(inst, Instruction::Illegal32 { .. }),
+                "{raw32:#010x}"
+            );
+            assert_eq!(inst.encode_raw32(), Some(raw32), "{inst}");
+            let parsed: Inst = inst.to_string().parse().unwrap();
+            assert_eq!(parsed.encode_raw32(), Some(raw32), "{inst}");
+        }
+
+        let inst = Inst::Illegal16 { raw16: 0x0000 };
+        assert_eq!(inst.encode_raw32(), None);
+        assert_eq!(inst.encode_raw16(), Some(0x0000));
+    }
+}
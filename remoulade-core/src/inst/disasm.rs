@@ -0,0 +1,902 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Disassembly in the syntax of `objdump -d`.
+//!
+//! ```text
+//! addi    sp,sp,-16
+//! sd      ra,8(sp)
+//! csrr    a0,mhartid
+//! beqz    a0,1c
+//! ```
+
+use std::fmt::{self, Debug, Display, Formatter};
+
+use crate::{
+    ext::Extensions,
+    freg::FRegType,
+    inst::{
+        float::{FFmt, FpKind},
+        imm::FenceMode,
+        vector::{VAddressing, VKind, VSrc, VsetKind},
+        AmoKind, BKind, CsrKind, HKind, IKind, Instruction, RKind,
+    },
+    reg::RegType,
+};
+
+pub(super) const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+pub(super) const FABI_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+pub(super) const ROUNDING_MODES: [&str; 8] = ["rne", "rtz", "rdn", "rup", "rmm", "5", "6", "dyn"];
+
+/// An [`Instruction`] as assembly.
+///
+/// Formatting an [`Instruction`] directly is the same as formatting [`Instruction::disassemble`],
+/// with `{:#}` selecting numeric register names.
+#[derive(Clone, Copy, Debug)]
+pub struct Disassembly<
+    'a,
+    I: RegType,
+    const E: bool,
+    const M: bool,
+    const A: bool,
+    F: FRegType,
+    X: Extensions,
+> {
+    inst: &'a Instruction<I, E, M, A, F, X>,
+    pc: Option<u64>,
+    numeric: bool,
+    spike: bool,
+}
+
+impl<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions>
+    Instruction<I, E, M, A, F, X>
+{
+    /// Disassembles this instruction, with ABI register names and the targets of jumps and
+    /// branches as offsets.
+    pub fn disassemble(&self) -> Disassembly<'_, I, E, M, A, F, X> {
+        Disassembly {
+            inst: self,
+            pc: None,
+            numeric: false,
+            spike: false,
+        }
+    }
+}
+
+impl<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions>
+    Disassembly<'_, I, E, M, A, F, X>
+{
+    /// Shows the targets of jumps and branches as addresses, for the instruction at `pc`.
+    pub fn at(mut self, pc: u64) -> Self {
+        self.pc = Some(pc);
+        self
+    }
+
+    /// Names registers `x0`-`x31` and `f0`-`f31` instead of by their ABI names.
+    pub fn numeric(mut self, numeric: bool) -> Self {
+        self.numeric = numeric;
+        self
+    }
+
+    /// Follows Spike's syntax instead, as in its logs: operands are separated by `, `, the targets
+    /// of jumps and branches are relative to `pc` and unknown encodings are `unknown`.
+    pub fn spike(mut self, spike: bool) -> Self {
+        self.spike = spike;
+        self
+    }
+
+    fn x(&self, reg: u8) -> String {
+        let reg = reg & 0x1f;
+        match self.numeric {
+            true => format!("x{reg}"),
+            false => ABI_NAMES[reg as usize].to_string(),
+        }
+    }
+
+    fn f(&self, reg: u8) -> String {
+        let reg = reg & 0x1f;
+        match self.numeric {
+            true => format!("f{reg}"),
+            false => FABI_NAMES[reg as usize].to_string(),
+        }
+    }
+
+    fn target(&self, offset: i32) -> String {
+        if self.spike {
+            let sign = if offset < 0 { '-' } else { '+' };
+            return format!("pc {sign} {:#x}", offset.unsigned_abs());
+        }
+        match self.pc {
+            Some(pc) => {
+                let target = pc.wrapping_add(offset as i64 as u64);
+                match I::BITS {
+                    32 => format!("{:x}", target as u32),
+                    _ => format!("{target:x}"),
+                }
+            }
+            None => offset.to_string(),
+        }
+    }
+
+    fn src(&self, src: VSrc) -> String {
+        match src {
+            VSrc::Vector(vs1) => format!("v{vs1}"),
+            VSrc::Scalar(rs1) => self.x(rs1 as u8),
+            VSrc::Imm(imm) => imm.to_string(),
+            VSrc::Float(fs1) => self.f(fs1 as u8),
+        }
+    }
+
+    /// The mnemonic and the operands.
+    fn parts(&self) -> (String, String) {
+        use Instruction::*;
+
+        let x = |reg: u8| self.x(reg);
+        let f = |reg: u8| self.f(reg);
+        let zero = |reg: u8| reg & 0x1f == 0;
+        let op = |name: &str| (name.to_string(), String::new());
+
+        match *self.inst {
+            UType { rd, u, kind } => {
+                let imm = u.i32() as u32 >> 12;
+                (dotted(kind), format!("{},{imm:#x}", x(rd as u8)))
+            }
+            Jal { rd, j } => {
+                let target = self.target(j.i32());
+                match rd as u8 & 0x1f {
+                    0 => ("j".to_string(), target),
+                    1 => ("jal".to_string(), target),
+                    _ => ("jal".to_string(), format!("{},{target}", x(rd as u8))),
+                }
+            }
+            IType { rd, rs1, i, kind } => {
+                let (rd, rs1, imm) = (rd as u8, rs1 as u8, i.i32());
+                let shamt = |bits: u32| imm as u32 & (bits - 1);
+                let unary = |name: &str| (name.to_string(), format!("{},{}", x(rd), x(rs1)));
+                match kind {
+                    IKind::Jalr => match (rd & 0x1f, rs1, imm) {
+                        (0, 1, 0) => op("ret"),
+                        (0, _, 0) => ("jr".to_string(), x(rs1)),
+                        (1, _, 0) => ("jalr".to_string(), x(rs1)),
+                        _ => ("jalr".to_string(), format!("{},{imm}({})", x(rd), x(rs1))),
+                    },
+                    IKind::Lb
+                    | IKind::Lh
+                    | IKind::Lw
+                    | IKind::Lbu
+                    | IKind::Lhu
+                    | IKind::Ld
+                    | IKind::Lwu
+                    | IKind::Lq
+                    | IKind::Ldu => (dotted(kind), format!("{},{imm}({})", x(rd), x(rs1))),
+                    IKind::Addi if zero(rd) && zero(rs1) && imm == 0 => op("nop"),
+                    IKind::Addi if zero(rs1) => ("li".to_string(), format!("{},{imm}", x(rd))),
+                    IKind::Addi if imm == 0 => unary("mv"),
+                    IKind::Addiw if imm == 0 => unary("sext.w"),
+                    IKind::Sltiu if imm == 1 => unary("seqz"),
+                    IKind::Xori if imm == -1 => unary("not"),
+                    IKind::Andi if imm == 0xff => unary("zext.b"),
+                    IKind::Addi
+                    | IKind::Slti
+                    | IKind::Sltiu
+                    | IKind::Xori
+                    | IKind::Ori
+                    | IKind::Andi
+                    | IKind::Addiw
+                    | IKind::Addid => (dotted(kind), format!("{},{},{imm}", x(rd), x(rs1))),
+                    IKind::Slli
+                    | IKind::Srli
+                    | IKind::Srai
+                    | IKind::Rori
+                    | IKind::Bclri
+                    | IKind::Bexti
+                    | IKind::Binvi
+                    | IKind::Bseti => (
+                        dotted(kind),
+                        format!("{},{},{}", x(rd), x(rs1), shamt(I::BITS)),
+                    ),
+                    IKind::Slliw | IKind::Srliw | IKind::Sraiw | IKind::Roriw => {
+                        (dotted(kind), format!("{},{},{}", x(rd), x(rs1), shamt(32)))
+                    }
+                    IKind::Sllid | IKind::Srlid | IKind::Sraid | IKind::SlliUw => {
+                        (dotted(kind), format!("{},{},{}", x(rd), x(rs1), shamt(64)))
+                    }
+                    IKind::Aes64ks1i => {
+                        (dotted(kind), format!("{},{},{}", x(rd), x(rs1), imm & 0xf))
+                    }
+                    IKind::Fencei => op("fence.i"),
+                    IKind::CboClean | IKind::CboFlush | IKind::CboInval | IKind::CboZero => {
+                        (dotted(kind), format!("0({})", x(rs1)))
+                    }
+                    _ => unary(&dotted(kind)),
+                }
+            }
+            BType { rs1, rs2, b, kind } => {
+                let (rs1, rs2) = (rs1 as u8, rs2 as u8);
+                let target = self.target(b.i32());
+                let compare =
+                    |name: &str, reg: u8| (name.to_string(), format!("{},{target}", x(reg)));
+                match kind {
+                    BKind::Beq if zero(rs2) => compare("beqz", rs1),
+                    BKind::Bne if zero(rs2) => compare("bnez", rs1),
+                    BKind::Bge if zero(rs2) => compare("bgez", rs1),
+                    BKind::Bge if zero(rs1) => compare("blez", rs2),
+                    BKind::Blt if zero(rs2) => compare("bltz", rs1),
+                    BKind::Blt if zero(rs1) => compare("bgtz", rs2),
+                    _ => (dotted(kind), format!("{},{},{target}", x(rs1), x(rs2))),
+                }
+            }
+            SType { rs1, rs2, s, kind } => (
+                dotted(kind),
+                format!("{},{}({})", x(rs2 as u8), s.i32(), x(rs1 as u8)),
+            ),
+            RType { rd, rs1, rs2, kind } => {
+                let (rd, rs1, rs2) = (rd as u8, rs1 as u8, rs2 as u8);
+                let unary =
+                    |name: &str, reg: u8| (name.to_string(), format!("{},{}", x(rd), x(reg)));
+                match kind {
+                    RKind::Sub if zero(rs1) => unary("neg", rs2),
+                    RKind::Subw if zero(rs1) => unary("negw", rs2),
+                    RKind::Sltu if zero(rs1) => unary("snez", rs2),
+                    RKind::Slt if zero(rs2) => unary("sltz", rs1),
+                    RKind::Slt if zero(rs1) => unary("sgtz", rs2),
+                    RKind::AddUw if zero(rs2) => unary("zext.w", rs1),
+                    RKind::ZextH => unary("zext.h", rs1),
+                    RKind::Aes32dsi { bs }
+                    | RKind::Aes32dsmi { bs }
+                    | RKind::Aes32esi { bs }
+                    | RKind::Aes32esmi { bs }
+                    | RKind::Sm4ed { bs }
+                    | RKind::Sm4ks { bs } => (
+                        dotted(kind),
+                        format!("{},{},{},{bs}", x(rd), x(rs1), x(rs2)),
+                    ),
+                    _ => (dotted(kind), format!("{},{},{}", x(rd), x(rs1), x(rs2))),
+                }
+            }
+            Fence { info, .. } => {
+                let flags = info.flags();
+                let set = |bits: [(bool, char); 4]| -> String {
+                    let set: String = bits.iter().filter(|(on, _)| *on).map(|(_, c)| c).collect();
+                    match set.is_empty() {
+                        true => "0".to_string(),
+                        false => set,
+                    }
+                };
+                let pred = set([
+                    (flags.pi(), 'i'),
+                    (flags.po(), 'o'),
+                    (flags.pr(), 'r'),
+                    (flags.pw(), 'w'),
+                ]);
+                let succ = set([
+                    (flags.si(), 'i'),
+                    (flags.so(), 'o'),
+                    (flags.sr(), 'r'),
+                    (flags.sw(), 'w'),
+                ]);
+                match info.mode() {
+                    FenceMode::Tso => op("fence.tso"),
+                    _ if info.is_pause() => op("pause"),
+                    _ if pred == "iorw" && succ == "iorw" => op("fence"),
+                    _ => ("fence".to_string(), format!("{pred},{succ}")),
+                }
+            }
+            Ecall => op("ecall"),
+            Ebreak => op("ebreak"),
+            Mret => op("mret"),
+            Sret => op("sret"),
+            Dret => op("dret"),
+            Wfi => op("wfi"),
+            SfenceVma { rs1, rs2 } => match (zero(rs1 as u8), zero(rs2 as u8)) {
+                (true, true) => op("sfence.vma"),
+                (_, true) => ("sfence.vma".into(), x(rs1 as u8)),
+                _ => (
+                    "sfence.vma".into(),
+                    format!("{},{}", x(rs1 as u8), x(rs2 as u8)),
+                ),
+            },
+            CsrType { rd, rs1, csr, kind } => {
+                let (rd, rs1) = (rd as u8, rs1 as u8);
+                let name = format!("{csr:?}").to_lowercase();
+                let counter = matches!(
+                    name.as_str(),
+                    "cycle" | "time" | "instret" | "cycleh" | "timeh" | "instreth"
+                );
+                // NOTE: `frflags`, `fsrm` and so on.
+                let float = match name.as_str() {
+                    "fflags" => Some("flags"),
+                    "frm" => Some("rm"),
+                    "fcsr" => Some("csr"),
+                    _ => None,
+                };
+                match (kind, float) {
+                    (CsrKind::Csrrw, _) if name == "cycle" && zero(rd) && zero(rs1) => op("unimp"),
+                    (CsrKind::Csrrs, _) if counter && zero(rs1) => (format!("rd{name}"), x(rd)),
+                    (CsrKind::Csrrs, Some(float)) if zero(rs1) => (format!("fr{float}"), x(rd)),
+                    (CsrKind::Csrrw, Some(float)) if zero(rd) => (format!("fs{float}"), x(rs1)),
+                    (CsrKind::Csrrw, Some(float)) => {
+                        (format!("fs{float}"), format!("{},{}", x(rd), x(rs1)))
+                    }
+                    (CsrKind::Csrrwi, Some(float)) if zero(rd) && float != "csr" => {
+                        (format!("fs{float}i"), rs1.to_string())
+                    }
+                    (CsrKind::Csrrs, _) if zero(rs1) => {
+                        ("csrr".to_string(), format!("{},{name}", x(rd)))
+                    }
+                    // NOTE: `csrw`, `csrsi` and so on when the old value is discarded.
+                    (CsrKind::Csrrw | CsrKind::Csrrs | CsrKind::Csrrc, _) if zero(rd) => (
+                        dotted(kind).replacen("csrr", "csr", 1),
+                        format!("{name},{}", x(rs1)),
+                    ),
+                    _ if zero(rd) => (
+                        dotted(kind).replacen("csrr", "csr", 1),
+                        format!("{name},{rs1}"),
+                    ),
+                    (CsrKind::Csrrw | CsrKind::Csrrs | CsrKind::Csrrc, _) => {
+                        (dotted(kind), format!("{},{name},{}", x(rd), x(rs1)))
+                    }
+                    _ => (dotted(kind), format!("{},{name},{rs1}", x(rd))),
+                }
+            }
+            AmoType {
+                rd,
+                rs1,
+                rs2,
+                aqrl,
+                kind,
+            } => {
+                // NOTE: `Amoaddw` is `amoadd.w`.
+                let name = dotted(kind);
+                let (name, width) = name.split_at(name.len() - 1);
+                let suffix = match (aqrl.aq(), aqrl.rl()) {
+                    (false, false) => "",
+                    (true, false) => ".aq",
+                    (false, true) => ".rl",
+                    (true, true) => ".aqrl",
+                };
+                let name = format!("{name}.{width}{suffix}");
+                match kind {
+                    AmoKind::Lrw | AmoKind::Lrd => {
+                        (name, format!("{},({})", x(rd as u8), x(rs1 as u8)))
+                    }
+                    _ => (
+                        name,
+                        format!("{},{},({})", x(rd as u8), x(rs2 as u8), x(rs1 as u8)),
+                    ),
+                }
+            }
+            HypervisorType { rd, rs1, rs2, kind } => {
+                let (rd, rs1, rs2) = (rd as u8, rs1 as u8, rs2 as u8);
+                match kind {
+                    HKind::HfenceVvma | HKind::HfenceGvma => match (zero(rs1), zero(rs2)) {
+                        (true, true) => op(&dotted(kind)),
+                        (_, true) => (dotted(kind), x(rs1)),
+                        _ => (dotted(kind), format!("{},{}", x(rs1), x(rs2))),
+                    },
+                    HKind::HsvB | HKind::HsvH | HKind::HsvW | HKind::HsvD => {
+                        (dotted(kind), format!("{},({})", x(rs2), x(rs1)))
+                    }
+                    _ => (dotted(kind), format!("{},({})", x(rd), x(rs1))),
+                }
+            }
+            FLoadType { rd, rs1, i, fmt } => (
+                format!("fl{}", width(fmt)),
+                format!("{},{}({})", f(rd as u8), i.i32(), x(rs1 as u8)),
+            ),
+            FStoreType { rs1, rs2, s, fmt } => (
+                format!("fs{}", width(fmt)),
+                format!("{},{}({})", f(rs2 as u8), s.i32(), x(rs1 as u8)),
+            ),
+            FmaType {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+                fmt,
+                kind,
+            } => (
+                format!("{}.{}", dotted(kind), suffix(fmt)),
+                format!(
+                    "{},{},{},{}{}",
+                    f(rd as u8),
+                    f(rs1 as u8),
+                    f(rs2 as u8),
+                    f(rs3 as u8),
+                    rounding(rm)
+                ),
+            ),
+            FpType {
+                rd,
+                rs1,
+                rs2,
+                rm,
+                fmt,
+                kind,
+            } => {
+                let rd = match kind.int_rd() {
+                    true => x(rd),
+                    false => f(rd),
+                };
+                let rs1_ = match kind.int_rs1() {
+                    true => x(rs1),
+                    false => f(rs1),
+                };
+                let rs2_ = f(rs2 as u8);
+                let fmt_ = suffix(fmt);
+                let int = |signed: bool, bits: u32| match (bits, signed) {
+                    (32, true) => "w",
+                    (32, false) => "wu",
+                    (64, true) => "l",
+                    (64, false) => "lu",
+                    (_, true) => "t",
+                    (_, false) => "tu",
+                };
+                match kind {
+                    FpKind::Fsgnj | FpKind::Fsgnjn | FpKind::Fsgnjx if rs1 == rs2 as u8 => {
+                        let name = match kind {
+                            FpKind::Fsgnj => "fmv",
+                            FpKind::Fsgnjn => "fneg",
+                            _ => "fabs",
+                        };
+                        (format!("{name}.{fmt_}"), format!("{rd},{rs1_}"))
+                    }
+                    FpKind::Fadd | FpKind::Fsub | FpKind::Fmul | FpKind::Fdiv => (
+                        format!("{}.{fmt_}", dotted(kind)),
+                        format!("{rd},{rs1_},{rs2_}{}", rounding(rm)),
+                    ),
+                    FpKind::Fsgnj
+                    | FpKind::Fsgnjn
+                    | FpKind::Fsgnjx
+                    | FpKind::Fmin
+                    | FpKind::Fmax
+                    | FpKind::Feq
+                    | FpKind::Flt
+                    | FpKind::Fle => (
+                        format!("{}.{fmt_}", dotted(kind)),
+                        format!("{rd},{rs1_},{rs2_}"),
+                    ),
+                    FpKind::Fsqrt => (
+                        format!("fsqrt.{fmt_}"),
+                        format!("{rd},{rs1_}{}", rounding(rm)),
+                    ),
+                    FpKind::Fclass => (format!("fclass.{fmt_}"), format!("{rd},{rs1_}")),
+                    FpKind::Fcvt { from } => (
+                        format!("fcvt.{fmt_}.{}", suffix(from)),
+                        format!("{rd},{rs1_}{}", rounding(rm)),
+                    ),
+                    FpKind::FcvtBf16S => (
+                        "fcvt.bf16.s".to_string(),
+                        format!("{rd},{rs1_}{}", rounding(rm)),
+                    ),
+                    FpKind::FcvtSBf16 => (
+                        "fcvt.s.bf16".to_string(),
+                        format!("{rd},{rs1_}{}", rounding(rm)),
+                    ),
+                    FpKind::FmvXF => (format!("fmv.x.{}", width(fmt)), format!("{rd},{rs1_}")),
+                    FpKind::FmvFX => (format!("fmv.{}.x", width(fmt)), format!("{rd},{rs1_}")),
+                    FpKind::FcvtXF { signed, bits } => (
+                        format!("fcvt.{}.{fmt_}", int(signed, bits)),
+                        format!("{rd},{rs1_}{}", rounding(rm)),
+                    ),
+                    FpKind::FcvtFX { signed, bits } => (
+                        format!("fcvt.{fmt_}.{}", int(signed, bits)),
+                        format!("{rd},{rs1_}{}", rounding(rm)),
+                    ),
+                }
+            }
+            VsetType { rd, kind } => {
+                let rd = x(rd as u8);
+                match kind {
+                    VsetKind::Vsetvli { rs1, vtypei } => (
+                        "vsetvli".to_string(),
+                        format!("{rd},{},{}", x(rs1 as u8), vtype(vtypei)),
+                    ),
+                    VsetKind::Vsetivli { uimm, vtypei } => (
+                        "vsetivli".to_string(),
+                        format!("{rd},{uimm},{}", vtype(vtypei)),
+                    ),
+                    VsetKind::Vsetvl { rs1, rs2 } => (
+                        "vsetvl".to_string(),
+                        format!("{rd},{},{}", x(rs1 as u8), x(rs2 as u8)),
+                    ),
+                }
+            }
+            VMemType { vd, rs1, vm, mem } => {
+                let dir = if mem.store { 's' } else { 'l' };
+                let (eew, nf) = (mem.eew, mem.nf);
+                let seg = match nf {
+                    1 => String::new(),
+                    _ => format!("seg{nf}"),
+                };
+                let (name, extra) = match mem.addressing {
+                    VAddressing::Unit => (format!("v{dir}{seg}e{eew}.v"), String::new()),
+                    VAddressing::UnitFaultFirst => (format!("vl{seg}e{eew}ff.v"), String::new()),
+                    VAddressing::Whole if mem.store => (format!("vs{nf}r.v"), String::new()),
+                    VAddressing::Whole => (format!("vl{nf}re{eew}.v"), String::new()),
+                    VAddressing::Mask => (format!("v{dir}m.v"), String::new()),
+                    VAddressing::Strided { rs2 } => (
+                        format!("v{dir}s{seg}e{eew}.v"),
+                        format!(",{}", x(rs2 as u8)),
+                    ),
+                    VAddressing::Indexed { vs2, ordered } => {
+                        let order = if ordered { 'o' } else { 'u' };
+                        (format!("v{dir}{order}x{seg}ei{eew}.v"), format!(",v{vs2}"))
+                    }
+                };
+                let mask = if vm { "" } else { ",v0.t" };
+                (name, format!("v{vd},({}){extra}{mask}", x(rs1 as u8)))
+            }
+            VArithType {
+                vd,
+                vs2,
+                src,
+                vm,
+                kind,
+            } => {
+                let name = dotted(kind);
+                let mask = if vm { "" } else { ",v0.t" };
+                let src_ = self.src(src);
+                let suffix = match src {
+                    VSrc::Vector(_) => "vv",
+                    VSrc::Scalar(_) => "vx",
+                    VSrc::Imm(_) => "vi",
+                    VSrc::Float(_) => "vf",
+                };
+                match kind {
+                    VKind::VmvXS => (name, format!("{},v{vs2}", x(vd))),
+                    VKind::VfmvFS => (name, format!("{},v{vs2}", f(vd))),
+                    VKind::VmvSX | VKind::VfmvSF => (name, format!("v{vd},{src_}")),
+                    VKind::Vcpop | VKind::Vfirst => {
+                        (format!("{name}.m"), format!("{},v{vs2}{mask}", x(vd)))
+                    }
+                    VKind::Vid => ("vid.v".to_string(), format!("v{vd}{mask}")),
+                    VKind::Viota | VKind::Vmsbf | VKind::Vmsof | VKind::Vmsif => {
+                        (format!("{name}.m"), format!("v{vd},v{vs2}{mask}"))
+                    }
+                    VKind::Vfsqrt | VKind::Vfclass => {
+                        (format!("{name}.v"), format!("v{vd},v{vs2}{mask}"))
+                    }
+                    // NOTE: The names of these already end in their operand types.
+                    _ if kind.selector().is_some() => (name, format!("v{vd},v{vs2}{mask}")),
+                    VKind::Vcompress => (format!("{name}.vm"), format!("v{vd},v{vs2},{src_}")),
+                    VKind::Vmv | VKind::Vfmv => (
+                        format!("{name}.v.{}", &suffix[1..]),
+                        format!("v{vd},{src_}"),
+                    ),
+                    VKind::Vmerge | VKind::Vfmerge | VKind::Vadc | VKind::Vsbc => (
+                        format!("{name}.{suffix}m"),
+                        format!("v{vd},v{vs2},{src_},v0"),
+                    ),
+                    VKind::Vmadc | VKind::Vmsbc if !vm => (
+                        format!("{name}.{suffix}m"),
+                        format!("v{vd},v{vs2},{src_},v0"),
+                    ),
+                    VKind::Vmadc | VKind::Vmsbc => {
+                        (format!("{name}.{suffix}"), format!("v{vd},v{vs2},{src_}"))
+                    }
+                    // Narrowing instructions take a double-width `vs2`
+                    VKind::Vnsrl | VKind::Vnsra | VKind::Vnclipu | VKind::Vnclip => (
+                        format!("{name}.w{}", &suffix[1..]),
+                        format!("v{vd},v{vs2},{src_}{mask}"),
+                    ),
+                    VKind::VwadduW
+                    | VKind::VwaddW
+                    | VKind::VwsubuW
+                    | VKind::VwsubW
+                    | VKind::VfwaddW
+                    | VKind::VfwsubW => (
+                        format!("{}.w{}", name.trim_end_matches(".w"), &suffix[1..]),
+                        format!("v{vd},v{vs2},{src_}{mask}"),
+                    ),
+                    VKind::Vmvr => {
+                        let nr = match src {
+                            VSrc::Imm(imm) => imm + 1,
+                            _ => 1,
+                        };
+                        (format!("vmv{nr}r.v"), format!("v{vd},v{vs2}"))
+                    }
+                    VKind::Vmandn
+                    | VKind::Vmand
+                    | VKind::Vmor
+                    | VKind::Vmxor
+                    | VKind::Vmorn
+                    | VKind::Vmnand
+                    | VKind::Vmnor
+                    | VKind::Vmxnor => (format!("{name}.mm"), format!("v{vd},v{vs2},{src_}")),
+                    VKind::Vredsum
+                    | VKind::Vredand
+                    | VKind::Vredor
+                    | VKind::Vredxor
+                    | VKind::Vredminu
+                    | VKind::Vredmin
+                    | VKind::Vredmaxu
+                    | VKind::Vredmax
+                    | VKind::Vfredusum
+                    | VKind::Vfredosum
+                    | VKind::Vfredmin
+                    | VKind::Vfredmax
+                    | VKind::Vwredsumu
+                    | VKind::Vwredsum
+                    | VKind::Vfwredusum
+                    | VKind::Vfwredosum => {
+                        (format!("{name}.vs"), format!("v{vd},v{vs2},{src_}{mask}"))
+                    }
+                    // NOTE: Multiply-adds take the multiplicand before `vs2`.
+                    VKind::Vmadd
+                    | VKind::Vnmsub
+                    | VKind::Vmacc
+                    | VKind::Vnmsac
+                    | VKind::Vfmadd
+                    | VKind::Vfnmadd
+                    | VKind::Vfmsub
+                    | VKind::Vfnmsub
+                    | VKind::Vfmacc
+                    | VKind::Vfnmacc
+                    | VKind::Vfmsac
+                    | VKind::Vfnmsac
+                    | VKind::Vwmaccu
+                    | VKind::Vwmacc
+                    | VKind::Vwmaccus
+                    | VKind::Vwmaccsu
+                    | VKind::Vfwmacc
+                    | VKind::Vfwnmacc
+                    | VKind::Vfwmsac
+                    | VKind::Vfwnmsac => (
+                        format!("{name}.{suffix}"),
+                        format!("v{vd},{src_},v{vs2}{mask}"),
+                    ),
+                    _ => (
+                        format!("{name}.{suffix}"),
+                        format!("v{vd},v{vs2},{src_}{mask}"),
+                    ),
+                }
+            }
+            Illegal32 { raw32 } => (".insn".to_string(), format!("4, {raw32:#010x}")),
+            Illegal16 { raw16 } => (".insn".to_string(), format!("2, {raw16:#06x}")),
+            Unused { .. } => unreachable!(),
+        }
+    }
+}
+
+impl<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions> Display
+    for Disassembly<'_, I, E, M, A, F, X>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        if self.spike && matches!(self.inst, Illegal32 { .. } | Illegal16 { .. }) {
+            return f.write_str("unknown");
+        }
+        match self.parts() {
+            (mnemonic, operands) if operands.is_empty() => f.write_str(&mnemonic),
+            (mnemonic, operands) if self.spike => {
+                write!(f, "{mnemonic:<7} {}", operands.replace(',', ", "))
+            }
+            (mnemonic, operands) => write!(f, "{mnemonic:<7} {operands}"),
+        }
+    }
+}
+
+impl<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions> Display
+    for Instruction<I, E, M, A, F, X>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.disassemble().numeric(f.alternate()), f)
+    }
+}
+
+/// The mnemonic for a `CamelCase` variant, e.g. `sext.b` for `SextB`.
+pub(super) fn dotted(kind: impl Debug) -> String {
+    let name = format!("{kind:?}");
+    let name = name.split(' ').next().unwrap_or_default();
+    let mut mnemonic = String::with_capacity(name.len() + 2);
+    for (i, c) in name.chars().enumerate() {
+        if i > 0 && c.is_ascii_uppercase() {
+            mnemonic.push('.');
+        }
+        mnemonic.push(c.to_ascii_lowercase());
+    }
+    mnemonic
+}
+
+/// The suffix of arithmetic on `fmt`.
+fn suffix(fmt: FFmt) -> &'static str {
+    match fmt {
+        FFmt::S => "s",
+        FFmt::D => "d",
+        FFmt::H => "h",
+        FFmt::Q => "q",
+    }
+}
+
+/// The suffix of loads, stores and moves of `fmt`.
+fn width(fmt: FFmt) -> &'static str {
+    match fmt {
+        FFmt::S => "w",
+        _ => suffix(fmt),
+    }
+}
+
+/// The rounding mode as an extra operand, left out when dynamic.
+fn rounding(rm: u8) -> String {
+    match rm {
+        0b111 => String::new(),
+        _ => format!(",{}", ROUNDING_MODES[rm as usize & 0b111]),
+    }
+}
+
+fn vtype(vtypei: u32) -> String {
+    let vsew = vtypei >> 3 & 0b111;
+    let lmul = match vtypei & 0b111 {
+        0b000 => "m1",
+        0b001 => "m2",
+        0b010 => "m4",
+        0b011 => "m8",
+        0b101 => "mf8",
+        0b110 => "mf4",
+        0b111 => "mf2",
+        _ => return vtypei.to_string(),
+    };
+    if vtypei >> 8 != 0 || vsew > 0b011 {
+        return vtypei.to_string();
+    }
+    let ta = if vtypei & 1 << 6 != 0 { "ta" } else { "tu" };
+    let ma = if vtypei & 1 << 7 != 0 { "ma" } else { "mu" };
+    format!("e{},{lmul},{ta},{ma}", 8 << vsew)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ext::Extensions, inst::Instruction};
+
+    #[derive(Clone, Copy)]
+    struct All;
+
+    impl Extensions for All {
+        const ZBA: bool = true;
+        const ZBB: bool = true;
+        const ZBS: bool = true;
+        const ZKNE: bool = true;
+        const ZKND: bool = true;
+        const ZICBOM: bool = true;
+        const ZIHINTPAUSE: bool = true;
+        const H: bool = true;
+        const V: bool = true;
+    }
+
+    type Inst = Instruction<u64, false, true, true, f64, All>;
+
+    #[test]
+    fn test_disassemble() {
+        let cases = [
+            (0xff010113, "addi    sp,sp,-16"),
+            (0x00113423, "sd      ra,8(sp)"),
+            (0x00813083, "ld      ra,8(sp)"),
+            (0x00000013, "nop"),
+            (0x00500513, "li      a0,5"),
+            (0x00058513, "mv      a0,a1"),
+            (0xfff54513, "not     a0,a0"),
+            (0x40a00533, "neg     a0,a0"),
+            (0x0005051b, "sext.w  a0,a0"),
+            (0x00153513, "seqz    a0,a0"),
+            (0x00a03533, "snez    a0,a0"),
+            (0x0ff57513, "zext.b  a0,a0"),
+            (0x00008067, "ret"),
+            (0x00050067, "jr      a0"),
+            (0x000500e7, "jalr    a0"),
+            (0x004282e7, "jalr    t0,4(t0)"),
+            (0x123455b7, "lui     a1,0x12345"),
+            (0x03f51513, "slli    a0,a0,63"),
+            (0x40355513, "srai    a0,a0,3"),
+            (0x00c0006f, "j       12"),
+            (0xff9ff0ef, "jal     -8"),
+            (0x00050463, "beqz    a0,8"),
+            (0x00a04463, "bgtz    a0,8"),
+            (0x00b54463, "blt     a0,a1,8"),
+            (0x0800053b, "zext.w  a0,zero"),
+            (0x080505bb, "zext.w  a1,a0"),
+            (0xf1402573, "csrr    a0,mhartid"),
+            (0x30051073, "csrw    mstatus,a0"),
+            (0x30046073, "csrsi   mstatus,8"),
+            (0x34151573, "csrrw   a0,mepc,a0"),
+            (0xc0002573, "rdcycle a0"),
+            (0x00102573, "frflags a0"),
+            (0x00251073, "fsrm    a0"),
+            (0xc0001073, "unimp"),
+            (0x0ff0000f, "fence"),
+            (0x0230000f, "fence   r,rw"),
+            (0x8330000f, "fence.tso"),
+            (0x0100000f, "pause"),
+            (0x0000100f, "fence.i"),
+            (0x00000073, "ecall"),
+            (0x00100073, "ebreak"),
+            (0x30200073, "mret"),
+            (0x10200073, "sret"),
+            (0x7b200073, "dret"),
+            (0x10500073, "wfi"),
+            (0x12000073, "sfence.vma"),
+            (0x12b50073, "sfence.vma a0,a1"),
+            (0x1005252f, "lr.w    a0,(a0)"),
+            (0x06b5352f, "amoadd.d.aqrl a0,a1,(a0)"),
+            (0x6005c573, "hlv.b   a0,(a1)"),
+            (0x22058073, "hfence.vvma a1"),
+            (0x0010a00f, "cbo.clean 0(ra)"),
+            (0x00852507, "flw     fa0,8(a0)"),
+            (0x02b57553, "fadd.d  fa0,fa0,fa1"),
+            (0x02b50553, "fadd.d  fa0,fa0,fa1,rne"),
+            (0x22b58553, "fmv.d   fa0,fa1"),
+            (0xc2059553, "fcvt.w.d a0,fa1,rtz"),
+            (0x4205f553, "fcvt.d.s fa0,fa1"),
+            (0xe2058553, "fmv.x.d a0,fa1"),
+            (0xa2b52553, "feq.d   a0,fa0,fa1"),
+            (0x62b5f543, "fmadd.d fa0,fa1,fa1,fa2"),
+            (0x0d0572d7, "vsetvli t0,a0,e32,m1,ta,ma"),
+            (0x02056087, "vle32.v v1,(a0)"),
+            (0x0ab56087, "vlse32.v v1,(a0),a1"),
+            (0x022080d7, "vadd.vv v1,v2,v1"),
+            (0x0020b0d7, "vadd.vi v1,v2,1,v0.t"),
+            (0xb62520d7, "vmacc.vv v1,v10,v2"),
+            (0x5e0030d7, "vmv.v.i v1,0"),
+            (0x422020d7, "vmv.x.s ra,v2"),
+            (0xc22520d7, "vwaddu.vv v1,v2,v10"),
+            (0xd22560d7, "vwaddu.wx v1,v2,a0"),
+            (0xb220b0d7, "vnsrl.wi v1,v2,1"),
+            (0x4a2320d7, "vzext.vf2 v1,v2"),
+            (0x481190d7, "vfcvt.f.x.v v1,v1,v0.t"),
+            (0x4a2a90d7, "vfncvt.rod.f.f.w v1,v2"),
+            (0x402500d7, "vadc.vvm v1,v2,v10,v0"),
+            (0x5e2520d7, "vcompress.vm v1,v2,v10"),
+            (0x522820d7, "viota.m v1,v2"),
+            (0xffffffff, ".insn   4, 0xffffffff"),
+        ];
+
+        for (raw32, expected) in cases {
+            let inst = Inst::decode_raw32(raw32);
+            assert_eq!(inst.to_string(), expected, "{raw32:#010x}");
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        let inst = Inst::decode_raw32(0x00b50463);
+        assert_eq!(inst.to_string(), "beq     a0,a1,8");
+        assert_eq!(format!("{inst:#}"), "beq     x10,x11,8");
+        assert_eq!(
+            inst.disassemble().at(0x1000).to_string(),
+            "beq     a0,a1,1008"
+        );
+
+        let inst = Instruction::<u32, false, true, true, f64>::decode_raw32(0xff9ff06f);
+        assert_eq!(inst.disassemble().at(0).to_string(), "j       fffffff8");
+        assert_eq!(
+            inst.disassemble().at(0).spike(true).to_string(),
+            "j       pc - 0x8"
+        );
+
+        let inst = Inst::decode_raw32(0x00b50463);
+        assert_eq!(
+            inst.disassemble().spike(true).to_string(),
+            "beq     a0, a1, pc + 0x8"
+        );
+        let inst = Inst::decode_raw32(0xffffffff);
+        assert_eq!(inst.disassemble().spike(true).to_string(), "unknown");
+    }
+}
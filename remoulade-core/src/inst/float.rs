@@ -0,0 +1,377 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Decoding of the scalar floating-point extensions.
+
+use crate::{
+    ext::Extensions,
+    freg::{FRd, FRegType, FRs1, FRs2, FRs3},
+    inst::{Fields32, Instruction, Opcode},
+    reg::RegType,
+};
+
+/// Floating-point format, in the order of the `fmt` field.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FFmt {
+    S,
+    D,
+    H,
+    Q,
+}
+
+impl FFmt {
+    pub fn bits(self) -> u32 {
+        match self {
+            Self::H => 16,
+            Self::S => 32,
+            Self::D => 64,
+            Self::Q => 128,
+        }
+    }
+
+    fn decode(bits: u32) -> Self {
+        match bits & 0b11 {
+            0b00 => Self::S,
+            0b01 => Self::D,
+            0b10 => Self::H,
+            _ => Self::Q,
+        }
+    }
+
+    fn encode(self) -> u32 {
+        match self {
+            Self::S => 0b00,
+            Self::D => 0b01,
+            Self::H => 0b10,
+            Self::Q => 0b11,
+        }
+    }
+
+    /// The `width` field of loads and stores.
+    fn width(self) -> u32 {
+        match self {
+            Self::H => 0b001,
+            Self::S => 0b010,
+            Self::D => 0b011,
+            Self::Q => 0b100,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FmaKind {
+    Fmadd,
+    Fmsub,
+    Fnmsub,
+    Fnmadd,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FpKind {
+    Fadd,
+    Fsub,
+    Fmul,
+    Fdiv,
+    Fsqrt,
+    Fsgnj,
+    Fsgnjn,
+    Fsgnjx,
+    Fmin,
+    Fmax,
+    /// Conversion from another format
+    Fcvt {
+        from: FFmt,
+    },
+    /// `fcvt.bf16.s`
+    FcvtBf16S,
+    /// `fcvt.s.bf16`
+    FcvtSBf16,
+    Feq,
+    Flt,
+    Fle,
+    Fclass,
+    /// `fmv.x.h`, `fmv.x.w` and `fmv.x.d`
+    FmvXF,
+    /// `fmv.h.x`, `fmv.w.x` and `fmv.d.x`
+    FmvFX,
+    /// Conversion to a `bits`-wide integer
+    FcvtXF {
+        signed: bool,
+        bits: u32,
+    },
+    /// Conversion from a `bits`-wide integer
+    FcvtFX {
+        signed: bool,
+        bits: u32,
+    },
+}
+
+impl FpKind {
+    /// Whether `rd` is an integer register.
+    pub fn int_rd(self) -> bool {
+        use FpKind::*;
+        matches!(self, Feq | Flt | Fle | Fclass | FmvXF | FcvtXF { .. })
+    }
+
+    /// Whether `rs1` is an integer register.
+    pub fn int_rs1(self) -> bool {
+        matches!(self, FpKind::FmvFX | FpKind::FcvtFX { .. })
+    }
+}
+
+impl<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions>
+    Instruction<I, E, M, A, F, X>
+{
+    /// Whether all instructions for `fmt` are available.
+    fn arithmetic(fmt: FFmt) -> bool {
+        match fmt {
+            FFmt::S => F::BITS >= 32,
+            FFmt::D => F::BITS >= 64,
+            FFmt::H => F::BITS >= 32 && X::ZFH,
+            FFmt::Q => F::BITS >= 128,
+        }
+    }
+
+    /// Whether `fmt` can be converted to and from the other available formats.
+    fn convertible(fmt: FFmt) -> bool {
+        match fmt {
+            FFmt::H => F::BITS >= 32 && (X::ZFH || X::ZFHMIN),
+            _ => Self::arithmetic(fmt),
+        }
+    }
+
+    /// Whether `fmt` can be loaded, stored and moved to and from integer registers.
+    fn movable(fmt: FFmt) -> bool {
+        match fmt {
+            FFmt::H => Self::convertible(fmt) || F::BITS >= 32 && X::ZFBFMIN,
+            _ => Self::arithmetic(fmt),
+        }
+    }
+
+    /// Decodes the LOAD-FP, STORE-FP, fused multiply-add and OP-FP major opcodes.
+    pub(super) fn decode_fp(raw32: u32) -> Option<Self> {
+        use FpKind::*;
+
+        let rm = raw32.funct3() as u8;
+        let fmt = FFmt::decode(raw32 >> 25);
+        let rd = (raw32 >> 7 & 0x1f) as u8;
+        let rs1 = (raw32 >> 15 & 0x1f) as u8;
+        let rs2 = raw32 >> 20 & 0x1f;
+        // NOTE: 0b101 and 0b110 are reserved, and 0b111 selects `frm`.
+        let rounding = matches!(rm, 0b000..=0b100 | 0b111);
+
+        let opcode = raw32.opcode();
+        let result = match opcode {
+            Opcode::Loadfp | Opcode::Storefp => {
+                let fmt = match raw32.funct3() {
+                    0b001 => FFmt::H,
+                    0b010 => FFmt::S,
+                    0b011 => FFmt::D,
+                    0b100 => FFmt::Q,
+                    _ => None?,
+                };
+                if !Self::movable(fmt) {
+                    None?
+                }
+                match opcode {
+                    Opcode::Loadfp => Self::FLoadType {
+                        rd: FRd::decode_raw32(raw32),
+                        rs1: raw32.rs1(),
+                        i: raw32.i(),
+                        fmt,
+                    },
+                    _ => Self::FStoreType {
+                        rs1: raw32.rs1(),
+                        rs2: FRs2::decode_raw32(raw32),
+                        s: raw32.s(),
+                        fmt,
+                    },
+                }
+            }
+
+            Opcode::Madd | Opcode::Msub | Opcode::Nmsub | Opcode::Nmadd => {
+                if !(Self::arithmetic(fmt) && rounding) {
+                    None?
+                }
+                let kind = match opcode {
+                    Opcode::Madd => FmaKind::Fmadd,
+                    Opcode::Msub => FmaKind::Fmsub,
+                    Opcode::Nmsub => FmaKind::Fnmsub,
+                    _ => FmaKind::Fnmadd,
+                };
+                Self::FmaType {
+                    rd: FRd::decode_raw32(raw32),
+                    rs1: FRs1::decode_raw32(raw32),
+                    rs2: FRs2::decode_raw32(raw32),
+                    rs3: FRs3::decode_raw32(raw32),
+                    rm,
+                    fmt,
+                    kind,
+                }
+            }
+
+            Opcode::Opfp => {
+                let arithmetic = Self::arithmetic(fmt);
+                let bf16 = F::BITS >= 32 && X::ZFBFMIN;
+                // NOTE: The 64-bit integer conversions only exist on RV64, and moves need an
+                //       integer register as wide as the format.
+                let rv64 = I::BITS >= 64;
+                let moves = Self::movable(fmt) && fmt.bits() <= I::BITS;
+
+                let (kind, legal) = match (raw32.funct5(), rs2, rm) {
+                    (0b00000, _, _) => (Fadd, arithmetic && rounding),
+                    (0b00001, _, _) => (Fsub, arithmetic && rounding),
+                    (0b00010, _, _) => (Fmul, arithmetic && rounding),
+                    (0b00011, _, _) => (Fdiv, arithmetic && rounding),
+                    (0b01011, 0, _) => (Fsqrt, arithmetic && rounding),
+                    (0b00100, _, 0b000) => (Fsgnj, arithmetic),
+                    (0b00100, _, 0b001) => (Fsgnjn, arithmetic),
+                    (0b00100, _, 0b010) => (Fsgnjx, arithmetic),
+                    (0b00101, _, 0b000) => (Fmin, arithmetic),
+                    (0b00101, _, 0b001) => (Fmax, arithmetic),
+                    (0b01000, 0b00110, _) if fmt == FFmt::S => (FcvtSBf16, bf16 && rounding),
+                    (0b01000, 0b01000, _) if fmt == FFmt::H => (FcvtBf16S, bf16 && rounding),
+                    (0b01000, 0..=3, _) => {
+                        let from = FFmt::decode(rs2);
+                        let legal = from != fmt
+                            && Self::convertible(from)
+                            && Self::convertible(fmt)
+                            && rounding;
+                        (Fcvt { from }, legal)
+                    }
+                    (0b10100, _, 0b000) => (Fle, arithmetic),
+                    (0b10100, _, 0b001) => (Flt, arithmetic),
+                    (0b10100, _, 0b010) => (Feq, arithmetic),
+                    (0b11100, 0, 0b000) => (FmvXF, moves),
+                    (0b11100, 0, 0b001) => (Fclass, arithmetic),
+                    (0b11110, 0, 0b000) => (FmvFX, moves),
+                    (funct5 @ (0b11000 | 0b11010), 0..=3, _) => {
+                        let signed = rs2 & 1 == 0;
+                        let bits = if rs2 < 2 { 32 } else { 64 };
+                        let kind = match funct5 {
+                            0b11000 => FcvtXF { signed, bits },
+                            _ => FcvtFX { signed, bits },
+                        };
+                        (kind, arithmetic && rounding && (bits == 32 || rv64))
+                    }
+                    _ => None?,
+                };
+                if !legal {
+                    None?
+                }
+
+                Self::FpType {
+                    rd,
+                    rs1,
+                    rs2: FRs2::decode_raw32(raw32),
+                    rm,
+                    fmt,
+                    kind,
+                }
+            }
+
+            _ => None?,
+        };
+
+        Some(result)
+    }
+
+    /// Encodes the LOAD-FP, STORE-FP, fused multiply-add and OP-FP major opcodes.
+    pub(super) fn encode_fp(&self) -> u32 {
+        use FpKind::*;
+
+        let reg = |reg: u8| reg as u32 & 0x1f;
+        match *self {
+            Self::FLoadType { rd, rs1, i, fmt } => {
+                i.encode_raw32()
+                    | reg(rs1 as u8) << 15
+                    | fmt.width() << 12
+                    | reg(rd as u8) << 7
+                    | 0b0000111
+            }
+            Self::FStoreType { rs1, rs2, s, fmt } => {
+                s.encode_raw32()
+                    | reg(rs2 as u8) << 20
+                    | reg(rs1 as u8) << 15
+                    | fmt.width() << 12
+                    | 0b0100111
+            }
+            Self::FmaType {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+                fmt,
+                kind,
+            } => {
+                let opcode = match kind {
+                    FmaKind::Fmadd => 0b1000011,
+                    FmaKind::Fmsub => 0b1000111,
+                    FmaKind::Fnmsub => 0b1001011,
+                    FmaKind::Fnmadd => 0b1001111,
+                };
+                reg(rs3 as u8) << 27
+                    | fmt.encode() << 25
+                    | reg(rs2 as u8) << 20
+                    | reg(rs1 as u8) << 15
+                    | (rm as u32 & 0b111) << 12
+                    | reg(rd as u8) << 7
+                    | opcode
+            }
+            Self::FpType {
+                rd,
+                rs1,
+                rs2,
+                rm,
+                fmt,
+                kind,
+            } => {
+                // NOTE: `None` stands for the rounding mode, and conversions select their formats
+                //       with `rs2`.
+                let (funct5, rs2, funct3) = match kind {
+                    Fadd => (0b00000, reg(rs2 as u8), None),
+                    Fsub => (0b00001, reg(rs2 as u8), None),
+                    Fmul => (0b00010, reg(rs2 as u8), None),
+                    Fdiv => (0b00011, reg(rs2 as u8), None),
+                    Fsqrt => (0b01011, 0, None),
+                    Fsgnj => (0b00100, reg(rs2 as u8), Some(0b000)),
+                    Fsgnjn => (0b00100, reg(rs2 as u8), Some(0b001)),
+                    Fsgnjx => (0b00100, reg(rs2 as u8), Some(0b010)),
+                    Fmin => (0b00101, reg(rs2 as u8), Some(0b000)),
+                    Fmax => (0b00101, reg(rs2 as u8), Some(0b001)),
+                    Fcvt { from } => (0b01000, from.encode(), None),
+                    FcvtBf16S => (0b01000, 0b01000, None),
+                    FcvtSBf16 => (0b01000, 0b00110, None),
+                    Fle => (0b10100, reg(rs2 as u8), Some(0b000)),
+                    Flt => (0b10100, reg(rs2 as u8), Some(0b001)),
+                    Feq => (0b10100, reg(rs2 as u8), Some(0b010)),
+                    FmvXF => (0b11100, 0, Some(0b000)),
+                    Fclass => (0b11100, 0, Some(0b001)),
+                    FmvFX => (0b11110, 0, Some(0b000)),
+                    FcvtXF { signed, bits } => {
+                        (0b11000, (bits == 64) as u32 * 2 + !signed as u32, None)
+                    }
+                    FcvtFX { signed, bits } => {
+                        (0b11010, (bits == 64) as u32 * 2 + !signed as u32, None)
+                    }
+                };
+                let funct3 = funct3.unwrap_or(rm as u32 & 0b111);
+                funct5 << 27
+                    | fmt.encode() << 25
+                    | rs2 << 20
+                    | reg(rs1) << 15
+                    | funct3 << 12
+                    | reg(rd) << 7
+                    | 0b1010011
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -24,6 +24,17 @@ impl ITypeImmediate {
     pub fn i32(&self) -> i32 {
         self.value as i32
     }
+
+    /// Returns `None` unless `value` fits in 12 bits.
+    pub fn checked_from_i32(value: i32) -> Option<Self> {
+        (-0x800..0x800).contains(&value).then_some(Self {
+            value: value as i16,
+        })
+    }
+
+    pub fn encode_raw32(&self) -> u32 {
+        (self.value as u32 & 0xfff) << 20
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -47,6 +58,19 @@ impl STypeImmediate {
     pub fn i32(&self) -> i32 {
         self.value as i32
     }
+
+    /// Returns `None` unless `value` fits in 12 bits.
+    pub fn checked_from_i32(value: i32) -> Option<Self> {
+        (-0x800..0x800).contains(&value).then_some(Self {
+            value: value as i16,
+        })
+    }
+
+    pub fn encode_raw32(&self) -> u32 {
+        let value = self.value as u32;
+        let parts = [value << 20 & 0xfe000000, value << 7 & 0x00000f80];
+        parts.into_iter().fold(0, |cur, part| cur | part)
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -71,6 +95,25 @@ impl BTypeImmediate {
     pub fn i32(&self) -> i32 {
         self.value as i32
     }
+
+    /// Returns `None` unless `value` is even and fits in 13 bits.
+    pub fn checked_from_i32(value: i32) -> Option<Self> {
+        let fits = (-0x1000..0x1000).contains(&value) && value & 1 == 0;
+        fits.then_some(Self {
+            value: value as i16,
+        })
+    }
+
+    pub fn encode_raw32(&self) -> u32 {
+        let value = self.value as u32;
+        let parts = [
+            value << 19 & 0x80000000,
+            value << 20 & 0x7e000000,
+            value << 7 & 0x00000f00,
+            value >> 4 & 0x00000080,
+        ];
+        parts.into_iter().fold(0, |cur, part| cur | part)
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -91,6 +134,15 @@ impl UTypeImmediate {
     pub fn i32(&self) -> i32 {
         self.value
     }
+
+    /// Returns `None` unless the 12 least significant bits of `value` are 0.
+    pub fn checked_from_i32(value: i32) -> Option<Self> {
+        (value & 0xfff == 0).then_some(Self { value })
+    }
+
+    pub fn encode_raw32(&self) -> u32 {
+        self.value as u32
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -115,6 +167,23 @@ impl JTypeImmediate {
     pub fn i32(&self) -> i32 {
         self.value
     }
+
+    /// Returns `None` unless `value` is even and fits in 21 bits.
+    pub fn checked_from_i32(value: i32) -> Option<Self> {
+        let fits = (-0x100000..0x100000).contains(&value) && value & 1 == 0;
+        fits.then_some(Self { value })
+    }
+
+    pub fn encode_raw32(&self) -> u32 {
+        let value = self.value as u32;
+        let parts = [
+            value << 11 & 0x80000000,
+            value << 20 & 0x7fe00000,
+            value << 9 & 0x00100000,
+            value & 0x000ff000,
+        ];
+        parts.into_iter().fold(0, |cur, part| cur | part)
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -137,6 +206,10 @@ impl FenceFlags {
         Self { flags }
     }
 
+    pub fn encode_raw32(&self) -> u32 {
+        (self.flags as u32) << 20
+    }
+
     pub fn pi(&self) -> bool {
         self.flags & Self::PI != 0
     }
@@ -183,23 +256,33 @@ impl FenceMode {
 
 #[derive(Clone, Copy, Debug)]
 pub struct FenceInfo {
-    mode: FenceMode,
+    /// The `fm` field, kept whole so reserved modes survive re-encoding
+    fm: u8,
     flags: FenceFlags,
 }
 
 impl FenceInfo {
     pub fn decode_raw32(raw32: u32) -> Self {
-        let mode = FenceMode::decode_raw32(raw32);
+        let fm = (raw32 >> 28) as u8;
         let flags = FenceFlags::decode_raw32(raw32);
-        Self { mode, flags }
+        Self { fm, flags }
+    }
+
+    pub fn encode_raw32(&self) -> u32 {
+        (self.fm as u32) << 28 | self.flags.encode_raw32()
     }
 
     pub fn mode(&self) -> FenceMode {
-        self.mode
+        FenceMode::decode_raw32((self.fm as u32) << 28)
     }
     pub fn flags(&self) -> FenceFlags {
         self.flags
     }
+
+    /// Whether this is the encoding of `pause`, as long as `rd` and `rs1` are `x0`.
+    pub fn is_pause(&self) -> bool {
+        matches!(self.mode(), FenceMode::None) && self.flags.flags == FenceFlags::PW
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -218,6 +301,10 @@ impl AmoAqrl {
         Self { flags }
     }
 
+    pub fn encode_raw32(&self) -> u32 {
+        ((self.flags & (Self::AQ | Self::RL)) as u32) << 25
+    }
+
     pub fn aq(&self) -> bool {
         self.flags & AmoAqrl::AQ != 0
     }
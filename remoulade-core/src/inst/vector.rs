@@ -0,0 +1,957 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Decoding of the vector extension.
+//!
+//! Vector registers are identified by their number, 0-31.
+//!
+//! `vfrec7.v` and `vfrsqrt7.v` aren't implemented and decode as illegal instructions.
+
+use crate::{
+    ext::Extensions,
+    freg::{FRegType, FRs1},
+    inst::{Fields32, Instruction},
+    reg::{IRs1, IRs2, RegType},
+};
+
+#[derive(Copy, Clone, Debug)]
+pub enum VsetKind {
+    Vsetvli { rs1: IRs1, vtypei: u32 },
+    Vsetivli { uimm: u32, vtypei: u32 },
+    Vsetvl { rs1: IRs1, rs2: IRs2 },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VAddressing {
+    Unit,
+    /// Unit-stride, trimming `vl` instead of trapping on faults past the first element
+    UnitFaultFirst,
+    /// Whole registers, ignoring `vtype` and `vl`
+    Whole,
+    /// `vlm.v`/`vsm.v`
+    Mask,
+    Strided {
+        rs2: IRs2,
+    },
+    /// Indexed, where `eew` is the width of the indices in `vs2`
+    ///
+    /// Accesses happen in element order, so ordered and unordered variants behave the same.
+    Indexed {
+        vs2: u8,
+        ordered: bool,
+    },
+}
+
+/// Vector loads and stores.
+#[derive(Copy, Clone, Debug)]
+pub struct VMem {
+    pub store: bool,
+    pub addressing: VAddressing,
+    /// Width of the elements in memory in bits
+    pub eew: u32,
+    /// Number of fields per segment, or registers for whole-register accesses
+    pub nf: u8,
+}
+
+/// Second source operand of vector arithmetic.
+#[derive(Copy, Clone, Debug)]
+pub enum VSrc {
+    Vector(u8),
+    Scalar(IRs1),
+    /// `simm5` or `uimm5`, depending on the instruction
+    Imm(i32),
+    Float(FRs1),
+}
+
+/// The operand categories of OP-V, which share their `funct6` encodings.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Category {
+    Opi,
+    Opm,
+    Opf,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VKind {
+    // OPI
+    Vadd,
+    Vsub,
+    Vrsub,
+    Vminu,
+    Vmin,
+    Vmaxu,
+    Vmax,
+    Vand,
+    Vor,
+    Vxor,
+    Vrgather,
+    Vslideup,
+    Vslidedown,
+    Vmerge,
+    Vmv,
+    Vmseq,
+    Vmsne,
+    Vmsltu,
+    Vmslt,
+    Vmsleu,
+    Vmsle,
+    Vmsgtu,
+    Vmsgt,
+    Vsaddu,
+    Vsadd,
+    Vssubu,
+    Vssub,
+    Vsll,
+    Vsmul,
+    /// `vmv<nr>r.v`, with `nr - 1` in the immediate
+    Vmvr,
+    Vsrl,
+    Vsra,
+    Vssrl,
+    Vssra,
+    /// Writes `vd` using `v0` as the carry in
+    Vadc,
+    /// Takes `v0` as the carry in when masked
+    Vmadc,
+    /// Writes `vd` using `v0` as the borrow in
+    Vsbc,
+    /// Takes `v0` as the borrow in when masked
+    Vmsbc,
+    Vrgatherei16,
+    Vnsrl,
+    Vnsra,
+    Vnclipu,
+    Vnclip,
+    Vwredsumu,
+    Vwredsum,
+
+    // OPM
+    Vredsum,
+    Vredand,
+    Vredor,
+    Vredxor,
+    Vredminu,
+    Vredmin,
+    Vredmaxu,
+    Vredmax,
+    Vaaddu,
+    Vaadd,
+    Vasubu,
+    Vasub,
+    Vslide1up,
+    Vslide1down,
+    /// Writes `x[vd]`
+    VmvXS,
+    VmvSX,
+    /// Writes `x[vd]`
+    Vcpop,
+    /// Writes `x[vd]`
+    Vfirst,
+    Vid,
+    Vmandn,
+    Vmand,
+    Vmor,
+    Vmxor,
+    Vmorn,
+    Vmnand,
+    Vmnor,
+    Vmxnor,
+    Vdivu,
+    Vdiv,
+    Vremu,
+    Vrem,
+    Vmulhu,
+    Vmul,
+    Vmulhsu,
+    Vmulh,
+    Vmadd,
+    Vnmsub,
+    Vmacc,
+    Vnmsac,
+    Vwaddu,
+    Vwadd,
+    Vwsubu,
+    Vwsub,
+    VwadduW,
+    VwaddW,
+    VwsubuW,
+    VwsubW,
+    Vwmulu,
+    Vwmulsu,
+    Vwmul,
+    Vwmaccu,
+    Vwmacc,
+    Vwmaccus,
+    Vwmaccsu,
+    VzextVf8,
+    VsextVf8,
+    VzextVf4,
+    VsextVf4,
+    VzextVf2,
+    VsextVf2,
+    Vcompress,
+    Vmsbf,
+    Vmsof,
+    Vmsif,
+    Viota,
+
+    // OPF
+    Vfadd,
+    Vfredusum,
+    Vfsub,
+    Vfredosum,
+    Vfmin,
+    Vfredmin,
+    Vfmax,
+    Vfredmax,
+    Vfsgnj,
+    Vfsgnjn,
+    Vfsgnjx,
+    Vfslide1up,
+    Vfslide1down,
+    /// Writes `f[vd]`
+    VfmvFS,
+    VfmvSF,
+    Vfmerge,
+    Vfmv,
+    Vmfeq,
+    Vmfle,
+    Vmflt,
+    Vmfne,
+    Vmfgt,
+    Vmfge,
+    Vfdiv,
+    Vfrdiv,
+    Vfmul,
+    Vfrsub,
+    Vfmadd,
+    Vfnmadd,
+    Vfmsub,
+    Vfnmsub,
+    Vfmacc,
+    Vfnmacc,
+    Vfmsac,
+    Vfnmsac,
+    Vfwadd,
+    Vfwredusum,
+    Vfwsub,
+    Vfwredosum,
+    VfwaddW,
+    VfwsubW,
+    Vfwmul,
+    Vfwmacc,
+    Vfwnmacc,
+    Vfwmsac,
+    Vfwnmsac,
+    VfcvtXuFV,
+    VfcvtXFV,
+    VfcvtFXuV,
+    VfcvtFXV,
+    VfcvtRtzXuFV,
+    VfcvtRtzXFV,
+    VfwcvtXuFV,
+    VfwcvtXFV,
+    VfwcvtFXuV,
+    VfwcvtFXV,
+    VfwcvtFFV,
+    VfwcvtRtzXuFV,
+    VfwcvtRtzXFV,
+    VfncvtXuFW,
+    VfncvtXFW,
+    VfncvtFXuW,
+    VfncvtFXW,
+    VfncvtFFW,
+    VfncvtRodFFW,
+    VfncvtRtzXuFW,
+    VfncvtRtzXFW,
+    Vfsqrt,
+    Vfclass,
+}
+
+impl VKind {
+    /// Whether the result is a mask rather than `SEW`-wide elements.
+    pub fn writes_mask(self) -> bool {
+        use VKind::*;
+        matches!(
+            self,
+            Vmseq
+                | Vmsne
+                | Vmsltu
+                | Vmslt
+                | Vmsleu
+                | Vmsle
+                | Vmsgtu
+                | Vmsgt
+                | Vmfeq
+                | Vmfle
+                | Vmflt
+                | Vmfne
+                | Vmfgt
+                | Vmfge
+                | Vmadc
+                | Vmsbc
+                | Vmsbf
+                | Vmsof
+                | Vmsif
+                | Vmandn
+                | Vmand
+                | Vmor
+                | Vmxor
+                | Vmorn
+                | Vmnand
+                | Vmnor
+                | Vmxnor
+        )
+    }
+
+    /// Whether `vd` is a scalar register rather than a vector register.
+    pub fn writes_scalar(self) -> bool {
+        matches!(
+            self,
+            VKind::VmvXS | VKind::Vcpop | VKind::Vfirst | VKind::VfmvFS
+        )
+    }
+
+    /// Whether the elements are floating point, as they are for every OPF instruction.
+    pub fn float(self) -> bool {
+        self.fields().1 == Category::Opf
+    }
+
+    /// The `vs1` selecting this instruction, for unary instructions sharing a `funct6`.
+    pub fn selector(self) -> Option<u32> {
+        use VKind::*;
+
+        let vs1 = match self {
+            VmvXS | VfmvFS => 0b00000,
+            Vcpop => 0b10000,
+            Vfirst | Vid => 0b10001,
+            VzextVf8 => 0b00010,
+            VsextVf8 => 0b00011,
+            VzextVf4 => 0b00100,
+            VsextVf4 => 0b00101,
+            VzextVf2 => 0b00110,
+            VsextVf2 => 0b00111,
+            Vmsbf => 0b00001,
+            Vmsof => 0b00010,
+            Vmsif => 0b00011,
+            Viota => 0b10000,
+            VfcvtXuFV => 0b00000,
+            VfcvtXFV => 0b00001,
+            VfcvtFXuV => 0b00010,
+            VfcvtFXV => 0b00011,
+            VfcvtRtzXuFV => 0b00110,
+            VfcvtRtzXFV => 0b00111,
+            VfwcvtXuFV => 0b01000,
+            VfwcvtXFV => 0b01001,
+            VfwcvtFXuV => 0b01010,
+            VfwcvtFXV => 0b01011,
+            VfwcvtFFV => 0b01100,
+            VfwcvtRtzXuFV => 0b01110,
+            VfwcvtRtzXFV => 0b01111,
+            VfncvtXuFW => 0b10000,
+            VfncvtXFW => 0b10001,
+            VfncvtFXuW => 0b10010,
+            VfncvtFXW => 0b10011,
+            VfncvtFFW => 0b10100,
+            VfncvtRodFFW => 0b10101,
+            VfncvtRtzXuFW => 0b10110,
+            VfncvtRtzXFW => 0b10111,
+            Vfsqrt => 0b00000,
+            Vfclass => 0b10000,
+            _ => None?,
+        };
+        Some(vs1)
+    }
+
+    /// The `funct6` of this kind, and whether it belongs to OPI, OPM or OPF.
+    fn fields(self) -> (u32, Category) {
+        use Category::*;
+        use VKind::*;
+
+        match self {
+            Vadd => (0b000000, Opi),
+            Vsub => (0b000010, Opi),
+            Vrsub => (0b000011, Opi),
+            Vminu => (0b000100, Opi),
+            Vmin => (0b000101, Opi),
+            Vmaxu => (0b000110, Opi),
+            Vmax => (0b000111, Opi),
+            Vand => (0b001001, Opi),
+            Vor => (0b001010, Opi),
+            Vxor => (0b001011, Opi),
+            Vrgather => (0b001100, Opi),
+            Vslideup => (0b001110, Opi),
+            Vslidedown => (0b001111, Opi),
+            Vmerge | Vmv => (0b010111, Opi),
+            Vmseq => (0b011000, Opi),
+            Vmsne => (0b011001, Opi),
+            Vmsltu => (0b011010, Opi),
+            Vmslt => (0b011011, Opi),
+            Vmsleu => (0b011100, Opi),
+            Vmsle => (0b011101, Opi),
+            Vmsgtu => (0b011110, Opi),
+            Vmsgt => (0b011111, Opi),
+            Vsaddu => (0b100000, Opi),
+            Vsadd => (0b100001, Opi),
+            Vssubu => (0b100010, Opi),
+            Vssub => (0b100011, Opi),
+            Vsll => (0b100101, Opi),
+            Vsmul | Vmvr => (0b100111, Opi),
+            Vsrl => (0b101000, Opi),
+            Vsra => (0b101001, Opi),
+            Vssrl => (0b101010, Opi),
+            Vssra => (0b101011, Opi),
+            Vadc => (0b010000, Opi),
+            Vmadc => (0b010001, Opi),
+            Vsbc => (0b010010, Opi),
+            Vmsbc => (0b010011, Opi),
+            Vrgatherei16 => (0b001110, Opi),
+            Vnsrl => (0b101100, Opi),
+            Vnsra => (0b101101, Opi),
+            Vnclipu => (0b101110, Opi),
+            Vnclip => (0b101111, Opi),
+            Vwredsumu => (0b110000, Opi),
+            Vwredsum => (0b110001, Opi),
+
+            Vredsum => (0b000000, Opm),
+            Vredand => (0b000001, Opm),
+            Vredor => (0b000010, Opm),
+            Vredxor => (0b000011, Opm),
+            Vredminu => (0b000100, Opm),
+            Vredmin => (0b000101, Opm),
+            Vredmaxu => (0b000110, Opm),
+            Vredmax => (0b000111, Opm),
+            Vaaddu => (0b001000, Opm),
+            Vaadd => (0b001001, Opm),
+            Vasubu => (0b001010, Opm),
+            Vasub => (0b001011, Opm),
+            Vslide1up => (0b001110, Opm),
+            Vslide1down => (0b001111, Opm),
+            VmvXS | VmvSX | Vcpop | Vfirst => (0b010000, Opm),
+            Vid | Vmsbf | Vmsof | Vmsif | Viota => (0b010100, Opm),
+            Vmandn => (0b011000, Opm),
+            Vmand => (0b011001, Opm),
+            Vmor => (0b011010, Opm),
+            Vmxor => (0b011011, Opm),
+            Vmorn => (0b011100, Opm),
+            Vmnand => (0b011101, Opm),
+            Vmnor => (0b011110, Opm),
+            Vmxnor => (0b011111, Opm),
+            Vdivu => (0b100000, Opm),
+            Vdiv => (0b100001, Opm),
+            Vremu => (0b100010, Opm),
+            Vrem => (0b100011, Opm),
+            Vmulhu => (0b100100, Opm),
+            Vmul => (0b100101, Opm),
+            Vmulhsu => (0b100110, Opm),
+            Vmulh => (0b100111, Opm),
+            Vmadd => (0b101001, Opm),
+            Vnmsub => (0b101011, Opm),
+            Vmacc => (0b101101, Opm),
+            Vnmsac => (0b101111, Opm),
+            Vwaddu => (0b110000, Opm),
+            Vwadd => (0b110001, Opm),
+            Vwsubu => (0b110010, Opm),
+            Vwsub => (0b110011, Opm),
+            VwadduW => (0b110100, Opm),
+            VwaddW => (0b110101, Opm),
+            VwsubuW => (0b110110, Opm),
+            VwsubW => (0b110111, Opm),
+            Vwmulu => (0b111000, Opm),
+            Vwmulsu => (0b111010, Opm),
+            Vwmul => (0b111011, Opm),
+            Vwmaccu => (0b111100, Opm),
+            Vwmacc => (0b111101, Opm),
+            Vwmaccus => (0b111110, Opm),
+            Vwmaccsu => (0b111111, Opm),
+            VzextVf8 | VsextVf8 | VzextVf4 | VsextVf4 | VzextVf2 | VsextVf2 => (0b010010, Opm),
+            Vcompress => (0b010111, Opm),
+
+            Vfadd => (0b000000, Opf),
+            Vfredusum => (0b000001, Opf),
+            Vfsub => (0b000010, Opf),
+            Vfredosum => (0b000011, Opf),
+            Vfmin => (0b000100, Opf),
+            Vfredmin => (0b000101, Opf),
+            Vfmax => (0b000110, Opf),
+            Vfredmax => (0b000111, Opf),
+            Vfsgnj => (0b001000, Opf),
+            Vfsgnjn => (0b001001, Opf),
+            Vfsgnjx => (0b001010, Opf),
+            Vfslide1up => (0b001110, Opf),
+            Vfslide1down => (0b001111, Opf),
+            VfmvFS | VfmvSF => (0b010000, Opf),
+            Vfmerge | Vfmv => (0b010111, Opf),
+            Vmfeq => (0b011000, Opf),
+            Vmfle => (0b011001, Opf),
+            Vmflt => (0b011011, Opf),
+            Vmfne => (0b011100, Opf),
+            Vmfgt => (0b011101, Opf),
+            Vmfge => (0b011111, Opf),
+            Vfdiv => (0b100000, Opf),
+            Vfrdiv => (0b100001, Opf),
+            Vfmul => (0b100100, Opf),
+            Vfrsub => (0b100111, Opf),
+            Vfmadd => (0b101000, Opf),
+            Vfnmadd => (0b101001, Opf),
+            Vfmsub => (0b101010, Opf),
+            Vfnmsub => (0b101011, Opf),
+            Vfmacc => (0b101100, Opf),
+            Vfnmacc => (0b101101, Opf),
+            Vfmsac => (0b101110, Opf),
+            Vfnmsac => (0b101111, Opf),
+            Vfwadd => (0b110000, Opf),
+            Vfwredusum => (0b110001, Opf),
+            Vfwsub => (0b110010, Opf),
+            Vfwredosum => (0b110011, Opf),
+            VfwaddW => (0b110100, Opf),
+            VfwsubW => (0b110110, Opf),
+            Vfwmul => (0b111000, Opf),
+            Vfwmacc => (0b111100, Opf),
+            Vfwnmacc => (0b111101, Opf),
+            Vfwmsac => (0b111110, Opf),
+            Vfwnmsac => (0b111111, Opf),
+            VfcvtXuFV | VfcvtXFV | VfcvtFXuV | VfcvtFXV | VfcvtRtzXuFV | VfcvtRtzXFV => {
+                (0b010010, Opf)
+            }
+            VfwcvtXuFV | VfwcvtXFV | VfwcvtFXuV | VfwcvtFXV | VfwcvtFFV | VfwcvtRtzXuFV
+            | VfwcvtRtzXFV => (0b010010, Opf),
+            VfncvtXuFW | VfncvtXFW | VfncvtFXuW | VfncvtFXW | VfncvtFFW | VfncvtRodFFW
+            | VfncvtRtzXuFW | VfncvtRtzXFW => (0b010010, Opf),
+            Vfsqrt | Vfclass => (0b010011, Opf),
+        }
+    }
+}
+
+impl<I: RegType, const E: bool, const M: bool, const A: bool, F: FRegType, X: Extensions>
+    Instruction<I, E, M, A, F, X>
+{
+    /// Decodes the OP-V major opcode.
+    pub(super) fn decode_opv(raw32: u32) -> Option<Self> {
+        use VKind::*;
+
+        let vd = (raw32 >> 7 & 0x1f) as u8;
+        let vs1 = (raw32 >> 15 & 0x1f) as u8;
+        let vs2 = (raw32 >> 20 & 0x1f) as u8;
+        let vm = raw32 >> 25 & 1 == 1;
+        let funct6 = raw32 >> 26;
+
+        // Operand categories
+        const IVV: u32 = 0b000;
+        const FVV: u32 = 0b001;
+        const MVV: u32 = 0b010;
+        const IVI: u32 = 0b011;
+        const IVX: u32 = 0b100;
+        const FVF: u32 = 0b101;
+        const MVX: u32 = 0b110;
+        const CFG: u32 = 0b111;
+
+        let funct3 = raw32.funct3();
+        if funct3 == CFG {
+            let rd = raw32.rd();
+            let kind = match raw32 >> 30 {
+                0b00 | 0b01 => VsetKind::Vsetvli {
+                    rs1: raw32.rs1(),
+                    vtypei: raw32 >> 20 & 0x7ff,
+                },
+                0b11 => VsetKind::Vsetivli {
+                    uimm: vs1 as u32,
+                    vtypei: raw32 >> 20 & 0x3ff,
+                },
+                _ if funct6 == 0b100000 && !vm => VsetKind::Vsetvl {
+                    rs1: raw32.rs1(),
+                    rs2: raw32.rs2(),
+                },
+                _ => None?,
+            };
+            return Some(Self::VsetType { rd, kind });
+        }
+
+        let kind = match (funct3, funct6) {
+            (IVV | IVX | IVI, 0b000000) => Vadd,
+            (IVV | IVX, 0b000010) => Vsub,
+            (IVX | IVI, 0b000011) => Vrsub,
+            (IVV | IVX, 0b000100) => Vminu,
+            (IVV | IVX, 0b000101) => Vmin,
+            (IVV | IVX, 0b000110) => Vmaxu,
+            (IVV | IVX, 0b000111) => Vmax,
+            (IVV | IVX | IVI, 0b001001) => Vand,
+            (IVV | IVX | IVI, 0b001010) => Vor,
+            (IVV | IVX | IVI, 0b001011) => Vxor,
+            (IVV | IVX | IVI, 0b001100) => Vrgather,
+            (IVX | IVI, 0b001110) => Vslideup,
+            (IVX | IVI, 0b001111) => Vslidedown,
+            (IVV | IVX | IVI, 0b010111) if !vm => Vmerge,
+            (IVV | IVX | IVI, 0b010111) if vs2 == 0 => Vmv,
+            (IVV | IVX | IVI, 0b011000) => Vmseq,
+            (IVV | IVX | IVI, 0b011001) => Vmsne,
+            (IVV | IVX, 0b011010) => Vmsltu,
+            (IVV | IVX, 0b011011) => Vmslt,
+            (IVV | IVX | IVI, 0b011100) => Vmsleu,
+            (IVV | IVX | IVI, 0b011101) => Vmsle,
+            (IVX | IVI, 0b011110) => Vmsgtu,
+            (IVX | IVI, 0b011111) => Vmsgt,
+            (IVV | IVX | IVI, 0b100000) => Vsaddu,
+            (IVV | IVX | IVI, 0b100001) => Vsadd,
+            (IVV | IVX, 0b100010) => Vssubu,
+            (IVV | IVX, 0b100011) => Vssub,
+            (IVV | IVX | IVI, 0b100101) => Vsll,
+            (IVV | IVX, 0b100111) => Vsmul,
+            (IVI, 0b100111) if vm && matches!(vs1, 0 | 1 | 3 | 7) => Vmvr,
+            (IVV | IVX | IVI, 0b101000) => Vsrl,
+            (IVV | IVX | IVI, 0b101001) => Vsra,
+            (IVV | IVX | IVI, 0b101010) => Vssrl,
+            (IVV | IVX | IVI, 0b101011) => Vssra,
+            (IVV | IVX | IVI, 0b010000) if !vm => Vadc,
+            (IVV | IVX | IVI, 0b010001) => Vmadc,
+            (IVV | IVX, 0b010010) if !vm => Vsbc,
+            (IVV | IVX, 0b010011) => Vmsbc,
+            (IVV, 0b001110) => Vrgatherei16,
+            (IVV | IVX | IVI, 0b101100) => Vnsrl,
+            (IVV | IVX | IVI, 0b101101) => Vnsra,
+            (IVV | IVX | IVI, 0b101110) => Vnclipu,
+            (IVV | IVX | IVI, 0b101111) => Vnclip,
+            (IVV, 0b110000) => Vwredsumu,
+            (IVV, 0b110001) => Vwredsum,
+
+            (MVV, 0b000000) => Vredsum,
+            (MVV, 0b000001) => Vredand,
+            (MVV, 0b000010) => Vredor,
+            (MVV, 0b000011) => Vredxor,
+            (MVV, 0b000100) => Vredminu,
+            (MVV, 0b000101) => Vredmin,
+            (MVV, 0b000110) => Vredmaxu,
+            (MVV, 0b000111) => Vredmax,
+            (MVV | MVX, 0b001000) => Vaaddu,
+            (MVV | MVX, 0b001001) => Vaadd,
+            (MVV | MVX, 0b001010) => Vasubu,
+            (MVV | MVX, 0b001011) => Vasub,
+            (MVX, 0b001110) => Vslide1up,
+            (MVX, 0b001111) => Vslide1down,
+            (MVV, 0b010000) => match vs1 {
+                0b00000 if vm => VmvXS,
+                0b10000 => Vcpop,
+                0b10001 => Vfirst,
+                _ => None?,
+            },
+            (MVX, 0b010000) if vm && vs2 == 0 => VmvSX,
+            (MVV, 0b010010) => match vs1 {
+                0b00010 => VzextVf8,
+                0b00011 => VsextVf8,
+                0b00100 => VzextVf4,
+                0b00101 => VsextVf4,
+                0b00110 => VzextVf2,
+                0b00111 => VsextVf2,
+                _ => None?,
+            },
+            (MVV, 0b010100) => match vs1 {
+                0b00001 => Vmsbf,
+                0b00010 => Vmsof,
+                0b00011 => Vmsif,
+                0b10000 => Viota,
+                0b10001 if vs2 == 0 => Vid,
+                _ => None?,
+            },
+            (MVV, 0b010111) if vm => Vcompress,
+            (MVV, 0b011000) if vm => Vmandn,
+            (MVV, 0b011001) if vm => Vmand,
+            (MVV, 0b011010) if vm => Vmor,
+            (MVV, 0b011011) if vm => Vmxor,
+            (MVV, 0b011100) if vm => Vmorn,
+            (MVV, 0b011101) if vm => Vmnand,
+            (MVV, 0b011110) if vm => Vmnor,
+            (MVV, 0b011111) if vm => Vmxnor,
+            (MVV | MVX, 0b100000) => Vdivu,
+            (MVV | MVX, 0b100001) => Vdiv,
+            (MVV | MVX, 0b100010) => Vremu,
+            (MVV | MVX, 0b100011) => Vrem,
+            (MVV | MVX, 0b100100) => Vmulhu,
+            (MVV | MVX, 0b100101) => Vmul,
+            (MVV | MVX, 0b100110) => Vmulhsu,
+            (MVV | MVX, 0b100111) => Vmulh,
+            (MVV | MVX, 0b101001) => Vmadd,
+            (MVV | MVX, 0b101011) => Vnmsub,
+            (MVV | MVX, 0b101101) => Vmacc,
+            (MVV | MVX, 0b101111) => Vnmsac,
+            (MVV | MVX, 0b110000) => Vwaddu,
+            (MVV | MVX, 0b110001) => Vwadd,
+            (MVV | MVX, 0b110010) => Vwsubu,
+            (MVV | MVX, 0b110011) => Vwsub,
+            (MVV | MVX, 0b110100) => VwadduW,
+            (MVV | MVX, 0b110101) => VwaddW,
+            (MVV | MVX, 0b110110) => VwsubuW,
+            (MVV | MVX, 0b110111) => VwsubW,
+            (MVV | MVX, 0b111000) => Vwmulu,
+            (MVV | MVX, 0b111010) => Vwmulsu,
+            (MVV | MVX, 0b111011) => Vwmul,
+            (MVV | MVX, 0b111100) => Vwmaccu,
+            (MVV | MVX, 0b111101) => Vwmacc,
+            (MVX, 0b111110) => Vwmaccus,
+            (MVV | MVX, 0b111111) => Vwmaccsu,
+
+            (FVV | FVF, 0b000000) => Vfadd,
+            (FVV, 0b000001) => Vfredusum,
+            (FVV | FVF, 0b000010) => Vfsub,
+            (FVV, 0b000011) => Vfredosum,
+            (FVV | FVF, 0b000100) => Vfmin,
+            (FVV, 0b000101) => Vfredmin,
+            (FVV | FVF, 0b000110) => Vfmax,
+            (FVV, 0b000111) => Vfredmax,
+            (FVV | FVF, 0b001000) => Vfsgnj,
+            (FVV | FVF, 0b001001) => Vfsgnjn,
+            (FVV | FVF, 0b001010) => Vfsgnjx,
+            (FVF, 0b001110) => Vfslide1up,
+            (FVF, 0b001111) => Vfslide1down,
+            (FVV, 0b010000) if vm && vs1 == 0 => VfmvFS,
+            (FVF, 0b010000) if vm && vs2 == 0 => VfmvSF,
+            (FVF, 0b010111) if !vm => Vfmerge,
+            (FVF, 0b010111) if vs2 == 0 => Vfmv,
+            (FVV | FVF, 0b011000) => Vmfeq,
+            (FVV | FVF, 0b011001) => Vmfle,
+            (FVV | FVF, 0b011011) => Vmflt,
+            (FVV | FVF, 0b011100) => Vmfne,
+            (FVF, 0b011101) => Vmfgt,
+            (FVF, 0b011111) => Vmfge,
+            (FVV | FVF, 0b100000) => Vfdiv,
+            (FVF, 0b100001) => Vfrdiv,
+            (FVV | FVF, 0b100100) => Vfmul,
+            (FVF, 0b100111) => Vfrsub,
+            (FVV | FVF, 0b101000) => Vfmadd,
+            (FVV | FVF, 0b101001) => Vfnmadd,
+            (FVV | FVF, 0b101010) => Vfmsub,
+            (FVV | FVF, 0b101011) => Vfnmsub,
+            (FVV | FVF, 0b101100) => Vfmacc,
+            (FVV | FVF, 0b101101) => Vfnmacc,
+            (FVV | FVF, 0b101110) => Vfmsac,
+            (FVV | FVF, 0b101111) => Vfnmsac,
+            (FVV | FVF, 0b110000) => Vfwadd,
+            (FVV, 0b110001) => Vfwredusum,
+            (FVV | FVF, 0b110010) => Vfwsub,
+            (FVV, 0b110011) => Vfwredosum,
+            (FVV | FVF, 0b110100) => VfwaddW,
+            (FVV | FVF, 0b110110) => VfwsubW,
+            (FVV | FVF, 0b111000) => Vfwmul,
+            (FVV | FVF, 0b111100) => Vfwmacc,
+            (FVV | FVF, 0b111101) => Vfwnmacc,
+            (FVV | FVF, 0b111110) => Vfwmsac,
+            (FVV | FVF, 0b111111) => Vfwnmsac,
+            (FVV, 0b010010) => match vs1 {
+                0b00000 => VfcvtXuFV,
+                0b00001 => VfcvtXFV,
+                0b00010 => VfcvtFXuV,
+                0b00011 => VfcvtFXV,
+                0b00110 => VfcvtRtzXuFV,
+                0b00111 => VfcvtRtzXFV,
+                0b01000 => VfwcvtXuFV,
+                0b01001 => VfwcvtXFV,
+                0b01010 => VfwcvtFXuV,
+                0b01011 => VfwcvtFXV,
+                0b01100 => VfwcvtFFV,
+                0b01110 => VfwcvtRtzXuFV,
+                0b01111 => VfwcvtRtzXFV,
+                0b10000 => VfncvtXuFW,
+                0b10001 => VfncvtXFW,
+                0b10010 => VfncvtFXuW,
+                0b10011 => VfncvtFXW,
+                0b10100 => VfncvtFFW,
+                0b10101 => VfncvtRodFFW,
+                0b10110 => VfncvtRtzXuFW,
+                0b10111 => VfncvtRtzXFW,
+                _ => None?,
+            },
+            (FVV, 0b010011) => match vs1 {
+                0b00000 => Vfsqrt,
+                0b10000 => Vfclass,
+                _ => None?,
+            },
+
+            _ => None?,
+        };
+
+        // NOTE: Shift amounts, slide offsets, gather indices and register counts are unsigned.
+        let unsigned = matches!(
+            kind,
+            Vsll | Vsrl
+                | Vsra
+                | Vssrl
+                | Vssra
+                | Vnsrl
+                | Vnsra
+                | Vnclipu
+                | Vnclip
+                | Vslideup
+                | Vslidedown
+                | Vrgather
+                | Vmvr
+        );
+        let src = match funct3 {
+            IVV | MVV | FVV => VSrc::Vector(vs1),
+            IVX | MVX => VSrc::Scalar(raw32.rs1()),
+            IVI if unsigned => VSrc::Imm(vs1 as i32),
+            IVI => VSrc::Imm((vs1 as i32) << 27 >> 27),
+            FVF => VSrc::Float(FRs1::decode_raw32(raw32)),
+            _ => None?,
+        };
+
+        Some(Self::VArithType {
+            vd,
+            vs2,
+            src,
+            vm,
+            kind,
+        })
+    }
+
+    /// Decodes the vector encodings of LOAD-FP and STORE-FP.
+    ///
+    /// Returns `None` for scalar floating-point widths as well as reserved encodings.
+    pub(super) fn decode_vmem(raw32: u32, store: bool) -> Option<Self> {
+        let eew = match raw32.funct3() {
+            0b000 => 8,
+            0b101 => 16,
+            0b110 => 32,
+            0b111 => 64,
+            _ => None?,
+        };
+        let vd = (raw32 >> 7 & 0x1f) as u8;
+        let field = raw32 >> 20 & 0x1f;
+        let vm = raw32 >> 25 & 1 == 1;
+        let mew = raw32 >> 28 & 1;
+        let nf = (raw32 >> 29) as u8 + 1;
+        if mew != 0 {
+            None?
+        }
+
+        let addressing = match raw32 >> 26 & 0b11 {
+            0b00 => match field {
+                0b00000 => VAddressing::Unit,
+                0b01000 if vm && nf.is_power_of_two() => VAddressing::Whole,
+                0b01011 if vm && nf == 1 && eew == 8 => VAddressing::Mask,
+                0b10000 if !store => VAddressing::UnitFaultFirst,
+                _ => None?,
+            },
+            0b01 => VAddressing::Indexed {
+                vs2: field as u8,
+                ordered: false,
+            },
+            0b10 => VAddressing::Strided { rs2: raw32.rs2() },
+            // NOTE: Only 0b11 is left of the two bits.
+            _ => VAddressing::Indexed {
+                vs2: field as u8,
+                ordered: true,
+            },
+        };
+
+        Some(Self::VMemType {
+            vd,
+            rs1: raw32.rs1(),
+            vm,
+            mem: VMem {
+                store,
+                addressing,
+                eew,
+                nf,
+            },
+        })
+    }
+
+    /// Encodes the OP-V major opcode.
+    ///
+    /// Returns `None` when `src` is of a kind the instruction doesn't take.
+    pub(super) fn encode_opv(&self) -> Option<u32> {
+        let reg = |reg: u8| reg as u32 & 0x1f;
+        let raw32 = match *self {
+            Self::VsetType { rd, kind } => {
+                let operands = match kind {
+                    VsetKind::Vsetvli { rs1, vtypei } => {
+                        (vtypei & 0x7ff) << 20 | reg(rs1 as u8) << 15
+                    }
+                    VsetKind::Vsetivli { uimm, vtypei } => {
+                        0b11 << 30 | (vtypei & 0x3ff) << 20 | (uimm & 0x1f) << 15
+                    }
+                    VsetKind::Vsetvl { rs1, rs2 } => {
+                        0b1000000 << 25 | reg(rs2 as u8) << 20 | reg(rs1 as u8) << 15
+                    }
+                };
+                operands | 0b111 << 12 | reg(rd as u8) << 7 | 0b1010111
+            }
+            Self::VArithType {
+                vd,
+                vs2,
+                src,
+                vm,
+                kind,
+            } => {
+                let (funct6, category) = kind.fields();
+                let funct3 = match (category, src) {
+                    (Category::Opi, VSrc::Vector(_)) => 0b000,
+                    (Category::Opf, VSrc::Vector(_)) => 0b001,
+                    (Category::Opm, VSrc::Vector(_)) => 0b010,
+                    (Category::Opi, VSrc::Imm(_)) => 0b011,
+                    (Category::Opi, VSrc::Scalar(_)) => 0b100,
+                    (Category::Opf, VSrc::Float(_)) => 0b101,
+                    (Category::Opm, VSrc::Scalar(_)) => 0b110,
+                    _ => None?,
+                };
+                // NOTE: Some unary instructions select their variant with `vs1`.
+                let vs1 = match (kind.selector(), src) {
+                    (Some(vs1), _) => vs1,
+                    (None, VSrc::Vector(vs1)) => reg(vs1),
+                    (None, VSrc::Scalar(rs1)) => reg(rs1 as u8),
+                    (None, VSrc::Imm(imm)) => imm as u32 & 0x1f,
+                    (None, VSrc::Float(rs1)) => reg(rs1 as u8),
+                };
+                funct6 << 26
+                    | (vm as u32) << 25
+                    | reg(vs2) << 20
+                    | vs1 << 15
+                    | funct3 << 12
+                    | reg(vd) << 7
+                    | 0b1010111
+            }
+            _ => unreachable!(),
+        };
+
+        Some(raw32)
+    }
+
+    /// Encodes the vector encodings of LOAD-FP and STORE-FP.
+    pub(super) fn encode_vmem(&self) -> u32 {
+        let Self::VMemType { vd, rs1, vm, mem } = *self else {
+            unreachable!()
+        };
+
+        let width = match mem.eew {
+            8 => 0b000,
+            16 => 0b101,
+            32 => 0b110,
+            _ => 0b111,
+        };
+        let (mop, field) = match mem.addressing {
+            VAddressing::Unit => (0b00, 0b00000),
+            VAddressing::Whole => (0b00, 0b01000),
+            VAddressing::Mask => (0b00, 0b01011),
+            VAddressing::UnitFaultFirst => (0b00, 0b10000),
+            VAddressing::Indexed { vs2, ordered } => {
+                let mop = if ordered { 0b11 } else { 0b01 };
+                (mop, vs2 as u32 & 0x1f)
+            }
+            VAddressing::Strided { rs2 } => (0b10, rs2 as u32 & 0x1f),
+        };
+        let opcode = if mem.store { 0b0100111 } else { 0b0000111 };
+        (mem.nf.wrapping_sub(1) as u32 & 0b111) << 29
+            | mop << 26
+            | (vm as u32) << 25
+            | field << 20
+            | (rs1 as u32 & 0x1f) << 15
+            | width << 12
+            | (vd as u32 & 0x1f) << 7
+            | opcode
+    }
+}
@@ -7,10 +7,22 @@
 //
 // Copyright (C) 2024 mumblingdrunkard
 
+#[cfg(all(feature = "jit", not(all(target_arch = "x86_64", target_os = "linux"))))]
+compile_error!("The `jit` feature is only supported on x86-64 Linux");
+
+pub mod bus;
 pub mod csr;
+pub mod debug;
+pub mod ext;
 pub mod freg;
+pub mod gdb;
 pub mod hart;
 pub mod inst;
-mod mmu;
+pub mod lockstep;
+pub mod mmu;
 pub mod reg;
+pub mod replay;
+pub mod snapshot;
+mod softfloat;
 mod util;
+pub mod vreg;
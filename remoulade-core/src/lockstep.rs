@@ -0,0 +1,523 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Differential testing against a reference model.
+//!
+//! [`Hart::lockstep`] executes one instruction for every commit in a reference trace and stops at
+//! the first one that retires differently, i.e. with another `pc`, encoding or privilege mode, or
+//! leaving registers, CSRs or memory different from what the reference wrote.
+//!
+//! References are read from Spike's commit logs with [`spike`], or from RVFI-DII execution traces
+//! (as recorded by TestRIG) with [`rvfi_dii`].
+
+use std::{
+    collections::VecDeque,
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+use crate::{
+    bus::Bus,
+    csr::Csr,
+    ext::Extensions,
+    freg::{FRegType, FRs1},
+    hart::{exec::Error, Commit, Effect, Hart, ParseCommitError, Trace},
+    reg::{IRs1, RegType},
+};
+
+/// How many of the instructions leading up to a mismatch it keeps.
+const HISTORY: usize = 4;
+
+/// The size of an RVFI-DII execution packet.
+const RVFI_PACKET: usize = 88;
+
+/// The commits in a log written by `spike --log-commits`.
+///
+/// Everything else in the log, like the disassembly `-l` adds and reports of exceptions, is
+/// skipped.
+pub fn spike(log: &str) -> impl Iterator<Item = Result<Commit, ParseCommitError>> + '_ {
+    log.lines()
+        .filter(|line| {
+            let privilege = line
+                .strip_prefix("core")
+                .and_then(|line| line.split_once(':'))
+                .and_then(|(_, rest)| rest.split_whitespace().next());
+            matches!(privilege, Some("0" | "1" | "2" | "3"))
+        })
+        .map(str::parse)
+}
+
+/// The commits in an RVFI-DII execution trace of an `xlen`-bit core.
+///
+/// Instructions that trapped didn't retire and are skipped, and the trace ends at the first
+/// packet with `rvfi_halt` set.
+/// The trace doesn't say which privilege mode instructions executed in or what CSRs they wrote.
+pub fn rvfi_dii(trace: &[u8], xlen: u32) -> impl Iterator<Item = Commit> + '_ {
+    trace
+        .chunks_exact(RVFI_PACKET)
+        .take_while(|packet| packet[86] == 0)
+        .filter(|packet| packet[85] == 0)
+        .map(move |packet| {
+            let field = |i: usize| {
+                let bytes = packet[8 * i..8 * i + 8]
+                    .try_into()
+                    .expect("Fields are 8 bytes");
+                u64::from_le_bytes(bytes)
+            };
+            let (pc, insn, rd_wdata) = (field(1), field(3), field(6));
+            let (mem_addr, mem_wdata) = (field(7), field(9));
+            let (rmask, wmask, rd) = (packet[80], packet[81], packet[84]);
+
+            let mut effects = Vec::new();
+            if rd != 0 {
+                let value = rd_wdata as u128;
+                effects.push(Effect::X { reg: rd, value });
+            }
+            // NOTE: The masks select the bytes of the data that were accessed.
+            if rmask != 0 {
+                let addr = mem_addr + rmask.trailing_zeros() as u64;
+                effects.push(Effect::Load { addr });
+            }
+            if wmask != 0 {
+                let (first, count) = (wmask.trailing_zeros(), wmask.count_ones());
+                let bytes = mem_wdata.to_le_bytes();
+                let data = bytes[first as usize..(first + count) as usize].to_vec();
+                let addr = mem_addr + first as u64;
+                effects.push(Effect::Store { addr, data });
+            }
+
+            Commit {
+                core: 0,
+                privilege: None,
+                pc: pc as u128,
+                insn: insn as u32,
+                len: if insn & 0b11 == 0b11 { 4 } else { 2 },
+                xlen,
+                effects,
+            }
+        })
+}
+
+/// The first instruction that retired differently from the reference.
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    /// How many instructions matched before this one
+    pub index: u64,
+    pub expected: Commit,
+    /// What the hart retired instead, if anything
+    pub actual: Option<Commit>,
+    /// The exception raised instead of retiring, if `trap` didn't take it
+    pub error: Option<Error>,
+    /// What differs, like `x11: expected 0x20, found 0x24`
+    pub differences: Vec<String>,
+    /// The instructions that matched just before this one, oldest first
+    pub history: Vec<Commit>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "instruction {} diverged from the reference", self.index)?;
+        for commit in &self.history {
+            writeln!(f, "  {commit}")?;
+        }
+        writeln!(f, "- {}", self.expected)?;
+        match (&self.actual, self.error) {
+            (Some(actual), _) => writeln!(f, "+ {actual}")?,
+            (None, Some(error)) => writeln!(f, "+ raised {error:?}")?,
+            (None, None) => writeln!(f, "+ halted")?,
+        }
+        for difference in &self.differences {
+            writeln!(f, "  {difference}")?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for Mismatch {}
+
+impl<
+        const ID: usize,
+        I: RegType,
+        const E: bool,
+        const M: bool,
+        const A: bool,
+        F: FRegType,
+        const ZIFENCEI: bool,
+        const C: bool,
+        X: Extensions,
+    > Hart<ID, I, E, M, A, F, ZIFENCEI, C, X>
+{
+    /// Executes in lockstep with `reference`, returning how many instructions matched.
+    ///
+    /// Exceptions are handed to `trap`, which takes them the way the reference did and returns
+    /// `false` if it can't.
+    /// Commits before the first one at the current `pc` are skipped, which drops boot code like
+    /// Spike's reset vector.
+    pub fn lockstep(
+        &mut self,
+        bus: &mut impl Bus,
+        reference: impl IntoIterator<Item = Commit>,
+        mut trap: impl FnMut(&mut Self, Error) -> bool,
+    ) -> Result<u64, Box<Mismatch>> {
+        // NOTE: Commits are collected by tracing, which may already be logging somewhere.
+        let silent = !self.tracing();
+        if silent {
+            self.set_trace(Some(Trace::new(io::sink())));
+        }
+        self.take_commit();
+
+        let mut history = VecDeque::with_capacity(HISTORY);
+        let mut index = 0;
+        let mut result = Ok(());
+        let start = self.pc().as_u128();
+        let xmask = u128::MAX >> (128 - I::BITS);
+        let reference = reference
+            .into_iter()
+            .skip_while(|commit| commit.pc & xmask != start);
+
+        for expected in reference {
+            let mut mismatch = Mismatch {
+                index,
+                expected,
+                actual: None,
+                error: None,
+                differences: Vec::new(),
+                history: Vec::new(),
+            };
+            let actual = loop {
+                match self.step(bus) {
+                    Ok(()) => break self.take_commit(),
+                    Err(e) if trap(self, e) => {}
+                    Err(e) => {
+                        mismatch.error = Some(e);
+                        break None;
+                    }
+                }
+            };
+
+            if let Some(actual) = &actual {
+                mismatch.differences = self.compare(&mismatch.expected, actual);
+            }
+            if actual.is_none() || !mismatch.differences.is_empty() {
+                mismatch.actual = actual;
+                mismatch.history = history.into();
+                result = Err(Box::new(mismatch));
+                break;
+            }
+
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(mismatch.expected);
+            index += 1;
+        }
+
+        if silent {
+            self.set_trace(None);
+        }
+        result.map(|()| index)
+    }
+
+    /// Describes how `actual` and the state it left differ from what `expected` says.
+    fn compare(&self, expected: &Commit, actual: &Commit) -> Vec<String> {
+        let mut differences = Vec::new();
+        let differ = |what: &str, expected: String, found: String| {
+            format!("{what}: expected {expected}, found {found}")
+        };
+        let hex = |value: u128| format!("{value:#x}");
+
+        let xmask = u128::MAX >> (128 - I::BITS);
+        if expected.pc & xmask != actual.pc {
+            differences.push(differ("pc", hex(expected.pc & xmask), hex(actual.pc)));
+        }
+        if (expected.insn, expected.len) != (actual.insn, actual.len) {
+            let insn = |commit: &Commit| match commit.len {
+                2 => format!("{:#06x}", commit.insn),
+                _ => format!("{:#010x}", commit.insn),
+            };
+            differences.push(differ("encoding", insn(expected), insn(actual)));
+        }
+        if let Some(privilege) = expected.privilege {
+            if Some(privilege) != actual.privilege {
+                let found = actual.privilege.unwrap_or_default().to_string();
+                differences.push(differ("privilege mode", privilege.to_string(), found));
+            }
+        }
+
+        // NOTE: Registers and CSRs are compared by the values they hold now, as the reference may
+        //       log writes the hart doesn't.
+        for effect in &expected.effects {
+            match *effect {
+                Effect::X { reg, value } => {
+                    let found = self.reg().get_rs1(IRs1::wrapping_from_u32(reg as u32));
+                    if found.as_u128() != value & xmask {
+                        differences.push(differ(
+                            &format!("x{reg}"),
+                            hex(value),
+                            hex(found.as_u128()),
+                        ));
+                    }
+                }
+                Effect::F { reg, value, bits } => {
+                    let mask = u128::MAX >> (128 - bits.min(F::BITS).max(1));
+                    let found = self.freg().get_rs1(FRs1::wrapping_from_u32(reg as u32));
+                    if found.to_bits() & mask != value & mask {
+                        differences.push(differ(
+                            &format!("f{reg}"),
+                            hex(value),
+                            hex(found.to_bits()),
+                        ));
+                    }
+                }
+                Effect::Csr { addr, value } => {
+                    let found = Csr::checked_from_u32(addr).map(|csr| self.read_csr(csr));
+                    let mask = u64::MAX >> (64 - I::BITS.min(64));
+                    if found != Some(value & mask) {
+                        let found =
+                            found.map_or("no such CSR".to_string(), |found| hex(found as u128));
+                        differences.push(differ(
+                            &format!("CSR {addr:#05x}"),
+                            hex(value as u128),
+                            found,
+                        ));
+                    }
+                }
+                Effect::Load { .. } | Effect::Store { .. } => {
+                    if !actual.effects.contains(effect) {
+                        differences.push(format!("missing {}", describe(effect)));
+                    }
+                }
+            }
+        }
+
+        for effect in &actual.effects {
+            let unexpected = match *effect {
+                Effect::X { reg, .. } => !expected
+                    .effects
+                    .iter()
+                    .any(|effect| matches!(*effect, Effect::X { reg: other, .. } if other == reg)),
+                Effect::F { reg, .. } => !expected
+                    .effects
+                    .iter()
+                    .any(|effect| matches!(*effect, Effect::F { reg: other, .. } if other == reg)),
+                // NOTE: References differ in which side effects on CSRs they log, if any.
+                Effect::Csr { .. } => false,
+                Effect::Load { .. } | Effect::Store { .. } => !expected.effects.contains(effect),
+            };
+            if unexpected {
+                differences.push(format!("unexpected {}", describe(effect)));
+            }
+        }
+
+        differences
+    }
+}
+
+fn describe(effect: &Effect) -> String {
+    match effect {
+        Effect::X { reg, value } => format!("write of {value:#x} to x{reg}"),
+        Effect::F { reg, value, .. } => format!("write of {value:#x} to f{reg}"),
+        Effect::Csr { addr, value } => format!("write of {value:#x} to CSR {addr:#05x}"),
+        Effect::Load { addr } => format!("load from {addr:#x}"),
+        Effect::Store { addr, data } => {
+            let digits: String = data
+                .iter()
+                .rev()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            format!("store of 0x{digits} to {addr:#x}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        bus::Ram,
+        csr::Csr,
+        hart::{exec::Error, testing::write_program, Hart, Trace},
+        inst::Instruction,
+        lockstep::{rvfi_dii, spike},
+    };
+
+    type Rv64 = Hart<0, u64, false, true, true, (), false, false>;
+
+    /// A program that stores and traps to a handler at `0x80000020`.
+    fn setup() -> (Rv64, Ram) {
+        let program = Instruction::<u64, false, true, true, ()>::assemble(
+            "
+                addi a0,zero,5
+                addi a1,a0,3
+                auipc t0,0
+                sw a1,64(t0)
+                ecall
+                nop
+                nop
+                nop
+            handler:
+                csrr a2,mcause
+                lw a3,64(t0)
+            ",
+        )
+        .unwrap();
+        let mut ram = Ram::new(0x8000_0000, 0x1000);
+        write_program(&mut ram, 0x8000_0000, &program);
+        let mut hart = Rv64::new();
+        hart.set_pc(0x8000_0000);
+        hart.write_csr(Csr::Mtvec, 0x8000_0020);
+        (hart, ram)
+    }
+
+    /// Takes exceptions in M-mode, like Spike does.
+    fn trap(hart: &mut Rv64, e: Error) -> bool {
+        hart.write_csr(Csr::Mepc, hart.pc());
+        hart.write_csr(Csr::Mcause, e as u64);
+        hart.set_pc(hart.read_csr(Csr::Mtvec));
+        true
+    }
+
+    /// Spike running the program from its reset vector, with `-l --log-commits`.
+    const LOG: &str = "\
+core   0: 0x0000000000001000 (0x00000297) auipc   t0, 0x0
+core   0: 3 0x0000000000001000 (0x00000297) x5  0x0000000000001000
+core   0: 0x0000000000001004 (0x02028593) addi    a1, t0, 32
+core   0: 3 0x0000000000001004 (0x02028593) x11 0x0000000000001020
+core   0: 0x0000000080000000 (0x00500513) li      a0, 5
+core   0: 3 0x0000000080000000 (0x00500513) x10 0x0000000000000005
+core   0: 0x0000000080000004 (0x00350593) addi    a1, a0, 3
+core   0: 3 0x0000000080000004 (0x00350593) x11 0x0000000000000008
+core   0: 0x0000000080000008 (0x00000297) auipc   t0, 0x0
+core   0: 3 0x0000000080000008 (0x00000297) x5  0x0000000080000008
+core   0: 0x000000008000000c (0x04b2a023) sw      a1, 64(t0)
+core   0: 3 0x000000008000000c (0x04b2a023) mem 0x0000000080000048 0x00000008
+core   0: 0x0000000080000010 (0x00000073) ecall
+core   0: exception trap_machine_ecall, epc 0x0000000080000010
+core   0: 0x0000000080000020 (0x34202673) csrr    a2, mcause
+core   0: 3 0x0000000080000020 (0x34202673) x12 0x000000000000000b
+core   0: 0x0000000080000024 (0x0402a683) lw      a3, 64(t0)
+core   0: 3 0x0000000080000024 (0x0402a683) x13 0x0000000000000008 mem 0x0000000080000048
+";
+
+    #[test]
+    fn test_spike() {
+        let reference = spike(LOG).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(reference.len(), 8);
+
+        let (mut hart, mut ram) = setup();
+        assert_eq!(hart.lockstep(&mut ram, reference.clone(), trap).unwrap(), 6);
+
+        // A wrong result is caught where it's written
+        let mut wrong = reference.clone();
+        wrong[3] = "core   0: 3 0x0000000080000004 (0x00350593) x11 0x0000000000000009"
+            .parse()
+            .unwrap();
+        let (mut hart, mut ram) = setup();
+        let mismatch = hart.lockstep(&mut ram, wrong, trap).unwrap_err();
+        assert_eq!(mismatch.index, 1);
+        assert_eq!(mismatch.differences, ["x11: expected 0x9, found 0x8"]);
+        assert_eq!(mismatch.history, reference[2..3]);
+        assert_eq!(
+            mismatch.to_string(),
+            "\
+instruction 1 diverged from the reference
+  core   0: 3 0x0000000080000000 (0x00500513) x10 0x0000000000000005
+- core   0: 3 0x0000000080000004 (0x00350593) x11 0x0000000000000009
++ core   0: 3 0x0000000080000004 (0x00350593) x11 0x0000000000000008
+  x11: expected 0x9, found 0x8
+"
+        );
+
+        // So is an exception the reference didn't raise
+        let (mut hart, mut ram) = setup();
+        let mismatch = hart
+            .lockstep(&mut ram, reference, |_, _| false)
+            .unwrap_err();
+        assert_eq!(mismatch.index, 4);
+        assert_eq!(mismatch.error, Some(Error::EcallFromMMode));
+        assert_eq!(mismatch.actual, None);
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_spike_log() {
+        let reference = spike(LOG).collect::<Result<Vec<_>, _>>().unwrap();
+        let out = Shared::default();
+        let (mut hart, mut ram) = setup();
+        hart.set_trace(Some(Trace::new(out.clone()).disasm(true)));
+        assert_eq!(hart.lockstep(&mut ram, reference, trap).unwrap(), 6);
+
+        // NOTE: The reset vector isn't run, and traps are taken by the embedder.
+        let log = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let expected: Vec<_> = LOG
+            .lines()
+            .skip(4)
+            .filter(|line| !line.contains("exception"))
+            .collect();
+        assert_eq!(log.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_rvfi_dii() {
+        let packet = |pc: u64, insn: u64, rd: u8, rd_wdata: u64, mem: (u64, u64, u8)| {
+            let mut packet = [0; 88];
+            let fields = [0, pc, pc + 4, insn, 0, 0, rd_wdata, mem.0, 0, mem.1];
+            for (i, field) in fields.iter().enumerate() {
+                packet[8 * i..8 * i + 8].copy_from_slice(&field.to_le_bytes());
+            }
+            packet[81] = mem.2;
+            packet[84] = rd;
+            packet
+        };
+        let mut trace = Vec::new();
+        trace.extend(packet(0x8000_0000, 0x00500513, 10, 5, (0, 0, 0)));
+        trace.extend(packet(0x8000_0004, 0x00350593, 11, 8, (0, 0, 0)));
+        trace.extend(packet(0x8000_0008, 0x00000297, 5, 0x8000_0008, (0, 0, 0)));
+        trace.extend(packet(0x8000_000c, 0x04b2a023, 0, 0, (0x8000_0048, 8, 0xf)));
+        let mut halt = [0; 88];
+        halt[86] = 1;
+        trace.extend(halt);
+        trace.extend(packet(0x8000_0010, 0x00000073, 0, 0, (0, 0, 0)));
+
+        let (mut hart, mut ram) = setup();
+        let reference = rvfi_dii(&trace, 64);
+        assert_eq!(hart.lockstep(&mut ram, reference, trap).unwrap(), 4);
+
+        // The store went elsewhere
+        trace[88 * 3 + 56] = 0x4c;
+        let (mut hart, mut ram) = setup();
+        let mismatch = hart
+            .lockstep(&mut ram, rvfi_dii(&trace, 64), trap)
+            .unwrap_err();
+        assert_eq!(
+            mismatch.differences,
+            [
+                "missing store of 0x00000008 to 0x8000004c",
+                "unexpected store of 0x00000008 to 0x80000048",
+            ]
+        );
+    }
+}
@@ -7,4 +7,6 @@
 //
 // Copyright (C) 2024 mumblingdrunkard
 
-mod cache;
+pub mod cache;
+pub(crate) mod paging;
+pub(crate) mod pmp;
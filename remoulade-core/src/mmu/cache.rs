@@ -7,9 +7,11 @@
 //
 // Copyright (C) 2024 mumblingdrunkard
 
-use std::marker::PhantomData;
-
-use crate::inst::Instruction;
+//! A timing model of a cache hierarchy.
+//!
+//! Caches only keep track of which lines they hold, not their data, so they tell how long
+//! accesses take without changing what they read.
+//! A hart models its caches with [`Hart::set_caches`](crate::hart::Hart::set_caches).
 
 #[derive(Copy, Clone, Debug)]
 #[repr(u8)]
@@ -34,6 +36,7 @@ impl FourWay {
     }
 }
 
+/// The order in which the ways of a 4-way set were last used, most recently used first.
 #[derive(Copy, Clone, Debug)]
 #[repr(u8)]
 #[allow(clippy::upper_case_acronyms)]
@@ -65,6 +68,7 @@ enum FourWayLru {
 }
 
 impl FourWayLru {
+    /// Makes `way` the most recently used.
     fn touch(&mut self, way: FourWay) {
         use FourWay::*;
         use FourWayLru::*;
@@ -149,39 +153,536 @@ impl FourWayLru {
         };
     }
 
+    /// Picks the least recently used way to be replaced, making it the most recently used.
     fn replace(&mut self) -> FourWay {
         use FourWay::*;
         use FourWayLru::*;
         let way = match *self {
-            ABCD => A,
-            ABDC => A,
-            ACBD => A,
-            ACDB => A,
-            ADBC => A,
-            ADCB => A,
-
-            BACD => B,
-            BADC => B,
-            BCAD => B,
-            BCDA => B,
-            BDAC => B,
-            BDCA => B,
-
-            CABD => C,
-            CADB => C,
-            CBAD => C,
-            CBDA => C,
-            CDAB => C,
-            CDBA => C,
-
-            DABC => D,
-            DACB => D,
-            DBAC => D,
-            DBCA => D,
-            DCAB => D,
-            DCBA => D,
+            BCDA => A,
+            BDCA => A,
+            CBDA => A,
+            CDBA => A,
+            DBCA => A,
+            DCBA => A,
+
+            ACDB => B,
+            ADCB => B,
+            CADB => B,
+            CDAB => B,
+            DACB => B,
+            DCAB => B,
+
+            ABDC => C,
+            ADBC => C,
+            BADC => C,
+            BDAC => C,
+            DABC => C,
+            DBAC => C,
+
+            ABCD => D,
+            ACBD => D,
+            BACD => D,
+            BCAD => D,
+            CABD => D,
+            CBAD => D,
         };
         self.touch(way);
         way
     }
 }
+
+/// How a set picks the line to replace.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Replacement {
+    /// True least recently used, for 4 ways
+    Lru,
+    /// Tree pseudo-LRU, for a power of two ways up to 64
+    Plru,
+    /// Pseudo-random, from a fixed seed so runs are reproducible
+    Random,
+    /// The line filled first
+    Fifo,
+}
+
+/// What happened to the accesses looking up a cache.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Lines replaced to make room for others
+    pub evictions: u64,
+    /// Dirty lines written to the next level
+    pub writebacks: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Line {
+    tag: u64,
+    dirty: bool,
+}
+
+/// Replacement state of a set.
+#[derive(Copy, Clone, Debug)]
+enum Order {
+    Lru(FourWayLru),
+    /// One bit per node of the tree, pointing towards the side to replace next
+    Plru(u64),
+    /// The way to replace next once the set is full
+    Fifo(usize),
+    Random,
+}
+
+/// Result of looking up a cache.
+struct Lookup {
+    hit: bool,
+    /// Address of a dirty line evicted to make room
+    writeback: Option<u64>,
+}
+
+/// A set-associative cache.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    sets: usize,
+    ways: usize,
+    line: u64,
+    replacement: Replacement,
+    write_back: bool,
+    write_allocate: bool,
+    latency: u64,
+    /// Lines by set, then way
+    lines: Vec<Option<Line>>,
+    orders: Vec<Order>,
+    /// State of the xorshift generator picking ways for [`Replacement::Random`]
+    random: u64,
+    stats: CacheStats,
+}
+
+impl Cache {
+    /// An empty cache of `sets` sets of `ways` lines of `line` bytes.
+    ///
+    /// Caches are write-back and write-allocate, and take no extra cycles to hit, until told
+    /// otherwise.
+    pub fn new(sets: usize, ways: usize, line: u64, replacement: Replacement) -> Self {
+        assert!(
+            sets.is_power_of_two() && line.is_power_of_two(),
+            "sets and line size have to be powers of two"
+        );
+        let order = match replacement {
+            Replacement::Lru => {
+                assert!(ways == 4, "true LRU is only modelled for 4 ways");
+                Order::Lru(FourWayLru::ABCD)
+            }
+            Replacement::Plru => {
+                assert!(
+                    ways.is_power_of_two() && ways <= 64,
+                    "pseudo-LRU needs a power of two ways up to 64"
+                );
+                Order::Plru(0)
+            }
+            Replacement::Random => Order::Random,
+            Replacement::Fifo => Order::Fifo(0),
+        };
+        assert!(ways > 0, "caches need at least one way");
+
+        Self {
+            sets,
+            ways,
+            line,
+            replacement,
+            write_back: true,
+            write_allocate: true,
+            latency: 0,
+            lines: vec![None; sets * ways],
+            orders: vec![order; sets],
+            random: 0x2545_f491_4f6c_dd1d,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Passes every write on to the next level instead of holding dirty lines.
+    pub fn write_through(mut self, write_through: bool) -> Self {
+        self.write_back = !write_through;
+        self
+    }
+
+    /// Whether writes missing the cache fill a line, or only go on to the next level.
+    pub fn write_allocate(mut self, write_allocate: bool) -> Self {
+        self.write_allocate = write_allocate;
+        self
+    }
+
+    /// Cycles taken by every access reaching this cache.
+    pub fn latency(mut self, latency: u64) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// The size of a line in bytes.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    pub fn replacement(&self) -> Replacement {
+        self.replacement
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn clear_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    /// Drops every line, dirty or not, without counting anything.
+    pub fn invalidate(&mut self) {
+        let fresh = Self::new(self.sets, self.ways, self.line, self.replacement);
+        self.lines = fresh.lines;
+        self.orders = fresh.orders;
+    }
+
+    /// The set and tag of `addr`.
+    fn locate(&self, addr: u64) -> (usize, u64) {
+        let line = addr / self.line;
+        (line as usize & (self.sets - 1), line / self.sets as u64)
+    }
+
+    fn find(&self, set: usize, tag: u64) -> Option<usize> {
+        (0..self.ways)
+            .find(|&way| matches!(self.lines[set * self.ways + way], Some(line) if line.tag == tag))
+    }
+
+    fn touch(&mut self, set: usize, way: usize) {
+        match &mut self.orders[set] {
+            Order::Lru(lru) => lru.touch(FourWay::from_u32(way as u32).unwrap()),
+            Order::Plru(bits) => {
+                let (mut node, mut lo, mut hi) = (0, 0, self.ways);
+                while hi - lo > 1 {
+                    let mid = (lo + hi) / 2;
+                    // NOTE: Point away from the way just used.
+                    if way < mid {
+                        *bits |= 1 << node;
+                        node = 2 * node + 1;
+                        hi = mid;
+                    } else {
+                        *bits &= !(1 << node);
+                        node = 2 * node + 2;
+                        lo = mid;
+                    }
+                }
+            }
+            Order::Fifo(_) | Order::Random => {}
+        }
+    }
+
+    /// Picks the way to replace in a full set.
+    fn victim(&mut self, set: usize) -> usize {
+        match &mut self.orders[set] {
+            Order::Lru(lru) => lru.replace() as usize,
+            Order::Plru(bits) => {
+                let (mut node, mut lo, mut hi) = (0, 0, self.ways);
+                while hi - lo > 1 {
+                    let mid = (lo + hi) / 2;
+                    if *bits >> node & 1 == 1 {
+                        node = 2 * node + 2;
+                        lo = mid;
+                    } else {
+                        node = 2 * node + 1;
+                        hi = mid;
+                    }
+                }
+                lo
+            }
+            Order::Fifo(next) => {
+                let way = *next;
+                *next = (way + 1) % self.ways;
+                way
+            }
+            Order::Random => {
+                self.random ^= self.random << 13;
+                self.random ^= self.random >> 7;
+                self.random ^= self.random << 17;
+                (self.random % self.ways as u64) as usize
+            }
+        }
+    }
+
+    /// Looks up the line holding `addr`, filling it on a miss unless it's a write that doesn't
+    /// allocate.
+    fn access(&mut self, addr: u64, write: bool) -> Lookup {
+        let (set, tag) = self.locate(addr);
+        let base = set * self.ways;
+        if let Some(way) = self.find(set, tag) {
+            self.stats.hits += 1;
+            self.touch(set, way);
+            if let Some(line) = &mut self.lines[base + way] {
+                line.dirty |= write && self.write_back;
+            }
+            return Lookup {
+                hit: true,
+                writeback: None,
+            };
+        }
+
+        self.stats.misses += 1;
+        if write && !self.write_allocate {
+            return Lookup {
+                hit: false,
+                writeback: None,
+            };
+        }
+
+        // NOTE: Empty ways are filled in order, so they're also the oldest for FIFO.
+        let way = match (0..self.ways).find(|&way| self.lines[base + way].is_none()) {
+            Some(way) => way,
+            None => self.victim(set),
+        };
+        let mut writeback = None;
+        if let Some(old) = self.lines[base + way] {
+            self.stats.evictions += 1;
+            if old.dirty {
+                self.stats.writebacks += 1;
+                writeback = Some((old.tag * self.sets as u64 + set as u64) * self.line);
+            }
+        }
+        self.lines[base + way] = Some(Line {
+            tag,
+            dirty: write && self.write_back,
+        });
+        self.touch(set, way);
+        Lookup {
+            hit: false,
+            writeback,
+        }
+    }
+
+    /// Writes back the line holding `addr` if it's dirty, and drops it if `invalidate`.
+    ///
+    /// Returns whether a dirty line was written back.
+    fn clean(&mut self, addr: u64, invalidate: bool) -> bool {
+        let (set, tag) = self.locate(addr);
+        let Some(way) = self.find(set, tag) else {
+            return false;
+        };
+        let line = &mut self.lines[set * self.ways + way];
+        let dirty = line.is_some_and(|line| line.dirty);
+        match invalidate {
+            true => *line = None,
+            false => *line = Some(Line { tag, dirty: false }),
+        }
+        if dirty {
+            self.stats.writebacks += 1;
+        }
+        dirty
+    }
+}
+
+/// Looks up `addr` in `levels`, the first being closest to the hart, and returns the cycles
+/// it took.
+fn lookup(levels: &mut [&mut Cache], memory_latency: u64, addr: u64, write: bool) -> u64 {
+    let Some((cache, below)) = levels.split_first_mut() else {
+        return memory_latency;
+    };
+    let found = cache.access(addr, write);
+    if let Some(victim) = found.writeback {
+        write_back(below, victim);
+    }
+
+    let through = write && !cache.write_back;
+    if found.hit && !through {
+        return cache.latency;
+    }
+    // NOTE: Misses fill the line from the next level, and writes that aren't held here go on
+    //       to it.
+    let write = write && (through || !cache.write_allocate);
+    cache.latency + lookup(below, memory_latency, addr, write)
+}
+
+/// Writes a dirty line evicted from a level to `levels` below it, which is done off the
+/// critical path and takes no cycles.
+fn write_back(levels: &mut [&mut Cache], addr: u64) {
+    if let Some((cache, below)) = levels.split_first_mut() {
+        if let Some(victim) = cache.access(addr, true).writeback {
+            write_back(below, victim);
+        }
+    }
+}
+
+/// The caches of a hart: split L1 instruction and data caches, and an optional unified L2.
+///
+/// Every level is private to the hart, so there is no coherence between the caches of harts.
+#[derive(Clone, Debug)]
+pub struct Caches {
+    l1i: Cache,
+    l1d: Cache,
+    l2: Option<Cache>,
+    /// Cycles taken by accesses missing every level
+    memory_latency: u64,
+}
+
+impl Caches {
+    pub fn new(l1i: Cache, l1d: Cache, l2: Option<Cache>, memory_latency: u64) -> Self {
+        Self {
+            l1i,
+            l1d,
+            l2,
+            memory_latency,
+        }
+    }
+
+    pub fn l1i(&self) -> &Cache {
+        &self.l1i
+    }
+
+    pub fn l1d(&self) -> &Cache {
+        &self.l1d
+    }
+
+    pub fn l2(&self) -> Option<&Cache> {
+        self.l2.as_ref()
+    }
+
+    pub fn clear_stats(&mut self) {
+        self.l1i.clear_stats();
+        self.l1d.clear_stats();
+        if let Some(l2) = &mut self.l2 {
+            l2.clear_stats();
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.l1i.invalidate();
+        self.l1d.invalidate();
+        if let Some(l2) = &mut self.l2 {
+            l2.invalidate();
+        }
+    }
+
+    /// Accesses `len` bytes at `addr`, returning the cycles it took and how many lines missed
+    /// the L1 cache.
+    pub(crate) fn access(&mut self, fetch: bool, addr: u64, len: u64, write: bool) -> (u64, u64) {
+        let Self {
+            l1i,
+            l1d,
+            l2,
+            memory_latency,
+        } = self;
+        let l1 = if fetch { l1i } else { l1d };
+        let misses = l1.stats.misses;
+
+        let line = l1.line;
+        let mut cycles = 0;
+        for index in addr / line..=(addr + len.max(1) - 1) / line {
+            cycles += match l2 {
+                Some(l2) => lookup(&mut [&mut *l1, l2], *memory_latency, index * line, write),
+                None => lookup(&mut [&mut *l1], *memory_latency, index * line, write),
+            };
+        }
+        (cycles, l1.stats.misses - misses)
+    }
+
+    /// Writes back the lines overlapping `len` bytes at `addr` at every level, and drops them if
+    /// `invalidate`, like the `cbo` instructions.
+    pub(crate) fn clean(&mut self, addr: u64, len: u64, invalidate: bool) {
+        let line = self
+            .l1d
+            .line
+            .min(self.l2.as_ref().map_or(u64::MAX, |l2| l2.line));
+        for index in addr / line..=(addr + len.max(1) - 1) / line {
+            // NOTE: Written back lines land in memory once every level is cleaned.
+            self.l1i.clean(index * line, invalidate);
+            self.l1d.clean(index * line, invalidate);
+            if let Some(l2) = &mut self.l2 {
+                l2.clean(index * line, invalidate);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, CacheStats, Caches, Replacement};
+
+    /// Accesses `lines` lines of 16 bytes in `cache`, and returns which hit.
+    fn hits(cache: &mut Cache, lines: &[u64]) -> Vec<bool> {
+        lines
+            .iter()
+            .map(|&line| cache.access(line * 16, false).hit)
+            .collect()
+    }
+
+    #[test]
+    fn test_replacement() {
+        // NOTE: A single set of 4 ways, so every line competes for the same ways.
+        let mut lru = Cache::new(1, 4, 16, Replacement::Lru);
+        assert_eq!(
+            hits(&mut lru, &[0, 1, 2, 3, 0, 4, 0, 1]),
+            [false, false, false, false, true, false, true, false]
+        );
+        let mut plru = Cache::new(1, 4, 16, Replacement::Plru);
+        assert_eq!(
+            hits(&mut plru, &[0, 1, 2, 3, 0, 4, 0, 2]),
+            [false, false, false, false, true, false, true, false]
+        );
+        let mut fifo = Cache::new(1, 4, 16, Replacement::Fifo);
+        assert_eq!(
+            hits(&mut fifo, &[0, 1, 2, 3, 0, 4, 0, 1]),
+            [false, false, false, false, true, false, false, false]
+        );
+        let mut random = Cache::new(1, 3, 16, Replacement::Random);
+        hits(&mut random, &[0, 1, 2, 3]);
+        assert_eq!(
+            hits(&mut random, &[0, 1, 2])
+                .iter()
+                .filter(|&&hit| hit)
+                .count(),
+            2
+        );
+        assert_eq!(random.stats().evictions, 2);
+
+        // NOTE: Lines only compete within their set.
+        let mut sets = Cache::new(2, 1, 16, Replacement::Fifo);
+        assert_eq!(
+            hits(&mut sets, &[0, 1, 0, 2, 1]),
+            [false, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let l1 = || Cache::new(1, 1, 16, Replacement::Fifo).latency(1);
+        let l2 = Cache::new(4, 2, 16, Replacement::Plru).latency(10);
+        let mut caches = Caches::new(l1(), l1(), Some(l2), 100);
+
+        // NOTE: Accesses take the latency of every level they reach, and cover each line.
+        assert_eq!(caches.access(false, 0x0, 8, false), (111, 1));
+        assert_eq!(caches.access(false, 0x8, 8, true), (1, 0));
+        assert_eq!(caches.access(true, 0x8, 16, false), (122, 2));
+        assert_eq!(caches.l1i().stats().misses, 2);
+        // The dirty line is written back to the L2 when replaced
+        assert_eq!(caches.access(false, 0x10, 4, false), (11, 1));
+        assert_eq!(
+            caches.l1d().stats(),
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                evictions: 1,
+                writebacks: 1,
+            }
+        );
+        caches.clean(0x0, 64, true);
+        assert_eq!(caches.l2().unwrap().stats().writebacks, 1);
+        assert_eq!(caches.access(false, 0x0, 4, false), (111, 1));
+
+        // NOTE: Write-through caches pass every write on, and without write-allocate, misses too.
+        let l1 = Cache::new(1, 1, 16, Replacement::Fifo)
+            .latency(1)
+            .write_through(true)
+            .write_allocate(false);
+        let mut caches = Caches::new(l1.clone(), l1, None, 100);
+        assert_eq!(caches.access(false, 0x0, 4, true), (101, 1));
+        assert_eq!(caches.access(false, 0x0, 4, false), (101, 1));
+        assert_eq!(caches.access(false, 0x0, 4, true), (101, 0));
+        assert_eq!(caches.l1d().stats().writebacks, 0);
+    }
+}
@@ -0,0 +1,283 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Page-based virtual memory (Sv32, Sv39 and Sv48) and the widened guest-physical schemes of the
+//! hypervisor extension (Sv32x4, Sv39x4 and Sv48x4).
+//!
+//! Accessed and dirty bits are never updated; accesses that would need them set fault instead
+//! (Svade).
+
+use crate::mmu::pmp::Access;
+
+pub(crate) const PAGE_SIZE: u64 = 4096;
+
+const V: u64 = 1 << 0;
+const R: u64 = 1 << 1;
+const W: u64 = 1 << 2;
+const X: u64 = 1 << 3;
+const U: u64 = 1 << 4;
+const A: u64 = 1 << 6;
+const D: u64 = 1 << 7;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Mode {
+    Sv32,
+    Sv39,
+    Sv48,
+}
+
+impl Mode {
+    /// Decodes the mode of `satp`, `vsatp` or `hgatp`, or returns `None` for an unsupported one.
+    ///
+    /// `Some(None)` is bare, i.e. no translation.
+    pub(crate) fn decode(xlen: u32, atp: u64) -> Option<Option<Self>> {
+        match (xlen, atp >> 60) {
+            (32, _) if atp >> 31 & 1 == 1 => Some(Some(Self::Sv32)),
+            (32, _) => Some(None),
+            (_, 0) => Some(None),
+            (_, 8) => Some(Some(Self::Sv39)),
+            (_, 9) => Some(Some(Self::Sv48)),
+            _ => None,
+        }
+    }
+
+    fn levels(self) -> u32 {
+        match self {
+            Self::Sv32 => 2,
+            Self::Sv39 => 3,
+            Self::Sv48 => 4,
+        }
+    }
+
+    fn vpn_bits(self) -> u32 {
+        match self {
+            Self::Sv32 => 10,
+            _ => 9,
+        }
+    }
+
+    fn pte_bytes(self) -> u64 {
+        match self {
+            Self::Sv32 => 4,
+            _ => 8,
+        }
+    }
+}
+
+/// A page table and how to walk it.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Table {
+    pub(crate) mode: Mode,
+    /// Physical address of the root table
+    pub(crate) root: u64,
+    /// Whether this is a G-stage table, which takes two more address bits at the root
+    pub(crate) widened: bool,
+}
+
+impl Table {
+    /// Reads the table from `satp`, `vsatp` or `hgatp`, or returns `None` for bare translation.
+    pub(crate) fn from_atp(xlen: u32, atp: u64, widened: bool) -> Option<Self> {
+        let mode = Mode::decode(xlen, atp).flatten()?;
+        let ppn = match mode {
+            Mode::Sv32 => atp & 0x3f_ffff,
+            _ => atp & 0xfff_ffff_ffff,
+        };
+        // NOTE: The widened root tables span four pages and are aligned accordingly.
+        let ppn = if widened { ppn & !0b11 } else { ppn };
+        Some(Self {
+            mode,
+            root: ppn * PAGE_SIZE,
+            widened,
+        })
+    }
+
+    pub(crate) fn pte_bytes(&self) -> u64 {
+        self.mode.pte_bytes()
+    }
+}
+
+/// What an access needs from the page it hits.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Request {
+    pub(crate) access: Access,
+    /// Whether the access is made from user mode
+    pub(crate) user: bool,
+    /// Whether supervisor mode may read and write user pages (SUM)
+    pub(crate) sum: bool,
+    /// Whether executable pages are readable (MXR)
+    pub(crate) mxr: bool,
+    /// Whether reads need execute permission instead (`hlvx`)
+    pub(crate) hlvx: bool,
+}
+
+/// Translates `addr` through `table`, reading entries with `read`.
+///
+/// Returns `fault` when the walk or the permissions don't allow the access.
+pub(crate) fn walk<E: Copy>(
+    table: Table,
+    addr: u64,
+    request: Request,
+    fault: E,
+    mut read: impl FnMut(u64) -> Result<u64, E>,
+) -> Result<u64, E> {
+    let mode = table.mode;
+    let levels = mode.levels();
+    let vpn_bits = mode.vpn_bits();
+    let extra = if table.widened { 2 } else { 0 };
+
+    // NOTE: Virtual addresses are sign-extended while guest-physical addresses are zero-extended.
+    let bits = 12 + vpn_bits * levels + extra;
+    let valid = match (mode, table.widened) {
+        (Mode::Sv32, false) => addr >> 32 == 0,
+        (_, true) => addr >> bits == 0,
+        (_, false) => ((addr << (64 - bits)) as i64 >> (64 - bits)) as u64 == addr,
+    };
+    if !valid {
+        return Err(fault);
+    }
+
+    let mut base = table.root;
+    for level in (0..levels).rev() {
+        let shift = 12 + vpn_bits * level;
+        let width = if level == levels - 1 {
+            vpn_bits + extra
+        } else {
+            vpn_bits
+        };
+        let index = addr >> shift & ((1 << width) - 1);
+        let pte = read(base + index * mode.pte_bytes())?;
+
+        // NOTE: The upper bits hold Svnapot and Svpbmt fields, which aren't supported.
+        let (ppn, reserved) = match mode {
+            Mode::Sv32 => (pte >> 10 & 0x3f_ffff, 0),
+            _ => (pte >> 10 & 0xfff_ffff_ffff, pte >> 54),
+        };
+        if pte & V == 0 || pte & (R | W) == W || reserved != 0 {
+            return Err(fault);
+        }
+
+        if pte & (R | X) == 0 {
+            if pte & (A | D | U) != 0 {
+                return Err(fault);
+            }
+            base = ppn * PAGE_SIZE;
+            continue;
+        }
+
+        let readable = pte & R != 0 || request.mxr && pte & X != 0;
+        let permitted = match request.access {
+            Access::Read if request.hlvx => pte & X != 0,
+            Access::Read => readable,
+            Access::Write => pte & W != 0,
+            Access::Execute => pte & X != 0,
+        };
+        let privileged = match request.user {
+            true => pte & U != 0,
+            false => pte & U == 0 || request.sum && request.access != Access::Execute,
+        };
+        let dirty = request.access != Access::Write || pte & D != 0;
+        // Superpages must be aligned to their size
+        let offset_bits = vpn_bits * level;
+        let aligned = ppn & ((1 << offset_bits) - 1) == 0;
+        if !(permitted && privileged && pte & A != 0 && dirty && aligned) {
+            return Err(fault);
+        }
+
+        let offset = addr & ((1 << shift) - 1);
+        return Ok(ppn << 12 | offset);
+    }
+
+    Err(fault)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{walk, Mode, Request, Table, A, D, R, U, V, W, X};
+    use crate::mmu::pmp::Access;
+    use std::collections::HashMap;
+
+    fn request(access: Access, user: bool) -> Request {
+        Request {
+            access,
+            user,
+            sum: false,
+            mxr: false,
+            hlvx: false,
+        }
+    }
+
+    #[test]
+    fn test_sv39() {
+        let root = 0x10_000;
+        let (next, last) = (0x11_000, 0x12_000);
+        let mut memory = HashMap::new();
+        // Pointers down to a user code page at 0x0, a gigapage at 0x4000_0000 and a misaligned
+        // megapage at 0x20_0000
+        memory.insert(root, next >> 12 << 10 | V);
+        memory.insert(root + 8, 0x4000_0000 >> 12 << 10 | V | R | W | A | D);
+        memory.insert(next, last >> 12 << 10 | V);
+        memory.insert(next + 8, 0x1000 >> 12 << 10 | V | R | A);
+        memory.insert(last, 0x80_000 >> 12 << 10 | V | X | U | A);
+
+        let table = Table {
+            mode: Mode::Sv39,
+            root,
+            widened: false,
+        };
+        let read = |addr: u64| memory.get(&addr).copied().ok_or(());
+        let translate = |addr, request| walk(table, addr, request, (), read);
+
+        assert_eq!(
+            translate(0x4012_3456, request(Access::Write, false)),
+            Ok(0x4012_3456)
+        );
+        assert_eq!(translate(0x4012_3456, request(Access::Read, true)), Err(()));
+        assert_eq!(
+            translate(0x0123, request(Access::Execute, true)),
+            Ok(0x80_123)
+        );
+        assert_eq!(translate(0x0123, request(Access::Read, true)), Err(()));
+        // Supervisor mode never executes user pages
+        assert_eq!(translate(0x0123, request(Access::Execute, false)), Err(()));
+        assert_eq!(translate(0x20_0000, request(Access::Read, false)), Err(()));
+        // Non-canonical addresses
+        assert_eq!(translate(1 << 38, request(Access::Read, false)), Err(()));
+
+        let mxr = Request {
+            mxr: true,
+            ..request(Access::Read, true)
+        };
+        assert_eq!(translate(0x0123, mxr), Ok(0x80_123));
+        let hlvx = Request {
+            hlvx: true,
+            ..request(Access::Read, true)
+        };
+        assert_eq!(translate(0x0123, hlvx), Ok(0x80_123));
+    }
+
+    #[test]
+    fn test_widened() {
+        // The root of Sv39x4 takes two more bits and lies in the fourth page
+        let root = 0x40_000;
+        let mut memory = HashMap::new();
+        memory.insert(root + 0x7ff * 8, 0x4000_0000 >> 12 << 10 | V | R | U | A);
+        let table = Table::from_atp(64, 8 << 60 | root >> 12 | 0b11, true).unwrap();
+        let read = |addr: u64| memory.get(&addr).copied().ok_or(());
+
+        let addr = 0x1ff_c000_0010;
+        assert_eq!(
+            walk(table, addr, request(Access::Read, true), (), read),
+            Ok(0x4000_0010)
+        );
+        assert_eq!(
+            walk(table, 1 << 41, request(Access::Read, true), (), read),
+            Err(())
+        );
+    }
+}
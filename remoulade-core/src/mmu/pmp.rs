@@ -0,0 +1,388 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Physical memory protection (PMP) with the Smepmp extensions.
+//!
+//! Entries have the smallest granularity of four bytes.
+
+use crate::snapshot::{Reader, Snapshot, SnapshotError, Writer};
+
+/// Kind of access checked against the PMP entries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+const R: u8 = 1 << 0;
+const W: u8 = 1 << 1;
+const X: u8 = 1 << 2;
+const A: u8 = 0b11 << 3;
+const L: u8 = 1 << 7;
+
+const TOR: u8 = 0b01 << 3;
+const NA4: u8 = 0b10 << 3;
+const NAPOT: u8 = 0b11 << 3;
+
+/// Machine mode lockdown
+const MML: u64 = 1 << 0;
+/// Machine mode whitelist policy
+const MMWP: u64 = 1 << 1;
+/// Rule locking bypass
+const RLB: u64 = 1 << 2;
+
+/// Permissions of `(machine, less privileged)` modes under MML, indexed by the `L`, `R`, `W` and
+/// `X` bits of an entry.
+const MML_PERMISSIONS: [(u8, u8); 16] = [
+    (0, 0),
+    (0, X),
+    (R | W, R),
+    (R | W, R | W),
+    (0, R),
+    (0, R | X),
+    (0, R | W),
+    (0, R | W | X),
+    (0, 0),
+    (X, 0),
+    (X, X),
+    (R | X, X),
+    (R, 0),
+    (R | X, 0),
+    (R | W, 0),
+    (R, R),
+];
+
+pub(crate) struct Pmp {
+    /// Number of implemented entries
+    entries: usize,
+    cfg: [u8; 64],
+    /// Bits `2..` of the addresses
+    addr: [u64; 64],
+    mseccfg: u64,
+    /// One past the last entry that isn't off
+    end: usize,
+}
+
+impl Pmp {
+    pub(crate) fn new(entries: usize) -> Self {
+        assert!(
+            matches!(entries, 0 | 16 | 64),
+            "unsupported number of PMP entries"
+        );
+        Self {
+            entries,
+            cfg: [0; 64],
+            addr: [0; 64],
+            mseccfg: 0,
+            end: 0,
+        }
+    }
+
+    pub(crate) fn cfg(&self, index: usize) -> u8 {
+        self.cfg[index]
+    }
+
+    pub(crate) fn addr(&self, index: usize) -> u64 {
+        self.addr[index]
+    }
+
+    pub(crate) fn mseccfg(&self) -> u64 {
+        self.mseccfg
+    }
+
+    fn locked(&self, index: usize) -> bool {
+        self.cfg[index] & L != 0 && self.mseccfg & RLB == 0
+    }
+
+    /// Writes the configuration of entry `index`, ignoring writes to locked entries.
+    pub(crate) fn write_cfg(&mut self, index: usize, value: u8) {
+        if index >= self.entries || self.locked(index) {
+            return;
+        }
+        // NOTE: Bits 6:5 are reserved and `W` without `R` is reserved unless MML gives it meaning.
+        let mut value = value & !0b0110_0000;
+        if self.mseccfg & MML == 0 && value & (R | W) == W {
+            value &= !W;
+        }
+        // NOTE: Under MML, only RLB allows adding rules that let machine mode execute.
+        let executable = value & X != 0 && value & W == 0 || value & (R | W) == W;
+        if self.mseccfg & (MML | RLB) == MML && value & L != 0 && executable {
+            return;
+        }
+
+        self.cfg[index] = value;
+        self.end = (0..self.entries)
+            .rev()
+            .find(|&index| self.cfg[index] & A != 0)
+            .map_or(0, |index| index + 1);
+    }
+
+    /// Writes the address of entry `index`, keeping the lower `bits` bits.
+    pub(crate) fn write_addr(&mut self, index: usize, value: u64, bits: u32) {
+        if index >= self.entries || self.locked(index) {
+            return;
+        }
+        // NOTE: A locked TOR entry also locks the start of its range.
+        let next = index + 1;
+        if next < self.entries && self.locked(next) && self.cfg[next] & A == TOR {
+            return;
+        }
+        self.addr[index] = value & (u64::MAX >> (64 - bits));
+    }
+
+    pub(crate) fn write_mseccfg(&mut self, value: u64) {
+        // NOTE: MML and MMWP stay set until reset.
+        let mut mseccfg = self.mseccfg | value & (MML | MMWP);
+        // NOTE: RLB can't be set once an entry is locked without it.
+        let locked = self.cfg.iter().any(|cfg| cfg & L != 0);
+        if self.mseccfg & RLB != 0 || !locked {
+            mseccfg = mseccfg & !RLB | value & RLB;
+        }
+        self.mseccfg = mseccfg;
+    }
+
+    /// The addresses `start..end` covered by entry `index`, if it's on.
+    fn range(&self, index: usize) -> Option<(u64, u64)> {
+        let addr = self.addr[index];
+        match self.cfg[index] & A {
+            TOR => {
+                let start = match index {
+                    0 => 0,
+                    _ => self.addr[index - 1] << 2,
+                };
+                Some((start, addr << 2))
+            }
+            NA4 => Some((addr << 2, (addr << 2) + 4)),
+            NAPOT => {
+                let ones = addr.trailing_ones();
+                let start = (addr & !((1 << ones) - 1)) << 2;
+                Some((start, start + (1 << (ones + 3))))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `len` bytes at `addr` may be accessed, from machine mode or otherwise.
+    pub(crate) fn check(&self, addr: u64, len: u64, access: Access, machine: bool) -> bool {
+        if self.entries == 0 {
+            return true;
+        }
+
+        let end = addr.saturating_add(len);
+        // NOTE: The lowest-numbered entry matching any byte decides.
+        for index in 0..self.end {
+            let Some((start, stop)) = self.range(index) else {
+                continue;
+            };
+            if start >= stop || addr >= stop || end <= start {
+                continue;
+            }
+            // Accesses only partially covered by the entry fail
+            if addr < start || end > stop {
+                return false;
+            }
+            return self.permits(self.cfg[index], access, machine);
+        }
+
+        match machine {
+            true => {
+                self.mseccfg & MMWP == 0 && !(self.mseccfg & MML != 0 && access == Access::Execute)
+            }
+            false => false,
+        }
+    }
+
+    fn permits(&self, cfg: u8, access: Access, machine: bool) -> bool {
+        let bit = match access {
+            Access::Read => R,
+            Access::Write => W,
+            Access::Execute => X,
+        };
+        if self.mseccfg & MML == 0 {
+            // NOTE: Unlocked entries don't apply to machine mode.
+            return machine && cfg & L == 0 || cfg & bit != 0;
+        }
+
+        let index = (cfg & L) >> 4 | (cfg & R) << 2 | (cfg & W) | (cfg & X) >> 2;
+        let (m, less) = MML_PERMISSIONS[index as usize];
+        match machine {
+            true => m & bit != 0,
+            false => less & bit != 0,
+        }
+    }
+}
+
+impl Snapshot for Pmp {
+    fn save(&self, w: &mut Writer) {
+        w.u64(self.entries as u64);
+        for index in 0..self.entries {
+            w.u8(self.cfg[index]);
+            w.u64(self.addr[index]);
+        }
+        w.u64(self.mseccfg);
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        r.expect("number of PMP entries", self.entries as u64)?;
+        for index in 0..self.entries {
+            self.cfg[index] = r.u8()?;
+            self.addr[index] = r.u64()?;
+        }
+        self.mseccfg = r.u64()?;
+        self.end = (0..self.entries)
+            .rev()
+            .find(|&index| self.cfg[index] & A != 0)
+            .map_or(0, |index| index + 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, Pmp, L, MML, MMWP, NA4, NAPOT, R, RLB, TOR, W, X};
+    use crate::{
+        csr::Csr,
+        ext::Extensions,
+        hart::{
+            exec::Error,
+            testing::{boot, load},
+            Hart, Privilege,
+        },
+        reg::{IRd, IRs1},
+    };
+
+    #[test]
+    fn test_matching() {
+        let mut pmp = Pmp::new(16);
+        // 0x1000..0x2000
+        pmp.write_addr(0, 0x1000 >> 2, 54);
+        pmp.write_addr(1, 0x2000 >> 2, 54);
+        pmp.write_cfg(1, TOR | R);
+        // 0x3000
+        pmp.write_addr(2, 0x3000 >> 2, 54);
+        pmp.write_cfg(2, NA4 | R | W);
+        // 0x4000..0x4100, overlapping a larger entry of lower priority
+        pmp.write_addr(3, (0x4000 | 0x7f) >> 2, 54);
+        pmp.write_cfg(3, NAPOT | X);
+        pmp.write_addr(4, (0x4000 | 0x7ff) >> 2, 54);
+        pmp.write_cfg(4, NAPOT | R | W | X);
+
+        let user = |addr, len, access| pmp.check(addr, len, access, false);
+        assert!(user(0x1000, 8, Access::Read));
+        assert!(!user(0x1000, 8, Access::Write));
+        assert!(!user(0x1ffc, 8, Access::Read));
+        assert!(user(0x3000, 4, Access::Write));
+        assert!(!user(0x3004, 4, Access::Write));
+        assert!(user(0x40fc, 4, Access::Execute));
+        assert!(!user(0x40fc, 4, Access::Read));
+        assert!(user(0x4100, 4, Access::Read));
+        assert!(!user(0x5000, 4, Access::Read));
+
+        // Unlocked entries leave machine mode alone
+        assert!(pmp.check(0x1000, 8, Access::Write, true));
+        assert!(pmp.check(0x5000, 4, Access::Execute, true));
+    }
+
+    #[test]
+    fn test_locking() {
+        let mut pmp = Pmp::new(16);
+        pmp.write_addr(1, 0x2000 >> 2, 54);
+        pmp.write_cfg(1, L | TOR | R | X);
+        assert!(!pmp.check(0x1000, 4, Access::Write, true));
+        assert!(pmp.check(0x1000, 4, Access::Execute, true));
+
+        // Neither the entry nor the start of its range can change
+        pmp.write_cfg(1, R | W | X);
+        pmp.write_addr(1, 0x3000 >> 2, 54);
+        pmp.write_addr(0, 0x1000 >> 2, 54);
+        assert_eq!(pmp.cfg(1), L | TOR | R | X);
+        assert_eq!(pmp.addr(1), 0x2000 >> 2);
+        assert_eq!(pmp.addr(0), 0);
+
+        // RLB can't be set once something is locked
+        pmp.write_mseccfg(RLB);
+        assert_eq!(pmp.mseccfg(), 0);
+    }
+
+    #[test]
+    fn test_smepmp() {
+        let mut pmp = Pmp::new(16);
+        pmp.write_mseccfg(RLB);
+        pmp.write_addr(0, (0x1000 | 0x7ff) >> 2, 54);
+        pmp.write_cfg(0, L | NAPOT | R | X);
+        pmp.write_addr(1, (0x2000 | 0x7ff) >> 2, 54);
+        pmp.write_cfg(1, NAPOT | R | W);
+        pmp.write_mseccfg(MML | MMWP);
+        // NOTE: Sticky bits stay set.
+        pmp.write_mseccfg(0);
+        assert_eq!(pmp.mseccfg(), MML | MMWP);
+
+        // Machine-mode code, user data and nothing else
+        assert!(pmp.check(0x1000, 4, Access::Execute, true));
+        assert!(!pmp.check(0x1000, 4, Access::Execute, false));
+        assert!(!pmp.check(0x2000, 4, Access::Read, true));
+        assert!(pmp.check(0x2000, 4, Access::Write, false));
+        assert!(!pmp.check(0x3000, 4, Access::Read, true));
+
+        // New machine-mode executable rules need RLB
+        pmp.write_cfg(2, L | NAPOT | X);
+        assert_eq!(pmp.cfg(2), 0);
+        pmp.write_cfg(2, L | NAPOT | R | W | X);
+        assert_eq!(pmp.cfg(2), L | NAPOT | R | W | X);
+    }
+
+    #[derive(Clone, Copy)]
+    struct Protected;
+
+    impl Extensions for Protected {
+        const PMP_ENTRIES: usize = 16;
+        const SMEPMP: bool = true;
+    }
+
+    #[test]
+    fn test_hart() {
+        // The PMP CSRs are looked up by their position in `Csr`
+        for i in 0..64 {
+            let addr = Csr::checked_from_u32(0x3B0 + i).unwrap();
+            assert_eq!(
+                addr.offset_in(Csr::Pmpaddr0, Csr::Pmpaddr63),
+                Some(i as usize)
+            );
+        }
+        let cfg = Csr::checked_from_u32(0x3AF).unwrap();
+        assert_eq!(cfg.offset_in(Csr::Pmpcfg0, Csr::Pmpcfg15), Some(15));
+
+        let program = [
+            0x00052083u32, // lw x1, 0(x10)
+            0x00152023,    // sw x1, 0(x10)
+            0x3a0025f3,    // csrr x11, pmpcfg0
+        ];
+        let mut ram = load(&program);
+
+        let mut hart: Hart<0, u64, false, false, false, (), false, false, Protected> = boot();
+        hart.reg_mut().set_rd(IRd::X10, 0x2000);
+        hart.set_privilege(Privilege::User);
+        assert_eq!(hart.step(&mut ram), Err(Error::InstructionAccessFault));
+
+        // Code at 0x1000..0x1800 and read-only data at 0x2000..0x2800
+        hart.write_csr(Csr::Pmpaddr0, (0x1000 | 0x3ff) >> 2);
+        hart.write_csr(Csr::Pmpaddr1, (0x2000 | 0x3ff) >> 2);
+        hart.write_csr(Csr::Pmpcfg0, ((NAPOT | R) as u64) << 8 | (NAPOT | X) as u64);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.step(&mut ram), Err(Error::StoreOrAmoAccessFault));
+
+        // Only machine mode may touch the PMP registers
+        hart.set_pc(0x1008);
+        assert_eq!(hart.step(&mut ram), Err(Error::IllegalInstruction));
+        hart.set_privilege(Privilege::Machine);
+        hart.step(&mut ram).unwrap();
+        assert_eq!(hart.reg().get_rs1(IRs1::X11), 0x191c);
+    }
+}
@@ -10,22 +10,251 @@
 //! Module containing register file and register types, as well as functions and utilities to use
 //! them effectively.
 
-pub trait RegType {}
-impl RegType for u32 {}
-impl RegType for u64 {}
-impl RegType for u128 {}
+use std::fmt::Debug;
+
+use crate::snapshot::{Reader, Snapshot, SnapshotError, Writer};
+
+/// Integer register type.
+///
+/// All arithmetic follows the RISC-V semantics for the respective `XLEN`, which means wrapping
+/// arithmetic, shift amounts truncated to `log2(XLEN)` bits and the divide-by-zero/overflow results
+/// from the M extension rather than panics.
+pub trait RegType: 'static + Copy + Default + Eq + Ord + Debug {
+    /// `XLEN`
+    const BITS: u32;
+
+    /// Truncates `value` to `XLEN` bits.
+    fn from_u128(value: u128) -> Self;
+    /// Truncates `value` to `XLEN` bits.
+    fn from_i128(value: i128) -> Self {
+        Self::from_u128(value as u128)
+    }
+    /// Zero-extends the register value.
+    fn as_u128(self) -> u128;
+    /// Sign-extends the register value.
+    fn as_i128(self) -> i128;
+
+    fn wrapping_add(self, rhs: Self) -> Self;
+    fn wrapping_sub(self, rhs: Self) -> Self;
+    fn wrapping_mul(self, rhs: Self) -> Self;
+
+    fn shl(self, shamt: u32) -> Self;
+    fn shr(self, shamt: u32) -> Self;
+    fn sra(self, shamt: u32) -> Self;
+
+    /// Signed less-than.
+    fn lt(self, rhs: Self) -> bool;
+
+    fn mulh(self, rhs: Self) -> Self;
+    fn mulhsu(self, rhs: Self) -> Self;
+    fn mulhu(self, rhs: Self) -> Self;
+    fn div(self, rhs: Self) -> Self;
+    fn divu(self, rhs: Self) -> Self;
+    fn rem(self, rhs: Self) -> Self;
+    fn remu(self, rhs: Self) -> Self;
+
+    fn clz(self) -> u32;
+    fn ctz(self) -> u32;
+    fn cpop(self) -> u32;
+    fn rol(self, shamt: u32) -> Self;
+    fn ror(self, shamt: u32) -> Self;
+    /// Reverses the order of bytes.
+    fn rev8(self) -> Self;
+
+    /// Sets every non-zero byte to all ones.
+    fn orc_b(self) -> Self {
+        let bytes = self
+            .as_u128()
+            .to_le_bytes()
+            .map(|b| if b == 0 { 0 } else { 0xff });
+        Self::from_u128(u128::from_le_bytes(bytes))
+    }
+
+    /// Lower half of the carry-less product.
+    fn clmul(self, rhs: Self) -> Self {
+        let (lhs, rhs) = (self.as_u128(), rhs.as_u128());
+        let value = (0..Self::BITS)
+            .filter(|i| rhs >> i & 1 == 1)
+            .fold(0, |acc, i| acc ^ lhs << i);
+        Self::from_u128(value)
+    }
+
+    /// Upper half of the carry-less product.
+    fn clmulh(self, rhs: Self) -> Self {
+        let (lhs, rhs) = (self.as_u128(), rhs.as_u128());
+        let value = (1..Self::BITS)
+            .filter(|i| rhs >> i & 1 == 1)
+            .fold(0, |acc, i| acc ^ lhs >> (Self::BITS - i));
+        Self::from_u128(value)
+    }
+
+    /// Bits `2 * XLEN - 2` to `XLEN - 1` of the carry-less product.
+    fn clmulr(self, rhs: Self) -> Self {
+        let (lhs, rhs) = (self.as_u128(), rhs.as_u128());
+        let value = (0..Self::BITS)
+            .filter(|i| rhs >> i & 1 == 1)
+            .fold(0, |acc, i| acc ^ lhs >> (Self::BITS - i - 1));
+        Self::from_u128(value)
+    }
+
+    /// Truncates the register value to 64 bits.
+    fn as_u64(self) -> u64 {
+        self.as_u128() as u64
+    }
+
+    /// Returns the register value as a physical address if it fits in 64 bits.
+    fn as_addr(self) -> Option<u64> {
+        u64::try_from(self.as_u128()).ok()
+    }
+}
+
+macro_rules! impl_reg_type {
+    ( $t:ty, $s:ty ) => {
+        fn from_u128(value: u128) -> Self {
+            value as $t
+        }
+        fn as_u128(self) -> u128 {
+            self as u128
+        }
+        fn as_i128(self) -> i128 {
+            self as $s as i128
+        }
+        fn wrapping_add(self, rhs: Self) -> Self {
+            <$t>::wrapping_add(self, rhs)
+        }
+        fn wrapping_sub(self, rhs: Self) -> Self {
+            <$t>::wrapping_sub(self, rhs)
+        }
+        fn wrapping_mul(self, rhs: Self) -> Self {
+            <$t>::wrapping_mul(self, rhs)
+        }
+        fn shl(self, shamt: u32) -> Self {
+            self.wrapping_shl(shamt)
+        }
+        fn shr(self, shamt: u32) -> Self {
+            self.wrapping_shr(shamt)
+        }
+        fn sra(self, shamt: u32) -> Self {
+            (self as $s).wrapping_shr(shamt) as $t
+        }
+        fn lt(self, rhs: Self) -> bool {
+            (self as $s) < (rhs as $s)
+        }
+        fn div(self, rhs: Self) -> Self {
+            match rhs {
+                0 => <$t>::MAX,
+                _ => (self as $s).wrapping_div(rhs as $s) as $t,
+            }
+        }
+        fn divu(self, rhs: Self) -> Self {
+            self.checked_div(rhs).unwrap_or(<$t>::MAX)
+        }
+        fn rem(self, rhs: Self) -> Self {
+            match rhs {
+                0 => self,
+                _ => (self as $s).wrapping_rem(rhs as $s) as $t,
+            }
+        }
+        fn remu(self, rhs: Self) -> Self {
+            self.checked_rem(rhs).unwrap_or(self)
+        }
+        fn clz(self) -> u32 {
+            self.leading_zeros()
+        }
+        fn ctz(self) -> u32 {
+            self.trailing_zeros()
+        }
+        fn cpop(self) -> u32 {
+            self.count_ones()
+        }
+        fn rol(self, shamt: u32) -> Self {
+            self.rotate_left(shamt)
+        }
+        fn ror(self, shamt: u32) -> Self {
+            self.rotate_right(shamt)
+        }
+        fn rev8(self) -> Self {
+            self.swap_bytes()
+        }
+    };
+}
+
+impl RegType for u32 {
+    const BITS: u32 = 32;
+    impl_reg_type!(u32, i32);
+
+    fn mulh(self, rhs: Self) -> Self {
+        ((self as i32 as i64 * rhs as i32 as i64) >> 32) as u32
+    }
+    fn mulhsu(self, rhs: Self) -> Self {
+        ((self as i32 as i64).wrapping_mul(rhs as i64) >> 32) as u32
+    }
+    fn mulhu(self, rhs: Self) -> Self {
+        ((self as u64 * rhs as u64) >> 32) as u32
+    }
+}
+
+impl RegType for u64 {
+    const BITS: u32 = 64;
+    impl_reg_type!(u64, i64);
+
+    fn mulh(self, rhs: Self) -> Self {
+        ((self as i64 as i128 * rhs as i64 as i128) >> 64) as u64
+    }
+    fn mulhsu(self, rhs: Self) -> Self {
+        ((self as i64 as i128).wrapping_mul(rhs as i128) >> 64) as u64
+    }
+    fn mulhu(self, rhs: Self) -> Self {
+        ((self as u128 * rhs as u128) >> 64) as u64
+    }
+}
+
+impl RegType for u128 {
+    const BITS: u32 = 128;
+    impl_reg_type!(u128, i128);
+
+    // There is no wider native type, so the signed variants are derived from the unsigned upper
+    // half by subtracting the contributions of the sign bits.
+    fn mulh(self, rhs: Self) -> Self {
+        let hi = self.mulhu(rhs);
+        let hi = if self.lt(0) { hi.wrapping_sub(rhs) } else { hi };
+        if rhs.lt(0) {
+            hi.wrapping_sub(self)
+        } else {
+            hi
+        }
+    }
+    fn mulhsu(self, rhs: Self) -> Self {
+        let hi = self.mulhu(rhs);
+        if self.lt(0) {
+            hi.wrapping_sub(rhs)
+        } else {
+            hi
+        }
+    }
+    fn mulhu(self, rhs: Self) -> Self {
+        let (a1, a0) = (self >> 64, self & u64::MAX as u128);
+        let (b1, b0) = (rhs >> 64, rhs & u64::MAX as u128);
+        let lo = a0 * b0;
+        let mid1 = a1 * b0;
+        let mid2 = a0 * b1;
+        let hi = a1 * b1;
+        let carry = ((lo >> 64) + (mid1 & u64::MAX as u128) + (mid2 & u64::MAX as u128)) >> 64;
+        hi + (mid1 >> 64) + (mid2 >> 64) + carry
+    }
+}
 
 pub struct RegFile<T: RegType> {
     reg: [T; 33],
 }
 
-impl<T: Default + RegType + Copy> Default for RegFile<T> {
+impl<T: RegType> Default for RegFile<T> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<T: Default + RegType + Copy> RegFile<T> {
+impl<T: RegType> RegFile<T> {
     pub fn new() -> Self {
         Self {
             reg: [T::default(); 33],
@@ -184,13 +413,13 @@ impl IRs1 {
             28 => IRs1::X28,
             29 => IRs1::X29,
             30 => IRs1::X30,
-            31 => IRs1::X31,
-            _ => unsafe { std::hint::unreachable_unchecked() },
+            // NOTE: Only 31 is left after masking to 5 bits.
+            _ => IRs1::X31,
         }
     }
 
     pub fn decode_raw32(raw32: u32) -> Self {
-        Self::checked_from_u32(raw32 >> 15 & 0x1f).expect("Extracted value was larger than 5 bits")
+        Self::wrapping_from_u32(raw32 >> 15)
     }
 }
 
@@ -236,13 +465,13 @@ impl IRs2 {
             28 => IRs2::X28,
             29 => IRs2::X29,
             30 => IRs2::X30,
-            31 => IRs2::X31,
-            _ => unsafe { std::hint::unreachable_unchecked() },
+            // NOTE: Only 31 is left after masking to 5 bits.
+            _ => IRs2::X31,
         }
     }
 
     pub fn decode_raw32(raw32: u32) -> Self {
-        Self::checked_from_u32(raw32 >> 20 & 0x1f).expect("Extracted value was larger than 5 bits")
+        Self::wrapping_from_u32(raw32 >> 20)
     }
 }
 
@@ -288,17 +517,22 @@ impl IRd {
             28 => IRd::X28,
             29 => IRd::X29,
             30 => IRd::X30,
-            31 => IRd::X31,
-            _ => unsafe { std::hint::unreachable_unchecked() },
+            // NOTE: Only 31 is left after masking to 5 bits.
+            _ => IRd::X31,
         }
     }
 
     pub fn decode_raw32(raw32: u32) -> Self {
-        Self::checked_from_u32(raw32 >> 7 & 0x1f).expect("Extracted value was larger than 5 bits")
+        Self::wrapping_from_u32(raw32 >> 7)
     }
 }
 
-impl<T: RegType + Copy> RegFile<T> {
+impl<T: RegType> RegFile<T> {
+    #[cfg(feature = "jit")]
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut T {
+        self.reg.as_mut_ptr()
+    }
+
     pub fn get_rs1(&self, rs1: IRs1) -> T {
         unsafe { *self.reg.get_unchecked(rs1 as usize) }
     }
@@ -314,6 +548,21 @@ impl<T: RegType + Copy> RegFile<T> {
     }
 }
 
+impl<T: RegType> Snapshot for RegFile<T> {
+    fn save(&self, w: &mut Writer) {
+        for value in &self.reg[..32] {
+            w.u128(value.as_u128());
+        }
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        for value in &mut self.reg[..32] {
+            *value = T::from_u128(r.u128()?);
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{IRd, IRs1, IRs2};
@@ -342,6 +591,41 @@ mod tests {
         assert_eq!(file.reg[32], 20);
     }
 
+    #[test]
+    fn test_m_edge_cases() {
+        use crate::reg::RegType;
+
+        assert_eq!(RegType::div(7u32, 0), u32::MAX);
+        assert_eq!(RegType::rem(7u32, 0), 7);
+        assert_eq!(RegType::div(i32::MIN as u32, -1i32 as u32), i32::MIN as u32);
+        assert_eq!(RegType::rem(i32::MIN as u32, -1i32 as u32), 0);
+
+        // The 128-bit products have no native widening type to fall back on
+        let (a, b) = (1u128 << 100, 1u128 << 60);
+        assert_eq!(a.mulhu(b), 1 << 32);
+        assert_eq!(a.wrapping_neg().mulh(b), (-1i128 << 32) as u128);
+        assert_eq!(u128::MAX.mulhu(u128::MAX), u128::MAX - 1);
+        assert_eq!(u128::MAX.mulhsu(u128::MAX), u128::MAX);
+        assert_eq!(u128::MAX.mulh(u128::MAX), 0);
+    }
+
+    #[test]
+    fn test_carry_less_multiplication() {
+        use crate::reg::RegType;
+
+        assert_eq!(0b11u32.clmul(0b11), 0b101);
+        assert_eq!(0b11u32.clmulh(0b11), 0);
+
+        let top = 1u32 << 31;
+        assert_eq!(top.clmul(top), 0);
+        assert_eq!(top.clmulh(top), 1 << 30);
+        assert_eq!(top.clmulr(top), 1 << 31);
+
+        let top = 1u64 << 63;
+        assert_eq!(top.clmulh(3), 1);
+        assert_eq!(top.clmulr(3), 3);
+    }
+
     #[test]
     fn test_decode_regs() {
         let raw32_expected = [
@@ -0,0 +1,442 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Deterministic record and replay.
+//!
+//! A hart on its own is deterministic.
+//! What isn't are its inputs: bytes read from devices, `time`, entropy from the host, and, when
+//! harts run on separate threads, the order in which they reach shared memory.
+//! A [`Journal`] shared by every hart and device records these as they happen, and replays them
+//! to reproduce the same execution.
+//!
+//! Harts are ordered at their atomic memory operations, which is where they synchronize.
+//! While replaying, a hart reaching one of these points waits until every event recorded before
+//! it has been replayed.
+
+use std::{
+    ops::Range,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+
+use crate::{
+    bus::{AccessFault, Bus},
+    snapshot::{Reader, Snapshot, SnapshotError, Writer},
+};
+
+/// A nondeterministic input to a hart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Bytes read from a device
+    Input { addr: u64, data: Vec<u8> },
+    /// A value read from `time` or `timeh`
+    Time(u64),
+    /// The value read from `seed`
+    Seed(u32),
+    /// An AMO or `lr`, which only orders the harts
+    Atomic,
+    /// Whether an `sc` succeeded
+    Sc(bool),
+}
+
+#[derive(Default)]
+struct State {
+    /// Events by the index of the hart they happened on, in the order they happened
+    events: Vec<(usize, Event)>,
+    replaying: bool,
+    /// Index of the next event to replay
+    next: usize,
+    /// Index of the first event that didn't happen again
+    diverged: Option<usize>,
+}
+
+impl State {
+    /// The event to replay next, unless replay has ended.
+    fn pending(&self) -> Option<&(usize, Event)> {
+        match self.replaying && self.diverged.is_none() {
+            true => self.events.get(self.next),
+            false => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Notified whenever replay moves on
+    turn: Condvar,
+}
+
+/// A log of the nondeterministic inputs to a machine.
+///
+/// Clones refer to the same log, so one can be given to every hart and device, on any thread.
+#[derive(Clone, Default)]
+pub struct Journal {
+    shared: Arc<Shared>,
+}
+
+impl Journal {
+    /// Creates an empty journal recording events as they happen.
+    pub fn record() -> Self {
+        Self::default()
+    }
+
+    /// Creates a journal replaying `events`, as returned by [`events`](Self::events).
+    ///
+    /// Once every event is replayed, or execution diverges from them, inputs are live again.
+    pub fn replay(events: Vec<(usize, Event)>) -> Self {
+        let state = State {
+            events,
+            replaying: true,
+            ..State::default()
+        };
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(state),
+                turn: Condvar::new(),
+            }),
+        }
+    }
+
+    /// The events recorded, or being replayed, by the index of the hart they happened on.
+    pub fn events(&self) -> Vec<(usize, Event)> {
+        self.lock().events.clone()
+    }
+
+    /// The index of the first event that didn't happen again while replaying.
+    pub fn diverged(&self) -> Option<usize> {
+        self.lock().diverged
+    }
+
+    /// Wraps the bus of hart `hart`, logging what it reads from the `devices`.
+    ///
+    /// While replaying, recorded reads are answered from the journal without reaching the
+    /// device.
+    /// Writes always reach the device.
+    pub fn bus<B: Bus>(&self, hart: usize, bus: B, devices: Vec<Range<u64>>) -> Logged<B> {
+        Logged {
+            journal: self.clone(),
+            hart,
+            bus,
+            devices,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits until the next event to replay happened on hart `hart`.
+    ///
+    /// The journal stays locked until the returned turn is dropped, so events on other harts
+    /// are ordered around whatever happens in between.
+    pub(crate) fn turn(&self, hart: usize) -> Turn<'_> {
+        let mut state = self.lock();
+        while state.pending().is_some_and(|&(other, _)| other != hart) {
+            state = self
+                .shared
+                .turn
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        Turn {
+            shared: &self.shared,
+            state,
+            hart,
+        }
+    }
+}
+
+/// Exclusive access to the journal for hart `hart`.
+pub(crate) struct Turn<'a> {
+    shared: &'a Shared,
+    state: MutexGuard<'a, State>,
+    hart: usize,
+}
+
+impl Turn<'_> {
+    /// The event to replay in place of the live input, if replaying.
+    pub(crate) fn replayed(&self) -> Option<&Event> {
+        self.state.pending().map(|(_, event)| event)
+    }
+
+    /// Logs `event` as having happened.
+    ///
+    /// While replaying, this moves on to the next event, or ends replay if it isn't the one
+    /// that was recorded.
+    pub(crate) fn commit(&mut self, event: Event) {
+        let state = &mut *self.state;
+        if !state.replaying {
+            state.events.push((self.hart, event));
+        } else if let Some((_, replayed)) = state.pending() {
+            match *replayed == event {
+                true => state.next += 1,
+                false => state.diverged = Some(state.next),
+            }
+        }
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        self.shared.turn.notify_all();
+    }
+}
+
+/// A bus logging what is read from devices in a [`Journal`].
+pub struct Logged<B> {
+    journal: Journal,
+    hart: usize,
+    bus: B,
+    devices: Vec<Range<u64>>,
+}
+
+impl<B> Logged<B> {
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+}
+
+impl<B: Bus> Bus for Logged<B> {
+    fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), AccessFault> {
+        if !self.devices.iter().any(|device| device.contains(&addr)) {
+            return self.bus.read(addr, buf);
+        }
+
+        let mut turn = self.journal.turn(self.hart);
+        match turn.replayed() {
+            Some(Event::Input { addr: at, data }) if *at == addr && data.len() == buf.len() => {
+                buf.copy_from_slice(data);
+            }
+            _ => self.bus.read(addr, buf)?,
+        }
+        turn.commit(Event::Input {
+            addr,
+            data: buf.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u64, buf: &[u8]) -> Result<(), AccessFault> {
+        self.bus.write(addr, buf)
+    }
+}
+
+impl Snapshot for Journal {
+    fn save(&self, w: &mut Writer) {
+        let state = self.lock();
+        w.u64(state.events.len() as u64);
+        for (hart, event) in &state.events {
+            w.u64(*hart as u64);
+            match event {
+                Event::Input { addr, data } => {
+                    w.u8(0);
+                    w.u64(*addr);
+                    w.u64(data.len() as u64);
+                    w.bytes(data);
+                }
+                Event::Time(value) => {
+                    w.u8(1);
+                    w.u64(*value);
+                }
+                Event::Seed(value) => {
+                    w.u8(2);
+                    w.u32(*value);
+                }
+                Event::Atomic => w.u8(3),
+                Event::Sc(success) => {
+                    w.u8(4);
+                    w.bool(*success);
+                }
+            }
+        }
+    }
+
+    /// Restores the recorded events and starts replaying them, for every clone of the journal.
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        let mut events = Vec::new();
+        for _ in 0..r.u64()? {
+            let hart = r.u64()? as usize;
+            let event = match r.u8()? {
+                0 => {
+                    let addr = r.u64()?;
+                    let len = r.u64()? as usize;
+                    let data = r.bytes(len)?.to_vec();
+                    Event::Input { addr, data }
+                }
+                1 => Event::Time(r.u64()?),
+                2 => Event::Seed(r.u32()?),
+                3 => Event::Atomic,
+                4 => Event::Sc(r.bool()?),
+                tag => Err(SnapshotError::Invalid(format!("{tag} is not an event")))?,
+            };
+            events.push((hart, event));
+        }
+        *self.lock() = State {
+            events,
+            replaying: true,
+            ..State::default()
+        };
+        self.shared.turn.notify_all();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+        ops::Range,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use super::Journal;
+    use crate::{
+        bus::{AccessFault, Bus, Ram},
+        ext::Extensions,
+        hart::{testing::write_program, Entropy, Hart},
+        inst::Instruction,
+        reg::{IRd, IRs1},
+        snapshot,
+    };
+
+    #[derive(Clone, Copy)]
+    struct Zkr;
+
+    impl Extensions for Zkr {
+        const ZKR: bool = true;
+    }
+
+    type Inst = Instruction<u64, false, true, true, ()>;
+
+    const NOISE: Range<u64> = 0..0x1000;
+
+    /// Memory, and a device reading as noise.
+    struct Noisy(Ram);
+
+    impl Bus for Noisy {
+        fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), AccessFault> {
+            if NOISE.contains(&addr) {
+                let noise = RandomState::new().build_hasher().finish();
+                buf.copy_from_slice(&noise.to_le_bytes()[..buf.len()]);
+                return Ok(());
+            }
+            self.0.read(addr, buf)
+        }
+
+        fn write(&mut self, addr: u64, buf: &[u8]) -> Result<(), AccessFault> {
+            self.0.write(addr, buf)
+        }
+    }
+
+    fn load(ram: &mut Ram, source: &str) {
+        write_program(ram, 0x8000_0000, &Inst::assemble(source).unwrap());
+    }
+
+    /// Runs a hart reading the device, `time` and `seed`, returning what it read.
+    fn inputs(journal: &Journal, time: u64) -> [u64; 3] {
+        let mut ram = Ram::new(0x8000_0000, 0x1000);
+        load(&mut ram, "ld a0,8(zero)\nrdtime a1\ncsrrw a2,seed,zero");
+        let mut bus = journal.bus(0, Noisy(ram), vec![NOISE]);
+
+        let mut hart = Hart::<0, u64, false, true, true, (), true, false, Zkr>::new();
+        hart.set_pc(0x8000_0000);
+        hart.set_entropy(Entropy::Host);
+        hart.set_time(time);
+        hart.set_journal(Some(journal.clone()));
+        hart.run(&mut bus, 3).unwrap();
+        [IRs1::X10, IRs1::X11, IRs1::X12].map(|reg| hart.reg().get_rs1(reg))
+    }
+
+    #[test]
+    fn test_replay() {
+        let journal = Journal::record();
+        let recorded = inputs(&journal, 1000);
+        assert_eq!(recorded[1], 1000);
+        assert_eq!(journal.events().len(), 3);
+
+        let mut file = Vec::new();
+        snapshot::save(&mut file, &[("journal", &journal)]).unwrap();
+        let mut replay = Journal::record();
+        snapshot::restore(&mut &file[..], &mut [("journal", &mut replay)]).unwrap();
+        assert_eq!(inputs(&replay, 2000), recorded);
+        assert_eq!(replay.diverged(), None);
+
+        // NOTE: Once replay is over, inputs are live again.
+        assert_eq!(inputs(&replay, 3000)[1], 3000);
+    }
+
+    /// Memory shared by harts on separate threads.
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<Ram>>);
+
+    impl Bus for Shared {
+        fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), AccessFault> {
+            self.0.lock().unwrap().read(addr, buf)
+        }
+
+        fn write(&mut self, addr: u64, buf: &[u8]) -> Result<(), AccessFault> {
+            self.0.lock().unwrap().write(addr, buf)
+        }
+    }
+
+    /// Runs a hart taking 50 tickets, writing its index to the log at each.
+    fn take_tickets<const ID: usize>(journal: Journal, mut bus: Shared) {
+        let mut hart = Hart::<ID, u64, false, true, true, (), true, false>::new();
+        hart.set_pc(0x8000_0000);
+        hart.set_journal(Some(journal));
+        hart.reg_mut().set_rd(IRd::X10, ID as u64);
+        hart.run(&mut bus, 4 + 50 * 5 + 1).unwrap();
+    }
+
+    /// Runs two harts on separate threads, returning the order they took their tickets in.
+    fn race(journal: &Journal) -> Vec<u8> {
+        let mut ram = Ram::new(0x8000_0000, 0x2000);
+        load(
+            &mut ram,
+            "
+                    auipc s1,1
+                    addi s2,s1,8
+                    li t0,1
+                    li t1,50
+            loop:   amoadd.w t2,t0,(s1)
+                    add t3,t2,s2
+                    sb a0,0(t3)
+                    addi t1,t1,-1
+                    bnez t1,loop
+                    nop
+            ",
+        );
+        let bus = Shared(Arc::new(Mutex::new(ram)));
+
+        let (journal0, bus0) = (journal.clone(), bus.clone());
+        let hart0 = thread::spawn(move || take_tickets::<0>(journal0, bus0));
+        let (journal1, bus1) = (journal.clone(), bus.clone());
+        let hart1 = thread::spawn(move || take_tickets::<1>(journal1, bus1));
+        hart0.join().unwrap();
+        hart1.join().unwrap();
+
+        let ram = bus.0.lock().unwrap();
+        ram.as_slice()[0x1008..0x1008 + 100].to_vec()
+    }
+
+    #[test]
+    fn test_replay_threads() {
+        let journal = Journal::record();
+        let recorded = race(&journal);
+        assert_eq!(recorded.iter().filter(|&&hart| hart == 1).count(), 50);
+
+        for _ in 0..4 {
+            let replay = Journal::replay(journal.events());
+            assert_eq!(race(&replay), recorded);
+            assert_eq!(replay.diverged(), None);
+        }
+    }
+}
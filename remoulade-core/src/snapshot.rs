@@ -0,0 +1,235 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Checkpoints of a whole machine.
+//!
+//! A machine is whatever the embedder puts together: harts, memory and devices.
+//! Each part implementing [`Snapshot`] is saved to a named section of a versioned binary file,
+//! so a machine can be booted once and restored any number of times, on any host.
+//!
+//! ```text
+//! "REMOULAD" | version: u32 | count: u32 | count * (name length: u32 | name | length: u64 | state)
+//! ```
+//!
+//! Numbers are little-endian.
+
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+    io::{self, Read, Write},
+};
+
+const MAGIC: [u8; 8] = *b"REMOULAD";
+
+/// Version of the snapshot format, bumped whenever the saved state of any part changes.
+pub const VERSION: u32 = 1;
+
+/// Raised when restoring a snapshot fails.
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The file isn't a snapshot, or is truncated or corrupt.
+    Invalid(String),
+    /// The snapshot was saved by another version of the format.
+    Version(u32),
+    /// The saved state doesn't fit the part it's restored into, e.g. a hart of another
+    /// configuration.
+    Mismatch(String),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Invalid(message) => write!(f, "invalid snapshot: {message}"),
+            Self::Version(version) => {
+                write!(f, "snapshot has version {version}, expected {VERSION}")
+            }
+            Self::Mismatch(message) => write!(f, "snapshot doesn't match: {message}"),
+        }
+    }
+}
+
+impl StdError for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Something whose state can be saved and restored, like a hart, memory or a device.
+///
+/// Only architectural state is saved.
+/// Caches such as translated code are dropped on restore.
+pub trait Snapshot {
+    fn save(&self, w: &mut Writer);
+
+    /// Replaces the state with the saved state.
+    ///
+    /// On error the state may be partially restored.
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError>;
+}
+
+/// Collects the saved state of a part.
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u128(&mut self, value: u128) {
+        self.bytes(&value.to_le_bytes());
+    }
+}
+
+/// Reads back the saved state of a part.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if len > self.buf.len() {
+            Err(SnapshotError::Invalid(
+                "unexpected end of state".to_string(),
+            ))?
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(SnapshotError::Invalid(format!("{value} is not a boolean"))),
+        }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn u128(&mut self) -> Result<u128, SnapshotError> {
+        Ok(u128::from_le_bytes(self.bytes(16)?.try_into().unwrap()))
+    }
+
+    /// Reads a value that has to be `expected` for the state to fit, like a size.
+    pub fn expect(&mut self, what: &str, expected: u64) -> Result<(), SnapshotError> {
+        match self.u64()? {
+            saved if saved == expected => Ok(()),
+            saved => Err(SnapshotError::Mismatch(format!(
+                "{what} is {saved}, not {expected}"
+            ))),
+        }
+    }
+}
+
+/// Saves `parts` to `out`, each to the section with the given name.
+pub fn save(out: &mut impl Write, parts: &[(&str, &dyn Snapshot)]) -> io::Result<()> {
+    out.write_all(&MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&(parts.len() as u32).to_le_bytes())?;
+    for (name, part) in parts {
+        let mut w = Writer::default();
+        part.save(&mut w);
+        out.write_all(&(name.len() as u32).to_le_bytes())?;
+        out.write_all(name.as_bytes())?;
+        out.write_all(&(w.buf.len() as u64).to_le_bytes())?;
+        out.write_all(&w.buf)?;
+    }
+    Ok(())
+}
+
+/// Restores `parts` from the sections with the given names.
+///
+/// The snapshot has to hold exactly these sections, in any order.
+pub fn restore(
+    input: &mut impl Read,
+    parts: &mut [(&str, &mut dyn Snapshot)],
+) -> Result<(), SnapshotError> {
+    let mut buf = Vec::new();
+    input.read_to_end(&mut buf)?;
+    let mut r = Reader { buf: &buf };
+
+    if r.bytes(MAGIC.len())? != MAGIC {
+        Err(SnapshotError::Invalid("not a snapshot".to_string()))?
+    }
+    match r.u32()? {
+        VERSION => {}
+        version => Err(SnapshotError::Version(version))?,
+    }
+
+    let mut sections = Vec::new();
+    for _ in 0..r.u32()? {
+        let len = r.u32()? as usize;
+        let name = std::str::from_utf8(r.bytes(len)?)
+            .map_err(|_| SnapshotError::Invalid("section name is not UTF-8".to_string()))?;
+        let len = usize::try_from(r.u64()?)
+            .map_err(|_| SnapshotError::Invalid(format!("section `{name}` is too large")))?;
+        sections.push((name, r.bytes(len)?));
+    }
+    if !r.buf.is_empty() {
+        Err(SnapshotError::Invalid("trailing data".to_string()))?
+    }
+
+    // NOTE: Sections are matched up front so nothing is restored from a snapshot of another
+    //       machine.
+    let mut states = Vec::new();
+    for (name, _) in parts.iter() {
+        match sections.iter().position(|&(section, _)| section == *name) {
+            Some(index) => states.push(sections.swap_remove(index).1),
+            None => Err(SnapshotError::Mismatch(format!("no section `{name}`")))?,
+        }
+    }
+    if let Some((name, _)) = sections.first() {
+        Err(SnapshotError::Mismatch(format!(
+            "unexpected section `{name}`"
+        )))?
+    }
+
+    for ((name, part), state) in parts.iter_mut().zip(states) {
+        let mut r = Reader { buf: state };
+        part.restore(&mut r)?;
+        if !r.buf.is_empty() {
+            Err(SnapshotError::Invalid(format!("trailing data in `{name}`")))?
+        }
+    }
+    Ok(())
+}
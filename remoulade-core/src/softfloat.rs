@@ -0,0 +1,915 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Software IEEE 754 binary floating-point arithmetic.
+//!
+//! Values are passed around as bit patterns in the lower bits of a `u128`.
+//! Every operation is correctly rounded in all five rounding modes and accrues the exceptions it
+//! raises in the layout of `fflags`, with NaN results replaced by the canonical NaN as RISC-V
+//! requires.
+//!
+//! Internally, finite values are unpacked to an integer significand and an exponent and combined
+//! exactly in 256 bits before a single rounding step.
+
+use std::cmp::Ordering;
+
+/// Invalid operation.
+pub const INVALID: u8 = 1 << 4;
+/// Division by zero.
+pub const DIVIDE_BY_ZERO: u8 = 1 << 3;
+pub const OVERFLOW: u8 = 1 << 2;
+pub const UNDERFLOW: u8 = 1 << 1;
+pub const INEXACT: u8 = 1;
+
+/// An IEEE 754 binary interchange format.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Format {
+    /// Width of the exponent field
+    exp: u32,
+    /// Width of the trailing significand field
+    frac: u32,
+}
+
+impl Format {
+    pub const HALF: Self = Self { exp: 5, frac: 10 };
+    /// Brain floating-point, which is not an IEEE format but behaves like one.
+    pub const BF16: Self = Self { exp: 8, frac: 7 };
+    pub const SINGLE: Self = Self { exp: 8, frac: 23 };
+    pub const DOUBLE: Self = Self { exp: 11, frac: 52 };
+    pub const QUAD: Self = Self { exp: 15, frac: 112 };
+
+    pub const fn bits(self) -> u32 {
+        1 + self.exp + self.frac
+    }
+
+    fn precision(self) -> i32 {
+        self.frac as i32 + 1
+    }
+
+    fn bias(self) -> i32 {
+        (1 << (self.exp - 1)) - 1
+    }
+
+    /// Exponent of the smallest normal number.
+    fn emin(self) -> i32 {
+        1 - self.bias()
+    }
+
+    /// Exponent of the largest finite number.
+    fn emax(self) -> i32 {
+        self.bias()
+    }
+
+    fn sign(self, sign: bool) -> u128 {
+        (sign as u128) << (self.bits() - 1)
+    }
+
+    fn frac_mask(self) -> u128 {
+        (1 << self.frac) - 1
+    }
+
+    fn exp_field(self) -> u128 {
+        ((1 << self.exp) - 1) << self.frac
+    }
+
+    pub fn canonical_nan(self) -> u128 {
+        self.exp_field() | 1 << (self.frac - 1)
+    }
+
+    fn infinity(self, sign: bool) -> u128 {
+        self.sign(sign) | self.exp_field()
+    }
+
+    fn max_finite(self, sign: bool) -> u128 {
+        self.infinity(sign) - 1
+    }
+
+    fn zero(self, sign: bool) -> u128 {
+        self.sign(sign)
+    }
+
+    /// Flips the sign bit.
+    pub fn negate(self, bits: u128) -> u128 {
+        bits ^ self.sign(true)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude,
+}
+
+impl Rounding {
+    /// Decodes the static rounding modes of the `rm` field and `frm`.
+    pub fn from_rm(rm: u8) -> Option<Self> {
+        let rounding = match rm {
+            0b000 => Self::NearestEven,
+            0b001 => Self::TowardZero,
+            0b010 => Self::Down,
+            0b011 => Self::Up,
+            0b100 => Self::NearestMaxMagnitude,
+            _ => None?,
+        };
+        Some(rounding)
+    }
+}
+
+/// A 256-bit unsigned integer, wide enough for the exact product of two binary128 significands.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct U256 {
+    hi: u128,
+    lo: u128,
+}
+
+impl U256 {
+    fn new(value: u128) -> Self {
+        Self { hi: 0, lo: value }
+    }
+
+    fn mul(lhs: u128, rhs: u128) -> Self {
+        let (l0, l1) = (lhs as u64 as u128, lhs >> 64);
+        let (r0, r1) = (rhs as u64 as u128, rhs >> 64);
+        let (p00, p01, p10, p11) = (l0 * r0, l0 * r1, l1 * r0, l1 * r1);
+        let mid = (p00 >> 64) + (p01 as u64 as u128) + (p10 as u64 as u128);
+        Self {
+            hi: p11 + (p01 >> 64) + (p10 >> 64) + (mid >> 64),
+            lo: p00 as u64 as u128 | mid << 64,
+        }
+    }
+
+    fn is_zero(self) -> bool {
+        self.hi == 0 && self.lo == 0
+    }
+
+    /// Number of significant bits.
+    fn bits(self) -> u32 {
+        match self.hi {
+            0 => 128 - self.lo.leading_zeros(),
+            hi => 256 - hi.leading_zeros(),
+        }
+    }
+
+    fn bit(self, n: u32) -> bool {
+        match n {
+            0..128 => self.lo >> n & 1 == 1,
+            128..256 => self.hi >> (n - 128) & 1 == 1,
+            _ => false,
+        }
+    }
+
+    fn shl(self, n: u32) -> Self {
+        match n {
+            0 => self,
+            1..128 => Self {
+                hi: self.hi << n | self.lo >> (128 - n),
+                lo: self.lo << n,
+            },
+            128..256 => Self {
+                hi: self.lo << (n - 128),
+                lo: 0,
+            },
+            _ => Self::new(0),
+        }
+    }
+
+    fn shr(self, n: u32) -> Self {
+        match n {
+            0 => self,
+            1..128 => Self {
+                hi: self.hi >> n,
+                lo: self.lo >> n | self.hi << (128 - n),
+            },
+            128..256 => Self::new(self.hi >> (n - 128)),
+            _ => Self::new(0),
+        }
+    }
+
+    /// Whether any of the lowest `n` bits are set.
+    fn any_below(self, n: u32) -> bool {
+        match n {
+            0 => false,
+            1..256 => !self.shl(256 - n).is_zero(),
+            _ => !self.is_zero(),
+        }
+    }
+
+    /// Shifts right, collecting the bits shifted out in the lowest bit.
+    fn shr_sticky(self, n: u32) -> Self {
+        let mut value = self.shr(n);
+        value.lo |= self.any_below(n) as u128;
+        value
+    }
+
+    fn add(self, rhs: Self) -> Self {
+        let (lo, carry) = self.lo.overflowing_add(rhs.lo);
+        Self {
+            hi: self.hi + rhs.hi + carry as u128,
+            lo,
+        }
+    }
+
+    fn sub(self, rhs: Self) -> Self {
+        let (lo, borrow) = self.lo.overflowing_sub(rhs.lo);
+        Self {
+            hi: self.hi - rhs.hi - borrow as u128,
+            lo,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Nan {
+        signaling: bool,
+    },
+    Infinity,
+    Zero,
+    /// `sig * 2^exp`
+    Finite {
+        exp: i32,
+        sig: u128,
+    },
+}
+
+fn unpack(fmt: Format, bits: u128) -> (bool, Value) {
+    let sign = bits >> (fmt.bits() - 1) & 1 == 1;
+    let field = (bits >> fmt.frac) as i32 & ((1 << fmt.exp) - 1);
+    let frac = bits & fmt.frac_mask();
+    let value = match (field, frac) {
+        (0, 0) => Value::Zero,
+        (0, _) => Value::Finite {
+            exp: fmt.emin() - fmt.frac as i32,
+            sig: frac,
+        },
+        (f, 0) if f == (1 << fmt.exp) - 1 => Value::Infinity,
+        (f, _) if f == (1 << fmt.exp) - 1 => Value::Nan {
+            signaling: frac >> (fmt.frac - 1) == 0,
+        },
+        (f, _) => Value::Finite {
+            exp: f - fmt.bias() - fmt.frac as i32,
+            sig: frac | 1 << fmt.frac,
+        },
+    };
+    (sign, value)
+}
+
+fn is_signaling(fmt: Format, bits: u128) -> bool {
+    matches!(unpack(fmt, bits).1, Value::Nan { signaling: true })
+}
+
+/// Shifts `sig` right by `shift` bits, rounding the result.
+///
+/// Returns the rounded value and whether it is inexact.
+fn shift_round(sig: U256, shift: i32, sign: bool, rm: Rounding) -> (u128, bool) {
+    if shift <= 0 {
+        return (sig.shl(-shift as u32).lo, false);
+    }
+    let shift = shift as u32;
+    let kept = sig.shr(shift).lo;
+    let half = sig.bit(shift - 1);
+    let sticky = sig.any_below(shift - 1);
+    let inexact = half || sticky;
+    let up = match rm {
+        Rounding::NearestEven => half && (sticky || kept & 1 == 1),
+        Rounding::NearestMaxMagnitude => half,
+        Rounding::TowardZero => false,
+        Rounding::Down => sign && inexact,
+        Rounding::Up => !sign && inexact,
+    };
+    (kept + up as u128, inexact)
+}
+
+/// Rounds `sig * 2^exp` to `fmt`.
+///
+/// The lowest bit of `sig` may be a sticky bit standing in for any number of non-zero bits below
+/// it, as long as it is well below the rounding position.
+fn round_pack(fmt: Format, sign: bool, exp: i32, sig: U256, rm: Rounding, flags: &mut u8) -> u128 {
+    if sig.is_zero() {
+        return fmt.zero(sign);
+    }
+
+    let p = fmt.precision();
+    // Exponent of the leading bit
+    let e = exp + sig.bits() as i32 - 1;
+    // Exponent of the last bit that fits, which is fixed for subnormals
+    let mut q = (e - (p - 1)).max(fmt.emin() - (p - 1));
+    let (mut m, inexact) = shift_round(sig, q - exp, sign, rm);
+    if m >> p != 0 {
+        m >>= 1;
+        q += 1;
+    }
+
+    // NOTE: Tininess is detected after rounding, i.e. as if the exponent range were unbounded.
+    if e < fmt.emin() && inexact {
+        let (unbounded, _) = shift_round(sig, e - (p - 1) - exp, sign, rm);
+        if !(e == fmt.emin() - 1 && unbounded >> p != 0) {
+            *flags |= UNDERFLOW;
+        }
+    }
+    if inexact {
+        *flags |= INEXACT;
+    }
+
+    let normal = m >> (p - 1) != 0;
+    if normal && q + p - 1 > fmt.emax() {
+        *flags |= OVERFLOW | INEXACT;
+        return match (rm, sign) {
+            (Rounding::NearestEven | Rounding::NearestMaxMagnitude, _) => fmt.infinity(sign),
+            (Rounding::Down, true) | (Rounding::Up, false) => fmt.infinity(sign),
+            _ => fmt.max_finite(sign),
+        };
+    }
+
+    let field = match normal {
+        true => (q + p - 1 + fmt.bias()) as u128,
+        false => 0,
+    };
+    fmt.sign(sign) | field << fmt.frac | m & fmt.frac_mask()
+}
+
+/// Adds two exact values, compressing the bits of the smaller one that fall far below the larger
+/// one into a sticky bit.
+fn sum(a: (bool, i32, U256), b: (bool, i32, U256)) -> (bool, i32, U256) {
+    let top = |(_, exp, sig): (bool, i32, U256)| exp + sig.bits() as i32;
+    let ((sa, ea, a), (sb, eb, b)) = match top(a) >= top(b) {
+        true => (a, b),
+        false => (b, a),
+    };
+
+    // Moves the leading bit of the larger operand to bit 254, leaving room for a carry
+    let shift = 255 - a.bits();
+    let a = a.shl(shift);
+    let exp = ea - shift as i32;
+    let b = match eb >= exp {
+        true => b.shl((eb - exp) as u32),
+        false => b.shr_sticky((exp - eb) as u32),
+    };
+
+    match (sa == sb, a >= b) {
+        (true, _) => (sa, exp, a.add(b)),
+        (false, true) => (sa, exp, a.sub(b)),
+        (false, false) => (sb, exp, b.sub(a)),
+    }
+}
+
+/// Result of an operation with a NaN operand, or `None` if there are none.
+fn propagate_nan(fmt: Format, operands: &[u128], flags: &mut u8) -> Option<u128> {
+    let nan = |&bits: &u128| matches!(unpack(fmt, bits).1, Value::Nan { .. });
+    if operands.iter().any(|&bits| is_signaling(fmt, bits)) {
+        *flags |= INVALID;
+    }
+    operands.iter().any(nan).then_some(fmt.canonical_nan())
+}
+
+fn invalid(fmt: Format, flags: &mut u8) -> u128 {
+    *flags |= INVALID;
+    fmt.canonical_nan()
+}
+
+/// Sign of an exact zero sum of operands with opposite signs.
+fn zero_sum_sign(rm: Rounding) -> bool {
+    rm == Rounding::Down
+}
+
+pub fn add(fmt: Format, a: u128, b: u128, rm: Rounding, flags: &mut u8) -> u128 {
+    if let Some(nan) = propagate_nan(fmt, &[a, b], flags) {
+        return nan;
+    }
+    match (unpack(fmt, a), unpack(fmt, b)) {
+        ((sa, Value::Infinity), (sb, Value::Infinity)) if sa != sb => invalid(fmt, flags),
+        ((_, Value::Infinity), _) => a,
+        (_, (_, Value::Infinity)) => b,
+        ((sa, Value::Zero), (sb, Value::Zero)) => match sa == sb {
+            true => a,
+            false => fmt.zero(zero_sum_sign(rm)),
+        },
+        ((_, Value::Zero), _) => b,
+        (_, (_, Value::Zero)) => a,
+        ((sa, Value::Finite { exp: ea, sig: ma }), (sb, Value::Finite { exp: eb, sig: mb })) => {
+            let (sign, exp, sig) = sum((sa, ea, U256::new(ma)), (sb, eb, U256::new(mb)));
+            let sign = match sig.is_zero() {
+                true => zero_sum_sign(rm),
+                false => sign,
+            };
+            round_pack(fmt, sign, exp, sig, rm, flags)
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn sub(fmt: Format, a: u128, b: u128, rm: Rounding, flags: &mut u8) -> u128 {
+    // NOTE: NaNs are replaced anyway, so their sign doesn't matter.
+    add(fmt, a, fmt.negate(b), rm, flags)
+}
+
+pub fn mul(fmt: Format, a: u128, b: u128, rm: Rounding, flags: &mut u8) -> u128 {
+    if let Some(nan) = propagate_nan(fmt, &[a, b], flags) {
+        return nan;
+    }
+    let ((sa, va), (sb, vb)) = (unpack(fmt, a), unpack(fmt, b));
+    let sign = sa ^ sb;
+    match (va, vb) {
+        (Value::Infinity, Value::Zero) | (Value::Zero, Value::Infinity) => invalid(fmt, flags),
+        (Value::Infinity, _) | (_, Value::Infinity) => fmt.infinity(sign),
+        (Value::Zero, _) | (_, Value::Zero) => fmt.zero(sign),
+        (Value::Finite { exp: ea, sig: ma }, Value::Finite { exp: eb, sig: mb }) => {
+            round_pack(fmt, sign, ea + eb, U256::mul(ma, mb), rm, flags)
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn div(fmt: Format, a: u128, b: u128, rm: Rounding, flags: &mut u8) -> u128 {
+    if let Some(nan) = propagate_nan(fmt, &[a, b], flags) {
+        return nan;
+    }
+    let ((sa, va), (sb, vb)) = (unpack(fmt, a), unpack(fmt, b));
+    let sign = sa ^ sb;
+    match (va, vb) {
+        (Value::Infinity, Value::Infinity) | (Value::Zero, Value::Zero) => invalid(fmt, flags),
+        (Value::Infinity, _) => fmt.infinity(sign),
+        (_, Value::Infinity) | (Value::Zero, _) => fmt.zero(sign),
+        (_, Value::Zero) => {
+            *flags |= DIVIDE_BY_ZERO;
+            fmt.infinity(sign)
+        }
+        (Value::Finite { exp: ea, sig: ma }, Value::Finite { exp: eb, sig: mb }) => {
+            // Line the significands up so the first quotient bit is 0 or 1
+            let (ma, mb, ea, eb) = match ma.leading_zeros().cmp(&mb.leading_zeros()) {
+                Ordering::Greater => {
+                    let shift = ma.leading_zeros() - mb.leading_zeros();
+                    (ma << shift, mb, ea - shift as i32, eb)
+                }
+                _ => {
+                    let shift = mb.leading_zeros() - ma.leading_zeros();
+                    (ma, mb << shift, ea, eb - shift as i32)
+                }
+            };
+
+            // Long division, producing two bits more than the precision and a sticky bit
+            let steps = fmt.precision() + 2;
+            let (mut rem, mut quotient) = (ma, 0u128);
+            for _ in 0..=steps {
+                quotient <<= 1;
+                if rem >= mb {
+                    rem -= mb;
+                    quotient |= 1;
+                }
+                rem <<= 1;
+            }
+            let sig = U256::new(quotient << 1 | (rem != 0) as u128);
+            round_pack(fmt, sign, ea - eb - steps - 1, sig, rm, flags)
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn sqrt(fmt: Format, a: u128, rm: Rounding, flags: &mut u8) -> u128 {
+    if let Some(nan) = propagate_nan(fmt, &[a], flags) {
+        return nan;
+    }
+    match unpack(fmt, a) {
+        (_, Value::Zero) => a,
+        (true, _) => invalid(fmt, flags),
+        (false, Value::Infinity) => a,
+        (false, Value::Finite { exp, sig }) => {
+            // Scales the radicand so the root has two bits more than the precision, keeping the
+            // exponent even
+            let bits = 128 - sig.leading_zeros() as i32;
+            let mut shift = (2 * (fmt.precision() + 3) - bits).max(0);
+            if (exp - shift) % 2 != 0 {
+                shift += 1;
+            }
+            let radicand = U256::new(sig).shl(shift as u32);
+
+            // Digit-by-digit square root
+            let (mut rem, mut root) = (0u128, 0u128);
+            for i in (0..radicand.bits().div_ceil(2)).rev() {
+                rem = rem << 2 | radicand.shr(2 * i).lo & 0b11;
+                let trial = root << 2 | 1;
+                root <<= 1;
+                if rem >= trial {
+                    rem -= trial;
+                    root |= 1;
+                }
+            }
+            let sig = U256::new(root << 1 | (rem != 0) as u128);
+            round_pack(fmt, false, (exp - shift) / 2 - 1, sig, rm, flags)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Computes `a * b + c` with a single rounding.
+pub fn fma(fmt: Format, a: u128, b: u128, c: u128, rm: Rounding, flags: &mut u8) -> u128 {
+    let ((sa, va), (sb, vb), (sc, vc)) = (unpack(fmt, a), unpack(fmt, b), unpack(fmt, c));
+    // NOTE: Multiplying infinity by zero is invalid even when the addend is a quiet NaN.
+    if matches!(
+        (va, vb),
+        (Value::Infinity, Value::Zero) | (Value::Zero, Value::Infinity)
+    ) {
+        let _ = propagate_nan(fmt, &[c], flags);
+        return invalid(fmt, flags);
+    }
+    if let Some(nan) = propagate_nan(fmt, &[a, b, c], flags) {
+        return nan;
+    }
+
+    let sp = sa ^ sb;
+    match (va, vb, vc) {
+        (Value::Infinity, _, _) | (_, Value::Infinity, _) => match vc {
+            Value::Infinity if sc != sp => invalid(fmt, flags),
+            _ => fmt.infinity(sp),
+        },
+        (_, _, Value::Infinity) => c,
+        (Value::Zero, _, Value::Zero) | (_, Value::Zero, Value::Zero) => match sp == sc {
+            true => c,
+            false => fmt.zero(zero_sum_sign(rm)),
+        },
+        (Value::Zero, _, _) | (_, Value::Zero, _) => c,
+        (Value::Finite { exp: ea, sig: ma }, Value::Finite { exp: eb, sig: mb }, vc) => {
+            let product = (sp, ea + eb, U256::mul(ma, mb));
+            let (sign, exp, sig) = match vc {
+                Value::Finite { exp, sig } => sum(product, (sc, exp, U256::new(sig))),
+                _ => product,
+            };
+            let sign = match sig.is_zero() {
+                true => zero_sum_sign(rm),
+                false => sign,
+            };
+            round_pack(fmt, sign, exp, sig, rm, flags)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Compares two values, returning `None` when unordered.
+fn compare(fmt: Format, a: u128, b: u128) -> Option<Ordering> {
+    let key = |bits: u128| match unpack(fmt, bits) {
+        (_, Value::Nan { .. }) => None,
+        (sign, _) => {
+            let magnitude = (bits & !fmt.sign(true)) as i128;
+            Some(if sign { -magnitude } else { magnitude })
+        }
+    };
+    Some(key(a)?.cmp(&key(b)?))
+}
+
+/// `feq`, which only signals for signaling NaNs.
+pub fn eq(fmt: Format, a: u128, b: u128, flags: &mut u8) -> bool {
+    if is_signaling(fmt, a) || is_signaling(fmt, b) {
+        *flags |= INVALID;
+    }
+    compare(fmt, a, b) == Some(Ordering::Equal)
+}
+
+/// `flt`, which signals for all NaNs.
+pub fn lt(fmt: Format, a: u128, b: u128, flags: &mut u8) -> bool {
+    let ordering = compare(fmt, a, b);
+    if ordering.is_none() {
+        *flags |= INVALID;
+    }
+    ordering == Some(Ordering::Less)
+}
+
+/// `fle`, which signals for all NaNs.
+pub fn le(fmt: Format, a: u128, b: u128, flags: &mut u8) -> bool {
+    let ordering = compare(fmt, a, b);
+    if ordering.is_none() {
+        *flags |= INVALID;
+    }
+    matches!(ordering, Some(Ordering::Less | Ordering::Equal))
+}
+
+/// IEEE 754-2019 `minimumNumber` or `maximumNumber`, where -0 is less than +0.
+pub fn min_max(fmt: Format, a: u128, b: u128, max: bool, flags: &mut u8) -> u128 {
+    let _ = propagate_nan(fmt, &[a, b], flags);
+    let ordering = match (compare(fmt, a, b), compare(fmt, a, a), compare(fmt, b, b)) {
+        (Some(Ordering::Equal), _, _) => (a >> (fmt.bits() - 1))
+            .cmp(&(b >> (fmt.bits() - 1)))
+            .reverse(),
+        (Some(ordering), _, _) => ordering,
+        (None, None, None) => return fmt.canonical_nan(),
+        (None, None, _) => return b,
+        (None, _, _) => return a,
+    };
+    match (ordering == Ordering::Less) ^ max {
+        true => a,
+        false => b,
+    }
+}
+
+/// `fclass`, returning a mask with a single bit set.
+pub fn classify(fmt: Format, a: u128) -> u16 {
+    let (sign, value) = unpack(fmt, a);
+    let subnormal = |sig: u128| sig >> fmt.frac == 0;
+    let bit = match (sign, value) {
+        (true, Value::Infinity) => 0,
+        (true, Value::Finite { sig, .. }) if !subnormal(sig) => 1,
+        (true, Value::Finite { .. }) => 2,
+        (true, Value::Zero) => 3,
+        (false, Value::Zero) => 4,
+        (false, Value::Finite { sig, .. }) if subnormal(sig) => 5,
+        (false, Value::Finite { .. }) => 6,
+        (false, Value::Infinity) => 7,
+        (_, Value::Nan { signaling: true }) => 8,
+        (_, Value::Nan { signaling: false }) => 9,
+    };
+    1 << bit
+}
+
+/// Converts between formats.
+pub fn convert(from: Format, to: Format, a: u128, rm: Rounding, flags: &mut u8) -> u128 {
+    if propagate_nan(from, &[a], flags).is_some() {
+        return to.canonical_nan();
+    }
+    match unpack(from, a) {
+        (sign, Value::Infinity) => to.infinity(sign),
+        (sign, Value::Zero) => to.zero(sign),
+        (sign, Value::Finite { exp, sig }) => round_pack(to, sign, exp, U256::new(sig), rm, flags),
+        (_, Value::Nan { .. }) => unreachable!(),
+    }
+}
+
+/// Converts to a `bits`-wide integer, saturating out-of-range values.
+///
+/// The result is returned in the lower `bits` bits.
+pub fn to_int(fmt: Format, a: u128, signed: bool, bits: u32, rm: Rounding, flags: &mut u8) -> u128 {
+    let mask = u128::MAX >> (128 - bits);
+    let (min, max) = match signed {
+        true => (1 << (bits - 1), mask >> 1),
+        false => (0, mask),
+    };
+
+    let (sign, magnitude, inexact) = match unpack(fmt, a) {
+        (_, Value::Nan { .. }) => (false, None, false),
+        (sign, Value::Infinity) => (sign, None, false),
+        (sign, Value::Zero) => (sign, Some(0), false),
+        (sign, Value::Finite { exp, sig }) => {
+            let sig = U256::new(sig);
+            match exp >= 0 {
+                true if sig.bits() as i32 + exp > 128 => (sign, None, false),
+                true => (sign, Some(sig.shl(exp as u32).lo), false),
+                false => {
+                    let (magnitude, inexact) = shift_round(sig, -exp, sign, rm);
+                    (sign, Some(magnitude), inexact)
+                }
+            }
+        }
+    };
+
+    let in_range = match (magnitude, sign, signed) {
+        (None, _, _) => false,
+        (Some(magnitude), false, _) => magnitude <= max,
+        (Some(magnitude), true, true) => magnitude <= min,
+        (Some(magnitude), true, false) => magnitude == 0,
+    };
+    match (in_range, magnitude) {
+        (true, Some(magnitude)) => {
+            if inexact {
+                *flags |= INEXACT;
+            }
+            match sign {
+                true => magnitude.wrapping_neg() & mask,
+                false => magnitude,
+            }
+        }
+        _ => {
+            *flags |= INVALID;
+            let nan = matches!(unpack(fmt, a).1, Value::Nan { .. });
+            match sign && !nan {
+                true => min,
+                false => max,
+            }
+        }
+    }
+}
+
+/// Converts an integer, given as its sign and magnitude.
+pub fn from_int(
+    fmt: Format,
+    negative: bool,
+    magnitude: u128,
+    rm: Rounding,
+    flags: &mut u8,
+) -> u128 {
+    match magnitude {
+        0 => fmt.zero(false),
+        _ => round_pack(fmt, negative, 0, U256::new(magnitude), rm, flags),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RNE: Rounding = Rounding::NearestEven;
+
+    fn single(value: f32) -> u128 {
+        value.to_bits() as u128
+    }
+
+    fn double(value: f64) -> u128 {
+        value.to_bits() as u128
+    }
+
+    /// Compares against the host for round-to-nearest-even.
+    #[test]
+    fn test_against_host() {
+        let values = [
+            0.0,
+            -0.0,
+            1.0,
+            -1.5,
+            3.0,
+            0.1,
+            1e-40,
+            -2.5e-39,
+            1e38,
+            3.4e38,
+            f32::MIN_POSITIVE,
+            f32::EPSILON,
+            f32::MAX,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            123456.79,
+            -7.0e-3,
+        ];
+        for &a in &values {
+            for &b in &values {
+                let mut flags = 0;
+                let fmt = Format::SINGLE;
+                let check = |ours: u128, host: f32| match host.is_nan() {
+                    true => assert_eq!(ours, fmt.canonical_nan(), "{a} {b}"),
+                    false => assert_eq!(ours, single(host), "{a} {b}"),
+                };
+                check(add(fmt, single(a), single(b), RNE, &mut flags), a + b);
+                check(sub(fmt, single(a), single(b), RNE, &mut flags), a - b);
+                check(mul(fmt, single(a), single(b), RNE, &mut flags), a * b);
+                check(div(fmt, single(a), single(b), RNE, &mut flags), a / b);
+                check(sqrt(fmt, single(a), RNE, &mut flags), a.sqrt());
+                for &c in &values {
+                    check(
+                        fma(fmt, single(a), single(b), single(c), RNE, &mut flags),
+                        a.mul_add(b, c),
+                    );
+                }
+                let (a, b) = (a as f64 * 1.1, b as f64 / 3.0);
+                let fmt = Format::DOUBLE;
+                let host = [a + b, a * b, a / b, a.sqrt(), a.mul_add(b, a)];
+                let ours = [
+                    add(fmt, double(a), double(b), RNE, &mut flags),
+                    mul(fmt, double(a), double(b), RNE, &mut flags),
+                    div(fmt, double(a), double(b), RNE, &mut flags),
+                    sqrt(fmt, double(a), RNE, &mut flags),
+                    fma(fmt, double(a), double(b), double(a), RNE, &mut flags),
+                ];
+                for (ours, host) in ours.into_iter().zip(host) {
+                    match host.is_nan() {
+                        true => assert_eq!(ours, fmt.canonical_nan()),
+                        false => assert_eq!(ours, double(host), "{a} {b}"),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_flags() {
+        let fmt = Format::SINGLE;
+        let mut flags = 0;
+        div(fmt, single(1.0), single(0.0), RNE, &mut flags);
+        assert_eq!(flags, DIVIDE_BY_ZERO);
+
+        let mut flags = 0;
+        div(fmt, single(1.0), single(3.0), RNE, &mut flags);
+        assert_eq!(flags, INEXACT);
+
+        let mut flags = 0;
+        mul(fmt, single(f32::MAX), single(2.0), RNE, &mut flags);
+        assert_eq!(flags, OVERFLOW | INEXACT);
+
+        let mut flags = 0;
+        mul(fmt, single(f32::MIN_POSITIVE), single(0.3), RNE, &mut flags);
+        assert_eq!(flags, UNDERFLOW | INEXACT);
+
+        let mut flags = 0;
+        sqrt(fmt, single(-1.0), RNE, &mut flags);
+        assert_eq!(flags, INVALID);
+
+        // Rounding toward zero saturates to the largest finite value
+        let mut flags = 0;
+        let value = mul(
+            fmt,
+            single(f32::MAX),
+            single(2.0),
+            Rounding::TowardZero,
+            &mut flags,
+        );
+        assert_eq!(value, single(f32::MAX));
+    }
+
+    #[test]
+    fn test_conversions() {
+        let mut flags = 0;
+        // 1/3 in single precision, rounded to half precision both ways
+        let third = single(1.0 / 3.0);
+        assert_eq!(
+            convert(Format::SINGLE, Format::HALF, third, RNE, &mut flags),
+            0x3555
+        );
+        let up = convert(
+            Format::SINGLE,
+            Format::HALF,
+            third,
+            Rounding::Up,
+            &mut flags,
+        );
+        assert_eq!(up, 0x3556);
+        assert_eq!(
+            convert(Format::SINGLE, Format::BF16, third, RNE, &mut flags),
+            0x3eab
+        );
+        assert_eq!(
+            convert(Format::HALF, Format::SINGLE, 0x3c00, RNE, &mut flags),
+            single(1.0)
+        );
+
+        let mut flags = 0;
+        let value = to_int(Format::SINGLE, single(-2.5), true, 32, RNE, &mut flags);
+        assert_eq!((value, flags), (0xffff_fffe, INEXACT));
+        let mut flags = 0;
+        let value = to_int(Format::SINGLE, single(-1.0), false, 32, RNE, &mut flags);
+        assert_eq!((value, flags), (0, INVALID));
+        let mut flags = 0;
+        let value = to_int(
+            Format::SINGLE,
+            Format::SINGLE.canonical_nan(),
+            true,
+            64,
+            RNE,
+            &mut flags,
+        );
+        assert_eq!((value, flags), (i64::MAX as u128, INVALID));
+
+        let mut flags = 0;
+        let value = from_int(Format::SINGLE, false, 0x1_0000_0001, RNE, &mut flags);
+        assert_eq!((value, flags), (single(4294967296.0), INEXACT));
+    }
+
+    #[test]
+    fn test_quad() {
+        let fmt = Format::QUAD;
+        let one = 0x3fff_0000_0000_0000_0000_0000_0000_0000;
+        let two = 0x4000_0000_0000_0000_0000_0000_0000_0000;
+        let three = 0x4000_8000_0000_0000_0000_0000_0000_0000;
+        let mut flags = 0;
+        let third = div(fmt, one, three, RNE, &mut flags);
+        assert_eq!(third, 0x3ffd_5555_5555_5555_5555_5555_5555_5555);
+        let root = sqrt(fmt, two, RNE, &mut flags);
+        assert_eq!(root, 0x3fff_6a09_e667_f3bc_c908_b2fb_1366_ea95);
+        assert_eq!(flags, INEXACT);
+
+        // Beyond the precision of a double, and exact in a quad
+        let mut flags = 0;
+        let value = from_int(fmt, false, (1 << 100) + 1, RNE, &mut flags);
+        let back = to_int(fmt, value, false, 128, RNE, &mut flags);
+        assert_eq!((back, flags), ((1 << 100) + 1, 0));
+        let value = convert(fmt, Format::DOUBLE, root, RNE, &mut flags);
+        assert_eq!(value, double(2f64.sqrt()));
+    }
+
+    #[test]
+    fn test_min_max() {
+        let fmt = Format::SINGLE;
+        let mut flags = 0;
+        // -0 is less than +0
+        assert_eq!(
+            min_max(fmt, single(-0.0), single(0.0), false, &mut flags),
+            single(-0.0)
+        );
+        assert_eq!(
+            min_max(fmt, single(0.0), single(-0.0), false, &mut flags),
+            single(-0.0)
+        );
+        assert_eq!(
+            min_max(fmt, single(0.0), single(-0.0), true, &mut flags),
+            single(0.0)
+        );
+        assert_eq!(
+            min_max(fmt, single(f32::NAN), single(2.0), false, &mut flags),
+            single(2.0)
+        );
+        assert_eq!(flags, 0);
+    }
+}
@@ -0,0 +1,88 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright (C) 2024 mumblingdrunkard
+
+//! Module containing the vector register file.
+
+use crate::snapshot::{Reader, Snapshot, SnapshotError, Writer};
+
+/// The 32 vector registers, stored back to back.
+///
+/// Register groups are consecutive registers, so element `i` of the group starting at `v` is found
+/// at the same offset whether it lives in `v` or one of the registers following it.
+pub struct VRegFile {
+    vlenb: usize,
+    bytes: Box<[u8]>,
+}
+
+impl VRegFile {
+    /// Creates a register file with `vlen`-bit registers.
+    pub fn new(vlen: u32) -> Self {
+        let vlenb = vlen as usize / 8;
+        Self {
+            vlenb,
+            bytes: vec![0; 32 * vlenb].into_boxed_slice(),
+        }
+    }
+
+    /// Width of a single register in bytes.
+    pub fn vlenb(&self) -> usize {
+        self.vlenb
+    }
+
+    /// The bytes of register `v`.
+    pub fn reg(&self, v: u8) -> &[u8] {
+        let start = v as usize * self.vlenb;
+        &self.bytes[start..start + self.vlenb]
+    }
+
+    pub fn reg_mut(&mut self, v: u8) -> &mut [u8] {
+        let start = v as usize * self.vlenb;
+        &mut self.bytes[start..start + self.vlenb]
+    }
+
+    /// Reads element `index` of the `eew`-bit elements of the group starting at `v`.
+    pub fn get(&self, v: u8, index: usize, eew: u32) -> u64 {
+        let width = eew as usize / 8;
+        let start = v as usize * self.vlenb + index * width;
+        let mut buf = [0; 8];
+        buf[..width].copy_from_slice(&self.bytes[start..start + width]);
+        u64::from_le_bytes(buf)
+    }
+
+    /// Writes the lower `eew` bits of `value` to element `index` of the group starting at `v`.
+    pub fn set(&mut self, v: u8, index: usize, eew: u32, value: u64) {
+        let width = eew as usize / 8;
+        let start = v as usize * self.vlenb + index * width;
+        self.bytes[start..start + width].copy_from_slice(&value.to_le_bytes()[..width]);
+    }
+
+    /// Reads bit `index` of mask register `v`.
+    pub fn mask(&self, v: u8, index: usize) -> bool {
+        self.reg(v)[index / 8] >> (index % 8) & 1 == 1
+    }
+
+    pub fn set_mask(&mut self, v: u8, index: usize, value: bool) {
+        let byte = &mut self.reg_mut(v)[index / 8];
+        *byte = *byte & !(1 << (index % 8)) | (value as u8) << (index % 8);
+    }
+}
+
+impl Snapshot for VRegFile {
+    fn save(&self, w: &mut Writer) {
+        w.u64(self.bytes.len() as u64);
+        w.bytes(&self.bytes);
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        r.expect("size of the vector registers", self.bytes.len() as u64)?;
+        let len = self.bytes.len();
+        self.bytes.copy_from_slice(r.bytes(len)?);
+        Ok(())
+    }
+}